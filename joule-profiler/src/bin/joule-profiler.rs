@@ -1,6 +1,17 @@
-use anyhow::Result;
+use joule_profiler::error::error_exit_code;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    joule_profiler::run().await
+async fn main() {
+    let exit_code = match joule_profiler::run().await {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            // Help, version and usage errors keep the exit codes of clap
+            if let Some(clap_err) = err.downcast_ref::<clap::Error>() {
+                clap_err.exit();
+            }
+            eprintln!("Error: {:?}", err);
+            error_exit_code(&err)
+        }
+    };
+    std::process::exit(exit_code);
 }
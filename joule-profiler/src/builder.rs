@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    cli::FakeMetric,
+    command::simple::collect_simple,
+    config::{Mode, ProfileConfig},
+    measurement::MeasurementResult,
+};
+
+/// Digits after the decimal point of the derived metrics, as with the command line default.
+const DEFAULT_PRECISION: usize = 3;
+
+/// Measure the energy of a command from a library, without building a [`ProfileConfig`].
+///
+/// The command is run once in simple mode, its output going to the standard output. The
+/// example reports fixed metrics with [`MeasurementBuilder::fake_source`], remove it to read
+/// RAPL.
+///
+/// ```
+/// use joule_profiler::{builder::MeasurementBuilder, cli::FakeMetric};
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let result = MeasurementBuilder::new()
+///     .sockets([0])
+///     .domains(["package", "dram"])
+///     .fake_source(vec![FakeMetric {
+///         name: "PACKAGE-0_0".to_string(),
+///         value: 1_500_000,
+///     }])
+///     .run(["echo", "hi"])
+///     .await?;
+///
+/// for metric in &result.metrics {
+///     println!("{}: {} {}", metric.name, metric.value, metric.unit);
+/// }
+/// assert_eq!(result.exit_code, 0);
+/// assert_eq!(result.metrics[0].name, "PACKAGE-0_0");
+/// assert_eq!(result.metrics[0].value, 1_500_000);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MeasurementBuilder {
+    config: ProfileConfig,
+}
+
+impl Default for MeasurementBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeasurementBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: ProfileConfig {
+                iterations: 1,
+                precision: DEFAULT_PRECISION,
+                time_series_bin_s: 1.0,
+                mode: Mode::SimpleMode,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Only measure the domains of these CPU sockets.
+    pub fn sockets(mut self, sockets: impl IntoIterator<Item = u32>) -> Self {
+        self.config.sockets = Some(sockets.into_iter().collect::<HashSet<u32>>());
+        self
+    }
+
+    /// Poll the RAPL counters at this interval in seconds, instead of only reading them
+    /// before and after the command.
+    pub fn polling(mut self, interval_s: f64) -> Self {
+        self.config.rapl_polling = Some(interval_s);
+        self
+    }
+
+    /// Only measure the domains with these names (or name prefixes), e.g. `package`.
+    pub fn domains(mut self, domains: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config.domain_options.domains = Some(domains.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Read the RAPL domains from this directory instead of the powercap one.
+    pub fn rapl_path(mut self, path: impl Into<String>) -> Self {
+        self.config.rapl_path = Some(path.into());
+        self
+    }
+
+    /// Report these metrics instead of reading the sources, to test without RAPL.
+    pub fn fake_source(mut self, metrics: Vec<FakeMetric>) -> Self {
+        self.config.fake_source = metrics;
+        self
+    }
+
+    /// Run the command and return its measurement.
+    pub async fn run(
+        mut self,
+        cmd: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<MeasurementResult> {
+        self.config.cmd = cmd.into_iter().map(Into::into).collect();
+
+        // One result per iteration, the builder runs a single one
+        collect_simple(&self.config)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("The measurement of {:?} has no result", self.config.cmd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn builder_measures_the_command() {
+        let result = MeasurementBuilder::new()
+            .sockets([0])
+            .domains(["package"])
+            .fake_source(vec![FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 2_000,
+            }])
+            .run(["echo", "hi"])
+            .await
+            .unwrap();
+
+        assert_eq!(result.exit_code, 0);
+        assert!(
+            result
+                .metrics
+                .iter()
+                .any(|metric| metric.name == "PACKAGE-0_0" && metric.value == 2_000)
+        );
+    }
+}
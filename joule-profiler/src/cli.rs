@@ -1,4 +1,11 @@
-use clap::{ArgAction, Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+
+use crate::{
+    config::OutputFormat,
+    error::JouleProfilerError,
+    output::{OUTPUT_FIELDS, influx::DEFAULT_INFLUX_MEASUREMENT},
+    util::file::FileMode,
+};
 
 /// joule-profiler: measure program energy consumption using Intel RAPL
 #[derive(Parser, Debug)]
@@ -17,17 +24,196 @@ pub struct Cli {
     /// If not provided, the profiler uses (by priority):
     ///   1. $JOULE_PROFILER_RAPL_PATH (if set)
     ///   2. /sys/devices/virtual/powercap/intel-rapl
+    ///
+    /// Several base paths can be given separated by commas, their domains are merged.
     #[arg(long = "rapl-path")]
     pub rapl_path: Option<String>,
 
-    /// Sockets to measure (e.g. 0 or 0,1)
+    /// Interface used to read the RAPL energy counters.
+    ///
+    /// The MSR backend reads /dev/cpu/N/msr directly (requires root and the
+    /// msr kernel module), useful when powercap sysfs is restricted.
+    #[arg(long = "rapl-backend", value_enum, default_value_t = RaplBackendKind::Powercap)]
+    pub rapl_backend: RaplBackendKind,
+
+    /// Maximum energy range in microjoules assumed for the powercap domains lacking
+    /// max_energy_range_uj (default: the 32-bit range, 0 excludes these domains)
+    #[arg(long = "assume-max-energy", default_value_t = u32::MAX as u64, value_name = "UJ")]
+    pub assume_max_energy: u64,
+
+    /// Keep only one of the powercap domains found twice with the same name, socket and
+    /// energy, which would be counted twice (a warning is always logged)
+    #[arg(long = "dedup-domains")]
+    pub dedup_domains: bool,
+
+    /// File of `name=normalized` rules renaming the powercap domains (e.g. `package-0=package`)
+    /// so the outputs are consistent across kernels
+    #[arg(long = "domain-map", value_name = "FILE")]
+    pub domain_map: Option<String>,
+
+    /// Octal permissions of the created output files (e.g. 600), world-writable modes are
+    /// refused
+    #[arg(
+        long = "output-mode",
+        value_name = "OCTAL",
+        default_value = "664",
+        value_parser = parse_file_mode
+    )]
+    pub output_mode: FileMode,
+
+    /// Domains to measure by name or name prefix (e.g. package,dram), an unknown domain is an
+    /// error listing the available ones
+    #[arg(long = "domains", value_name = "NAMES", value_delimiter = ',')]
+    pub domains: Option<Vec<String>>,
+
+    /// Only measure the domains matching one of these name globs, case insensitive (e.g.
+    /// PACKAGE*,DRAM)
+    #[arg(long = "include-sensors", value_name = "GLOBS", value_delimiter = ',')]
+    pub include_sensors: Vec<String>,
+
+    /// Leave out the domains matching one of these name globs, winning over
+    /// --include-sensors
+    #[arg(long = "exclude-sensors", value_name = "GLOBS", value_delimiter = ',')]
+    pub exclude_sensors: Vec<String>,
+
+    /// Sockets to measure, a comma list of sockets and ranges (e.g. 0, 0,1 or 0,2-3)
     #[arg(short = 's', long = "sockets")]
     pub sockets: Option<String>,
 
+    /// When to color the terminal output, `auto` disables it when stdout is not a terminal
+    /// or NO_COLOR is set
+    #[arg(long = "color", value_enum, default_value_t = ColorMode::Auto, value_name = "WHEN")]
+    pub color: ColorMode,
+
+    /// Disable colors, same as `--color never`
+    #[arg(long = "no-color")]
+    pub no_color: bool,
+
     #[command(subcommand)]
     pub command: ProfilerCommand,
 }
 
+/// Interface used to read the RAPL energy counters
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RaplBackendKind {
+    /// Powercap sysfs interface
+    #[default]
+    #[value(alias = "sysfs")]
+    Powercap,
+    /// Model specific registers
+    Msr,
+}
+
+/// When to color the terminal output
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Color only when writing to a terminal and NO_COLOR is unset
+    #[default]
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
+
+/// Unit of a printed energy
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnergyUnit {
+    /// Microjoules, as an integer
+    Uj,
+    /// Millijoules
+    Mj,
+    /// Joules
+    J,
+    /// Kilojoules
+    Kj,
+    /// Watt-hours
+    Wh,
+}
+
+impl EnergyUnit {
+    /// Symbol of the unit, as in the `unit` of the metrics.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            EnergyUnit::Uj => "µJ",
+            EnergyUnit::Mj => "mJ",
+            EnergyUnit::J => "J",
+            EnergyUnit::Kj => "kJ",
+            EnergyUnit::Wh => "Wh",
+        }
+    }
+
+    /// Convert an energy in microjoules to the unit.
+    pub fn from_uj(self, energy_uj: f64) -> f64 {
+        let uj_per_unit = match self {
+            EnergyUnit::Uj => 1.0,
+            EnergyUnit::Mj => 1e3,
+            EnergyUnit::J => 1e6,
+            EnergyUnit::Kj => 1e9,
+            EnergyUnit::Wh => 3.6e9,
+        };
+        energy_uj / uj_per_unit
+    }
+}
+
+/// Display override of one metric
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricFormat {
+    pub name: String,
+    pub unit: String,
+    pub precision: usize,
+}
+
+/// Weight of one metric in the `SCORE` metric
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreWeight {
+    pub name: String,
+    pub weight: f64,
+}
+
+/// Metric reported by the fake source, in microjoules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeMetric {
+    pub name: String,
+    pub value: u64,
+}
+
+/// Environment variable set for the profiled program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+}
+
+/// Rounding applied to integer divisions of energy totals
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round to the nearest integer (half away from zero)
+    Nearest,
+    /// Truncate towards zero
+    #[default]
+    Down,
+}
+
+/// Rule detecting the outlier iterations of a metric, dropped from its summary
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlierMethod {
+    /// Outside 1.5 interquartile ranges below the first or above the third quartile
+    Iqr,
+    /// Modified z-score above 3.5, from the median absolute deviation
+    Mad,
+}
+
+/// Output streams of the program scanned for the phase tokens
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenStream {
+    #[default]
+    Stdout,
+    Stderr,
+    /// Both streams, read in order from a shared pipe
+    Both,
+}
+
 /// Subcommands of joule-profiler
 #[derive(Subcommand, Debug)]
 pub enum ProfilerCommand {
@@ -39,42 +225,548 @@ pub enum ProfilerCommand {
 
     /// List available RAPL energy domains
     ListSensors(ListArgs),
+
+    /// Run the same command at several polling rates to compare accuracy and overhead
+    Calibrate(CalibrateArgs),
+
+    /// Measure a baseline and a candidate command and report the change of each metric
+    Compare(CompareArgs),
+
+    /// Print the version and the RAPL capabilities of the machine, for bug reports
+    Env,
+
+    /// Render a JSON result previously written by joule-profiler as terminal output
+    Show(ShowArgs),
+
+    /// Record the energy and power of each domain to a CSV file at a fixed rate, without
+    /// running a command, until the duration elapses or Ctrl-C
+    Log(LogArgs),
+
+    /// Print the power of each domain at a fixed rate, without running a command, until
+    /// Ctrl-C and then the energy of the session
+    Watch(WatchArgs),
 }
 
 /// Fields common to both Simple and Phases modes
 #[derive(Parser, Debug)]
 pub struct CommonArgs {
     /// Export results as JSON instead of pretty terminal output
-    #[arg(long, conflicts_with = "csv")]
+    #[arg(long, conflicts_with_all = ["csv", "format", "markdown", "influx", "prometheus"])]
     pub json: bool,
 
+    /// Write a canonical JSON for golden comparisons: sorted keys and metrics, without the
+    /// fields depending on the run timing (durations, measure counts, power, polling)
+    #[arg(long = "canonical-json")]
+    pub canonical_json: bool,
+
+    /// Nest the JSON metrics under their socket (`{"0": [...], "1": [...]}`) instead of a
+    /// flat array, the metrics without a socket going under `other`
+    #[arg(long = "json-group-by-socket")]
+    pub json_group_by_socket: bool,
+
     /// Export results as CSV (semicolon-separated values)
-    #[arg(long, conflicts_with = "json")]
+    #[arg(long, conflicts_with_all = ["json", "format", "markdown", "influx", "prometheus"])]
     pub csv: bool,
 
+    /// Export results as GitHub-flavored Markdown tables, one row per iteration or phase
+    #[arg(long, conflicts_with_all = ["json", "csv", "format", "influx", "prometheus"])]
+    pub markdown: bool,
+
+    /// Export results as InfluxDB line protocol, a line per metric of each iteration or phase
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "format", "prometheus"])]
+    pub influx: bool,
+
+    /// Measurement name of the InfluxDB lines
+    #[arg(
+        long = "influx-measurement",
+        value_name = "NAME",
+        default_value = DEFAULT_INFLUX_MEASUREMENT
+    )]
+    pub influx_measurement: String,
+
+    /// Export results in the Prometheus text format for the node_exporter textfile
+    /// collector, energy, duration and exit code gauges labelled with the command and iteration
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "influx", "format"])]
+    pub prometheus: bool,
+
+    /// Also print the results in the terminal when they are written to a file (JSON, CSV...)
+    #[arg(long = "also-terminal")]
+    pub also_terminal: bool,
+
+    /// Run this shell command once the results file is written, with its path appended as
+    /// an argument and the exit code and energy in JP_EXIT_CODE and JP_TOTAL_UJ
+    #[arg(long = "post-hook", value_name = "CMD")]
+    pub post_hook: Option<String>,
+
+    /// Output format ("none" discards the results, e.g. to time the profiler itself)
+    #[arg(long = "format", value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Write a self-contained HTML report with a chart to the file, requires the `html`
+    /// feature
+    #[cfg(feature = "html")]
+    #[arg(
+        long = "html",
+        value_name = "FILE",
+        conflicts_with_all = ["json", "csv", "markdown", "influx", "prometheus", "format", "jouleit_file", "msgpack"]
+    )]
+    pub html: Option<String>,
+
+    /// Write the results to the file as MessagePack, the JSON documents in a compact
+    /// binary encoding
+    #[arg(
+        long = "msgpack",
+        value_name = "FILE",
+        conflicts_with_all = ["json", "csv", "markdown", "influx", "prometheus", "format", "jouleit_file"]
+    )]
+    pub msgpack: Option<String>,
+
     /// Number of iterations (>=1)
     #[arg(short = 'n', long = "iterations")]
     pub iterations: Option<usize>,
 
-    /// Output file for CSV/JSON (else data<TIMESTAMP>.csv/json)
+    /// Stop the iterations once they ran for this wall time, even before -n is reached
+    #[arg(
+        long = "time-budget",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        requires = "iterations"
+    )]
+    pub time_budget: Option<f64>,
+
+    /// Stop the iterations once they consumed this energy (packages and DRAM), even before
+    /// -n is reached
+    #[arg(
+        long = "energy-budget",
+        value_name = "JOULES",
+        value_parser = parse_joules,
+        requires = "iterations"
+    )]
+    pub energy_budget: Option<f64>,
+
+    /// Output file for CSV/JSON (else data<TIMESTAMP>.csv/json), `-` for stdout with the
+    /// program output moved to stderr
     #[arg(long = "jouleit-file")]
     pub jouleit_file: Option<String>,
 
+    /// Add the results to the end of the `--jouleit-file` instead of overwriting it, the CSV
+    /// header being only written to an empty file and the JSON documents one per line
+    #[arg(long = "append", requires = "jouleit_file")]
+    pub append: bool,
+
+    /// Discard the profiled program stdout, unless redirected with --output-file, the phase
+    /// tokens still being detected
+    #[arg(short = 'q', long = "quiet")]
+    pub quiet: bool,
+
+    /// Run the command through `sh -c`, its arguments joined with spaces, so that pipelines,
+    /// redirections and globs work. The shell interprets the whole command line: never pass
+    /// it untrusted input
+    #[arg(long = "shell")]
+    pub shell: bool,
+
+    /// Check the setup (sources, sockets, token pattern, program on PATH) and print what would
+    /// be measured, without running the command
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
     /// Redirect profiled program stdout to this file
     #[arg(short = 'o', long = "output-file")]
     pub output_file: Option<String>,
 
+    /// Redirect profiled program stderr to this file
+    #[arg(long = "stderr-file", conflicts_with = "merge_stderr")]
+    pub stderr_file: Option<String>,
+
+    /// Merge profiled program stderr into its stdout (also used for token matching in phases mode)
+    #[arg(long = "merge-stderr")]
+    pub merge_stderr: bool,
+
+    /// Feed this file to the profiled program stdin, reopened for every iteration, instead of
+    /// the inherited stdin
+    #[arg(long = "stdin-file", value_name = "FILE")]
+    pub stdin_file: Option<String>,
+
     /// Command to execute (everything after `--`)
     #[arg(last = true)]
     pub cmd: Vec<String>,
 
-    #[arg(long = "rapl-polling")]
+    /// Also read the RAPL counters at this interval in seconds (> 0) while the command runs
+    #[arg(long = "rapl-polling", value_name = "SECONDS")]
     pub rapl_polling: Option<f64>,
+
+    /// Drop the energy of the first and last N polling intervals, which straddle the spawn
+    /// and the exit of the command (use with --rapl-polling)
+    #[arg(long = "trim-samples", default_value_t = 0, value_name = "N")]
+    pub trim_samples: usize,
+
+    /// Consecutive polling intervals without any energy before reporting frozen counters
+    /// (0 disables the check)
+    #[arg(long = "stall-intervals", default_value_t = 100, value_name = "N")]
+    pub stall_intervals: u32,
+
+    /// Abort the measurement when the counters stop advancing instead of only logging an error
+    #[arg(long = "strict")]
+    pub strict: bool,
+
+    /// Events buffered per source worker before the profiler waits for it (default: 64),
+    /// raise it for commands printing many phase tokens per second
+    #[arg(
+        long = "event-buffer",
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub event_buffer: Option<u16>,
+
+    /// Also read the energy with the other RAPL backend (MSR with powercap and the reverse)
+    /// and fail when a domain differs by more than --cross-check-tolerance between them
+    #[arg(long = "cross-check")]
+    pub cross_check: bool,
+
+    /// Largest accepted difference between the backends with --cross-check, in percent
+    #[arg(
+        long = "cross-check-tolerance",
+        default_value_t = 5.0,
+        value_name = "PCT"
+    )]
+    pub cross_check_tolerance: f64,
+
+    /// Only log a warning when the backends diverge with --cross-check
+    #[arg(long = "cross-check-warn-only")]
+    pub cross_check_warn_only: bool,
+
+    /// With polling, only count the energy of the intervals where the machine CPU utilization
+    /// (sampled from /proc/stat) exceeds this percentage, excluding idle waiting
+    #[arg(long = "min-cpu-utilization", value_parser = parse_percent, value_name = "PERCENT")]
+    pub min_cpu_utilization: Option<f64>,
+
+    /// Also export the measured energy to an OpenTelemetry collector (OTLP/HTTP endpoint),
+    /// requires the `otlp` cargo feature
+    #[arg(long = "otlp", value_name = "ENDPOINT")]
+    pub otlp: Option<String>,
+
+    /// Rounding of integer energy averages and estimates (default: down, nearest for the
+    /// container energy estimate)
+    #[arg(long = "rounding", value_enum)]
+    pub rounding: Option<Rounding>,
+
+    /// Number of decimal places used for floating point values in the output
+    #[arg(long = "precision", default_value_t = 3, value_name = "N")]
+    pub precision: usize,
+
+    /// Append derived columns to the CSV output (total energy, EDP)
+    #[arg(long = "derived")]
+    pub derived: bool,
+
+    /// Always write the CSV iteration column, even for a single iteration
+    #[arg(long = "always-iteration-column")]
+    pub always_iteration_column: bool,
+
+    /// Field separator of the CSV output (default: ';'), the fields containing it are quoted
+    #[arg(long = "csv-delimiter", value_name = "CHAR", value_parser = parse_csv_delimiter)]
+    pub csv_delimiter: Option<char>,
+
+    /// Only write these non-metric CSV columns and JSON keys (e.g. command,duration_ms),
+    /// the metrics are always written
+    #[arg(
+        long = "fields",
+        value_name = "FIELD,...",
+        value_delimiter = ',',
+        value_parser = parse_output_field
+    )]
+    pub fields: Vec<String>,
+
+    /// Add the energy of each domain relative to a reference domain, in thousandths: an exact
+    /// metric (PACKAGE-0_0) or a domain prefix compared per socket (PACKAGE)
+    #[arg(long = "ratio-to", value_name = "DOMAIN")]
+    pub ratio_to: Option<String>,
+
+    /// Add a SCORE metric, the weighted sum of metrics given as NAME:WEIGHT pairs
+    /// (e.g. PACKAGE-0_0:1.0,DRAM_0:0.5)
+    #[arg(
+        long = "score",
+        value_name = "NAME:WEIGHT,...",
+        value_delimiter = ',',
+        value_parser = parse_score_weight
+    )]
+    pub score: Vec<ScoreWeight>,
+
+    /// Only output the metrics whose name matches the glob (e.g. 'PACKAGE*'), all the
+    /// metrics are still measured
+    #[arg(long = "metrics", value_name = "GLOB")]
+    pub metrics: Option<String>,
+
+    /// Unit and decimals used to display a metric in the terminal (e.g. 'PACKAGE-0_0=J:3'),
+    /// the value is scaled between units of the same quantity; can be repeated
+    #[arg(
+        long = "metric-format",
+        value_name = "NAME=UNIT:PRECISION",
+        value_parser = parse_metric_format
+    )]
+    pub metric_format: Vec<MetricFormat>,
+
+    /// Unit of the energy metrics in the terminal, JSON and CSV outputs, converted from the
+    /// measured microjoules when the results are written
+    #[arg(long = "unit", value_enum, value_name = "UNIT")]
+    pub unit: Option<EnergyUnit>,
+
+    /// Append CSV columns with the energy of each domain summed across the sockets
+    /// (PACKAGE_TOTAL, DRAM_TOTAL...)
+    #[arg(long = "csv-include-total")]
+    pub csv_include_total: bool,
+
+    /// Append the start_us and end_us CSV columns of the phases, their start and end in
+    /// microseconds since the Unix epoch
+    #[arg(long = "csv-timestamps")]
+    pub csv_timestamps: bool,
+
+    /// Add the energy of the packages and DRAM of each socket (TOTAL_<socket>) and of all
+    /// the sockets (TOTAL) to the metrics
+    #[arg(long = "totals")]
+    pub totals: bool,
+
+    /// Stop the iterations as soon as the command exits with a nonzero code, the results
+    /// gathered so far are still written and the profiler fails
+    #[arg(long = "fail-fast")]
+    pub fail_fast: bool,
+
+    /// Set the `performance` CPU frequency governor during the measurement (requires root),
+    /// the previous governors are restored afterwards
+    #[arg(long = "stabilize-governor")]
+    pub stabilize_governor: bool,
+
+    /// Drop the page cache before each iteration (requires root) to measure from a cold
+    /// cache, e.g. for I/O-bound programs
+    #[arg(long = "drop-caches")]
+    pub drop_caches: bool,
+
+    /// Niceness of the profiled program, from -20 (highest priority, requires privileges)
+    /// to 19
+    #[arg(
+        long = "nice",
+        value_name = "N",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    pub nice: Option<i32>,
+
+    /// Run the profiled program with the SCHED_FIFO realtime policy (requires root or
+    /// CAP_SYS_NICE), a refused change is only a warning
+    #[arg(long = "realtime")]
+    pub realtime: bool,
+
+    /// Bind the memory of the profiled program to this NUMA node (with `numactl --membind`),
+    /// so its DRAM energy is attributed to the memory controller of the node
+    #[arg(long = "numa-node", value_name = "N")]
+    pub numa_node: Option<u32>,
+
+    /// Set an environment variable for the profiled program (e.g. OMP_NUM_THREADS=4), on
+    /// top of the profiler environment; can be repeated
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub env: Vec<EnvVar>,
+
+    /// Run the profiled program from this directory instead of the current one
+    #[arg(long = "workdir", value_name = "PATH")]
+    pub workdir: Option<String>,
+
+    /// Stop the profiled program if it still runs after this many seconds (SIGTERM, then
+    /// SIGKILL after a grace period), its exit code is then reported as -2
+    #[arg(long = "timeout", value_name = "SECONDS", value_parser = parse_seconds)]
+    pub timeout: Option<f64>,
+
+    /// Also measure the energy of the NVIDIA GPUs with NVML (GPU_<INDEX> metrics in mJ),
+    /// requires the `nvml` feature
+    #[arg(long = "gpu")]
+    pub gpu: bool,
+
+    /// Also report the peak RSS (PEAK_RSS_KB) and the CPU time (CPU_TIME_MS) of the
+    /// command, sampled from procfs
+    #[arg(long = "procfs")]
+    pub procfs: bool,
+
+    /// Replace RAPL with a source reporting these metrics in every phase (NAME=UJ,...), to
+    /// exercise the outputs without hardware
+    #[arg(
+        long = "fake-source",
+        value_name = "NAME=UJ",
+        value_delimiter = ',',
+        value_parser = parse_fake_metric,
+        hide = true
+    )]
+    pub fake_source: Vec<FakeMetric>,
 }
 
 /// Arguments for Simple mode
 #[derive(Parser, Debug)]
 pub struct SimpleArgs {
+    /// Cgroup v2 directory of a container to estimate its energy (CONTAINER_ENERGY).
+    ///
+    /// The packages energy is attributed to the container according to its share
+    /// of the busy CPU time (cpu.stat usage_usec), this is only an approximation.
+    #[arg(long = "cgroup", value_name = "PATH")]
+    pub cgroup: Option<String>,
+
+    /// Estimate the energy of each thread of the command (THREAD_ENERGY_<TID>_<NAME>).
+    ///
+    /// The threads CPU time is sampled from /proc/<pid>/task while the command runs and the
+    /// packages energy is split proportionally, this is only an estimate; the threads of
+    /// child processes are not included.
+    #[arg(long = "per-thread", conflicts_with_all = ["result_pattern", "remote"])]
+    pub per_thread: bool,
+
+    /// Estimate the profiler overhead by measuring a no-op command (`true`) first and
+    /// subtract it from the results, clamped to zero; useful for very short commands
+    #[arg(long = "subtract-overhead", conflicts_with = "remote")]
+    pub subtract_overhead: bool,
+
+    /// Measure the idle machine for this many seconds before the command to establish the
+    /// idle power of each domain, and subtract the idle energy over the duration of each run,
+    /// clamped to zero
+    #[arg(
+        long = "idle-sample",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        conflicts_with = "remote"
+    )]
+    pub idle_sample: Option<f64>,
+
+    /// Fit the total energy versus the iteration index and report the slope and R² in the
+    /// summary, flagging a significant drift (e.g. thermal)
+    #[arg(long = "trend", conflicts_with = "remote")]
+    pub trend: bool,
+
+    /// Add WHAT_IF_CAP_ENERGY, a crude estimate of the packages energy at this power cap: the
+    /// energy is scaled by the cap over the average power, ignoring the slowdown of a real cap
+    #[arg(
+        long = "what-if-cap",
+        value_name = "WATTS",
+        value_parser = parse_watts,
+        conflicts_with = "remote"
+    )]
+    pub what_if_cap: Option<f64>,
+
+    /// Check that the build dominated the machine: add CPU_UTILIZATION, the machine CPU
+    /// utilization over the run (from /proc/stat), and warn when the CPU was idle over 20 %
+    /// of the time, e.g. for `make -j` whose short-lived children are all captured by RAPL
+    #[arg(long = "build-mode", conflicts_with = "remote")]
+    pub build_mode: bool,
+
+    /// Start the command but only measure its energy after this delay, e.g. to skip the
+    /// warm-up of a service
+    #[arg(
+        long = "start-delay",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["result_pattern", "per_thread", "remote"]
+    )]
+    pub start_delay: Option<f64>,
+
+    /// Measure for this fixed duration (after --start-delay), then stop the command with
+    /// SIGTERM if it is still running
+    #[arg(
+        long = "duration",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["result_pattern", "per_thread", "remote"]
+    )]
+    pub duration: Option<f64>,
+
+    /// Run the command back to back until the runs last this many seconds, measuring them
+    /// as one and reporting the energy and duration of one run, for the commands too short
+    /// for the counters resolution
+    #[arg(
+        long = "min-duration",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["result_pattern", "per_thread", "start_delay", "duration", "remote"]
+    )]
+    pub min_duration: Option<f64>,
+
+    /// Split the run in this many intervals of equal duration and report the energy of each
+    /// one, the sources being measured on a timer while the command runs
+    #[arg(
+        long = "simple-samples",
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["result_pattern", "per_thread", "start_delay", "duration", "min_duration", "remote"]
+    )]
+    pub simple_samples: Option<u16>,
+
+    /// Print to stderr the energy accumulated per domain path, before it is summed per
+    /// domain name and socket into the metrics (for debugging the attribution)
+    #[arg(long = "debug-diff", conflicts_with = "remote")]
+    pub debug_diff: bool,
+
+    /// Report the absolute values of the energy counters at the first and the last snapshot
+    /// with their range, for debugging the counter wraps
+    #[arg(
+        long = "raw-counters",
+        alias = "raw-readings",
+        conflicts_with = "remote"
+    )]
+    pub raw_counters: bool,
+
+    /// Regex extracting a numeric result from the program stdout (e.g. "ops/sec: ([0-9.]+)"),
+    /// the energy per result is reported as ENERGY_PER_RESULT
+    #[arg(long = "result-pattern", value_name = "REGEX")]
+    pub result_pattern: Option<String>,
+
+    /// The result of --result-pattern is a throughput (e.g. ops/sec): also report
+    /// OPS_PER_WATT, the throughput per watt of average packages and DRAM power
+    #[arg(long = "ops-per-watt", requires = "result_pattern")]
+    pub ops_per_watt: bool,
+
+    /// Measure the command on a remote host over SSH (e.g. user@host), joule-profiler must
+    /// be installed there; its JSON results are reported locally
+    #[arg(long = "remote", value_name = "USER@HOST")]
+    pub remote: Option<String>,
+
+    /// Only print the total package energy to stdout, in the given unit (default: uj), for
+    /// shell capture; the program output goes to stderr and iterations are averaged
+    #[arg(
+        long = "print-total",
+        value_enum,
+        value_name = "UNIT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "uj"
+    )]
+    pub print_total: Option<EnergyUnit>,
+
+    /// Write the energy per wall-clock bin to a file as `second;domain;energy_uj` rows
+    /// (use with --rapl-polling)
+    #[arg(long = "time-series", value_name = "FILE")]
+    pub time_series: Option<String>,
+
+    /// Write the energy and power of every polling interval to a file, as JSON if it ends
+    /// with `.json`, else as `second;domain;energy_uj;power_w` rows (use with --rapl-polling)
+    #[arg(long = "samples-file", value_name = "FILE")]
+    pub samples_file: Option<String>,
+
+    /// Width of the energy time series bins in seconds
+    #[arg(
+        long = "time-series-bin",
+        default_value_t = 1.0,
+        value_name = "SECONDS",
+        value_parser = parse_seconds
+    )]
+    pub time_series_bin: f64,
+
+    /// Detect the steps of the package power between the time series bins, as turbo boost
+    /// engaging and disengaging, and report the fraction of time in the high-power state
+    #[arg(long = "detect-turbo", requires = "time_series")]
+    pub detect_turbo: bool,
+
+    /// Run the command again, up to this many times, when it exits with a nonzero code, the
+    /// measurement of the failed runs being discarded
+    #[arg(long = "retries", value_name = "N", default_value_t = 0)]
+    pub retries: u32,
+
+    /// Drop the outlier iterations of each metric from its summary, with at least four
+    /// iterations
+    #[arg(long = "drop-outliers", value_enum, value_name = "METHOD")]
+    pub drop_outliers: Option<OutlierMethod>,
+
     #[command(flatten)]
     pub common: CommonArgs,
 }
@@ -84,8 +776,9 @@ pub struct SimpleArgs {
 pub struct PhasesArgs {
     /// Regex pattern to detect phase tokens in program output.
     ///
-    /// Matches tokens in stdout; if the pattern has a capture group, the
-    /// captured text is used as the token name. Energy phases computed:
+    /// Matches tokens in stdout; the token name is the capture group named
+    /// `name` (or `phase`), else the first capture group, else the full match
+    /// (e.g. 'PHASE:(?<name>\w+)'). Energy phases computed:
     ///   - global (START -> END)
     ///   - START -> first_token
     ///   - token_i -> token_i+1
@@ -97,10 +790,301 @@ pub struct PhasesArgs {
     )]
     pub token_pattern: String,
 
+    /// Align each phase boundary on the nearest completed measure instead of the
+    /// measure taken when the token is handled (reduces boundary fuzz when polling)
+    #[arg(long = "align-boundaries")]
+    pub align_boundaries: bool,
+
+    /// JSON file of phase timings written by the program, `[{"name", "timestamp_us"}]`,
+    /// used instead of the token pattern to delimit the phases
+    #[arg(long = "phase-timings", value_name = "FILE")]
+    pub phase_timings: Option<String>,
+
+    /// Only report the token to token phases, excluding START -> first token and
+    /// last token -> END (setup and teardown)
+    #[arg(long = "inner-only")]
+    pub inner_only: bool,
+
+    /// Add to each phase of the JSON output a `derivation` text explaining its boundaries
+    #[arg(long = "describe-phases")]
+    pub describe_phases: bool,
+
+    /// Stop echoing the program output past this many bytes (tokens are still matched)
+    #[arg(long = "max-output-bytes", value_name = "BYTES")]
+    pub max_output_bytes: Option<usize>,
+
+    /// Run the program on a pseudo-terminal so it sees a TTY (line buffering, colors, prompts)
+    /// while its output is still captured for token matching
+    #[arg(long = "pty")]
+    pub pty: bool,
+
+    /// Print each phase energy to stderr as soon as its end token is crossed
+    #[arg(long = "live")]
+    pub live: bool,
+
+    /// Once the command closed its output, wait at most this long for it to exit before
+    /// stopping it with SIGTERM (default: wait until it exits or the profiler is interrupted)
+    #[arg(long = "exit-timeout", value_name = "SECONDS", value_parser = parse_seconds)]
+    pub exit_timeout: Option<f64>,
+
+    /// Output streams of the program scanned for the tokens, stderr being captured through a
+    /// pipe when scanned (--merge-stderr scans both)
+    #[arg(
+        long = "token-stream",
+        value_enum,
+        value_name = "STREAM",
+        default_value_t = TokenStream::Stdout,
+        conflicts_with = "pty"
+    )]
+    pub token_stream: TokenStream,
+
+    /// Only print the total package energy of the phases to stdout, in the given unit
+    /// (default: uj), for shell capture; the program output goes to stderr and iterations
+    /// are averaged
+    #[arg(
+        long = "print-total",
+        value_enum,
+        value_name = "UNIT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "uj"
+    )]
+    pub print_total: Option<EnergyUnit>,
+
+    /// Write the phases to a file in the Chrome Trace Event Format, to view their timeline
+    /// in Perfetto or chrome://tracing
+    #[arg(long = "trace-events", value_name = "FILE")]
+    pub trace_events: Option<String>,
+
     #[command(flatten)]
     pub common: CommonArgs,
 }
 
+/// Arguments for Calibrate mode
+#[derive(Parser, Debug)]
+pub struct CalibrateArgs {
+    /// Comma-separated polling rates in seconds to compare ("none" disables polling)
+    #[arg(
+        long = "rates",
+        value_delimiter = ',',
+        default_value = "none,0.1,0.01,0.001",
+        value_parser = parse_polling_rate,
+        value_name = "RATES"
+    )]
+    pub rates: Vec<Option<f64>>,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Arguments for the compare command
+#[derive(Parser, Debug)]
+pub struct CompareArgs {
+    /// Command line of the reference, run through `sh -c` (e.g. "./old --input data")
+    #[arg(long = "baseline", value_name = "COMMAND")]
+    pub baseline: String,
+
+    /// Command line compared to the baseline, run through `sh -c`
+    #[arg(long = "candidate", value_name = "COMMAND")]
+    pub candidate: String,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Parses a polling rate in seconds, "none" meaning no polling.
+fn parse_polling_rate(value: &str) -> Result<Option<f64>, String> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 => Ok(Some(rate)),
+        _ => Err(format!(
+            "invalid polling rate '{}', expected a positive number of seconds or 'none'",
+            value
+        )),
+    }
+}
+
+/// Shortest duration accepted in seconds, the timers and intervals cannot be shorter.
+const MIN_SECONDS: f64 = 0.001;
+
+/// Longest duration accepted in seconds (about 31 years), larger values overflow a
+/// `Duration`.
+const MAX_SECONDS: f64 = 1e9;
+
+/// Parses a duration in seconds between 1 ms and [`MAX_SECONDS`].
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if (MIN_SECONDS..=MAX_SECONDS).contains(&seconds) => Ok(seconds),
+        _ => Err(format!(
+            "invalid duration '{}', expected a number of seconds between {} and {}",
+            value, MIN_SECONDS, MAX_SECONDS
+        )),
+    }
+}
+
+/// Parses the CSV field separator, a single character other than a quote or a line break.
+fn parse_csv_delimiter(value: &str) -> Result<char, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(delimiter), None) if !matches!(delimiter, '"' | '\n' | '\r') => Ok(delimiter),
+        _ => Err(format!(
+            "invalid CSV delimiter '{}', expected a single character other than '\"' or a line break",
+            value
+        )),
+    }
+}
+
+/// Parses a field of `--fields`, one of the non-metric CSV columns and JSON keys.
+fn parse_output_field(value: &str) -> Result<String, String> {
+    let field = value.trim();
+    if OUTPUT_FIELDS.contains(&field) {
+        Ok(field.to_string())
+    } else {
+        Err(format!(
+            "unknown field '{}', expected one of: {}",
+            value,
+            OUTPUT_FIELDS.join(", ")
+        ))
+    }
+}
+
+/// Parses a strictly positive power in watts.
+fn parse_watts(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(watts) if watts > 0.0 && watts.is_finite() => Ok(watts),
+        _ => Err(format!(
+            "invalid power '{}', expected a positive number of watts",
+            value
+        )),
+    }
+}
+
+/// Parses an energy in joules, which must be positive.
+fn parse_joules(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(joules) if joules > 0.0 && joules.is_finite() => Ok(joules),
+        _ => Err(format!(
+            "invalid energy '{}', expected a positive number of joules",
+            value
+        )),
+    }
+}
+
+/// Parses the octal permissions of the output files, which must not be world-writable.
+fn parse_file_mode(value: &str) -> Result<FileMode, String> {
+    let value = value.trim();
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode > 0o777 => Err(format!(
+            "invalid file mode '{}', expected permission bits up to 777",
+            value
+        )),
+        Ok(mode) if mode & 0o002 != 0 => Err(format!(
+            "invalid file mode '{}', output files must not be world-writable",
+            value
+        )),
+        Ok(mode) => Ok(FileMode(mode)),
+        Err(_) => Err(format!(
+            "invalid file mode '{}', expected an octal mode (e.g. 600)",
+            value
+        )),
+    }
+}
+
+/// Parses a metric format as `NAME=UNIT:PRECISION`.
+fn parse_metric_format(value: &str) -> Result<MetricFormat, String> {
+    let invalid = || {
+        format!(
+            "invalid metric format '{}', expected NAME=UNIT:PRECISION (e.g. PACKAGE-0_0=J:3)",
+            value
+        )
+    };
+
+    let (name, format) = value.split_once('=').ok_or_else(invalid)?;
+    let (unit, precision) = format.rsplit_once(':').ok_or_else(invalid)?;
+    let precision = precision.trim().parse().map_err(|_| invalid())?;
+    if name.trim().is_empty() || unit.trim().is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(MetricFormat {
+        name: name.trim().to_string(),
+        unit: unit.trim().to_string(),
+        precision,
+    })
+}
+
+/// Parses a score weight as `NAME:WEIGHT`, the weight being a non-negative number.
+fn parse_score_weight(value: &str) -> Result<ScoreWeight, String> {
+    let invalid = || {
+        format!(
+            "invalid score weight '{}', expected NAME:WEIGHT with a non-negative weight \
+             (e.g. DRAM_0:0.5)",
+            value
+        )
+    };
+
+    let (name, weight) = value.rsplit_once(':').ok_or_else(invalid)?;
+    let weight: f64 = weight.trim().parse().map_err(|_| invalid())?;
+    if name.trim().is_empty() || !weight.is_finite() || weight < 0.0 {
+        return Err(invalid());
+    }
+
+    Ok(ScoreWeight {
+        name: name.trim().to_string(),
+        weight,
+    })
+}
+
+/// Parses a fake source metric as `NAME=UJ`.
+fn parse_fake_metric(value: &str) -> Result<FakeMetric, String> {
+    let invalid = || {
+        format!(
+            "invalid fake metric '{}', expected NAME=UJ (e.g. PACKAGE-0_0=1000)",
+            value
+        )
+    };
+
+    let (name, energy) = value.split_once('=').ok_or_else(invalid)?;
+    if name.trim().is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(FakeMetric {
+        name: name.trim().to_string(),
+        value: energy.trim().parse().map_err(|_| invalid())?,
+    })
+}
+
+/// Parses an environment variable as `KEY=VALUE`, the value may be empty or contain `=`.
+fn parse_env_var(value: &str) -> Result<EnvVar, JouleProfilerError> {
+    match value.split_once('=') {
+        Some((key, env_value)) if !key.is_empty() => Ok(EnvVar {
+            key: key.to_string(),
+            value: env_value.to_string(),
+        }),
+        _ => Err(JouleProfilerError::InvalidEnvVar(format!(
+            "'{}', expected KEY=VALUE (e.g. OMP_NUM_THREADS=4)",
+            value
+        ))),
+    }
+}
+
+/// Parses a percentage in [0, 100].
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent),
+        _ => Err(format!(
+            "invalid percentage '{}', expected a number between 0 and 100",
+            value
+        )),
+    }
+}
+
 /// Arguments for ListSources subcommand
 #[derive(Parser, Debug)]
 pub struct ListArgs {
@@ -111,4 +1095,48 @@ pub struct ListArgs {
     /// Output as CSV (header + rows)
     #[arg(long = "csv")]
     pub csv: bool,
+
+    /// Print the raw discovered RAPL domains (path, name, socket, max energy) for debugging
+    #[arg(long = "dump-domains")]
+    pub dump_domains: bool,
+
+    /// Print a short description of each discovered RAPL domain type
+    #[arg(long = "explain")]
+    pub explain: bool,
+}
+
+/// Arguments for the log command
+#[derive(Parser, Debug)]
+pub struct LogArgs {
+    /// Time between two rows of each domain in seconds
+    #[arg(long = "interval", default_value_t = 1.0, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub interval: f64,
+
+    /// CSV file receiving the `timestamp_us;domain;energy_uj;power_w` rows
+    #[arg(long = "file", value_name = "FILE")]
+    pub file: String,
+
+    /// Stop after this duration, otherwise log until Ctrl-C
+    #[arg(long = "duration", value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<f64>,
+}
+
+/// Arguments for the watch command
+#[derive(Parser, Debug)]
+pub struct WatchArgs {
+    /// Time between two power lines in seconds
+    #[arg(long = "interval", default_value_t = 1.0, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub interval: f64,
+}
+
+/// Arguments for the show command
+#[derive(Parser, Debug)]
+pub struct ShowArgs {
+    /// JSON result file written with --json
+    #[arg(value_name = "RESULT_JSON")]
+    pub file: String,
+
+    /// Number of decimal places used for floating point values in the output
+    #[arg(long = "precision", default_value_t = 3, value_name = "N")]
+    pub precision: usize,
 }
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    command::simple::measure_simple_iterations,
+    config::{CalibrateConfig, ProfileConfig},
+    measurement::CalibrationResult,
+    output::{Displayer, OutputFormatTrait},
+};
+
+/// Measure and display the command at every polling rate, returning 0 as the calibration
+/// does not keep the exit codes of the runs.
+pub async fn run_calibrate(
+    config: &ProfileConfig,
+    calibrate_config: &CalibrateConfig,
+) -> Result<i32> {
+    info!(
+        "Running calibration with {} polling rate(s)",
+        calibrate_config.polling_rates.len()
+    );
+
+    let results = measure_calibration(config, calibrate_config).await?;
+
+    let mut displayer = Displayer::try_from(config)?;
+    displayer.calibration(config, &results)?;
+    Ok(0)
+}
+
+/// Measure the command in simple mode once per configured polling rate.
+pub async fn measure_calibration(
+    config: &ProfileConfig,
+    calibrate_config: &CalibrateConfig,
+) -> Result<Vec<CalibrationResult>> {
+    let mut results = Vec::with_capacity(calibrate_config.polling_rates.len());
+
+    for polling_rate in &calibrate_config.polling_rates {
+        info!("Calibrating with polling rate {:?}", polling_rate);
+
+        let rate_config = ProfileConfig {
+            rapl_polling: *polling_rate,
+            ..config.clone()
+        };
+
+        let iterations = measure_simple_iterations(&rate_config).await?;
+        results.push(CalibrationResult::from_iterations(
+            *polling_rate,
+            &iterations,
+            config.rounding(),
+        ));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Mode, test_util::fake_rapl_tree};
+    use tempfile::tempdir;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn calibration_rates_produce_distinct_measure_counts() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+
+        let calibrate_config = CalibrateConfig {
+            polling_rates: vec![None, Some(0.005)],
+        };
+
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sleep".to_string(), "0.1".to_string()],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            mode: Mode::CalibrateMode(calibrate_config.clone()),
+            ..Default::default()
+        };
+
+        let results = measure_calibration(&config, &calibrate_config)
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].polling_rate_s, None);
+        assert_eq!(results[1].polling_rate_s, Some(0.005));
+        assert_ne!(results[0].measure_count, results[1].measure_count);
+        assert!(results[1].measure_count > results[0].measure_count);
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    command::{command_exit_code, simple::measure_simple_iterations},
+    config::{CompareConfig, ProfileConfig},
+    measurement::{MeasurementResult, MetricComparison},
+    output::{Displayer, OutputFormatTrait},
+};
+
+/// Measure the baseline and candidate commands and display the change of each metric,
+/// returning the exit code of the last failed run.
+pub async fn run_compare(config: &ProfileConfig, compare_config: &CompareConfig) -> Result<i32> {
+    let (baseline, candidate) = measure_commands(config, compare_config).await?;
+
+    let mut displayer = Displayer::try_from(config)?;
+    displayer.comparison(
+        config,
+        &MetricComparison::from_iterations(&baseline, &candidate),
+    )?;
+
+    Ok(command_exit_code(
+        baseline
+            .iter()
+            .chain(&candidate)
+            .map(|result| result.exit_code),
+    ))
+}
+
+/// Measure the iterations of both commands and compare their metrics.
+pub async fn measure_comparison(
+    config: &ProfileConfig,
+    compare_config: &CompareConfig,
+) -> Result<Vec<MetricComparison>> {
+    let (baseline, candidate) = measure_commands(config, compare_config).await?;
+    Ok(MetricComparison::from_iterations(&baseline, &candidate))
+}
+
+/// Measure all the iterations of the baseline command, then those of the candidate.
+async fn measure_commands(
+    config: &ProfileConfig,
+    compare_config: &CompareConfig,
+) -> Result<(Vec<MeasurementResult>, Vec<MeasurementResult>)> {
+    let mut results = Vec::with_capacity(2);
+
+    for command in [&compare_config.baseline, &compare_config.candidate] {
+        info!("Comparing the command {:?}", command);
+
+        // Through a shell, so that the command line is quoted as typed
+        let command_config = ProfileConfig {
+            cmd: vec!["sh".to_string(), "-c".to_string(), command.clone()],
+            ..config.clone()
+        };
+        results.push(measure_simple_iterations(&command_config).await?);
+    }
+
+    let candidate = results.pop().unwrap_or_default();
+    let baseline = results.pop().unwrap_or_default();
+    Ok((baseline, candidate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::FakeMetric, config::Mode};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn both_commands_are_measured_and_compared() {
+        let compare_config = CompareConfig {
+            baseline: "true".to_string(),
+            candidate: "sleep 0.05".to_string(),
+        };
+        let config = ProfileConfig {
+            iterations: 2,
+            fake_source: vec![FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 1_000,
+            }],
+            mode: Mode::CompareMode(compare_config.clone()),
+            ..Default::default()
+        };
+
+        let comparison = measure_comparison(&config, &compare_config).await.unwrap();
+
+        let package = comparison
+            .iter()
+            .find(|metric| metric.name == "PACKAGE-0_0")
+            .unwrap();
+        assert_eq!(package.baseline, 1_000.0);
+        assert_eq!(package.delta, 0.0);
+        assert_eq!(package.change_pct, Some(0.0));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    ffi::OsStr,
+    fmt::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    command::{NUMACTL, init_sources, phases::token_regex, program_command, program_name},
+    config::{Mode, ProfileConfig},
+    error::JouleProfilerError,
+    source::Sensor,
+};
+
+/// Run the setup of the measurement without spawning the command nor measuring: the token
+/// pattern is compiled, the program resolved and the sources initialized, then what would
+/// be measured is printed. The first failing check is returned as the error.
+pub fn run_dry_run(config: &ProfileConfig) -> Result<()> {
+    if config.cmd.is_empty() {
+        return Err(JouleProfilerError::NoCommand.into());
+    }
+
+    if let Mode::PhaseMode(phases_config) = &config.mode {
+        token_regex(&phases_config.token_pattern)?;
+    }
+
+    // Checks the NUMA node, the working directory and the stdin file
+    program_command(config)?;
+    let mut programs = vec![program_name(config)];
+    if program_name(config) == NUMACTL && !config.shell {
+        programs.push(&config.cmd[0]);
+    }
+    let path_var = std::env::var_os("PATH").unwrap_or_default();
+    let mut resolved = Vec::with_capacity(programs.len());
+    for program in programs {
+        let path = find_program(program, &path_var, config.workdir.as_deref().map(Path::new))
+            .ok_or_else(|| JouleProfilerError::command_not_found(program))?;
+        debug!("Resolved {} to {}", program, path.display());
+        resolved.push(path);
+    }
+
+    let sensors = init_sources(config)?.sensors()?;
+
+    print!("{}", dry_run_report(config, &resolved, &sensors));
+    Ok(())
+}
+
+/// Path of the program as the spawn would find it: a name is searched in the `PATH`
+/// directories, a path with a slash is relative to the working directory. `None` if it is
+/// not an executable file.
+fn find_program(program: &str, path_var: &OsStr, workdir: Option<&Path>) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = match workdir {
+            Some(workdir) => workdir.join(program),
+            None => PathBuf::from(program),
+        };
+        return is_executable(&path).then_some(path);
+    }
+
+    std::env::split_paths(path_var)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Summary of what would be measured.
+fn dry_run_report(config: &ProfileConfig, programs: &[PathBuf], sensors: &[Sensor]) -> String {
+    let mut report = String::new();
+
+    let mode = match &config.mode {
+        Mode::SimpleMode => "simple",
+        Mode::PhaseMode(_) => "phases",
+        Mode::CalibrateMode(_) => "calibrate",
+        Mode::CompareMode(_) => "compare",
+    };
+    let programs: Vec<String> = programs
+        .iter()
+        .map(|program| program.display().to_string())
+        .collect();
+
+    let _ = writeln!(report, "Command         : {}", config.cmd.join(" "));
+    let _ = writeln!(report, "Program         : {}", programs.join(", "));
+    let _ = writeln!(report, "Mode            : {}", mode);
+    if let Mode::PhaseMode(phases_config) = &config.mode {
+        let _ = writeln!(report, "Token pattern   : {}", phases_config.token_pattern);
+    }
+    let _ = writeln!(report, "Iterations      : {}", config.iterations.max(1));
+    let _ = writeln!(report, "Sensors         : {}", sensors.len());
+    for sensor in sensors {
+        let _ = writeln!(
+            report,
+            "  {:<20} {:<4} [{}]",
+            sensor.name, sensor.unit, sensor.source
+        );
+    }
+    let _ = writeln!(report, "Dry run, the command was not executed");
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{set_permissions, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{cli::FakeMetric, config::PhasesConfig};
+
+    #[test]
+    fn programs_are_searched_in_path_then_relative_to_the_workdir() {
+        let dir = tempdir().unwrap();
+        let program = dir.path().join("bench");
+        write(&program, "#!/bin/sh\n").unwrap();
+        let not_executable = dir.path().join("data");
+        write(&not_executable, "").unwrap();
+        set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path_var =
+            std::env::join_paths(["/nonexistent", dir.path().to_str().unwrap()]).unwrap();
+        assert_eq!(
+            find_program("bench", &path_var, None),
+            Some(program.clone())
+        );
+        assert_eq!(find_program("data", &path_var, None), None);
+        assert_eq!(find_program("missing", &path_var, None), None);
+
+        assert_eq!(
+            find_program("./bench", OsStr::new(""), Some(dir.path())),
+            Some(dir.path().join("./bench"))
+        );
+        assert_eq!(find_program("./bench", OsStr::new(""), None), None);
+    }
+
+    fn phases_config(token_pattern: &str) -> ProfileConfig {
+        ProfileConfig {
+            cmd: vec!["true".to_string()],
+            mode: Mode::PhaseMode(PhasesConfig {
+                token_pattern: token_pattern.to_string(),
+                ..Default::default()
+            }),
+            fake_source: vec![FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 100,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dry_run_reports_the_sensors_without_running_the_command() {
+        let dir = tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let config = ProfileConfig {
+            cmd: vec![format!("touch {}", marker.display())],
+            shell: true,
+            ..phases_config("__[A-Z]+__")
+        };
+
+        run_dry_run(&config).unwrap();
+        assert!(!marker.exists());
+
+        let report = dry_run_report(
+            &config,
+            &[PathBuf::from("/bin/sh")],
+            &[Sensor {
+                name: "PACKAGE-0_0".to_string(),
+                unit: "µJ".to_string(),
+                source: "fake".to_string(),
+            }],
+        );
+        assert!(report.contains("Program         : /bin/sh\n"));
+        assert!(report.contains("Token pattern   : __[A-Z]+__\n"));
+        assert!(report.contains("  PACKAGE-0_0          µJ   [fake]\n"));
+    }
+
+    #[test]
+    fn dry_run_fails_on_the_first_invalid_setup() {
+        let err = run_dry_run(&phases_config("(")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JouleProfilerError>(),
+            Some(JouleProfilerError::InvalidPattern(_))
+        ));
+
+        let config = ProfileConfig {
+            cmd: vec!["joule-profiler-missing-program".to_string()],
+            ..phases_config("__[A-Z]+__")
+        };
+        let err = run_dry_run(&config).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JouleProfilerError>(),
+            Some(JouleProfilerError::CommandNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use crate::{
+    cli::RaplBackendKind,
+    config::EnvConfig,
+    source::rapl::{Rapl, discover_rapl_domains, domain::rapl_base_path},
+};
+
+pub fn run_env(config: &EnvConfig) -> Result<()> {
+    print!("{}", env_report(config));
+    Ok(())
+}
+
+/// Build the diagnostic report of the build and of the RAPL capabilities of the machine.
+fn env_report(config: &EnvConfig) -> String {
+    let mut report = String::new();
+
+    let base_path = match config.rapl_backend {
+        RaplBackendKind::Powercap => rapl_base_path(config.rapl_path.as_deref()),
+        RaplBackendKind::Msr => "/dev/cpu/*/msr".to_string(),
+    };
+    let backend = match config.rapl_backend {
+        RaplBackendKind::Powercap => "powercap",
+        RaplBackendKind::Msr => "msr",
+    };
+
+    let _ = writeln!(report, "joule-profiler  : {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "OS              : {}", std::env::consts::OS);
+    let _ = writeln!(report, "RAPL backend    : {}", backend);
+    let _ = writeln!(report, "RAPL base path  : {}", base_path);
+
+    match discover_rapl_domains(
+        config.rapl_path.as_deref(),
+        None,
+        config.rapl_backend,
+        &config.domain_options,
+    ) {
+        Ok((domains, rapl_backend)) => {
+            let sockets: HashSet<u32> = domains.iter().map(|domain| domain.socket).collect();
+            let domains_count = domains.len();
+            let readable = Rapl::with_backend(domains, None, rapl_backend)
+                .read_snapshot()
+                .is_ok();
+
+            let _ = writeln!(report, "Sockets         : {}", sockets.len());
+            let _ = writeln!(report, "Domains         : {}", domains_count);
+            let _ = writeln!(
+                report,
+                "Read permission : {}",
+                if readable { "yes" } else { "no" }
+            );
+        }
+        Err(e) => {
+            let _ = writeln!(report, "RAPL            : unavailable ({})", e);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn env_report_contains_version_and_sockets() {
+        let dir = tempdir().unwrap();
+        for socket in 0..2 {
+            let package = dir.path().join(format!("intel-rapl:{}", socket));
+            create_dir_all(&package).unwrap();
+            write(package.join("name"), format!("package-{}", socket)).unwrap();
+            write(package.join("energy_uj"), "100").unwrap();
+            write(package.join("max_energy_range_uj"), "1000").unwrap();
+        }
+
+        let report = env_report(&EnvConfig {
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            rapl_backend: RaplBackendKind::Powercap,
+            domain_options: Default::default(),
+        });
+
+        assert!(report.contains(env!("CARGO_PKG_VERSION")));
+        assert!(report.contains("Sockets         : 2"));
+        assert!(report.contains("Read permission : yes"));
+    }
+
+    #[test]
+    fn env_report_notes_missing_rapl() {
+        let dir = tempdir().unwrap();
+        let report = env_report(&EnvConfig {
+            rapl_path: Some(dir.path().join("missing").to_string_lossy().to_string()),
+            rapl_backend: RaplBackendKind::Powercap,
+            domain_options: Default::default(),
+        });
+
+        assert!(report.contains("RAPL            : unavailable"));
+    }
+}
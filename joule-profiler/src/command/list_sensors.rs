@@ -3,19 +3,283 @@ use anyhow::Result;
 use crate::{
     config::ListSensorsConfig,
     output::{Displayer, OutputFormatTrait},
-    source::{MetricReader, rapl::init_rapl},
+    source::{
+        MetricReader, Sensor,
+        rapl::{
+            RaplOptions, discover_rapl_domains,
+            domain::{RaplDomain, check_os},
+            init_rapl,
+        },
+    },
 };
 
+/// Description of the RAPL domain types, keyed by their normalized name.
+const DOMAIN_DESCRIPTIONS: [(&str, &str); 5] = [
+    (
+        "package",
+        "whole CPU socket: cores, caches, integrated GPU and memory controller",
+    ),
+    (
+        "core",
+        "CPU cores only (power plane 0), included in package",
+    ),
+    (
+        "uncore",
+        "integrated GPU and other uncore devices (power plane 1), included in package",
+    ),
+    (
+        "dram",
+        "memory attached to the socket, not included in package",
+    ),
+    (
+        "psys",
+        "whole platform (SoC, memory, chipset...) on recent client CPUs",
+    ),
+];
+
 pub fn run_list_sensors(config: &ListSensorsConfig) -> Result<()> {
-    let sources = vec![init_rapl(config.rapl_path.as_deref(), None, None)?];
+    // Nothing to list is not an error where the sensors cannot exist
+    if check_os().is_err() {
+        println!("No sensors available on this platform");
+        return Ok(());
+    }
+
+    if config.dump_domains || config.explain {
+        let (domains, _) = discover_rapl_domains(
+            config.rapl_path.as_deref(),
+            None,
+            config.rapl_backend,
+            &config.domain_options,
+        )?;
+        if config.dump_domains {
+            print!("{}", dump_domains(&domains));
+        }
+        if config.explain {
+            print!("{}", explain_domains(&domains));
+        }
+    }
+
+    let sources = [init_rapl(
+        config.rapl_path.as_deref(),
+        None,
+        config.rapl_backend,
+        &config.domain_options,
+        RaplOptions::default(),
+    )?];
 
-    let sensors: Vec<_> = sources
+    let mut sensors: Vec<_> = sources
         .iter()
         .flat_map(|source| source.get_sensors())
         .flatten()
         .collect();
+    sort_sensors(&mut sensors);
 
     let mut displayer = Displayer::try_from(config)?;
     displayer.list_sensors(config, &sensors)?;
     Ok(())
 }
+
+/// Sort the sensors by socket then name so the listing does not depend on the discovery order.
+fn sort_sensors(sensors: &mut [Sensor]) {
+    sensors.sort_by(|a, b| {
+        (sensor_socket(a), &a.name, &a.source).cmp(&(sensor_socket(b), &b.name, &b.source))
+    });
+}
+
+/// Socket of a sensor from its `NAME_<socket>` name, sensors without one are listed last.
+fn sensor_socket(sensor: &Sensor) -> u32 {
+    sensor
+        .name
+        .rsplit_once('_')
+        .and_then(|(_, socket)| socket.parse().ok())
+        .unwrap_or(u32::MAX)
+}
+
+/// Domain type of a domain name, without its socket suffix (`package-0` -> `package`).
+fn normalize_domain_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.rsplit_once('-') {
+        Some((kind, index)) if index.chars().all(|c| c.is_ascii_digit()) => kind.to_string(),
+        _ => name,
+    }
+}
+
+/// Describe each discovered domain type once, in discovery order.
+fn explain_domains(domains: &[RaplDomain]) -> String {
+    let mut kinds: Vec<String> = Vec::new();
+    for domain in domains {
+        let kind = normalize_domain_name(&domain.name);
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+
+    kinds
+        .iter()
+        .map(|kind| {
+            let description = DOMAIN_DESCRIPTIONS
+                .iter()
+                .find(|(name, _)| name == kind)
+                .map_or("no description available", |(_, description)| description);
+            format!("{:<8}: {}\n", kind, description)
+        })
+        .collect()
+}
+
+/// Format the raw discovered domains for debugging.
+fn dump_domains(domains: &[RaplDomain]) -> String {
+    domains
+        .iter()
+        .map(|domain| format!("{:#?}\n", domain))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::OutputFormat, source::rapl::domain::get_domains, util::file::FileMode};
+    use std::{
+        collections::HashSet,
+        fs::{create_dir_all, read_to_string, write},
+    };
+    use tempfile::tempdir;
+
+    fn sensor(name: &str) -> Sensor {
+        Sensor {
+            name: name.to_string(),
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        }
+    }
+
+    #[test]
+    fn sensors_are_sorted_by_socket_then_name() {
+        let mut sensors = vec![
+            sensor("PACKAGE-1_1"),
+            sensor("PSYS"),
+            sensor("PACKAGE-0_0"),
+            sensor("DRAM_1"),
+            sensor("CORE_0"),
+        ];
+        sort_sensors(&mut sensors);
+
+        let names: Vec<&str> = sensors.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["CORE_0", "PACKAGE-0_0", "DRAM_1", "PACKAGE-1_1", "PSYS"]
+        );
+    }
+
+    fn list_config(output_format: OutputFormat) -> ListSensorsConfig {
+        ListSensorsConfig {
+            output_format,
+            file_mode: FileMode::default(),
+            rapl_path: None,
+            rapl_backend: Default::default(),
+            domain_options: Default::default(),
+            dump_domains: false,
+            explain: false,
+        }
+    }
+
+    /// Write the sensors in the format and read the file back.
+    fn written_sensors(output_format: OutputFormat, sensors: &[Sensor]) -> String {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sensors").to_string_lossy().to_string();
+        let config = list_config(output_format);
+
+        let mut displayer =
+            Displayer::new(&config.output_format, Some(&path), config.file_mode, false).unwrap();
+        displayer.list_sensors(&config, sensors).unwrap();
+        read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn sensor_listings_round_trip_in_a_stable_order() {
+        let mut sensors = vec![
+            sensor("DRAM_1"),
+            sensor("PACKAGE-0_0"),
+            Sensor {
+                unit: "mJ".to_string(),
+                source: "nvml".to_string(),
+                ..sensor("GPU_0")
+            },
+        ];
+        let mut shuffled = sensors.clone();
+        shuffled.reverse();
+        sort_sensors(&mut sensors);
+        sort_sensors(&mut shuffled);
+        assert_eq!(sensors, shuffled);
+
+        let json = written_sensors(OutputFormat::Json, &sensors);
+        let parsed: Vec<Sensor> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, sensors);
+        assert_eq!(json, written_sensors(OutputFormat::Json, &shuffled));
+
+        let csv = written_sensors(OutputFormat::Csv, &sensors);
+        let parsed: Vec<Sensor> = csv
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split(';').collect();
+                Sensor {
+                    name: fields[0].to_string(),
+                    unit: fields[1].to_string(),
+                    source: fields[2].to_string(),
+                }
+            })
+            .collect();
+        assert_eq!(parsed, sensors);
+
+        let unique: HashSet<&Sensor> = sensors.iter().chain(&shuffled).collect();
+        assert_eq!(unique.len(), 3);
+    }
+
+    #[test]
+    fn explain_describes_discovered_domain_types() {
+        let domain = |name: &str, socket| RaplDomain {
+            path: format!("/{}/energy_uj", name).into(),
+            name: name.to_string(),
+            socket,
+            max_energy_uj: 1_000,
+        };
+        let domains = [
+            domain("package-0", 0),
+            domain("core", 0),
+            domain("package-1", 1),
+            domain("dram", 1),
+            domain("mystery", 0),
+        ];
+
+        let explanation = explain_domains(&domains);
+        let lines: Vec<&str> = explanation.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("package : whole CPU socket"));
+        assert!(lines[1].starts_with("core    : CPU cores only"));
+        assert!(lines[2].starts_with("dram    : memory attached"));
+        assert_eq!(lines[3], "mystery : no description available");
+    }
+
+    #[test]
+    fn dump_domains_contains_discovered_paths() {
+        let dir = tempdir().unwrap();
+        let package = dir.path().join("intel-rapl:0");
+        let core = package.join("intel-rapl:0:0");
+        create_dir_all(&core).unwrap();
+
+        for (path, name) in [(&package, "package-0"), (&core, "core")] {
+            write(path.join("name"), name).unwrap();
+            write(path.join("energy_uj"), "100").unwrap();
+            write(path.join("max_energy_range_uj"), "1000").unwrap();
+        }
+
+        let domains = get_domains(dir.path().to_str(), None, &Default::default()).unwrap();
+        let dump = dump_domains(&domains);
+
+        assert!(dump.contains(&package.join("energy_uj").display().to_string()));
+        assert!(dump.contains(&core.join("energy_uj").display().to_string()));
+        assert!(dump.contains("package-0"));
+        assert!(dump.contains("max_energy_uj: 1000"));
+    }
+}
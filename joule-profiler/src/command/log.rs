@@ -0,0 +1,150 @@
+use std::{
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::info;
+use tokio::time::{Instant, MissedTickBehavior, interval};
+
+use crate::{
+    config::LogConfig,
+    output::report_written,
+    source::{
+        MetricReader,
+        rapl::{Rapl, discover_rapl_domains},
+    },
+    util::{
+        file::{create_file_with_user_permissions, get_absolute_path},
+        time::get_timestamp,
+    },
+};
+
+/// Record the energy of each domain every interval until the duration elapses or Ctrl-C.
+pub async fn run_log(config: &LogConfig) -> Result<()> {
+    let (domains, backend) = discover_rapl_domains(
+        config.rapl_path.as_deref(),
+        config.sockets.as_ref(),
+        config.rapl_backend,
+        &config.domain_options,
+    )?;
+    let mut rapl = Rapl::with_backend(domains, Some(config.interval_s), backend);
+
+    let path = get_absolute_path(&config.file)?;
+    let mut writer = BufWriter::new(create_file_with_user_permissions(&path, config.file_mode)?);
+
+    let interval = Duration::from_secs_f64(config.interval_s);
+    let cycles = config
+        .duration_s
+        .map(|duration_s| (duration_s / config.interval_s).round().max(1.0) as u64);
+    info!("Logging the energy every {:?} to {}", interval, path);
+
+    let written = log_energy(&mut rapl, &mut writer, interval, cycles).await?;
+    info!("Logged {} intervals", written);
+
+    report_written("CSV", &path);
+    Ok(())
+}
+
+/// Write one `timestamp_us;domain;energy_uj;power_w` row per domain and interval, flushed after
+/// each interval so the rows survive a crash. Stops after `cycles` intervals if given, or on
+/// Ctrl-C, and returns the number of intervals written.
+async fn log_energy(
+    rapl: &mut Rapl,
+    out: &mut impl Write,
+    interval_duration: Duration,
+    cycles: Option<u64>,
+) -> Result<u64> {
+    writeln!(out, "timestamp_us;domain;energy_uj;power_w")?;
+    out.flush()?;
+
+    let mut ticker = interval(interval_duration);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    // The first snapshot is the origin of the first interval
+    ticker.tick().await;
+    rapl.measure()?;
+    let mut last = Instant::now();
+    let mut written = 0;
+
+    while cycles.is_none_or(|cycles| written < cycles) {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => {
+                info!("Interrupted, stopping the log");
+                break;
+            }
+        }
+
+        rapl.measure()?;
+        let now = Instant::now();
+        let elapsed_us = now.duration_since(last).as_micros().max(1) as f64;
+        last = now;
+
+        let timestamp_us = get_timestamp();
+        for metric in rapl.take_interval_metrics() {
+            writeln!(
+                out,
+                "{};{};{};{:.3}",
+                timestamp_us,
+                metric.name,
+                metric.value,
+                // µJ per µs are watts
+                metric.value as f64 / elapsed_us
+            )?;
+        }
+        out.flush()?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read_to_string, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::source::rapl::domain::DomainOptions;
+
+    #[tokio::test]
+    async fn log_writes_a_row_per_domain_and_interval() {
+        let dir = tempdir().unwrap();
+        for (zone, name) in [
+            ("intel-rapl:0", "package-0"),
+            ("intel-rapl:0/intel-rapl:0:0", "core"),
+        ] {
+            let zone = dir.path().join(zone);
+            create_dir_all(&zone).unwrap();
+            write(zone.join("name"), name).unwrap();
+            write(zone.join("energy_uj"), "1000").unwrap();
+            write(zone.join("max_energy_range_uj"), "262143328850").unwrap();
+        }
+        let (domains, backend) = discover_rapl_domains(
+            dir.path().to_str(),
+            None,
+            Default::default(),
+            &DomainOptions::default(),
+        )
+        .unwrap();
+        let mut rapl = Rapl::with_backend(domains, Some(0.005), backend);
+
+        let csv = dir.path().join("energy.csv");
+        let mut file = std::fs::File::create(&csv).unwrap();
+        let written = log_energy(&mut rapl, &mut file, Duration::from_millis(5), Some(3))
+            .await
+            .unwrap();
+
+        assert_eq!(written, 3);
+        let content = read_to_string(&csv).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 1 + 3 * 2);
+        assert_eq!(lines[0], "timestamp_us;domain;energy_uj;power_w");
+        assert!(lines[1].contains(";CORE_0;0;"));
+        assert!(lines[2].contains(";PACKAGE-0_0;0;"));
+    }
+}
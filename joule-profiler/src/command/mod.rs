@@ -1,42 +1,1043 @@
-use std::process::{Command, ExitStatus, Stdio};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Read, Write, pipe},
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
+use ::log::{debug, info, warn};
+use anyhow::{Context, Result, anyhow};
 
-use crate::{error::JouleProfilerError, util::file::create_file_with_user_permissions};
+use crate::{
+    cli::{EnergyUnit, EnvVar, RaplBackendKind},
+    config::ProfileConfig,
+    error::JouleProfilerError,
+    output::format_float,
+    source::{
+        MetricSource, SourceManager, fake::init_fake, nvml::init_nvml, procfs::init_procfs,
+        rapl::init_rapl,
+    },
+    util::{
+        file::{FileMode, create_file_with_user_permissions},
+        pty::{PtyReader, open_pty},
+    },
+};
 
+pub mod calibrate;
+pub mod compare;
+pub mod dry_run;
+pub mod env;
 pub mod list_sensors;
+pub mod log;
 pub mod phases;
+pub mod remote;
+pub mod show;
 pub mod simple;
+pub mod watch;
+
+/// Create the metrics sources and their manager, with the RAPL counters read a second time
+/// with the other backend when cross-checking.
+/// The fake source replaces the hardware sources when configured.
+pub fn init_sources(config: &ProfileConfig) -> Result<SourceManager> {
+    if !config.fake_source.is_empty() {
+        let mut sources = vec![init_fake(&config.fake_source)];
+        if config.procfs {
+            sources.push(init_procfs());
+        }
+        return Ok(manager_with_buffer(SourceManager::new(sources), config));
+    }
+
+    let mut backends = vec![config.rapl_backend];
+    if config.cross_check.is_some() {
+        backends.push(match config.rapl_backend {
+            RaplBackendKind::Powercap => RaplBackendKind::Msr,
+            RaplBackendKind::Msr => RaplBackendKind::Powercap,
+        });
+    }
+
+    let mut sources = backends
+        .into_iter()
+        .map(|backend| {
+            init_rapl(
+                config.rapl_path.as_deref(),
+                config.sockets.as_ref(),
+                backend,
+                &config.domain_options,
+                config.rapl_options(),
+            )
+        })
+        .collect::<Result<Vec<MetricSource>>>()?;
+    if config.gpu {
+        sources.push(init_nvml()?);
+    }
+    if config.procfs {
+        sources.push(init_procfs());
+    }
+
+    let mut manager = SourceManager::with_watchdog(sources, config.watchdog);
+    if let Some(cross_check) = config.cross_check {
+        manager.set_cross_check(cross_check);
+    }
+    Ok(manager_with_buffer(manager, config))
+}
+
+/// Apply the `--event-buffer` of the configuration to the manager.
+fn manager_with_buffer(mut manager: SourceManager, config: &ProfileConfig) -> SourceManager {
+    if let Some(event_buffer) = config.event_buffer {
+        manager.set_event_buffer(event_buffer);
+    }
+    manager
+}
+
+/// Exit code of the profiler after the iterations: that of the last iteration which failed,
+/// 0 if the command always succeeded.
+pub fn command_exit_code(exit_codes: impl IntoIterator<Item = i32>) -> i32 {
+    exit_codes
+        .into_iter()
+        .filter(|exit_code| *exit_code != 0)
+        .last()
+        .unwrap_or(0)
+}
+
+/// With `--fail-fast`, fail when an iteration of the command exited with a nonzero code, once
+/// the results gathered until then are displayed.
+pub fn check_fail_fast(
+    config: &ProfileConfig,
+    exit_codes: impl IntoIterator<Item = i32>,
+) -> Result<()> {
+    if !config.fail_fast {
+        return Ok(());
+    }
+    match exit_codes
+        .into_iter()
+        .enumerate()
+        .find(|(_, exit_code)| *exit_code != 0)
+    {
+        Some((idx, exit_code)) => Err(JouleProfilerError::CommandFailed {
+            iteration: idx + 1,
+            exit_code,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Write only the package energy of each iteration with `--print-total`, averaged over the
+/// iterations, followed by a newline.
+pub fn write_total(
+    out: &mut impl Write,
+    unit: EnergyUnit,
+    precision: usize,
+    packages_uj: &[u64],
+) -> Result<()> {
+    let total_uj: u64 = packages_uj.iter().sum();
+    let mean_uj = total_uj as f64 / packages_uj.len().max(1) as f64;
+
+    match unit {
+        EnergyUnit::Uj => writeln!(out, "{}", mean_uj.round() as u64)?,
+        _ => writeln!(out, "{}", format_float(unit.from_uj(mean_uj), precision))?,
+    }
+    Ok(())
+}
+
+/// Exit code reported for a command stopped by `--timeout`.
+pub const TIMEOUT_EXIT_CODE: i32 = -2;
+
+/// Time left to a command to exit after SIGTERM before it is killed with SIGKILL.
+const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Interval between two checks of the command exit while it terminates.
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Wrapper binding the memory of the program to a NUMA node with --numa-node.
+const NUMACTL: &str = "numactl";
+
+/// Shell interpreting the command with --shell.
+const SHELL: &str = "sh";
+
+/// Directory holding one `node<N>` entry per NUMA node of the machine.
+const NUMA_NODES_PATH: &str = "/sys/devices/system/node";
 
 /// Executes the configured command and returns its exit code and status.
-pub fn run_command(cmd: &[String], output_file: Option<&String>) -> Result<(i32, ExitStatus)> {
+///
+/// The command is awaited without blocking the runtime, so that the polling workers of the
+/// sources keep running on a single-threaded or busy runtime. It is terminated if it still
+/// runs after the configured timeout.
+pub async fn run_command(config: &ProfileConfig) -> Result<(i32, ExitStatus)> {
+    let mut child = tokio::process::Command::from(build_command(config)?)
+        .spawn()
+        .map_err(|e| command_error(program_name(config), e))?;
+    if let Some(pid) = child.id() {
+        check_scheduling(config, pid);
+    }
+
+    let Some(timeout_s) = config.timeout_s else {
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| command_error(program_name(config), e))?;
+        return Ok((status.code().unwrap_or(1), status));
+    };
+
+    let timeout = Duration::from_secs_f64(timeout_s);
+    if let Ok(status) = tokio::time::timeout(timeout, child.wait()).await {
+        let status = status.context("Failed to wait on child")?;
+        return Ok((status.code().unwrap_or(1), status));
+    }
+
+    warn!(
+        "The command is still running after {} s, stopping it",
+        timeout_s
+    );
+    let status = terminate_async_command(&mut child).await?;
+    Ok((TIMEOUT_EXIT_CODE, status))
+}
+
+/// Run the `--post-hook` with the path of the results file appended, the exit code of the
+/// command and the energy in µJ being passed as `JP_EXIT_CODE` and `JP_TOTAL_UJ`.
+///
+/// The hook runs like the command, in its environment and working directory but without
+/// its redirections, priority nor timeout. A failing hook is only reported, the results are
+/// already written.
+pub async fn run_post_hook(
+    config: &ProfileConfig,
+    results_path: Option<&str>,
+    exit_code: i32,
+    total_uj: u64,
+) {
+    let (Some(hook), Some(path)) = (&config.post_hook, results_path) else {
+        return;
+    };
+
+    let mut env = config.env.clone();
+    env.extend([
+        EnvVar {
+            key: "JP_EXIT_CODE".to_string(),
+            value: exit_code.to_string(),
+        },
+        EnvVar {
+            key: "JP_TOTAL_UJ".to_string(),
+            value: total_uj.to_string(),
+        },
+    ]);
+    // The path is a positional parameter of the script, it is not parsed by the shell
+    let hook_config = ProfileConfig {
+        cmd: vec![
+            SHELL.to_string(),
+            "-c".to_string(),
+            format!("{} \"$1\"", hook),
+            SHELL.to_string(),
+            path.to_string(),
+        ],
+        shell: false,
+        env,
+        numa_node: None,
+        nice: None,
+        realtime: false,
+        timeout_s: None,
+        quiet: false,
+        output_file: None,
+        stderr_file: None,
+        merge_stderr: false,
+        stdin_file: None,
+        ..config.clone()
+    };
+
+    info!("Running the post hook on {}", path);
+    match run_command(&hook_config).await {
+        Ok((0, _)) => debug!("Post hook succeeded"),
+        Ok((code, _)) => warn!("The post hook failed with exit code {}", code),
+        Err(e) => warn!("Cannot run the post hook: {}", e),
+    }
+}
+
+/// Send SIGTERM to the command awaited by [`run_command`], then SIGKILL if it still runs
+/// after a grace period.
+async fn terminate_async_command(child: &mut tokio::process::Child) -> Result<ExitStatus> {
+    // The pid is only known until the command is reaped
+    let Some(pid) = child.id() else {
+        return child.wait().await.context("Failed to wait on child");
+    };
+
+    info!("Terminating the command {}", pid);
+    // SAFETY: kill has no memory safety preconditions, the child is not reaped yet so its pid
+    // cannot have been reused
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+
+    if let Ok(status) = tokio::time::timeout(TERMINATE_GRACE_PERIOD, child.wait()).await {
+        return status.context("Failed to wait on child");
+    }
+
+    warn!("The command ignored SIGTERM, killing it");
+    child.kill().await.context("Failed to kill child")?;
+    child.wait().await.context("Failed to wait on child")
+}
+
+/// Send SIGTERM to the command, then SIGKILL if it still runs after a grace period.
+pub fn terminate_command(child: &mut Child) -> Result<ExitStatus> {
+    if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+        return Ok(status);
+    }
+
+    info!("Terminating the command {}", child.id());
+    // SAFETY: kill has no memory safety preconditions, the child is not reaped yet so its pid
+    // cannot have been reused
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+
+    let deadline = Instant::now() + TERMINATE_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+            return Ok(status);
+        }
+        thread::sleep(TIMEOUT_POLL_INTERVAL);
+    }
+
+    warn!("The command ignored SIGTERM, killing it");
+    child.kill().context("Failed to kill child")?;
+    child.wait().context("Failed to wait on child")
+}
+
+/// Terminates a command from another thread once its timeout expires, for the callers
+/// blocked reading its output.
+///
+/// The timer must be cancelled before the command is reaped, so that its pid cannot have
+/// been reused when it is signaled.
+pub struct CommandTimer {
+    deadline: Instant,
+    cancel: Sender<()>,
+    thread: JoinHandle<bool>,
+}
+
+impl CommandTimer {
+    pub fn start(pid: u32, timeout_s: f64) -> Self {
+        let deadline = Instant::now() + Duration::from_secs_f64(timeout_s);
+        let (cancel, cancelled) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            let pid = pid as libc::pid_t;
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if cancelled.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                return false;
+            }
+
+            warn!(
+                "The command is still running after {} s, stopping it",
+                timeout_s
+            );
+            // SAFETY: kill has no memory safety preconditions, the command is not reaped
+            // before the timer is cancelled
+            unsafe { libc::kill(pid, libc::SIGTERM) };
+            if cancelled.recv_timeout(TERMINATE_GRACE_PERIOD) == Err(RecvTimeoutError::Timeout) {
+                warn!("The command ignored SIGTERM, killing it");
+                // SAFETY: as above
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+            true
+        });
+
+        Self {
+            deadline,
+            cancel,
+            thread,
+        }
+    }
+
+    /// Stop the timer, returns the time left before the timeout, `None` if it expired and
+    /// the command was signaled.
+    pub fn cancel(self) -> Option<Duration> {
+        let _ = self.cancel.send(());
+        let expired = self.thread.join().unwrap_or(true);
+        (!expired).then(|| self.deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// Terminate the command if it is still running, see [`terminate_command`], and return its
+/// exit code.
+pub fn stop_command(mut child: Child) -> Result<i32> {
+    let status = terminate_command(&mut child)?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Command running the configured program, under `numactl --membind` when its memory is
+/// bound to a NUMA node, with the configured environment variables, working directory and
+/// stdin.
+fn program_command(config: &ProfileConfig) -> Result<Command> {
+    let mut command = match config.numa_node {
+        Some(node) => {
+            check_numa_node(Path::new(NUMA_NODES_PATH), node)?;
+            debug!(
+                "Binding the memory of the program to the NUMA node {}",
+                node
+            );
+            numa_command(&command_line(config), node)
+        }
+        None => {
+            let cmd = command_line(config);
+            let mut command = Command::new(&cmd[0]);
+            command.args(&cmd[1..]);
+            command
+        }
+    };
+
+    command.envs(config.env.iter().map(|var| (&var.key, &var.value)));
+    if let Some(workdir) = &config.workdir {
+        // Checked here, the spawn error would blame the program
+        if !Path::new(workdir).is_dir() {
+            return Err(JouleProfilerError::WorkdirNotFound(workdir.clone()).into());
+        }
+        command.current_dir(workdir);
+    }
+    command.stdin(stdin_stdio(config)?);
+    set_scheduling(&mut command, config);
+    Ok(command)
+}
+
+/// Stdin of the profiled program, the configured file or inherited otherwise, the phases
+/// mode only reading the program output.
+fn stdin_stdio(config: &ProfileConfig) -> Result<Stdio> {
+    match &config.stdin_file {
+        Some(path) => {
+            let file = File::open(path).map_err(|e| {
+                JouleProfilerError::StdinFileNotReadable(format!("{:?}: {}", path, e))
+            })?;
+            Ok(Stdio::from(file))
+        }
+        None => Ok(Stdio::inherit()),
+    }
+}
+
+/// Program and arguments of the command, joined with spaces into a single `sh -c` script
+/// with --shell so that the pipelines, redirections and globs are interpreted.
+fn command_line(config: &ProfileConfig) -> Vec<String> {
+    if config.shell {
+        vec![SHELL.to_string(), "-c".to_string(), config.cmd.join(" ")]
+    } else {
+        config.cmd.clone()
+    }
+}
+
+/// Run the command with its memory allocated only on the NUMA node, so the DRAM energy is
+/// attributed to the memory controller of that node.
+fn numa_command(cmd: &[String], node: u32) -> Command {
+    let mut command = Command::new(NUMACTL);
+    command
+        .arg(format!("--membind={}", node))
+        .arg("--")
+        .args(cmd);
+    command
+}
+
+/// Check that the NUMA node exists on the machine.
+fn check_numa_node(nodes_path: &Path, node: u32) -> Result<()> {
+    if nodes_path.join(format!("node{}", node)).is_dir() {
+        return Ok(());
+    }
+    Err(JouleProfilerError::InvalidNumaNode(format!(
+        "node {} not found in {}",
+        node,
+        nodes_path.display()
+    ))
+    .into())
+}
+
+/// Program spawned for the command, reported when it cannot be executed.
+fn program_name(config: &ProfileConfig) -> &str {
+    match config.numa_node {
+        Some(_) => NUMACTL,
+        None if config.shell => SHELL,
+        None => &config.cmd[0],
+    }
+}
+
+/// Build the configured command with its stdout and stderr redirections.
+pub fn build_command(config: &ProfileConfig) -> Result<Command> {
+    let cmd = &config.cmd;
     if cmd.is_empty() {
         return Err(JouleProfilerError::NoCommand.into());
     }
 
-    let mut command = Command::new(&cmd[0]);
-    command.args(&cmd[1..]);
+    let mut command = program_command(config)?;
+
+    let stdout_file = config
+        .output_file
+        .as_ref()
+        .map(|path| open_output_file(path, config.file_mode))
+        .transpose()?;
 
-    if let Some(path) = &output_file {
-        let file = create_file_with_user_permissions(path).map_err(|e| {
-            JouleProfilerError::OutputFileCreationFailed(format!("{:?}: {}", path, e))
-        })?;
-        command.stdout(Stdio::from(file));
+    if config.merge_stderr {
+        let stderr = match &stdout_file {
+            Some(file) => Stdio::from(file.try_clone()?),
+            None if config.quiet => Stdio::null(),
+            None if config.results_on_stdout() => Stdio::inherit(),
+            None => Stdio::from(std::io::stdout()),
+        };
+        command.stderr(stderr);
     } else {
-        command.stdout(Stdio::inherit());
+        command.stderr(stderr_stdio(config)?);
     }
 
-    command.stderr(Stdio::inherit());
+    match stdout_file {
+        Some(file) => command.stdout(Stdio::from(file)),
+        None if config.quiet => command.stdout(Stdio::null()),
+        // Stdout only holds the results, the program output must not be captured with them
+        None if config.results_on_stdout() => command.stdout(Stdio::from(std::io::stderr())),
+        None => command.stdout(Stdio::inherit()),
+    };
 
-    let status = command.status().map_err(|e| {
-        if e.kind() == std::io::ErrorKind::NotFound {
-            JouleProfilerError::CommandNotFound(cmd[0].clone())
+    Ok(command)
+}
+
+/// Spawn the configured command with its redirections and scheduling priority.
+pub fn spawn_command(config: &ProfileConfig) -> Result<Child> {
+    let child = build_command(config)?
+        .spawn()
+        .map_err(|e| command_error(program_name(config), e))?;
+    check_scheduling(config, child.id());
+    Ok(child)
+}
+
+/// Apply the configured niceness and realtime scheduling in the forked child before it execs
+/// the program, so that the program never runs with the default scheduling. A refused change
+/// (e.g. missing privileges) does not prevent the spawn, see [`check_scheduling`].
+fn set_scheduling(command: &mut Command, config: &ProfileConfig) {
+    let (nice, realtime) = (config.nice, config.realtime);
+    if nice.is_none() && !realtime {
+        return;
+    }
+
+    // SAFETY: the closure only calls the async-signal-safe setpriority and sched_setscheduler,
+    // without allocating, the parameter outliving the call which only reads it
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice {
+                libc::setpriority(libc::PRIO_PROCESS, 0, nice);
+            }
+            if realtime {
+                let param = libc::sched_param {
+                    sched_priority: libc::sched_get_priority_min(libc::SCHED_FIFO),
+                };
+                libc::sched_setscheduler(0, libc::SCHED_FIFO, &param);
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Check that the configured niceness and realtime scheduling were applied to the spawned
+/// program, a refused change being only a warning. Nothing is checked once the program exited.
+pub fn check_scheduling(config: &ProfileConfig, pid: u32) {
+    if let Some(nice) = config.nice {
+        // SAFETY: getpriority has no memory safety preconditions, errno is reset since -1 is
+        // a valid priority
+        let priority = unsafe {
+            *libc::__errno_location() = 0;
+            libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t)
+        };
+        let exited = std::io::Error::last_os_error().raw_os_error() != Some(0);
+        if exited {
+            debug!("Cannot read the niceness of {}, it already exited", pid);
+        } else if priority == nice {
+            debug!("Set the niceness of {} to {}", pid, nice);
         } else {
-            JouleProfilerError::CommandExecutionFailed(e.to_string())
+            warn!(
+                "Failed to set the niceness of {} to {} (a negative niceness requires root or \
+                 CAP_SYS_NICE), continuing at {}",
+                pid, nice, priority
+            );
         }
-    })?;
+    }
 
-    let exit_code = status.code().unwrap_or(1);
+    if config.realtime {
+        // SAFETY: sched_getscheduler has no memory safety preconditions
+        let policy = unsafe { libc::sched_getscheduler(pid as libc::pid_t) };
+        if policy == libc::SCHED_FIFO {
+            debug!("Running {} with SCHED_FIFO", pid);
+        } else if policy >= 0 {
+            warn!(
+                "Failed to run {} with SCHED_FIFO (requires root or CAP_SYS_NICE), continuing",
+                pid
+            );
+        }
+    }
+}
 
-    Ok((exit_code, status))
+/// Convert an error spawning the profiled program.
+pub fn command_error(program: &str, e: std::io::Error) -> JouleProfilerError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        JouleProfilerError::command_not_found(program)
+    } else {
+        JouleProfilerError::CommandExecutionFailed(e.to_string())
+    }
+}
+
+/// Stderr destination of the profiled program, a file if configured or inherited otherwise.
+pub fn stderr_stdio(config: &ProfileConfig) -> Result<Stdio> {
+    match &config.stderr_file {
+        Some(path) => Ok(Stdio::from(open_output_file(path, config.file_mode)?)),
+        None => Ok(Stdio::inherit()),
+    }
+}
+
+/// Spawn the configured command with its stdout captured, stderr being captured too when merged.
+///
+/// With `pty` the output goes through a pseudo-terminal so the command sees a TTY.
+pub fn spawn_captured(
+    config: &ProfileConfig,
+    pty: bool,
+) -> Result<(Child, BufReader<Box<dyn Read>>)> {
+    if config.cmd.is_empty() {
+        return Err(JouleProfilerError::NoCommand.into());
+    }
+
+    let mut command = program_command(config)?;
+
+    if pty {
+        let (master, slave) = open_pty()?;
+        command.stdout(slave.try_clone()?);
+        if config.merge_stderr {
+            command.stderr(slave);
+        } else {
+            command.stderr(stderr_stdio(config)?);
+        }
+
+        let child = command
+            .spawn()
+            .map_err(|e| command_error(program_name(config), e))?;
+        check_scheduling(config, child.id());
+
+        // Release the parent's copies of the slave so the reader reaches the end of output
+        drop(command);
+
+        let output: Box<dyn Read> = Box::new(PtyReader::new(master));
+        return Ok((child, BufReader::new(output)));
+    }
+
+    // When merging, stdout and stderr share the same pipe so both are read
+    let merged_reader = if config.merge_stderr {
+        let (reader, writer) = pipe()?;
+        command.stdout(writer.try_clone()?);
+        command.stderr(writer);
+        Some(reader)
+    } else {
+        command.stdout(Stdio::piped());
+        command.stderr(stderr_stdio(config)?);
+        None
+    };
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| command_error(program_name(config), e))?;
+    check_scheduling(config, child.id());
+
+    // Release the parent's copies of the pipe writer so the reader reaches EOF
+    drop(command);
+
+    let output: Box<dyn Read> = match merged_reader {
+        Some(reader) => Box::new(reader),
+        None => Box::new(
+            child
+                .stdout
+                .take()
+                .context("Failed to capture child stdout")?,
+        ),
+    };
+
+    Ok((child, BufReader::new(output)))
+}
+
+/// Spawn the configured command with its stderr captured, its stdout going to the output
+/// file if configured.
+pub fn spawn_captured_stderr(config: &ProfileConfig) -> Result<(Child, BufReader<Box<dyn Read>>)> {
+    if config.cmd.is_empty() {
+        return Err(JouleProfilerError::NoCommand.into());
+    }
+
+    let mut command = program_command(config)?;
+    match &config.output_file {
+        Some(path) => command.stdout(Stdio::from(open_output_file(path, config.file_mode)?)),
+        None if config.results_on_stdout() => command.stdout(Stdio::from(std::io::stderr())),
+        None => command.stdout(Stdio::inherit()),
+    };
+    command.stderr(Stdio::piped());
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| command_error(program_name(config), e))?;
+    check_scheduling(config, child.id());
+
+    let output: Box<dyn Read> = Box::new(
+        child
+            .stderr
+            .take()
+            .context("Failed to capture child stderr")?,
+    );
+    Ok((child, BufReader::new(output)))
+}
+
+/// Numbered lines of a captured output, lines that are not valid UTF-8 are skipped.
+pub fn output_lines(reader: impl BufRead) -> impl Iterator<Item = Result<(usize, String)>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| match line {
+            Ok(line) => Some(Ok((idx + 1, line))),
+            Err(e) if e.kind() == ErrorKind::InvalidData => None,
+            Err(e) => Some(Err(anyhow!(
+                "Failed to read line {} from command output: {}",
+                idx + 1,
+                e
+            ))),
+        })
+}
+
+/// Forward a captured output line to the output file if configured, stdout otherwise.
+pub fn echo_line(out_file: Option<&mut File>, line: &str) -> Result<()> {
+    match out_file {
+        Some(file) => writeln!(file, "{}", line)?,
+        None => println!("{}", line),
+    }
+    Ok(())
+}
+
+/// Create a file receiving the profiled program output.
+pub fn open_output_file(path: &str, file_mode: FileMode) -> Result<File> {
+    let file = create_file_with_user_permissions(path, file_mode)
+        .map_err(|e| JouleProfilerError::OutputFileCreationFailed(format!("{:?}: {}", path, e)))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, EnvVar};
+    use clap::Parser;
+    use std::fs::read_to_string;
+    use tempfile::tempdir;
+
+    fn shell_config(script: &str) -> ProfileConfig {
+        ProfileConfig {
+            cmd: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exit_code_is_the_last_failure() {
+        assert_eq!(command_exit_code([0, 0]), 0);
+        assert_eq!(command_exit_code([2, 0, 1, 0]), 1);
+        assert_eq!(command_exit_code([]), 0);
+    }
+
+    #[test]
+    fn fail_fast_reports_the_failed_iteration() {
+        let config = ProfileConfig {
+            fail_fast: true,
+            ..Default::default()
+        };
+
+        assert!(check_fail_fast(&config, [0, 0]).is_ok());
+        let err = check_fail_fast(&config, [0, 3]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JouleProfilerError>(),
+            Some(JouleProfilerError::CommandFailed {
+                iteration: 2,
+                exit_code: 3
+            })
+        ));
+        assert!(check_fail_fast(&ProfileConfig::default(), [1]).is_ok());
+    }
+
+    #[test]
+    fn total_is_the_mean_package_energy() {
+        let mut out = Vec::new();
+        write_total(&mut out, EnergyUnit::Uj, 3, &[1_500_000, 2_500_000]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "2000000\n");
+
+        let mut out = Vec::new();
+        write_total(&mut out, EnergyUnit::J, 3, &[1_500_000]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1.500\n");
+    }
+
+    #[test]
+    fn nice_is_applied_to_the_child() {
+        let config = ProfileConfig {
+            nice: Some(10),
+            ..shell_config("sleep 1")
+        };
+
+        let mut child = spawn_command(&config).unwrap();
+        let stat = read_to_string(format!("/proc/{}/stat", child.id())).unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        // The niceness is the 19th field, the 17th after the parenthesized name
+        let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 1..]
+            .split_whitespace()
+            .collect();
+        assert_eq!(fields[16], "10");
+    }
+
+    #[test]
+    fn nice_is_applied_before_the_program_starts() {
+        let dir = tempdir().unwrap();
+        let stat_path = dir.path().join("stat");
+        let config = ProfileConfig {
+            nice: Some(5),
+            output_file: Some(stat_path.to_string_lossy().to_string()),
+            ..shell_config("cat /proc/$$/stat")
+        };
+
+        let mut child = spawn_command(&config).unwrap();
+        child.wait().unwrap();
+
+        // Read by the program as soon as it started
+        let stat = read_to_string(&stat_path).unwrap();
+        let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 1..]
+            .split_whitespace()
+            .collect();
+        assert_eq!(fields[16], "5");
+    }
+
+    #[test]
+    fn numa_node_runs_the_command_under_numactl() {
+        let cmd = vec![
+            "./bench".to_string(),
+            "--size".to_string(),
+            "10".to_string(),
+        ];
+        let command = numa_command(&cmd, 1);
+
+        assert_eq!(command.get_program(), "numactl");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--membind=1", "--", "./bench", "--size", "10"]);
+    }
+
+    #[tokio::test]
+    async fn shell_interprets_the_joined_command() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("output.txt");
+
+        let config = ProfileConfig {
+            cmd: vec![
+                "printf 'b\\na\\n'".to_string(),
+                "|".to_string(),
+                "sort".to_string(),
+            ],
+            shell: true,
+            output_file: Some(output_path.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            command_line(&config),
+            ["sh", "-c", "printf 'b\\na\\n' | sort"]
+        );
+
+        let (exit_code, _) = run_command(&config).await.unwrap();
+
+        assert_eq!(exit_code, 0);
+        assert_eq!(read_to_string(&output_path).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn missing_numa_node_is_rejected() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("node0")).unwrap();
+
+        assert!(check_numa_node(dir.path(), 0).is_ok());
+        let err = check_numa_node(dir.path(), 1).unwrap_err();
+        assert!(err.to_string().contains("node 1 not found"));
+    }
+
+    #[tokio::test]
+    async fn env_and_workdir_are_applied_to_the_command() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("output.txt");
+
+        let config = ProfileConfig {
+            output_file: Some(output_path.to_string_lossy().to_string()),
+            env: vec![EnvVar {
+                key: "OMP_NUM_THREADS".to_string(),
+                value: "4".to_string(),
+            }],
+            workdir: Some(dir.path().to_string_lossy().to_string()),
+            ..shell_config("echo $OMP_NUM_THREADS; pwd")
+        };
+
+        run_command(&config).await.unwrap();
+
+        let canonical_dir = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            read_to_string(&output_path).unwrap(),
+            format!("4\n{}\n", canonical_dir.display())
+        );
+    }
+
+    #[tokio::test]
+    async fn post_hook_receives_the_results_path_and_energy() {
+        let dir = tempdir().unwrap();
+        let hook_output = dir.path().join("hook.txt");
+        let results = dir.path().join("results with spaces.json");
+
+        let config = ProfileConfig {
+            post_hook: Some(format!(
+                "printf '%s %s %s' \"$JP_EXIT_CODE\" \"$JP_TOTAL_UJ\" > {}",
+                hook_output.display()
+            )),
+            // The redirections of the command do not apply to the hook
+            output_file: Some(dir.path().join("output.txt").to_string_lossy().to_string()),
+            ..shell_config("true")
+        };
+        let path = results.to_string_lossy();
+        run_post_hook(&config, Some(&path), 3, 1_520_000).await;
+
+        assert_eq!(
+            read_to_string(&hook_output).unwrap(),
+            format!("3 1520000 {}", path)
+        );
+        assert!(!dir.path().join("output.txt").exists());
+
+        // A failing hook is only reported
+        let config = ProfileConfig {
+            post_hook: Some("exit 1".to_string()),
+            ..shell_config("true")
+        };
+        run_post_hook(&config, Some(&path), 0, 0).await;
+    }
+
+    #[tokio::test]
+    async fn missing_workdir_is_rejected() {
+        let config = ProfileConfig {
+            workdir: Some("/nonexistent/workdir".to_string()),
+            ..shell_config("true")
+        };
+
+        let err = run_command(&config).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JouleProfilerError>(),
+            Some(JouleProfilerError::WorkdirNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn env_without_equals_is_rejected() {
+        let err = Cli::try_parse_from([
+            "joule-profiler",
+            "simple",
+            "--env",
+            "OMP_NUM_THREADS",
+            "--",
+            "true",
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid environment variable"));
+    }
+
+    #[tokio::test]
+    async fn run_command_stops_the_command_at_the_timeout() {
+        let config = ProfileConfig {
+            timeout_s: Some(0.1),
+            ..shell_config("exec sleep 30")
+        };
+
+        let begin = Instant::now();
+        let (exit_code, _) = run_command(&config).await.unwrap();
+
+        assert_eq!(exit_code, TIMEOUT_EXIT_CODE);
+        assert!(begin.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn command_ignoring_sigterm_is_killed_after_the_grace_period() {
+        let mut child = spawn_command(&shell_config("trap '' TERM; exec sleep 30")).unwrap();
+        // Let the shell ignore SIGTERM, inherited by sleep
+        thread::sleep(Duration::from_millis(100));
+
+        let status = terminate_command(&mut child).unwrap();
+
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(libc::SIGKILL)
+        );
+    }
+
+    #[test]
+    fn stopped_command_ignoring_sigterm_is_killed() {
+        let child = spawn_command(&shell_config("trap '' TERM; exec sleep 30")).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let begin = Instant::now();
+        // Killed by a signal, without an exit code
+        assert_eq!(stop_command(child).unwrap(), 1);
+        assert!(begin.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn run_command_redirects_stderr_to_file() {
+        let dir = tempdir().unwrap();
+        let stderr_path = dir.path().join("stderr.txt");
+
+        let config = ProfileConfig {
+            stderr_file: Some(stderr_path.to_string_lossy().to_string()),
+            ..shell_config("echo oops >&2")
+        };
+
+        let (exit_code, _) = run_command(&config).await.unwrap();
+
+        assert_eq!(exit_code, 0);
+        assert_eq!(read_to_string(&stderr_path).unwrap(), "oops\n");
+    }
+
+    #[tokio::test]
+    async fn run_command_merges_stderr_into_output_file() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("output.txt");
+
+        let config = ProfileConfig {
+            output_file: Some(output_path.to_string_lossy().to_string()),
+            merge_stderr: true,
+            ..shell_config("echo out; echo err >&2")
+        };
+
+        run_command(&config).await.unwrap();
+
+        assert_eq!(read_to_string(&output_path).unwrap(), "out\nerr\n");
+    }
+
+    #[tokio::test]
+    async fn stdin_file_is_fed_to_every_iteration() {
+        let dir = tempdir().unwrap();
+        let stdin_path = dir.path().join("stdin.txt");
+        std::fs::write(&stdin_path, "input\n").unwrap();
+        let output_path = dir.path().join("output.txt");
+
+        let config = ProfileConfig {
+            stdin_file: Some(stdin_path.to_string_lossy().to_string()),
+            output_file: Some(output_path.to_string_lossy().to_string()),
+            ..shell_config("cat")
+        };
+
+        run_command(&config).await.unwrap();
+        assert_eq!(read_to_string(&output_path).unwrap(), "input\n");
+        // Reopened from its start
+        run_command(&config).await.unwrap();
+        assert_eq!(read_to_string(&output_path).unwrap(), "input\n");
+    }
+
+    #[tokio::test]
+    async fn missing_stdin_file_is_rejected() {
+        let config = ProfileConfig {
+            stdin_file: Some("/nonexistent/stdin.txt".to_string()),
+            ..shell_config("cat")
+        };
+
+        let err = run_command(&config).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JouleProfilerError>(),
+            Some(JouleProfilerError::StdinFileNotReadable(_))
+        ));
+    }
 }
@@ -1,35 +1,65 @@
 use std::{
-    fs::File,
-    io::{BufRead, BufReader, ErrorKind},
-    process::{Command, Stdio},
+    fs::{File, read_to_string},
+    path::Path,
+    process::Child,
+    time::Duration,
 };
 
-use anyhow::{Context, Result, bail};
-use regex::Regex;
-use std::io::Write;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use tokio::{
+    sync::mpsc::{UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+    time::{Instant, timeout},
+};
 
 use crate::{
+    cli::TokenStream,
+    command::{
+        CommandTimer, TIMEOUT_EXIT_CODE, check_fail_fast, command_exit_code, echo_line,
+        init_sources, open_output_file, output_lines, run_post_hook, spawn_captured,
+        spawn_captured_stderr, spawn_command, stop_command, terminate_command, write_total,
+    },
     config::{PhasesConfig, ProfileConfig},
     error::JouleProfilerError,
-    measurement::{Phase, PhaseMeasurementResult, PhaseResult, PhaseToken},
-    output::{Displayer, OutputFormatTrait},
-    source::{SourceManager, rapl::init_rapl},
-    util::{file::create_file_with_user_permissions, time::get_timestamp},
+    measurement::{
+        Phase, PhaseMeasurementResult, PhaseResult, PhaseToken, energy_totals, package_energy_uj,
+        ratio_metrics, score_metric, total_energy_uj,
+    },
+    output::{
+        Displayer, OutputFormatTrait,
+        otlp::{export_to_collector, phases_points},
+        trace_events::write_trace_events,
+    },
+    source::{Metric, Metrics, SourceManager},
+    util::{
+        interrupt::interrupted,
+        page_cache::{DROP_CACHES_PATH, drop_page_cache},
+        time::get_timestamp,
+    },
 };
 
-pub async fn run_phases(config: &ProfileConfig, phases_config: &PhasesConfig) -> Result<()> {
-    let sources = vec![init_rapl(
-        config.rapl_path.as_deref(),
-        config.sockets.as_ref(),
-        config.rapl_polling,
-    )?];
-    let mut manager = SourceManager::new(sources);
-
-    let mut results = Vec::new();
+/// Measure and display the phases of the command, returning its exit code.
+pub async fn run_phases(config: &ProfileConfig, phases_config: &PhasesConfig) -> Result<i32> {
+    let results = collect_phases(config, phases_config).await?;
 
-    for _ in 0..config.iterations {
-        manager.start_workers().await;
-        results.push(measure_phases(&mut manager, config, phases_config).await?);
+    let exit_codes = results.iter().map(|result| result.exit_code);
+    if let Some(unit) = config.print_total {
+        let packages_uj: Vec<u64> = results
+            .iter()
+            .map(|result| {
+                result
+                    .phases
+                    .iter()
+                    .map(|phase| package_energy_uj(&phase.metrics))
+                    .sum()
+            })
+            .collect();
+        write_total(&mut std::io::stdout(), unit, config.precision, &packages_uj)?;
+        check_fail_fast(config, exit_codes.clone())?;
+        return Ok(command_exit_code(exit_codes));
     }
 
     let mut displayer = Displayer::try_from(config)?;
@@ -39,20 +69,93 @@ pub async fn run_phases(config: &ProfileConfig, phases_config: &PhasesConfig) ->
         displayer.phases_single(config, &results[0])?;
     }
 
-    Ok(())
+    if let Some(path) = &config.trace_events {
+        write_trace_events(path, &results, config.file_mode)?;
+    }
+
+    export_to_collector(config, phases_points(config, &results)).await;
+
+    let total_uj = results
+        .iter()
+        .flat_map(|result| &result.phases)
+        .map(|phase| total_energy_uj(&phase.metrics))
+        .sum();
+    run_post_hook(
+        config,
+        displayer.results_path(),
+        command_exit_code(exit_codes.clone()),
+        total_uj,
+    )
+    .await;
+    check_fail_fast(config, exit_codes.clone())?;
+    Ok(command_exit_code(exit_codes))
+}
+
+/// Measure all the configured iterations of the command in phases mode, without displaying
+/// the results (the phases are still printed as they end with `--live`).
+pub async fn collect_phases(
+    config: &ProfileConfig,
+    phases_config: &PhasesConfig,
+) -> Result<Vec<PhaseMeasurementResult>> {
+    let mut manager = init_sources(config)?;
+    let live = phases_config.live.then(|| LivePhases::new(&mut manager));
+
+    let mut results = Vec::new();
+
+    let mut stop = config.stop_condition();
+    let mut drop_caches = config.drop_caches;
+    loop {
+        // Before the iteration time, the write back of the dirty pages is not measured
+        if drop_caches {
+            drop_caches = drop_page_cache(Path::new(DROP_CACHES_PATH));
+        }
+
+        let begin = Instant::now();
+        manager.start_workers().await;
+        let result = measure_phases(&mut manager, config, phases_config, live.as_ref()).await?;
+        let energy_uj = result
+            .phases
+            .iter()
+            .map(|phase| total_energy_uj(&phase.metrics))
+            .sum();
+        stop.record(begin.elapsed().as_millis(), energy_uj);
+        if config.fail_fast && result.exit_code != 0 {
+            stop.fail();
+        }
+        results.push(result);
+
+        if interrupted() {
+            stop.interrupt();
+        }
+        if let Some(reason) = stop.reached() {
+            info!(
+                "Stopping after {} iteration(s): {}",
+                stop.iterations(),
+                reason
+            );
+            break;
+        }
+    }
+
+    if let Some(live) = live {
+        live.finish().await;
+    }
+    Ok(results)
 }
 
 async fn measure_phases(
     manager: &mut SourceManager,
     config: &ProfileConfig,
     phases_config: &PhasesConfig,
+    live: Option<&LivePhases>,
 ) -> Result<PhaseMeasurementResult> {
-    let regex = Regex::new(&phases_config.token_pattern).map_err(|e| {
-        JouleProfilerError::InvalidPattern(format!("{}: {}", phases_config.token_pattern, e))
-    })?;
-
     let mut phases = Vec::new();
 
+    if phases_config.phase_timings.is_none() {
+        // Fail before starting the sources on a pattern unusable as phase delimiter
+        token_regex(&phases_config.token_pattern)?;
+    }
+
     manager.start().await?;
 
     let begin_timestamp = get_timestamp();
@@ -64,82 +167,22 @@ async fn measure_phases(
 
     manager.measure().await?;
 
-    let mut command = Command::new(&config.cmd[0]);
-    if config.cmd.len() > 1 {
-        command.args(&config.cmd[1..]);
-    }
-
-    command.stdout(Stdio::piped());
-    command.stderr(Stdio::inherit());
-
-    let mut child = command.spawn().map_err(|e| {
-        if e.kind() == std::io::ErrorKind::NotFound {
-            JouleProfilerError::CommandNotFound(config.cmd[0].clone())
-        } else {
-            JouleProfilerError::CommandExecutionFailed(e.to_string())
+    let exit_code = match &phases_config.phase_timings {
+        Some(path) => {
+            run_with_timings(
+                manager,
+                config,
+                phases_config,
+                Path::new(path),
+                begin_timestamp,
+                &mut phases,
+                live,
+            )
+            .await?
         }
-    })?;
-
-    let stdout = child
-        .stdout
-        .take()
-        .context("Failed to capture child stdout")?;
-    let reader = BufReader::new(stdout);
-
-    let mut out_file: Option<File> = if let Some(path) = &config.output_file {
-        let file = create_file_with_user_permissions(path).map_err(|e| {
-            JouleProfilerError::OutputFileCreationFailed(format!("{:?}: {}", path, e))
-        })?;
-        Some(file)
-    } else {
-        None
+        None => run_with_tokens(manager, config, phases_config, &mut phases, live).await?,
     };
 
-    for (line_number, line_res) in reader.lines().enumerate() {
-        let line = match line_res {
-            Ok(l) => l,
-            Err(e) if e.kind() == ErrorKind::InvalidData => {
-                continue;
-            }
-            Err(e) => {
-                bail!(
-                    "Failed to read line {} from command output: {}",
-                    line_number + 1,
-                    e
-                );
-            }
-        };
-
-        if let Some(f) = out_file.as_mut() {
-            writeln!(f, "{}", line)?;
-        } else {
-            println!("{}", line);
-        }
-
-        // Check if line matches the regex pattern
-        if let Some(captures) = regex.captures(&line) {
-            // Get the full match or the first capture group
-            let token = if let Some(capture) = captures.get(1) {
-                capture.as_str().to_string()
-            } else {
-                captures.get(0).unwrap().as_str().to_string()
-            };
-
-            let phase_timestamp = get_timestamp();
-
-            manager.phase().await?;
-
-            phases.push(Phase {
-                token: PhaseToken::Token(token),
-                timestamp: phase_timestamp,
-                line_number: Some(line_number + 1),
-            });
-        }
-    }
-
-    let status = child.wait().context("Failed to wait on child")?;
-    let exit_code = status.code().unwrap_or(1);
-
     manager.measure().await?;
 
     let end_timestamp = get_timestamp();
@@ -154,7 +197,16 @@ async fn measure_phases(
 
     for (i, phases) in phases.windows(2).enumerate() {
         let (begin_phase, end_phase) = (&phases[0], &phases[1]);
-        let metrics = sources_result.measures[i].clone();
+        let mut metrics = phase_metrics(&sources_result.measures, i);
+        if let Some(reference) = &config.ratio_to {
+            metrics.extend(ratio_metrics(&metrics, reference, config.rounding()));
+        }
+        if !config.score.is_empty() {
+            metrics.push(score_metric(&metrics, &config.score, config.rounding())?);
+        }
+        if config.totals {
+            metrics.extend(energy_totals(&metrics));
+        }
         let duration_ms = (end_phase.timestamp - begin_phase.timestamp) / 1000;
 
         let phase_mesurement = PhaseResult::new(
@@ -164,15 +216,958 @@ async fn measure_phases(
             end_phase.line_number,
             metrics,
             duration_ms,
+        )
+        .starting_at(begin_phase.timestamp.saturating_sub(begin_timestamp))
+        .between(begin_phase.timestamp, end_phase.timestamp)
+        .with_counter_wraps(sources_result.counter_wraps.get(i).copied().unwrap_or(0))
+        .with_power_range(
+            sources_result
+                .phase_power_ranges
+                .get(i)
+                .cloned()
+                .unwrap_or_default(),
         );
         phases_measurements.push(phase_mesurement);
     }
 
+    if phases_config.inner_only {
+        phases_measurements = inner_phases(phases_measurements);
+    }
+
     let duration_ms = (end_timestamp - begin_timestamp) / 1000;
 
     Ok(PhaseMeasurementResult {
         phases: phases_measurements,
         duration_ms,
         exit_code,
+        failed_sources: sources_result.failed_sources,
+        polling: sources_result.polling,
+    })
+}
+
+/// Compile the token pattern, rejecting the ones matching an empty string since they would
+/// match every line and start a phase on each of them.
+pub fn token_regex(pattern: &str) -> Result<Regex> {
+    let regex = Regex::new(pattern)
+        .map_err(|e| JouleProfilerError::InvalidPattern(format!("{}: {}", pattern, e)))?;
+
+    if regex.is_match("") {
+        return Err(JouleProfilerError::InvalidPattern(format!(
+            "{}: matches an empty string, so every line would be a phase token",
+            pattern
+        ))
+        .into());
+    }
+    Ok(regex)
+}
+
+/// Capture groups naming the phase token, by precedence.
+const TOKEN_GROUP_NAMES: [&str; 2] = ["name", "phase"];
+
+/// Token of a line matching the pattern: the group named `name` or `phase` if the pattern
+/// has one, else the first capture group, else the full match.
+fn phase_token(captures: &Captures) -> String {
+    TOKEN_GROUP_NAMES
+        .iter()
+        .find_map(|name| captures.name(name))
+        .or_else(|| captures.get(1))
+        .or_else(|| captures.get(0))
+        .map(|capture| capture.as_str().to_string())
+        .unwrap_or_default()
+}
+
+/// Metrics measured during the phase at the index, empty when the sources recorded fewer
+/// phases than the tokens delimited.
+fn phase_metrics(measures: &[Metrics], index: usize) -> Metrics {
+    measures.get(index).cloned().unwrap_or_else(|| {
+        warn!(
+            "No metrics measured for phase {}, the sources recorded only {} phase(s)",
+            index,
+            measures.len()
+        );
+        Metrics::default()
     })
 }
+
+/// Keep only the phases delimited by two tokens, dropping the ones bounded by START or END.
+fn inner_phases(phases: Vec<PhaseResult>) -> Vec<PhaseResult> {
+    let inner: Vec<PhaseResult> = phases
+        .into_iter()
+        .filter(|phase| phase.start_token.is_some() && phase.end_token.is_some())
+        .collect();
+
+    if inner.is_empty() {
+        warn!("No phase between two tokens, at least two tokens are needed with --inner-only");
+    }
+    inner
+}
+
+/// Run the command and delimit the phases with the tokens matched in its output.
+async fn run_with_tokens(
+    manager: &mut SourceManager,
+    config: &ProfileConfig,
+    phases_config: &PhasesConfig,
+    phases: &mut Vec<Phase>,
+    live: Option<&LivePhases>,
+) -> Result<i32> {
+    let regex = token_regex(&phases_config.token_pattern)?;
+
+    let stream = phases_config.token_stream;
+    let (mut child, reader) = match stream {
+        TokenStream::Stdout => spawn_captured(config, phases_config.pty)?,
+        TokenStream::Stderr => spawn_captured_stderr(config)?,
+        TokenStream::Both => spawn_captured(
+            &ProfileConfig {
+                merge_stderr: true,
+                ..config.clone()
+            },
+            phases_config.pty,
+        )?,
+    };
+    let timer = config
+        .timeout_s
+        .map(|timeout_s| CommandTimer::start(child.id(), timeout_s));
+
+    // The captured stderr is forwarded to its own file or stream
+    let echo_path = match stream {
+        TokenStream::Stderr => config.stderr_file.as_ref(),
+        _ => config.output_file.as_ref(),
+    };
+    let mut out_file: Option<File> = echo_path
+        .map(|path| open_output_file(path, config.file_mode))
+        .transpose()?;
+
+    let mut echoed_bytes = 0;
+    let mut truncated = false;
+
+    for line in output_lines(reader) {
+        let (line_number, line) = line?;
+
+        // Past the cap the output is dropped, tokens are still matched
+        if !truncated {
+            echoed_bytes += line.len() + 1;
+            if phases_config
+                .max_output_bytes
+                .is_some_and(|max| echoed_bytes > max)
+            {
+                warn!(
+                    "Command output exceeds {} bytes, the remaining output is discarded",
+                    phases_config.max_output_bytes.unwrap_or_default()
+                );
+                truncated = true;
+            } else {
+                match out_file.as_mut() {
+                    // Still read for the tokens, only the echo is dropped
+                    None if config.quiet && stream != TokenStream::Stderr => {}
+                    None if stream == TokenStream::Stderr || config.results_on_stdout() => {
+                        eprintln!("{}", line)
+                    }
+                    out_file => echo_line(out_file, &line)?,
+                }
+            }
+        }
+
+        // Check if line matches the regex pattern
+        if let Some(captures) = regex.captures(&line) {
+            let token = phase_token(&captures);
+
+            let phase_timestamp = get_timestamp();
+
+            if phases_config.align_boundaries {
+                manager.phase_at(phase_timestamp).await?;
+            } else {
+                manager.phase().await?;
+            }
+
+            phases.push(Phase {
+                token: PhaseToken::Token(token),
+                timestamp: phase_timestamp,
+                line_number: Some(line_number),
+            });
+
+            if let Some(live) = live {
+                live.emit(phases);
+            }
+        }
+    }
+
+    // The output also ends when the timer stopped the command
+    let timeout = match timer {
+        Some(timer) => match timer.cancel() {
+            Some(remaining) => Some(remaining),
+            None => {
+                terminate_command(&mut child)?;
+                return Ok(TIMEOUT_EXIT_CODE);
+            }
+        },
+        None => None,
+    };
+
+    wait_after_output(child, phases_config.exit_timeout_s, timeout).await
+}
+
+/// Interval between two checks of the command exit once its output is closed.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Wait for the command to exit once its output is closed, it may keep running (e.g. a
+/// daemon).
+///
+/// The command is stopped with SIGTERM if it still runs after `timeout_s` or when the
+/// profiler is interrupted, the phases measured so far are kept. It is terminated as timed
+/// out if it still runs at the end of the `--timeout` time left, `command_timeout`.
+async fn wait_after_output(
+    mut child: Child,
+    timeout_s: Option<f64>,
+    command_timeout: Option<Duration>,
+) -> Result<i32> {
+    if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+        return Ok(status.code().unwrap_or(1));
+    }
+
+    info!("The command closed its output but is still running, waiting for it to exit");
+    let deadline = timeout_s.map(|timeout_s| Instant::now() + Duration::from_secs_f64(timeout_s));
+    let command_deadline = command_timeout.map(|timeout| Instant::now() + timeout);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+            return Ok(status.code().unwrap_or(1));
+        }
+        if command_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            warn!("The command is still running at the end of its timeout, stopping it");
+            terminate_command(&mut child)?;
+            return Ok(TIMEOUT_EXIT_CODE);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            warn!(
+                "The command is still running {} s after closing its output",
+                timeout_s.unwrap_or_default()
+            );
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(EXIT_POLL_INTERVAL) => {}
+            _ = &mut ctrl_c => {
+                warn!("Interrupted while waiting for the command to exit");
+                break;
+            }
+        }
+    }
+
+    stop_command(child)
+}
+
+/// Maximum wait for a source to report a completed phase with `--live`.
+const LIVE_PHASE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Prints each phase to stderr as soon as its end boundary is crossed.
+///
+/// The phases are printed by a task waiting for the sources, so that reading the output of
+/// the command is not delayed. The last phase, ending with the command, is only reported
+/// with the results.
+struct LivePhases {
+    /// Boundaries of the completed phases, sent to the printing task
+    boundaries: UnboundedSender<(Phase, Phase)>,
+    /// Task printing the phases, returning the timestamps in microseconds at which they were
+    /// printed
+    printer: JoinHandle<Vec<u128>>,
+}
+
+impl LivePhases {
+    /// Subscribe to the phases completed by the sources, before their workers are started.
+    fn new(manager: &mut SourceManager) -> Self {
+        let mut receiver = manager.live_phases();
+        let sources_count = manager.sources_count();
+        let (boundaries, mut completed) = unbounded_channel::<(Phase, Phase)>();
+
+        let printer = tokio::spawn(async move {
+            let mut emitted_at = Vec::new();
+            while let Some((begin, end)) = completed.recv().await {
+                let mut metrics = Vec::new();
+                for _ in 0..sources_count {
+                    match timeout(LIVE_PHASE_TIMEOUT, receiver.recv()).await {
+                        Ok(Some(source_metrics)) => metrics.extend(source_metrics),
+                        _ => {
+                            warn!(
+                                "A source did not report the phase {} -> {} in time",
+                                begin.token, end.token
+                            );
+                            break;
+                        }
+                    }
+                }
+
+                eprintln!("{}", live_line(&begin, &end, &metrics));
+                emitted_at.push(get_timestamp());
+            }
+            emitted_at
+        });
+
+        Self {
+            boundaries,
+            printer,
+        }
+    }
+
+    /// Print the phase between the two last boundaries once each source completed it.
+    fn emit(&self, phases: &[Phase]) {
+        let [.., begin, end] = phases else {
+            return;
+        };
+        // The printer only stops once the boundaries are dropped
+        let _ = self.boundaries.send((begin.clone(), end.clone()));
+    }
+
+    /// Wait for the phases still to print, returning the timestamps at which each phase was
+    /// printed.
+    async fn finish(self) -> Vec<u128> {
+        drop(self.boundaries);
+        self.printer.await.unwrap_or_default()
+    }
+}
+
+/// Format a phase printed with `--live`, its metrics sorted by name.
+fn live_line(begin: &Phase, end: &Phase, metrics: &[Metric]) -> String {
+    let mut metrics: Vec<&Metric> = metrics.iter().collect();
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let metrics: Vec<String> = metrics
+        .iter()
+        .map(|metric| format!("{}={} {}", metric.name, metric.value, metric.unit))
+        .collect();
+
+    format!(
+        "[phase] {} -> {} ({} ms): {}",
+        begin.token,
+        end.token,
+        end.timestamp.saturating_sub(begin.timestamp) / 1000,
+        metrics.join(", ")
+    )
+}
+
+/// One phase boundary of a timings file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub timestamp_us: u128,
+}
+
+/// Interval between two reads of the timings file while the command runs.
+const TIMINGS_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Parse a timings file, `None` if it is not (yet) a complete JSON array.
+pub fn parse_phase_timings(content: &str) -> Option<Vec<PhaseTiming>> {
+    serde_json::from_str(content).ok()
+}
+
+/// Run the command and delimit the phases with the boundaries it writes to a timings file.
+///
+/// The file is read again while the command runs so each boundary is measured
+/// close to its timestamp, entries older than the start of the run are ignored.
+async fn run_with_timings(
+    manager: &mut SourceManager,
+    config: &ProfileConfig,
+    phases_config: &PhasesConfig,
+    path: &Path,
+    begin_timestamp: u128,
+    phases: &mut Vec<Phase>,
+    live: Option<&LivePhases>,
+) -> Result<i32> {
+    let mut child = spawn_command(config)?;
+    let deadline = config
+        .timeout_s
+        .map(|timeout_s| Instant::now() + Duration::from_secs_f64(timeout_s));
+
+    let mut handled = 0;
+
+    loop {
+        let status = child.try_wait().context("Failed to wait on child")?;
+
+        let timings = read_to_string(path)
+            .ok()
+            .and_then(|content| parse_phase_timings(&content))
+            .unwrap_or_default();
+
+        for timing in timings.into_iter().skip(handled) {
+            handled += 1;
+
+            let previous = phases
+                .last()
+                .map_or(begin_timestamp, |phase| phase.timestamp);
+            if timing.timestamp_us < previous || timing.timestamp_us > get_timestamp() {
+                warn!(
+                    "Ignoring phase '{}' of {}: timestamp {} out of the run",
+                    timing.name,
+                    path.display(),
+                    timing.timestamp_us
+                );
+                continue;
+            }
+
+            if phases_config.align_boundaries {
+                manager.phase_at(timing.timestamp_us).await?;
+            } else {
+                manager.phase().await?;
+            }
+
+            phases.push(Phase {
+                token: PhaseToken::Token(timing.name),
+                timestamp: timing.timestamp_us,
+                line_number: None,
+            });
+
+            if let Some(live) = live {
+                live.emit(phases);
+            }
+        }
+
+        // The file is read one last time once the command exited
+        if let Some(status) = status {
+            return Ok(status.code().unwrap_or(1));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            warn!(
+                "The command is still running after {} s, stopping it",
+                config.timeout_s.unwrap_or_default()
+            );
+            terminate_command(&mut child)?;
+            return Ok(TIMEOUT_EXIT_CODE);
+        }
+
+        tokio::time::sleep(TIMINGS_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        source::rapl::{RaplOptions, init_rapl},
+        test_util::fake_rapl_tree,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn phases_missing_from_the_measures_have_no_metrics() {
+        let metric = Metric {
+            name: "PACKAGE-0_0".to_string(),
+            value: 100,
+            unit: "µJ".to_string(),
+            source: "fake".to_string(),
+        };
+        let measures = vec![vec![metric]];
+
+        assert_eq!(phase_metrics(&measures, 0)[0].value, 100);
+        assert!(phase_metrics(&measures, 1).is_empty());
+        assert!(phase_metrics(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn named_groups_take_precedence_over_the_first_group() {
+        let token = |pattern: &str, line: &str| {
+            phase_token(&Regex::new(pattern).unwrap().captures(line).unwrap())
+        };
+
+        assert_eq!(token(r"PHASE:(?<name>\w+)", "PHASE:load"), "load");
+        assert_eq!(
+            token(r"(\d+) PHASE:(?<phase>\w+)", "12 PHASE:compute"),
+            "compute"
+        );
+        assert_eq!(token(r"(?<phase>\w+)/(?<name>\w+)", "step/flush"), "flush");
+        assert_eq!(token(r"=== ([A-Z_]+) ===", "=== INIT ==="), "INIT");
+        assert_eq!(token(r"__[A-Z0-9_]+__", "start __A__"), "__A__");
+    }
+
+    #[test]
+    fn inner_only_keeps_token_to_token_phases() {
+        let tokens = [
+            PhaseToken::Start,
+            PhaseToken::Token("__A__".to_string()),
+            PhaseToken::Token("__B__".to_string()),
+            PhaseToken::Token("__C__".to_string()),
+            PhaseToken::End,
+        ];
+        let phases = tokens
+            .windows(2)
+            .map(|pair| PhaseResult::new(&pair[0], &pair[1], None, None, Vec::new(), 0))
+            .collect();
+
+        let names: Vec<String> = inner_phases(phases)
+            .into_iter()
+            .map(|phase| phase.name)
+            .collect();
+        assert_eq!(names, vec!["__A__ -> __B__", "__B__ -> __C__"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn output_cap_keeps_matching_tokens() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let output_path = dir.path().join("output.txt");
+
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z0-9_]+__".to_string(),
+            max_output_bytes: Some(100),
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo __A__; i=0; while [ $i -lt 200 ]; do echo noise; i=$((i+1)); done; echo __B__"
+                    .to_string(),
+            ],
+            output_file: Some(output_path.to_string_lossy().to_string()),
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let sources = vec![
+            init_rapl(
+                config.rapl_path.as_deref(),
+                None,
+                Default::default(),
+                &Default::default(),
+                RaplOptions::default(),
+            )
+            .unwrap(),
+        ];
+        let mut manager = SourceManager::new(sources);
+        manager.start_workers().await;
+
+        let result = measure_phases(&mut manager, &config, &phases_config, None)
+            .await
+            .unwrap();
+
+        let output = read_to_string(&output_path).unwrap();
+        assert!(output.len() <= 100);
+        assert!(output.starts_with("__A__\nnoise\n"));
+        assert_eq!(result.exit_code, 0);
+        assert_eq!(result.phases.len(), 3);
+        assert_eq!(result.phases[2].name, "__B__ -> END");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn empty_matching_pattern_is_rejected() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+
+        let phases_config = PhasesConfig {
+            token_pattern: "^(__[A-Z]+__)?".to_string(),
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["echo".to_string(), "hello".to_string()],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let sources = vec![
+            init_rapl(
+                config.rapl_path.as_deref(),
+                None,
+                Default::default(),
+                &Default::default(),
+                RaplOptions::default(),
+            )
+            .unwrap(),
+        ];
+        let mut manager = SourceManager::new(sources);
+        manager.start_workers().await;
+
+        let err = measure_phases(&mut manager, &config, &phases_config, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JouleProfilerError>(),
+            Some(JouleProfilerError::InvalidPattern(_))
+        ));
+        assert!(token_regex("__[A-Z]+__").is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pty_gives_the_command_a_tty() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z0-9_]+__".to_string(),
+            pty: true,
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "if [ -t 1 ]; then echo __TTY__; else echo __NOTTY__; fi".to_string(),
+            ],
+            output_file: Some(dir.path().join("out.txt").to_string_lossy().to_string()),
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let sources = vec![
+            init_rapl(
+                config.rapl_path.as_deref(),
+                None,
+                Default::default(),
+                &Default::default(),
+                RaplOptions::default(),
+            )
+            .unwrap(),
+        ];
+        let mut manager = SourceManager::new(sources);
+        manager.start_workers().await;
+
+        let result = measure_phases(&mut manager, &config, &phases_config, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.phases[0].name, "START -> __TTY__");
+        assert_eq!(result.exit_code, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn command_closing_its_output_early_is_waited_then_stopped() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+
+        let measure = |script: &str, exit_timeout_s: Option<f64>| {
+            let phases_config = PhasesConfig {
+                token_pattern: "__[A-Z0-9_]+__".to_string(),
+                exit_timeout_s,
+                ..Default::default()
+            };
+            let config = ProfileConfig {
+                iterations: 1,
+                cmd: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+                output_file: Some(dir.path().join("out.txt").to_string_lossy().to_string()),
+                rapl_path: Some(dir.path().to_string_lossy().to_string()),
+                ..Default::default()
+            };
+            async move {
+                let sources = vec![
+                    init_rapl(
+                        config.rapl_path.as_deref(),
+                        None,
+                        Default::default(),
+                        &Default::default(),
+                        RaplOptions::default(),
+                    )
+                    .unwrap(),
+                ];
+                let mut manager = SourceManager::new(sources);
+                manager.start_workers().await;
+                measure_phases(&mut manager, &config, &phases_config, None)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Still running after closing stdout, then exits on its own
+        let result = measure("echo __A__; exec >&-; sleep 0.2; exit 3", None).await;
+        assert_eq!(result.exit_code, 3);
+        assert_eq!(result.phases.len(), 2);
+        assert!(result.phases[1].duration_ms >= 200);
+
+        // Stopped once the timeout elapsed
+        let begin = Instant::now();
+        let result = measure("echo __A__; exec >&-; exec sleep 30", Some(0.2)).await;
+        assert!(begin.elapsed() < Duration::from_secs(10));
+        assert_eq!(result.exit_code, 1);
+        assert_eq!(result.phases[0].name, "START -> __A__");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn command_running_past_its_timeout_is_stopped_with_its_phases() {
+        let dir = tempdir().unwrap();
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z0-9_]+__".to_string(),
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo __A__; exec sleep 30".to_string(),
+            ],
+            output_file: Some(dir.path().join("out.txt").to_string_lossy().to_string()),
+            timeout_s: Some(0.2),
+            fake_source: vec![crate::cli::FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 100,
+            }],
+            ..Default::default()
+        };
+
+        let mut manager = init_sources(&config).unwrap();
+        manager.start_workers().await;
+        let begin = Instant::now();
+        let result = measure_phases(&mut manager, &config, &phases_config, None)
+            .await
+            .unwrap();
+
+        assert!(begin.elapsed() < Duration::from_secs(10));
+        assert_eq!(result.exit_code, TIMEOUT_EXIT_CODE);
+        assert_eq!(result.phases.len(), 2);
+        assert_eq!(result.phases[1].metrics[0].value, 100);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tokens_printed_to_stderr_are_detected_with_the_stderr_stream() {
+        let dir = tempdir().unwrap();
+        let stderr_path = dir.path().join("err.txt");
+        let measure = |token_stream| {
+            let phases_config = PhasesConfig {
+                token_pattern: "__[A-Z]+__".to_string(),
+                token_stream,
+                ..Default::default()
+            };
+            let config = ProfileConfig {
+                iterations: 1,
+                cmd: vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "echo __A__ >&2; echo output; echo __B__ >&2".to_string(),
+                ],
+                output_file: Some(dir.path().join("out.txt").to_string_lossy().to_string()),
+                stderr_file: Some(stderr_path.to_string_lossy().to_string()),
+                fake_source: vec![crate::cli::FakeMetric {
+                    name: "PACKAGE-0_0".to_string(),
+                    value: 100,
+                }],
+                ..Default::default()
+            };
+            async move {
+                let mut manager = init_sources(&config).unwrap();
+                manager.start_workers().await;
+                measure_phases(&mut manager, &config, &phases_config, None)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let stdout_only = measure(TokenStream::Stdout).await;
+        assert_eq!(stdout_only.phases.len(), 1);
+
+        let stderr = measure(TokenStream::Stderr).await;
+        let names: Vec<&str> = stderr
+            .phases
+            .iter()
+            .map(|phase| phase.name.as_str())
+            .collect();
+        assert_eq!(names, ["START -> __A__", "__A__ -> __B__", "__B__ -> END"]);
+        assert_eq!(read_to_string(&stderr_path).unwrap(), "__A__\n__B__\n");
+        assert_eq!(
+            read_to_string(dir.path().join("out.txt")).unwrap(),
+            "output\n"
+        );
+
+        let both = measure(TokenStream::Both).await;
+        assert_eq!(both.phases.len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tokens_echoed_from_the_stdin_file_are_detected() {
+        let dir = tempdir().unwrap();
+        let stdin_path = dir.path().join("stdin.txt");
+        std::fs::write(&stdin_path, "__A__\ninput\n__B__\n").unwrap();
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z]+__".to_string(),
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["cat".to_string()],
+            stdin_file: Some(stdin_path.to_string_lossy().to_string()),
+            output_file: Some(dir.path().join("out.txt").to_string_lossy().to_string()),
+            fake_source: vec![crate::cli::FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 100,
+            }],
+            ..Default::default()
+        };
+
+        let mut manager = init_sources(&config).unwrap();
+        manager.start_workers().await;
+        let result = measure_phases(&mut manager, &config, &phases_config, None)
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = result
+            .phases
+            .iter()
+            .map(|phase| phase.name.as_str())
+            .collect();
+        assert_eq!(names, ["START -> __A__", "__A__ -> __B__", "__B__ -> END"]);
+        assert_eq!(result.exit_code, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn live_phases_are_emitted_before_the_command_exits() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z0-9_]+__".to_string(),
+            live: true,
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo __A__; echo __B__; sleep 0.3".to_string(),
+            ],
+            output_file: Some(dir.path().join("out.txt").to_string_lossy().to_string()),
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let sources = vec![
+            init_rapl(
+                config.rapl_path.as_deref(),
+                None,
+                Default::default(),
+                &Default::default(),
+                RaplOptions::default(),
+            )
+            .unwrap(),
+        ];
+        let mut manager = SourceManager::new(sources);
+        let live = LivePhases::new(&mut manager);
+        manager.start_workers().await;
+
+        let result = measure_phases(&mut manager, &config, &phases_config, Some(&live))
+            .await
+            .unwrap();
+        let exited_at = get_timestamp();
+
+        assert_eq!(result.phases.len(), 3);
+        let emitted_at = live.finish().await;
+        assert_eq!(emitted_at.len(), 2);
+        // Both phases were printed while the command was still sleeping
+        assert!(emitted_at.iter().all(|at| exited_at - at >= 200_000));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn live_phase_waits_for_the_sources_off_the_output_loop() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let source = init_rapl(
+            Some(&dir.path().to_string_lossy()),
+            None,
+            Default::default(),
+            &Default::default(),
+            RaplOptions::default(),
+        )
+        .unwrap();
+        // The workers are not started, the source never reports the phase
+        let mut manager = SourceManager::new(vec![source]);
+        let live = LivePhases::new(&mut manager);
+        let phase = |token: PhaseToken| Phase {
+            token,
+            timestamp: get_timestamp(),
+            line_number: None,
+        };
+
+        let begin = Instant::now();
+        live.emit(&[phase(PhaseToken::Start), phase(PhaseToken::End)]);
+        assert!(begin.elapsed() < LIVE_PHASE_TIMEOUT / 2);
+
+        // Printed once the wait for the source timed out
+        assert_eq!(live.finish().await.len(), 1);
+        assert!(begin.elapsed() >= LIVE_PHASE_TIMEOUT);
+    }
+
+    #[test]
+    fn live_line_lists_sorted_metrics() {
+        let phase = |token: PhaseToken, timestamp| Phase {
+            token,
+            timestamp,
+            line_number: None,
+        };
+        let metric = |name: &str, value| Metric {
+            name: name.to_string(),
+            value,
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        };
+
+        let line = live_line(
+            &phase(PhaseToken::Start, 1_000),
+            &phase(PhaseToken::Token("__A__".to_string()), 6_000),
+            &[metric("PACKAGE-0_0", 30), metric("CORE_0", 10)],
+        );
+        assert_eq!(
+            line,
+            "[phase] START -> __A__ (5 ms): CORE_0=10 µJ, PACKAGE-0_0=30 µJ"
+        );
+    }
+
+    #[test]
+    fn parse_timings_file() {
+        let timings =
+            parse_phase_timings(r#"[{"name": "load", "timestamp_us": 1700000000000000}]"#).unwrap();
+        assert_eq!(
+            timings,
+            vec![PhaseTiming {
+                name: "load".to_string(),
+                timestamp_us: 1_700_000_000_000_000,
+            }]
+        );
+
+        // A file still being written is retried later
+        assert_eq!(parse_phase_timings(r#"[{"name": "lo"#), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn timings_file_drives_phases() {
+        let dir = tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let timings_path = dir.path().join("timings.json");
+
+        let script = format!(
+            "sleep 0.02; printf '[{{\"name\":\"load\",\"timestamp_us\":%s}}]' $(date +%s%6N) > {}; sleep 0.02",
+            timings_path.display()
+        );
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z0-9_]+__".to_string(),
+            phase_timings: Some(timings_path.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sh".to_string(), "-c".to_string(), script],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let sources = vec![
+            init_rapl(
+                config.rapl_path.as_deref(),
+                None,
+                Default::default(),
+                &Default::default(),
+                RaplOptions::default(),
+            )
+            .unwrap(),
+        ];
+        let mut manager = SourceManager::new(sources);
+        manager.start_workers().await;
+
+        let result = measure_phases(&mut manager, &config, &phases_config, None)
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = result.phases.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["START -> load", "load -> END"]);
+        assert_eq!(result.exit_code, 0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+
+use crate::{
+    command::show::{ShownResult, parse_result},
+    config::ProfileConfig,
+    error::JouleProfilerError,
+    measurement::MeasurementResult,
+};
+
+/// Runs a shell script on a remote host and returns its stdout.
+pub trait RemoteTransport {
+    fn run(&mut self, host: &str, script: &str) -> Result<String>;
+}
+
+/// Runs the remote scripts with the `ssh` client, its stderr is shown locally.
+pub struct SshTransport;
+
+impl RemoteTransport for SshTransport {
+    fn run(&mut self, host: &str, script: &str) -> Result<String> {
+        let output = Command::new("ssh")
+            .arg(host)
+            .arg(script)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| {
+                JouleProfilerError::CommandExecutionFailed(format!("ssh {}: {}", host, e))
+            })?;
+
+        debug!("ssh {} exited with {}", host, output.status);
+        String::from_utf8(output.stdout).context("Remote output is not valid UTF-8")
+    }
+}
+
+/// Measure the command on the remote host with the joule-profiler installed there.
+pub fn measure_remote(
+    transport: &mut impl RemoteTransport,
+    host: &str,
+    config: &ProfileConfig,
+) -> Result<Vec<MeasurementResult>> {
+    info!("Measuring {:?} on {}", config.cmd, host);
+
+    let output = transport.run(host, &remote_script(config))?;
+    let (_, result) = parse_result(&output)?;
+
+    match result {
+        ShownResult::Simple(result) => Ok(vec![result]),
+        ShownResult::SimpleIterations(results) => Ok(results),
+        _ => Err(JouleProfilerError::InvalidJsonFormat(format!(
+            "expected simple mode results from {}",
+            host
+        ))
+        .into()),
+    }
+}
+
+/// Script running joule-profiler on the remote host and printing only its JSON results.
+///
+/// The program output and the remote messages are sent to stderr so stdout only holds
+/// the JSON file, the exit status of joule-profiler is kept.
+fn remote_script(config: &ProfileConfig) -> String {
+    let mut args = vec![
+        "joule-profiler".to_string(),
+        "simple".to_string(),
+        "--json".to_string(),
+        "--jouleit-file".to_string(),
+        "\"$f\"".to_string(),
+        "-n".to_string(),
+        config.iterations.max(1).to_string(),
+    ];
+    if let Some(sockets) = &config.sockets {
+        let mut sockets: Vec<_> = sockets.iter().collect();
+        sockets.sort();
+        let sockets: Vec<_> = sockets.iter().map(|socket| socket.to_string()).collect();
+        // Global option, placed before the subcommand
+        args.insert(1, format!("--sockets {}", sockets.join(",")));
+    }
+    if let Some(rate) = config.rapl_polling {
+        args.push(format!("--rapl-polling {}", rate));
+    }
+    if config.shell {
+        args.push("--shell".to_string());
+    }
+    args.push("--".to_string());
+    args.extend(config.cmd.iter().map(|arg| shell_quote(arg)));
+
+    format!(
+        "f=$(mktemp) && {} >&2; status=$?; cat \"$f\"; rm -f \"$f\"; exit $status",
+        args.join(" ")
+    )
+}
+
+/// Quote an argument for a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns crafted JSON instead of connecting to a host.
+    struct FakeTransport {
+        output: String,
+        scripts: Vec<(String, String)>,
+    }
+
+    impl RemoteTransport for FakeTransport {
+        fn run(&mut self, host: &str, script: &str) -> Result<String> {
+            self.scripts.push((host.to_string(), script.to_string()));
+            Ok(self.output.clone())
+        }
+    }
+
+    fn config(iterations: usize) -> ProfileConfig {
+        ProfileConfig {
+            iterations,
+            cmd: vec!["./bench".to_string(), "it's".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn remote_iterations_are_parsed_from_json() {
+        let mut transport = FakeTransport {
+            output: r#"{
+                "command": "./bench it's",
+                "mode": "simple-iterations",
+                "iterations": [
+                    {
+                        "iteration": 1,
+                        "metrics": [
+                            {"name": "PACKAGE-0_0", "value": 1200, "unit": "µJ", "source": "powercap"}
+                        ],
+                        "duration_ms": 15,
+                        "exit_code": 0,
+                        "measure_count": 2,
+                        "measure_delta": 0,
+                        "failed_sources": []
+                    },
+                    {
+                        "iteration": 2,
+                        "metrics": [
+                            {"name": "PACKAGE-0_0", "value": 1300, "unit": "µJ", "source": "powercap"}
+                        ],
+                        "duration_ms": 16,
+                        "exit_code": 0,
+                        "measure_count": 2,
+                        "measure_delta": 0,
+                        "failed_sources": []
+                    }
+                ]
+            }"#
+            .to_string(),
+            scripts: Vec::new(),
+        };
+
+        let results = measure_remote(&mut transport, "bench@lab", &config(2)).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].metrics[0].name, "PACKAGE-0_0");
+        assert_eq!(results[1].metrics[0].value, 1300);
+        assert_eq!(results[1].duration_ms, 16);
+
+        let (host, script) = &transport.scripts[0];
+        assert_eq!(host, "bench@lab");
+        assert!(script.contains("joule-profiler simple --json --jouleit-file \"$f\" -n 2"));
+        assert!(script.contains(r"-- './bench' 'it'\''s'"));
+    }
+
+    #[test]
+    fn remote_phases_results_are_rejected() {
+        let mut transport = FakeTransport {
+            output: r#"{"command": "./bench", "mode": "phases", "exit_code": 0, "phases": [], "failed_sources": []}"#
+                .to_string(),
+            scripts: Vec::new(),
+        };
+
+        assert!(measure_remote(&mut transport, "lab", &config(1)).is_err());
+    }
+}
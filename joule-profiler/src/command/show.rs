@@ -0,0 +1,207 @@
+use std::fs::read_to_string;
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+    config::{OutputFormat, ProfileConfig, ShowConfig},
+    error::JouleProfilerError,
+    measurement::{MeasurementResult, PhaseMeasurementResult},
+    output::{Displayer, OutputFormatTrait},
+    util::file::FileMode,
+};
+
+/// Measurement results read back from a JSON output.
+pub enum ShownResult {
+    Simple(MeasurementResult),
+    SimpleIterations(Vec<MeasurementResult>),
+    Phases(PhaseMeasurementResult),
+    PhasesIterations(Vec<PhaseMeasurementResult>),
+}
+
+pub fn run_show(config: &ShowConfig) -> Result<()> {
+    info!("Showing result file {}", config.file);
+
+    let content =
+        read_to_string(&config.file).with_context(|| format!("Failed to read {}", config.file))?;
+    let (command, result) = parse_result(&content)?;
+
+    let profile_config = ProfileConfig {
+        cmd: command.split_whitespace().map(String::from).collect(),
+        precision: config.precision,
+        ..Default::default()
+    };
+    let mut displayer = Displayer::new(&OutputFormat::Terminal, None, FileMode::default(), false)?;
+
+    match &result {
+        ShownResult::Simple(result) => displayer.simple_single(&profile_config, result),
+        ShownResult::SimpleIterations(results) => {
+            displayer.simple_iterations(&profile_config, results)
+        }
+        ShownResult::Phases(result) => displayer.phases_single(&profile_config, result),
+        ShownResult::PhasesIterations(results) => {
+            displayer.phases_iterations(&profile_config, results)
+        }
+    }
+}
+
+/// Rebuild the command and the measurement results of a JSON output from its `mode`.
+pub fn parse_result(content: &str) -> Result<(String, ShownResult)> {
+    let mut root: Value = serde_json::from_str(content)
+        .map_err(|e| JouleProfilerError::InvalidJsonFormat(e.to_string()))?;
+
+    let command = root["command"].as_str().unwrap_or_default().to_string();
+    let mode = root["mode"].as_str().unwrap_or_default().to_string();
+
+    let result = match mode.as_str() {
+        "simple" => ShownResult::Simple(from_value(root)?),
+        "simple-iterations" => {
+            ShownResult::SimpleIterations(from_value(root["iterations"].take())?)
+        }
+        "phases" => ShownResult::Phases(from_value(root)?),
+        "phases-iterations" => {
+            ShownResult::PhasesIterations(from_value(root["iterations"].take())?)
+        }
+        _ => {
+            return Err(JouleProfilerError::InvalidJsonFormat(format!(
+                "cannot show results of mode '{}'",
+                mode
+            ))
+            .into());
+        }
+    };
+
+    Ok((command, result))
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T> {
+    serde_json::from_value(value)
+        .map_err(|e| JouleProfilerError::InvalidJsonFormat(e.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        config::{Mode, PhasesConfig},
+        measurement::{PhaseResult, PhaseToken},
+        test_util::metric,
+    };
+
+    fn write_json(write: impl FnOnce(&mut Displayer)) -> String {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("result.json").to_string_lossy().to_string();
+
+        let mut displayer =
+            Displayer::new(&OutputFormat::Json, Some(&path), FileMode::default(), false).unwrap();
+        write(&mut displayer);
+
+        read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn simple_iterations_round_trip_through_json() {
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string(), "--fast".to_string()],
+            ..Default::default()
+        };
+        let result = MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", 1_000), metric("DRAM_0", 200)],
+            duration_ms: 42,
+            exit_code: 3,
+            measure_count: 5,
+            measure_delta: 10,
+            read_spread_us: 7,
+            ..Default::default()
+        };
+
+        let json = write_json(|displayer| {
+            displayer
+                .simple_iterations(&config, &[result.clone(), result.clone()])
+                .unwrap()
+        });
+        let (command, shown) = parse_result(&json).unwrap();
+
+        assert_eq!(command, "./bench --fast");
+        let ShownResult::SimpleIterations(results) = shown else {
+            panic!("expected simple iterations");
+        };
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].metrics[0].name, "PACKAGE-0_0");
+        assert_eq!(results[1].metrics[0].value, 1_000);
+        assert_eq!(results[1].metrics[1].value, 200);
+        assert_eq!(results[1].duration_ms, 42);
+        assert_eq!(results[1].exit_code, 3);
+        assert_eq!(results[1].read_spread_us, 7);
+
+        let mut displayer =
+            Displayer::new(&OutputFormat::Terminal, None, FileMode::default(), false).unwrap();
+        displayer.simple_iterations(&config, &results).unwrap();
+    }
+
+    #[test]
+    fn phases_iterations_round_trip_through_json() {
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            mode: Mode::PhaseMode(PhasesConfig {
+                token_pattern: "__[A-Z]+__".to_string(),
+                describe_phases: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let parse = PhaseToken::Token("__PARSE__".to_string());
+        let result = PhaseMeasurementResult {
+            phases: vec![
+                PhaseResult::new(
+                    &PhaseToken::Start,
+                    &parse,
+                    None,
+                    Some(4),
+                    vec![metric("PACKAGE-0_0", 10)],
+                    5,
+                ),
+                PhaseResult::new(
+                    &parse,
+                    &PhaseToken::End,
+                    Some(4),
+                    None,
+                    vec![metric("PACKAGE-0_0", 30)],
+                    7,
+                ),
+            ],
+            duration_ms: 12,
+            ..Default::default()
+        };
+
+        let json = write_json(|displayer| {
+            displayer
+                .phases_iterations(&config, std::slice::from_ref(&result))
+                .unwrap()
+        });
+        let (_, shown) = parse_result(&json).unwrap();
+
+        let ShownResult::PhasesIterations(results) = shown else {
+            panic!("expected phases iterations");
+        };
+        assert_eq!(results[0].duration_ms, 12);
+        assert_eq!(results[0].phases.len(), 2);
+        assert_eq!(results[0].phases[0].name, "START -> __PARSE__");
+        assert_eq!(results[0].phases[0].end_line, Some(4));
+        assert_eq!(
+            results[0].phases[1].start_token.as_deref(),
+            Some("__PARSE__")
+        );
+        assert_eq!(results[0].phases[1].metrics[0].value, 30);
+    }
+
+    #[test]
+    fn unsupported_mode_is_rejected() {
+        let json = r#"{"command": "./bench", "mode": "calibrate", "rates": []}"#;
+        assert!(parse_result(json).is_err());
+    }
+}
@@ -1,31 +1,80 @@
-use anyhow::Result;
-use log::{debug, info};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    process::Child,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use regex::Regex;
 
 use crate::{
-    command::run_command,
+    cli::Rounding,
+    command::{
+        CommandTimer, TIMEOUT_EXIT_CODE, check_fail_fast, command_exit_code, echo_line,
+        init_sources, open_output_file, output_lines,
+        remote::{SshTransport, measure_remote},
+        run_command, run_post_hook, spawn_captured, spawn_command, stop_command, terminate_command,
+        write_total,
+    },
     config::ProfileConfig,
-    measurement::MeasurementResult,
-    output::{Displayer, OutputFormatTrait},
-    source::{Metric, SourceManager, rapl::init_rapl},
-    util::time::get_timestamp,
+    error::JouleProfilerError,
+    measurement::{
+        CalibrationResult, MeasurementResult, PowerStates, container_energy,
+        cpu_utilization_metric, divide_rounded, energy_per_result, energy_totals, energy_uj,
+        is_energy, is_idle_build, ops_per_watt_metric, package_energy_uj, ratio_metrics,
+        resample_intervals, score_metric, thread_energy, what_if_cap_metric,
+    },
+    output::{
+        Displayer, OutputFormatTrait,
+        otlp::{export_to_collector, simple_points},
+        time_series::{write_samples, write_time_series},
+    },
+    source::{
+        Metric, PowerSample, SourceManager, TimeBin,
+        cgroup::{cpu_share, cpu_utilization, read_cpu_ticks, read_cpu_usage},
+        proc::{ThreadCpuTime, ThreadSampler},
+    },
+    util::{
+        interrupt::interrupted,
+        page_cache::{DROP_CACHES_PATH, drop_page_cache},
+        stats::step_levels,
+        time::get_timestamp,
+    },
 };
 
-pub async fn run_simple(config: &ProfileConfig) -> Result<()> {
-    info!("Running simple mode");
+/// First period of the measures taken with --simple-samples.
+const SIMPLE_SAMPLES_PERIOD: Duration = Duration::from_millis(10);
 
-    let sources = vec![init_rapl(
-        config.rapl_path.as_deref(),
-        config.sockets.as_ref(),
-        config.rapl_polling,
-    )?];
-    let mut manager = SourceManager::new(sources);
+/// Interval between two samples of the threads CPU time with --per-thread.
+const THREAD_SAMPLING_INTERVAL: Duration = Duration::from_millis(10);
 
-    let mut results = Vec::new();
+/// Interval between two checks of the command exit in a measurement window.
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
-    debug!("Simple mode with {} iteration(s)", config.iterations);
-    for _ in 0..config.iterations {
-        manager.start_workers().await;
-        results.push(measure_simple(&mut manager, config).await?);
+/// Ratio of the high to the low power level above which --detect-turbo reports power steps.
+const TURBO_STEP_RATIO: f64 = 1.25;
+
+/// Runs of a no-op command averaged to estimate the profiler overhead.
+const OVERHEAD_RUNS: usize = 3;
+
+/// Measure and display the command in simple mode, returning its exit code.
+pub async fn run_simple(config: &ProfileConfig) -> Result<i32> {
+    let results = collect_simple(config).await?;
+
+    let exit_codes = results.iter().map(|result| result.exit_code);
+    if let Some(unit) = config.print_total {
+        let packages_uj: Vec<u64> = results
+            .iter()
+            .map(|result| package_energy_uj(&result.metrics))
+            .collect();
+        write_total(&mut std::io::stdout(), unit, config.precision, &packages_uj)?;
+        check_fail_fast(config, exit_codes.clone())?;
+        return Ok(command_exit_code(exit_codes));
     }
 
     let mut displayer = Displayer::try_from(config)?;
@@ -34,36 +83,1035 @@ pub async fn run_simple(config: &ProfileConfig) -> Result<()> {
     } else {
         displayer.simple_single(config, &results[0])?;
     }
+
+    export_to_collector(config, simple_points(config, &results)).await;
+    let total_uj = results.iter().map(MeasurementResult::total_energy_uj).sum();
+    run_post_hook(
+        config,
+        displayer.results_path(),
+        command_exit_code(exit_codes.clone()),
+        total_uj,
+    )
+    .await;
+    check_fail_fast(config, exit_codes.clone())?;
+    Ok(command_exit_code(exit_codes))
+}
+
+/// Measure the command in simple mode, locally or on the remote host, without displaying
+/// the results.
+pub async fn collect_simple(config: &ProfileConfig) -> Result<Vec<MeasurementResult>> {
+    info!("Running simple mode");
+
+    match &config.remote {
+        Some(host) => measure_remote(&mut SshTransport, host, config),
+        None => measure_simple_iterations(config).await,
+    }
+}
+
+/// Run all the configured iterations of the command and return their measurements.
+pub async fn measure_simple_iterations(config: &ProfileConfig) -> Result<Vec<MeasurementResult>> {
+    let mut manager = init_sources(config)?;
+
+    let idle = match config.idle_sample_s {
+        Some(sample_s) => Some(measure_idle(&mut manager, sample_s).await?),
+        None => None,
+    };
+    let overhead = if config.subtract_overhead {
+        measure_overhead(&mut manager, config, idle.as_ref()).await?
+    } else {
+        Vec::new()
+    };
+
+    let mut results = Vec::new();
+    let mut time_series = Vec::new();
+    let mut samples = Vec::new();
+
+    debug!("Simple mode with {} iteration(s)", config.iterations);
+    let mut stop = config.stop_condition();
+    let mut drop_caches = config.drop_caches;
+    loop {
+        // Before the iteration time, the write back of the dirty pages is not measured
+        if drop_caches {
+            drop_caches = drop_page_cache(Path::new(DROP_CACHES_PATH));
+        }
+
+        let begin = Instant::now();
+        let mut attempts = 1;
+        let (mut result, bins, iteration_samples) = loop {
+            // The workers are started again as the sources state is per run
+            manager.start_workers().await;
+            let (result, bins, iteration_samples) =
+                measure_simple(&mut manager, config, &overhead, idle.as_ref()).await?;
+            if result.exit_code == 0 || attempts > config.retries {
+                break (result, bins, iteration_samples);
+            }
+            info!(
+                "Attempt {} exited with code {}, discarding it and retrying ({} of {} retries)",
+                attempts, result.exit_code, attempts, config.retries
+            );
+            attempts += 1;
+        };
+        result.attempts = attempts;
+        stop.record(begin.elapsed().as_millis(), result.total_energy_uj());
+        if config.fail_fast && result.exit_code != 0 {
+            stop.fail();
+        }
+        results.push(result);
+        time_series.push(bins);
+        samples.push(iteration_samples);
+
+        if interrupted() {
+            stop.interrupt();
+        }
+        if let Some(reason) = stop.reached() {
+            info!(
+                "Stopping after {} iteration(s): {}",
+                stop.iterations(),
+                reason
+            );
+            break;
+        }
+    }
+
+    if let Some(path) = &config.time_series {
+        write_time_series(
+            path,
+            config.time_series_bin_s,
+            &time_series,
+            config.file_mode,
+        )?;
+    }
+    if let Some(path) = &config.samples_file {
+        write_samples(path, &samples, config.file_mode)?;
+    }
+    if config.detect_turbo {
+        let states = power_states(&time_series, config.time_series_bin_s);
+        for (result, states) in results.iter_mut().zip(states) {
+            result.power_states = states;
+        }
+        write_power_states(&mut std::io::stderr(), &results)?;
+    }
+
+    Ok(results)
+}
+
+/// Power states of each package metric with two power levels over the time series bins,
+/// for each iteration, e.g. with turbo boost.
+///
+/// The levels are split over the bins of all the iterations so that the fractions compare
+/// between iterations. The last bin of an iteration, shorter than the others, is ignored.
+fn power_states(time_series: &[Vec<TimeBin>], bin_s: f64) -> Vec<BTreeMap<String, PowerStates>> {
+    let mut power: BTreeMap<&str, Vec<Vec<f64>>> = BTreeMap::new();
+    for (idx, bins) in time_series.iter().enumerate() {
+        let full_bins = &bins[..bins.len().saturating_sub(1)];
+        for metric in full_bins.iter().flat_map(|bin| &bin.metrics) {
+            let Some(energy_uj) = energy_uj(metric).filter(|_| metric.name.starts_with("PACKAGE"))
+            else {
+                continue;
+            };
+            let iterations = power.entry(&metric.name).or_default();
+            iterations.resize_with(time_series.len(), Vec::new);
+            iterations[idx].push(energy_uj as f64 / 1e6 / bin_s);
+        }
+    }
+
+    let mut states = vec![BTreeMap::new(); time_series.len()];
+    for (name, iterations) in power {
+        let all: Vec<f64> = iterations.concat();
+        let Some(levels) = step_levels(&all, TURBO_STEP_RATIO) else {
+            debug!("No power steps for {}", name);
+            continue;
+        };
+        for (iteration_states, iteration) in states.iter_mut().zip(&iterations) {
+            let (high_fraction, steps) = levels.occupancy(iteration);
+            iteration_states.insert(
+                name.to_string(),
+                PowerStates {
+                    low_power_w: levels.low,
+                    high_power_w: levels.high,
+                    high_fraction,
+                    steps,
+                },
+            );
+        }
+    }
+    states
+}
+
+/// Write the power levels of each package metric with power steps and the fraction of time
+/// of each iteration in the high-power state.
+fn write_power_states(out: &mut impl Write, results: &[MeasurementResult]) -> Result<()> {
+    let Some(first) = results.first() else {
+        return Ok(());
+    };
+
+    for (name, levels) in &first.power_states {
+        writeln!(
+            out,
+            "{}: power steps between {:.1} W and {:.1} W, consistent with turbo boost",
+            name, levels.low_power_w, levels.high_power_w
+        )?;
+        for (idx, result) in results.iter().enumerate() {
+            let Some(states) = result.power_states.get(name) else {
+                continue;
+            };
+            writeln!(
+                out,
+                "  iteration {}: {:.0} % of the time in the high-power state ({} steps)",
+                idx + 1,
+                states.high_fraction * 100.0,
+                states.steps
+            )?;
+        }
+    }
     Ok(())
 }
 
+/// Estimate the fixed energy cost of the profiler (fork/exec and measurements) by measuring
+/// a no-op command, averaged over a few runs. The idle energy is subtracted as from the
+/// command, so that it is not subtracted twice.
+async fn measure_overhead(
+    manager: &mut SourceManager,
+    config: &ProfileConfig,
+    idle: Option<&IdleBaseline>,
+) -> Result<Vec<Metric>> {
+    // Spawned like the command but run once, without a measurement window, a batch, a
+    // sampling of the run nor derived metrics, which are not part of the fixed cost
+    let noop_config = ProfileConfig {
+        cmd: vec!["true".to_string()],
+        shell: false,
+        output_file: None,
+        stderr_file: None,
+        stdin_file: None,
+        result_pattern: None,
+        ops_per_watt: false,
+        start_delay_s: None,
+        duration_s: None,
+        timeout_s: None,
+        min_duration_s: None,
+        simple_samples: None,
+        per_thread: false,
+        cgroup: None,
+        build_mode: false,
+        idle_sample_s: None,
+        retries: 0,
+        debug_diff: false,
+        raw_counters: false,
+        what_if_cap_w: None,
+        ratio_to: None,
+        score: Vec::new(),
+        totals: false,
+        ..config.clone()
+    };
+
+    let mut results = Vec::new();
+    for _ in 0..OVERHEAD_RUNS {
+        manager.start_workers().await;
+        let (result, _, _) = measure_simple(manager, &noop_config, &[], idle).await?;
+        results.push(result);
+    }
+
+    let overhead = CalibrationResult::from_iterations(None, &results, config.rounding()).metrics;
+    for metric in &overhead {
+        info!(
+            "Profiler overhead of {}: {} {}",
+            metric.name, metric.value, metric.unit
+        );
+    }
+    Ok(overhead)
+}
+
+/// Subtract the profiler overhead from the energy metrics of the same name, clamped to zero.
+fn subtract_overhead(metrics: &mut [Metric], overhead: &[Metric]) {
+    for metric in metrics.iter_mut().filter(|metric| is_energy(metric)) {
+        if let Some(cost) = overhead.iter().find(|cost| cost.name == metric.name) {
+            metric.value = metric.value.saturating_sub(cost.value);
+        }
+    }
+}
+
+/// Idle power of the machine per metric, measured before the iterations with --idle-sample.
+struct IdleBaseline {
+    /// Idle rate of each energy metric, in its unit per second (µJ/s for RAPL)
+    rates: BTreeMap<String, f64>,
+}
+
+impl IdleBaseline {
+    fn new(metrics: &[Metric], sample_s: f64) -> Self {
+        let rates = metrics
+            .iter()
+            .filter(|metric| is_energy(metric))
+            .map(|metric| (metric.name.clone(), metric.value as f64 / sample_s))
+            .collect();
+        Self { rates }
+    }
+
+    /// Subtract the idle energy over the duration from the energy metrics, clamped to zero.
+    fn subtract(&self, metrics: &mut [Metric], duration_ms: u128) {
+        let duration_s = duration_ms as f64 / 1000.0;
+        for metric in metrics.iter_mut().filter(|metric| is_energy(metric)) {
+            if let Some(rate) = self.rates.get(&metric.name) {
+                let idle = (rate * duration_s).round() as u64;
+                metric.value = metric.value.saturating_sub(idle);
+            }
+        }
+    }
+}
+
+/// Measure the energy of the idle machine over the sample window to establish its idle
+/// power, before the command runs.
+async fn measure_idle(manager: &mut SourceManager, sample_s: f64) -> Result<IdleBaseline> {
+    info!("Sampling the idle power for {} s", sample_s);
+    manager.start_workers().await;
+    manager.start().await?;
+
+    let begin = Instant::now();
+    manager.measure().await?;
+    tokio::time::sleep(Duration::from_secs_f64(sample_s)).await;
+    manager.measure().await?;
+    let elapsed_s = begin.elapsed().as_secs_f64();
+
+    let result = manager.join().await?;
+    let metrics: Vec<Metric> = result.measures.into_iter().flatten().collect();
+    let baseline = IdleBaseline::new(&metrics, elapsed_s);
+    for (name, rate) in &baseline.rates {
+        info!("Idle power of {}: {:.0} per second", name, rate);
+    }
+    Ok(baseline)
+}
+
+/// Measure one run of the command, with its energy time series and polling samples if
+/// enabled.
+///
+/// The overhead and the idle energy are subtracted from the measured metrics before the
+/// derived ones are computed.
 async fn measure_simple(
     manager: &mut SourceManager,
     config: &ProfileConfig,
-) -> Result<MeasurementResult> {
+    overhead: &[Metric],
+    idle: Option<&IdleBaseline>,
+) -> Result<(MeasurementResult, Vec<TimeBin>, Vec<PowerSample>)> {
+    let cgroup = config.cgroup.as_deref().map(Path::new);
+
+    // With a measurement window the command starts first, the energy of the delay is not counted
+    let mut window_child = None;
+    let mut timeout = None;
+    if config.start_delay_s.is_some() || config.duration_s.is_some() {
+        manager.pause().await?;
+        window_child = Some(spawn_command(config)?);
+        timeout = config
+            .timeout_s
+            .map(|timeout_s| Instant::now() + Duration::from_secs_f64(timeout_s));
+        if let Some(delay_s) = config.start_delay_s {
+            info!("Waiting {} s before measuring", delay_s);
+            tokio::time::sleep(Duration::from_secs_f64(delay_s)).await;
+        }
+    }
+
     manager.start().await?;
 
     let begin_time = get_timestamp();
+    let begin_usage = cgroup.map(read_cpu_usage).transpose()?;
+    let begin_ticks = config.build_mode.then(read_cpu_ticks).transpose()?;
 
     manager.measure().await?;
 
-    let (exit_code, _) = run_command(&config.cmd, config.output_file.as_ref())?;
+    let mut threads = Vec::new();
+    let mut repetitions = 1;
+    let (exit_code, result_value) = match (&mut window_child, &config.result_pattern) {
+        (Some(child), _) => (wait_window(child, config.duration_s, timeout).await?, None),
+        // The capturing runs block on the output, off the runtime threads of the workers
+        (None, Some(pattern)) => {
+            let (config, pattern) = (config.clone(), pattern.clone());
+            tokio::task::spawn_blocking(move || run_command_with_result(&config, &pattern))
+                .await??
+        }
+        (None, None) if config.per_thread => {
+            let blocking_config = config.clone();
+            let (exit_code, sampled) =
+                tokio::task::spawn_blocking(move || run_command_sampling_threads(&blocking_config))
+                    .await??;
+            threads = sampled;
+            (exit_code, None)
+        }
+        (None, None) if config.simple_samples.is_some() => {
+            (run_command_measuring(manager, config).await?, None)
+        }
+        (None, None) => {
+            let (exit_code, runs) = run_command_batch(config).await?;
+            repetitions = runs;
+            (exit_code, None)
+        }
+    };
 
     manager.measure().await?;
 
+    let end_usage = cgroup.map(read_cpu_usage).transpose()?;
+    let end_ticks = config.build_mode.then(read_cpu_ticks).transpose()?;
     let end_time = get_timestamp();
 
+    if let Some(child) = window_child {
+        stop_command(child)?;
+    }
+
     let result = manager.join().await?;
+    if config.debug_diff {
+        eprint!("{}", raw_counters_report(&result.raw_counters));
+    }
 
+    let mut duration_ms = (end_time - begin_time) / 1000;
     let mut metrics: Vec<Metric> = result.measures.into_iter().flatten().collect();
+    if let Some(idle) = idle {
+        idle.subtract(&mut metrics, duration_ms);
+    }
+    if repetitions > 1 {
+        debug!("Dividing the energy of {} runs", repetitions);
+        per_run(&mut metrics, repetitions, config.rounding());
+        duration_ms = divide_rounded(duration_ms, repetitions.into(), config.rounding());
+    }
+    // The overhead is the one of a single run, every run of a batch paid it
+    subtract_overhead(&mut metrics, overhead);
+    if let Some(reference) = &config.ratio_to {
+        metrics.extend(ratio_metrics(&metrics, reference, config.rounding()));
+    }
+    if let (Some(begin), Some(end)) = (begin_usage, end_usage) {
+        let share = cpu_share(&begin, &end);
+        // A share of the measured energy, rounded to the nearest unless asked otherwise
+        let rounding = config.rounding.unwrap_or(Rounding::Nearest);
+        metrics.push(container_energy(&metrics, share, rounding));
+    }
+    if let (Some(begin), Some(end)) = (begin_ticks, end_ticks) {
+        let utilization = cpu_utilization(&begin, &end);
+        if is_idle_build(utilization) {
+            warn!(
+                "The CPU was idle {:.0} % of the build, I/O stalls dilute the energy attributed \
+                 to the build",
+                (1.0 - utilization) * 100.0
+            );
+        }
+        metrics.push(cpu_utilization_metric(utilization));
+    }
+    if config.per_thread {
+        metrics.extend(thread_energy(&metrics, &threads, config.rounding()));
+    }
+    if !config.score.is_empty() {
+        metrics.push(score_metric(&metrics, &config.score, config.rounding())?);
+    }
+    if config.totals {
+        metrics.extend(energy_totals(&metrics));
+    }
+    if let Some(metric) =
+        result_value.and_then(|value| energy_per_result(&metrics, value, config.rounding()))
+    {
+        metrics.push(metric);
+    }
+    if let Some(metric) = result_value
+        .filter(|_| config.ops_per_watt)
+        .and_then(|value| ops_per_watt_metric(&metrics, value, duration_ms, config.rounding()))
+    {
+        metrics.push(metric);
+    }
+    if let Some(metric) = config
+        .what_if_cap_w
+        .and_then(|cap_w| what_if_cap_metric(&metrics, duration_ms, cap_w, config.rounding()))
+    {
+        metrics.push(metric);
+    }
     metrics.sort_by_key(|metric| metric.name.clone());
-    let duration_ms = (end_time - begin_time) / 1000;
 
-    Ok(MeasurementResult {
+    let measurement = MeasurementResult {
         exit_code,
+        attempts: 1,
+        repetitions,
         duration_ms,
         measure_count: result.count,
         metrics,
         measure_delta: result.measure_delta,
-    })
+        read_spread_us: result.max_read_spread_us,
+        failed_sources: result.failed_sources,
+        result_value,
+        polling: result.polling,
+        power_range: result.power_range,
+        raw: if config.raw_counters {
+            result.raw_readings
+        } else {
+            BTreeMap::new()
+        },
+        intervals: config
+            .simple_samples
+            .map(|count| resample_intervals(&result.samples, count))
+            .unwrap_or_default(),
+        counter_wraps: result.counter_wraps.iter().sum(),
+        // Split over the time series of all the iterations once they are measured
+        power_states: BTreeMap::new(),
+    };
+    Ok((measurement, result.time_series, result.samples))
+}
+
+/// Run the command back to back until the batch lasted `--min-duration`, once without it,
+/// returning the exit code of the last run and the number of runs. A failed run ends the
+/// batch.
+async fn run_command_batch(config: &ProfileConfig) -> Result<(i32, u32)> {
+    let Some(min_duration_s) = config.min_duration_s else {
+        return Ok((run_command(config).await?.0, 1));
+    };
+
+    let min_duration = Duration::from_secs_f64(min_duration_s);
+    let begin = Instant::now();
+    let mut runs = 0;
+    loop {
+        let exit_code = run_command(config).await?.0;
+        runs += 1;
+        if exit_code != 0 || begin.elapsed() >= min_duration || interrupted() {
+            if runs > 1 {
+                info!("Ran the command {} times in {:?}", runs, begin.elapsed());
+            }
+            return Ok((exit_code, runs));
+        }
+    }
+}
+
+/// Run the command while measuring the sources on a timer for `--simple-samples`, the
+/// timer period doubling every few measures so that long runs are not measured more often
+/// than needed to split them in equal intervals.
+async fn run_command_measuring(manager: &SourceManager, config: &ProfileConfig) -> Result<i32> {
+    let measures_per_period = 4 * config.simple_samples.unwrap_or(1).max(1);
+    let mut period = SIMPLE_SAMPLES_PERIOD;
+    let mut measures = 0;
+
+    let run = run_command(config);
+    tokio::pin!(run);
+    loop {
+        tokio::select! {
+            result = &mut run => return Ok(result?.0),
+            _ = tokio::time::sleep(period) => {
+                manager.measure().await?;
+                measures += 1;
+                if measures % measures_per_period == 0 {
+                    period *= 2;
+                    debug!("Measuring every {:?}", period);
+                }
+            }
+        }
+    }
+}
+
+/// Divide the energy metrics of a batch by its number of runs, the energy of one run. The
+/// other metrics (memory peaks, CPU times...) are left as those of the whole batch.
+fn per_run(metrics: &mut [Metric], runs: u32, rounding: Rounding) {
+    for metric in metrics.iter_mut().filter(|metric| is_energy(metric)) {
+        metric.value = divide_rounded(metric.value.into(), runs.into(), rounding) as u64;
+    }
+}
+
+/// List the energy per domain path accumulated by the sources, before the aggregation per
+/// domain name and socket into metrics.
+fn raw_counters_report(raw_counters: &BTreeMap<String, u64>) -> String {
+    let mut report = String::from("Raw energy counters per domain:\n");
+    for (path, value) in raw_counters {
+        report.push_str(&format!("  {} = {} µJ\n", path, value));
+    }
+    report
+}
+
+/// Run the command and extract the last numeric result matched in its output.
+fn run_command_with_result(config: &ProfileConfig, pattern: &str) -> Result<(i32, Option<f64>)> {
+    let regex = Regex::new(pattern)
+        .map_err(|e| JouleProfilerError::InvalidPattern(format!("{}: {}", pattern, e)))?;
+
+    let (mut child, reader) = spawn_captured(config, false)?;
+    let timer = config
+        .timeout_s
+        .map(|timeout_s| CommandTimer::start(child.id(), timeout_s));
+
+    let mut out_file: Option<File> = config
+        .output_file
+        .as_ref()
+        .map(|path| open_output_file(path, config.file_mode))
+        .transpose()?;
+
+    let mut result_value = None;
+    for line in output_lines(reader) {
+        let (line_number, line) = line?;
+        match out_file.as_mut() {
+            None if config.quiet => {}
+            None if config.results_on_stdout() => eprintln!("{}", line),
+            out_file => echo_line(out_file, &line)?,
+        }
+
+        if let Some(value) = parse_result(&regex, &line) {
+            debug!("Result {} found on line {}", value, line_number);
+            result_value = Some(value);
+        }
+    }
+
+    if result_value.is_none() {
+        warn!("No result matching '{}' in the command output", pattern);
+    }
+
+    let Some(timer) = timer else {
+        let status = child.wait().context("Failed to wait on child")?;
+        return Ok((status.code().unwrap_or(1), result_value));
+    };
+
+    // A command still running after closing its output is waited for until the timeout
+    if let Some(remaining) = timer.cancel() {
+        let deadline = Instant::now() + remaining;
+        while Instant::now() < deadline {
+            if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+                return Ok((status.code().unwrap_or(1), result_value));
+            }
+            sleep(WINDOW_POLL_INTERVAL);
+        }
+        warn!("The command is still running after its timeout, stopping it");
+    }
+
+    terminate_command(&mut child)?;
+    Ok((TIMEOUT_EXIT_CODE, result_value))
+}
+
+/// Wait for the command to exit, for the end of the measurement window or for the timeout
+/// of the command, which is then terminated.
+///
+/// Returns the exit code of the command, or 0 if it is still running at the end of the window.
+async fn wait_window(
+    child: &mut Child,
+    duration_s: Option<f64>,
+    timeout: Option<Instant>,
+) -> Result<i32> {
+    let deadline =
+        duration_s.map(|duration_s| Instant::now() + Duration::from_secs_f64(duration_s));
+
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+            if deadline.is_some() {
+                warn!("The command exited before the end of the measurement window");
+            }
+            return Ok(status.code().unwrap_or(1));
+        }
+        if timeout.is_some_and(|timeout| Instant::now() >= timeout) {
+            warn!("The command is still running after its timeout, stopping it");
+            terminate_command(child)?;
+            return Ok(TIMEOUT_EXIT_CODE);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            info!("End of the measurement window");
+            return Ok(0);
+        }
+        tokio::time::sleep(WINDOW_POLL_INTERVAL).await;
+    }
+}
+
+/// Run the command while sampling the CPU time of its threads.
+fn run_command_sampling_threads(config: &ProfileConfig) -> Result<(i32, Vec<ThreadCpuTime>)> {
+    let mut child = spawn_command(config)?;
+    let deadline = config
+        .timeout_s
+        .map(|timeout_s| Instant::now() + Duration::from_secs_f64(timeout_s));
+
+    let mut sampler = ThreadSampler::default();
+    let exit_code = loop {
+        // Sampled before reaping the child, its task directory disappears afterwards
+        sampler.sample(child.id());
+        if let Some(status) = child.try_wait().context("Failed to wait on child")? {
+            break status.code().unwrap_or(1);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            warn!("The command is still running after its timeout, stopping it");
+            terminate_command(&mut child)?;
+            break TIMEOUT_EXIT_CODE;
+        }
+        sleep(THREAD_SAMPLING_INTERVAL);
+    };
+
+    let threads = sampler.into_threads();
+    debug!("Sampled the CPU time of {} thread(s)", threads.len());
+    Ok((exit_code, threads))
+}
+
+/// Parse the number captured by the result pattern, the first capture group or the whole match.
+fn parse_result(regex: &Regex, line: &str) -> Option<f64> {
+    let captures = regex.captures(line)?;
+    let matched = captures.get(1).or_else(|| captures.get(0))?;
+    matched.as_str().trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        measurement::{ENERGY_PER_RESULT, RATIO_UNIT},
+        source::Metric,
+        test_util::{fake_rapl_tree, metric_in},
+    };
+
+    #[test]
+    fn result_pattern_extracts_number() {
+        let regex = Regex::new(r"ops/sec: ([0-9.]+)").unwrap();
+
+        assert_eq!(parse_result(&regex, "ops/sec: 12345"), Some(12345.0));
+        assert_eq!(parse_result(&regex, "warmup done"), None);
+    }
+
+    #[test]
+    fn command_result_gives_energy_per_result() {
+        let config = ProfileConfig {
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo 'ops/sec: 1000'; echo 'ops/sec: 2500'".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let (exit_code, result) = run_command_with_result(&config, r"ops/sec: ([0-9.]+)").unwrap();
+        assert_eq!(exit_code, 0);
+        assert_eq!(result, Some(2500.0));
+
+        let metrics = vec![Metric {
+            name: "PACKAGE-0_0".to_string(),
+            value: 5_000_000,
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        }];
+        let metric = energy_per_result(&metrics, result.unwrap(), Default::default()).unwrap();
+        assert_eq!(metric.name, ENERGY_PER_RESULT);
+        assert_eq!(metric.value, 2_000);
+    }
+
+    #[test]
+    fn overhead_is_subtracted_and_clamped_to_zero() {
+        let mut metrics = vec![
+            metric_in("PACKAGE-0_0", 10_000, "µJ"),
+            metric_in("DRAM_0", 300, "µJ"),
+            metric_in("CORE_0", 4_000, "µJ"),
+            metric_in("PACKAGE-0_0_RATIO", 500, RATIO_UNIT),
+            metric_in("PEAK_RSS_KB", 2_000, "kB"),
+        ];
+        let overhead = [
+            metric_in("PACKAGE-0_0", 1_500, "µJ"),
+            metric_in("DRAM_0", 400, "µJ"),
+            metric_in("PACKAGE-0_0_RATIO", 100, "µJ"),
+            metric_in("PEAK_RSS_KB", 1_000, "kB"),
+        ];
+
+        subtract_overhead(&mut metrics, &overhead);
+
+        // Only the energies are subtracted
+        let values: Vec<_> = metrics.iter().map(|metric| metric.value).collect();
+        assert_eq!(values, [8_500, 0, 4_000, 500, 2_000]);
+    }
+
+    #[test]
+    fn idle_energy_over_the_duration_is_subtracted_and_clamped_to_zero() {
+        let idle = IdleBaseline::new(
+            &[
+                metric_in("PACKAGE-0_0", 4_000_000, "µJ"),
+                metric_in("DRAM_0", 1_000_000, "µJ"),
+                metric_in("PACKAGE-0_0_RATIO", 500, RATIO_UNIT),
+                metric_in("CPU_TIME_MS", 20, "ms"),
+            ],
+            2.0,
+        );
+        assert_eq!(idle.rates["PACKAGE-0_0"], 2_000_000.0);
+        assert!(!idle.rates.contains_key("PACKAGE-0_0_RATIO"));
+        assert!(!idle.rates.contains_key("CPU_TIME_MS"));
+
+        let mut metrics = vec![
+            metric_in("PACKAGE-0_0", 10_000_000, "µJ"),
+            metric_in("DRAM_0", 300_000, "µJ"),
+            metric_in("CORE_0", 4_000, "µJ"),
+        ];
+        idle.subtract(&mut metrics, 1_500);
+
+        let values: Vec<_> = metrics.iter().map(|metric| metric.value).collect();
+        assert_eq!(values, [7_000_000, 0, 4_000]);
+    }
+
+    // A single-threaded runtime, the polling worker only runs while the command is awaited
+    #[tokio::test]
+    async fn polling_runs_while_the_command_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sleep".to_string(), "0.3".to_string()],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            rapl_polling: Some(0.01),
+            ..Default::default()
+        };
+
+        let results = measure_simple_iterations(&config).await.unwrap();
+
+        // About 30 polls over the sleep, only the two explicit measures if starved
+        assert!(
+            results[0].measure_count >= 10,
+            "{} measures",
+            results[0].measure_count
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_runs_are_retried_and_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let marker = dir.path().join("ran");
+        // Fails on its first run only
+        let script = format!("test -f {0} || {{ touch {0}; exit 1; }}", marker.display());
+
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sh".to_string(), "-c".to_string(), script],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            retries: 2,
+            ..Default::default()
+        };
+
+        let results = measure_simple_iterations(&config).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].exit_code, 0);
+        assert_eq!(results[0].attempts, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn raw_counters_are_only_reported_with_the_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let config = |raw_counters| ProfileConfig {
+            iterations: 1,
+            cmd: vec!["true".to_string()],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            raw_counters,
+            ..Default::default()
+        };
+
+        let results = measure_simple_iterations(&config(false)).await.unwrap();
+        assert!(results[0].raw.is_empty());
+
+        let results = measure_simple_iterations(&config(true)).await.unwrap();
+        let path = dir.path().join("intel-rapl:0/energy_uj");
+        let reading = results[0].raw[path.to_str().unwrap()];
+        assert_eq!(
+            (reading.start_uj, reading.end_uj, reading.max_energy_uj),
+            (1000, 1000, 1_000_000)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn simple_samples_split_the_run_in_equal_intervals() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let counter = dir.path().join("intel-rapl:0/energy_uj");
+        // The energy is consumed in the first half of the run
+        let script = format!("sleep 0.1; echo 1600 > {}; sleep 0.2", counter.display());
+
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sh".to_string(), "-c".to_string(), script],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            simple_samples: Some(2),
+            ..Default::default()
+        };
+
+        let results = measure_simple_iterations(&config).await.unwrap();
+        let intervals = &results[0].intervals;
+        assert_eq!(intervals.len(), 2);
+        let energies: Vec<u64> = intervals
+            .iter()
+            .map(|interval| interval.metrics[0].value)
+            .collect();
+        assert_eq!(energies.iter().sum::<u64>(), 600);
+        assert!(energies[0] > energies[1], "{:?}", energies);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn short_runs_are_repeated_and_divided_per_run() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_rapl_tree(dir.path());
+        let counter = dir.path().join("intel-rapl:0/energy_uj");
+        // Each run consumes 600 µJ
+        let script = format!(
+            "echo $(( $(cat {0}) + 600 )) > {0}; sleep 0.02",
+            counter.display()
+        );
+
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sh".to_string(), "-c".to_string(), script],
+            rapl_path: Some(dir.path().to_string_lossy().to_string()),
+            min_duration_s: Some(0.1),
+            ..Default::default()
+        };
+
+        let results = measure_simple_iterations(&config).await.unwrap();
+        assert!(
+            results[0].repetitions > 1,
+            "{} runs",
+            results[0].repetitions
+        );
+        let package = results[0]
+            .metrics
+            .iter()
+            .find(|metric| metric.name == "PACKAGE-0_0")
+            .unwrap();
+        assert_eq!(package.value, 600);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn measurement_window_stops_at_the_timeout() {
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["sleep".to_string(), "5".to_string()],
+            duration_s: Some(5.0),
+            timeout_s: Some(0.1),
+            fake_source: vec![crate::cli::FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 100,
+            }],
+            ..Default::default()
+        };
+
+        let begin = Instant::now();
+        let results = measure_simple_iterations(&config).await.unwrap();
+        assert_eq!(results[0].exit_code, TIMEOUT_EXIT_CODE);
+        assert!(begin.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn overhead_of_the_fake_source_is_subtracted() {
+        let config = |subtract_overhead| ProfileConfig {
+            iterations: 1,
+            cmd: vec!["true".to_string()],
+            fake_source: vec![crate::cli::FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 2_000,
+            }],
+            subtract_overhead,
+            ..Default::default()
+        };
+
+        let results = measure_simple_iterations(&config(false)).await.unwrap();
+        assert_eq!(results[0].metrics[0].value, 2_000);
+
+        // The no-op run reports the same fixed energy, leaving nothing to the command
+        let results = measure_simple_iterations(&config(true)).await.unwrap();
+        assert_eq!(results[0].metrics[0].name, "PACKAGE-0_0");
+        assert_eq!(results[0].metrics[0].value, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn overhead_run_has_no_window_nor_batch() {
+        let config = ProfileConfig {
+            iterations: 1,
+            cmd: vec!["true".to_string()],
+            fake_source: vec![crate::cli::FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 2_000,
+            }],
+            subtract_overhead: true,
+            start_delay_s: Some(1.0),
+            min_duration_s: Some(0.05),
+            ..Default::default()
+        };
+        let mut manager = init_sources(&config).unwrap();
+
+        let begin = Instant::now();
+        let overhead = measure_overhead(&mut manager, &config, None).await.unwrap();
+
+        // Each of the runs would wait for the start delay
+        assert!(begin.elapsed() < Duration::from_secs(1));
+        // A batch would divide the fixed energy by its number of runs
+        assert_eq!(overhead[0].name, "PACKAGE-0_0");
+        assert_eq!(overhead[0].value, 2_000);
+
+        // The command is still measured with its options
+        let results = measure_simple_iterations(&ProfileConfig {
+            start_delay_s: None,
+            ..config
+        })
+        .await
+        .unwrap();
+        assert!(results[0].repetitions > 1);
+        assert_eq!(results[0].metrics[0].value, 0);
+    }
+
+    #[test]
+    fn overhead_is_subtracted_from_each_run_of_a_batch() {
+        let mut metrics = vec![
+            metric_in("PACKAGE-0_0", 1_000, "µJ"),
+            metric_in("PEAK_RSS_KB", 2_048, "KB"),
+        ];
+
+        // What measure_simple does with a batch of 4 runs
+        per_run(&mut metrics, 4, Rounding::default());
+        subtract_overhead(&mut metrics, &[metric_in("PACKAGE-0_0", 50, "µJ")]);
+
+        assert_eq!(metrics[0].value, 200);
+        assert_eq!(metrics[1].value, 2_048);
+    }
+
+    #[test]
+    fn power_states_are_reported_per_iteration_in_any_energy_unit() {
+        let bins = |package_mj: &[u64]| -> Vec<TimeBin> {
+            package_mj
+                .iter()
+                .enumerate()
+                .map(|(index, value)| TimeBin {
+                    index: index as u64,
+                    metrics: vec![
+                        Metric {
+                            name: "PACKAGE-0_0".to_string(),
+                            value: *value,
+                            unit: "mJ".to_string(),
+                            source: "powercap".to_string(),
+                        },
+                        Metric {
+                            name: "DRAM_0".to_string(),
+                            value: *value,
+                            unit: "mJ".to_string(),
+                            source: "powercap".to_string(),
+                        },
+                    ],
+                })
+                .collect()
+        };
+        // The last bins are shorter and ignored
+        let time_series = [
+            bins(&[10_000, 10_000, 20_000, 20_000, 1]),
+            bins(&[10_000, 10_000, 10_000, 1]),
+        ];
+
+        let states = power_states(&time_series, 1.0);
+
+        let state = |high_fraction, steps| PowerStates {
+            low_power_w: 10.0,
+            high_power_w: 20.0,
+            high_fraction,
+            steps,
+        };
+        assert_eq!(
+            states,
+            [
+                BTreeMap::from([("PACKAGE-0_0".to_string(), state(0.5, 1))]),
+                BTreeMap::from([("PACKAGE-0_0".to_string(), state(0.0, 0))]),
+            ]
+        );
+
+        let results: Vec<MeasurementResult> = states
+            .into_iter()
+            .map(|power_states| MeasurementResult {
+                power_states,
+                ..Default::default()
+            })
+            .collect();
+        let mut out = Vec::new();
+        write_power_states(&mut out, &results).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "PACKAGE-0_0: power steps between 10.0 W and 20.0 W, consistent with turbo boost\n  \
+             iteration 1: 50 % of the time in the high-power state (1 steps)\n  \
+             iteration 2: 0 % of the time in the high-power state (0 steps)\n"
+        );
+    }
 }
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, io::Write, time::Duration};
+
+use anyhow::Result;
+use log::info;
+use tokio::time::{Instant, MissedTickBehavior, interval};
+
+use crate::{
+    config::WatchConfig,
+    output::terminal::{TITLE_STYLE, VALUE_STYLE, paint},
+    source::{
+        MetricReader,
+        rapl::{Rapl, discover_rapl_domains},
+    },
+};
+
+/// Energy of each domain over the watched session, printed when it ends.
+#[derive(Debug, Default)]
+struct SessionTotal {
+    energy_uj: BTreeMap<String, u64>,
+    elapsed_us: u128,
+}
+
+/// Print the instantaneous power of each domain every interval until Ctrl-C, then the energy
+/// and average power of the session.
+pub async fn run_watch(config: &WatchConfig) -> Result<()> {
+    let (domains, backend) = discover_rapl_domains(
+        config.rapl_path.as_deref(),
+        config.sockets.as_ref(),
+        config.rapl_backend,
+        &config.domain_options,
+    )?;
+    let mut rapl = Rapl::with_backend(domains, Some(config.interval_s), backend);
+
+    let interval = Duration::from_secs_f64(config.interval_s);
+    info!("Watching the power every {:?}", interval);
+
+    let mut out = anstream::stdout();
+    let total = watch_power(&mut rapl, &mut out, interval, None).await?;
+    write_session_total(&mut out, &total)
+}
+
+/// Write a line with the power of each domain per interval, stopping after `cycles` intervals
+/// if given, or on Ctrl-C.
+async fn watch_power(
+    rapl: &mut Rapl,
+    out: &mut impl Write,
+    interval_duration: Duration,
+    cycles: Option<u64>,
+) -> Result<SessionTotal> {
+    let mut ticker = interval(interval_duration);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    // The first snapshot is the origin of the first interval
+    ticker.tick().await;
+    rapl.measure()?;
+    let start = Instant::now();
+    let mut last = start;
+    let mut total = SessionTotal::default();
+    let mut watched = 0;
+
+    while cycles.is_none_or(|cycles| watched < cycles) {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => {
+                info!("Interrupted, stopping the watch");
+                break;
+            }
+        }
+
+        rapl.measure()?;
+        let now = Instant::now();
+        let elapsed_us = now.duration_since(last).as_micros().max(1) as f64;
+        last = now;
+
+        let mut line = format!("{:>8.1} s", now.duration_since(start).as_secs_f64());
+        for metric in rapl.take_interval_metrics() {
+            // µJ per µs are watts
+            let power = format!("{:>8.2}", metric.value as f64 / elapsed_us);
+            line.push_str(&format!(
+                "  {} {} W",
+                metric.name,
+                paint(VALUE_STYLE, &power)
+            ));
+            *total.energy_uj.entry(metric.name).or_default() += metric.value;
+        }
+        writeln!(out, "{}", line)?;
+        out.flush()?;
+        watched += 1;
+    }
+
+    total.elapsed_us = last.duration_since(start).as_micros();
+    Ok(total)
+}
+
+/// Write the energy and average power of each domain over the session.
+fn write_session_total(out: &mut impl Write, total: &SessionTotal) -> Result<()> {
+    let elapsed_s = total.elapsed_us as f64 / 1e6;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{}",
+        paint(TITLE_STYLE, &format!("Session total ({:.1} s)", elapsed_s))
+    )?;
+    for (name, energy_uj) in &total.energy_uj {
+        let energy = format!("{:>10.3}", *energy_uj as f64 / 1e6);
+        let power = format!(
+            "{:>8.2}",
+            *energy_uj as f64 / total.elapsed_us.max(1) as f64
+        );
+        writeln!(
+            out,
+            "  {:<20}: {} J  {} W",
+            name,
+            paint(VALUE_STYLE, &energy),
+            paint(VALUE_STYLE, &power)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::source::rapl::domain::DomainOptions;
+
+    #[tokio::test]
+    async fn watch_prints_a_line_per_interval_and_the_total() {
+        let dir = tempdir().unwrap();
+        let zone = dir.path().join("intel-rapl:0");
+        create_dir_all(&zone).unwrap();
+        write(zone.join("name"), "package-0").unwrap();
+        write(zone.join("energy_uj"), "1000").unwrap();
+        write(zone.join("max_energy_range_uj"), "262143328850").unwrap();
+        let (domains, backend) = discover_rapl_domains(
+            dir.path().to_str(),
+            None,
+            Default::default(),
+            &DomainOptions::default(),
+        )
+        .unwrap();
+        let mut rapl = Rapl::with_backend(domains, Some(0.005), backend);
+
+        let mut out = Vec::new();
+        let total = watch_power(&mut rapl, &mut out, Duration::from_millis(5), Some(3))
+            .await
+            .unwrap();
+        write_session_total(&mut out, &total).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3 + 3);
+        assert!(lines[0].contains("PACKAGE-0_0"));
+        assert!(lines[4].contains("Session total"));
+        assert!(lines[5].contains("PACKAGE-0_0"));
+        assert_eq!(total.energy_uj["PACKAGE-0_0"], 0);
+    }
+}
@@ -1,40 +1,375 @@
-use crate::cli::{Cli, ProfilerCommand};
+use crate::cli::{
+    Cli, CommonArgs, EnergyUnit, EnvVar, FakeMetric, MetricFormat, OutlierMethod, ProfilerCommand,
+    RaplBackendKind, Rounding, ScoreWeight, TokenStream,
+};
+use crate::error::JouleProfilerError;
+use crate::measurement::StopCondition;
+use crate::output::STDOUT_PATH;
+use crate::source::{
+    CrossCheck, Watchdog,
+    rapl::{RaplOptions, domain::DomainOptions},
+};
+use crate::util::file::FileMode;
+use clap::ValueEnum;
 use std::collections::HashSet;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ProfileConfig {
     pub iterations: usize,
+    pub time_budget_s: Option<f64>,
+    pub energy_budget_j: Option<f64>,
     pub output_format: OutputFormat,
     pub jouleit_file: Option<String>,
+    pub quiet: bool,
+    pub shell: bool,
+    pub dry_run: bool,
+    pub append: bool,
+    pub also_terminal: bool,
+    /// Shell command run with the path of the results file once it is written
+    pub post_hook: Option<String>,
+    pub canonical_json: bool,
+    pub json_group_by_socket: bool,
+    pub file_mode: FileMode,
     pub output_file: Option<String>,
+    pub stderr_file: Option<String>,
+    pub merge_stderr: bool,
+    pub stdin_file: Option<String>,
+    pub cgroup: Option<String>,
+    pub per_thread: bool,
+    pub subtract_overhead: bool,
+    pub idle_sample_s: Option<f64>,
+    pub debug_diff: bool,
+    pub raw_counters: bool,
+    pub trend: bool,
+    pub what_if_cap_w: Option<f64>,
+    pub build_mode: bool,
+    pub start_delay_s: Option<f64>,
+    pub duration_s: Option<f64>,
+    pub min_duration_s: Option<f64>,
+    pub simple_samples: Option<usize>,
+    pub result_pattern: Option<String>,
+    pub ops_per_watt: bool,
+    pub remote: Option<String>,
+    pub print_total: Option<EnergyUnit>,
+    pub time_series: Option<String>,
+    pub time_series_bin_s: f64,
+    pub samples_file: Option<String>,
+    pub detect_turbo: bool,
+    pub retries: u32,
+    pub drop_outliers: Option<OutlierMethod>,
+    pub trace_events: Option<String>,
     pub cmd: Vec<String>,
     pub sockets: Option<HashSet<u32>>,
     pub rapl_polling: Option<f64>,
+    pub min_cpu_utilization: Option<f64>,
+    pub trim_samples: usize,
+    pub watchdog: Watchdog,
+    pub cross_check: Option<CrossCheck>,
+    /// Events buffered per source worker, the default of the manager if not set
+    pub event_buffer: Option<usize>,
     pub rapl_path: Option<String>,
+    pub rapl_backend: RaplBackendKind,
+    pub domain_options: DomainOptions,
+    pub precision: usize,
+    /// Rounding of the energy aggregations, the default of each aggregation if not set
+    pub rounding: Option<Rounding>,
+    pub derived: bool,
+    pub always_iteration_column: bool,
+    pub csv_include_total: bool,
+    pub csv_timestamps: bool,
+    pub totals: bool,
+    pub fail_fast: bool,
+    pub influx_measurement: String,
+    pub csv_delimiter: Option<char>,
+    pub fields: Vec<String>,
+    pub ratio_to: Option<String>,
+    pub score: Vec<ScoreWeight>,
+    pub metrics: Option<String>,
+    pub metric_formats: Vec<MetricFormat>,
+    pub energy_unit: Option<EnergyUnit>,
+    pub stabilize_governor: bool,
+    pub drop_caches: bool,
+    pub nice: Option<i32>,
+    pub realtime: bool,
+    pub numa_node: Option<u32>,
+    pub env: Vec<EnvVar>,
+    pub workdir: Option<String>,
+    pub timeout_s: Option<f64>,
+    pub gpu: bool,
+    pub procfs: bool,
+    pub fake_source: Vec<FakeMetric>,
+    pub otlp_endpoint: Option<String>,
     pub mode: Mode,
 }
 
-#[derive(Debug, Clone)]
+impl ProfileConfig {
+    /// Rounding of the integer averages and estimates, truncating by default.
+    pub fn rounding(&self) -> Rounding {
+        self.rounding.unwrap_or_default()
+    }
+
+    /// Whether the results are written to stdout, with `--print-total` or `--jouleit-file -`,
+    /// the program output then goes to stderr.
+    pub fn results_on_stdout(&self) -> bool {
+        self.print_total.is_some() || self.jouleit_file.as_deref() == Some(STDOUT_PATH)
+    }
+
+    /// Limits of the iterations, the first one reached stops them.
+    pub fn stop_condition(&self) -> StopCondition {
+        StopCondition::new(self.iterations, self.time_budget_s, self.energy_budget_j)
+    }
+
+    /// Measurement options of the RAPL source.
+    pub fn rapl_options(&self) -> RaplOptions {
+        RaplOptions {
+            polling_rate_s: self.rapl_polling,
+            min_utilization: self.min_cpu_utilization,
+            time_bin_s: self.time_series.as_ref().map(|_| self.time_series_bin_s),
+            keep_samples: self.samples_file.is_some() || self.simple_samples.is_some(),
+            trim_samples: self.trim_samples,
+        }
+    }
+
+    /// Reject the values the measurement cannot run with, instead of failing later on.
+    fn validate(&self) -> Result<(), JouleProfilerError> {
+        if self.iterations == 0 {
+            return Err(JouleProfilerError::InvalidIterations(self.iterations));
+        }
+        if let Some(interval_s) = self.rapl_polling
+            && (interval_s <= 0.0 || interval_s.is_nan())
+        {
+            return Err(JouleProfilerError::InvalidPollingInterval(interval_s));
+        }
+        if self.post_hook.is_some()
+            && (matches!(
+                self.output_format,
+                OutputFormat::Terminal | OutputFormat::None
+            ) || self.results_on_stdout())
+        {
+            return Err(JouleProfilerError::PostHookWithoutFile);
+        }
+        if self.also_terminal && self.results_on_stdout() {
+            return Err(JouleProfilerError::AlsoTerminalOnStdout);
+        }
+        if !self.metric_formats.is_empty()
+            && !matches!(self.output_format, OutputFormat::Terminal)
+            && !self.also_terminal
+        {
+            return Err(JouleProfilerError::MetricFormatWithoutTerminal);
+        }
+        Ok(())
+    }
+
+    /// Build a profiling configuration from the arguments shared by all profiling modes.
+    fn new(
+        common: CommonArgs,
+        mode: Mode,
+        rapl_path: Option<String>,
+        rapl_backend: RaplBackendKind,
+        domain_options: DomainOptions,
+        sockets: Option<HashSet<u32>>,
+        file_mode: FileMode,
+    ) -> Self {
+        #[cfg(feature = "html")]
+        let html = common.html;
+        #[cfg(not(feature = "html"))]
+        let html: Option<String> = None;
+
+        Self {
+            iterations: common.iterations.unwrap_or(1),
+            time_budget_s: common.time_budget,
+            energy_budget_j: common.energy_budget,
+            output_format: match (&html, &common.msgpack) {
+                #[cfg(feature = "html")]
+                (Some(_), _) => OutputFormat::Html,
+                (_, Some(_)) => OutputFormat::Msgpack,
+                _ => output_format(
+                    common.format,
+                    common.json,
+                    common.csv,
+                    common.markdown,
+                    common.influx,
+                    common.prometheus,
+                ),
+            },
+            jouleit_file: html.or(common.msgpack).or(common.jouleit_file),
+            append: common.append,
+            quiet: common.quiet,
+            shell: common.shell,
+            dry_run: common.dry_run,
+            file_mode,
+            also_terminal: common.also_terminal,
+            post_hook: common.post_hook,
+            canonical_json: common.canonical_json,
+            json_group_by_socket: common.json_group_by_socket,
+            output_file: common.output_file,
+            stderr_file: common.stderr_file,
+            merge_stderr: common.merge_stderr,
+            stdin_file: common.stdin_file,
+            cgroup: None,
+            per_thread: false,
+            subtract_overhead: false,
+            idle_sample_s: None,
+            debug_diff: false,
+            raw_counters: false,
+            trend: false,
+            what_if_cap_w: None,
+            build_mode: false,
+            start_delay_s: None,
+            duration_s: None,
+            min_duration_s: None,
+            simple_samples: None,
+            result_pattern: None,
+            ops_per_watt: false,
+            remote: None,
+            print_total: None,
+            time_series: None,
+            time_series_bin_s: 1.0,
+            samples_file: None,
+            detect_turbo: false,
+            retries: 0,
+            drop_outliers: None,
+            trace_events: None,
+            cmd: common.cmd,
+            sockets,
+            rapl_polling: common.rapl_polling,
+            min_cpu_utilization: common.min_cpu_utilization.map(|percent| percent / 100.0),
+            trim_samples: common.trim_samples,
+            watchdog: Watchdog {
+                max_stalled_intervals: common.stall_intervals,
+                strict: common.strict,
+            },
+            event_buffer: common.event_buffer.map(usize::from),
+            cross_check: common.cross_check.then_some(CrossCheck {
+                tolerance_pct: common.cross_check_tolerance,
+                strict: !common.cross_check_warn_only,
+            }),
+            rapl_path,
+            rapl_backend,
+            domain_options,
+            precision: common.precision,
+            rounding: common.rounding,
+            derived: common.derived,
+            always_iteration_column: common.always_iteration_column,
+            csv_include_total: common.csv_include_total,
+            csv_timestamps: common.csv_timestamps,
+            totals: common.totals,
+            fail_fast: common.fail_fast,
+            influx_measurement: common.influx_measurement,
+            csv_delimiter: common.csv_delimiter,
+            fields: common.fields,
+            ratio_to: common.ratio_to,
+            score: common.score,
+            metrics: common.metrics,
+            metric_formats: common.metric_format,
+            energy_unit: common.unit,
+            stabilize_governor: common.stabilize_governor,
+            drop_caches: common.drop_caches,
+            nice: common.nice,
+            realtime: common.realtime,
+            numa_node: common.numa_node,
+            env: common.env,
+            workdir: common.workdir,
+            timeout_s: common.timeout,
+            gpu: common.gpu,
+            procfs: common.procfs,
+            fake_source: common.fake_source,
+            otlp_endpoint: common.otlp,
+            mode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::enum_variant_names)]
 pub enum Mode {
+    #[default]
     SimpleMode,
     PhaseMode(PhasesConfig),
+    CalibrateMode(CalibrateConfig),
+    CompareMode(CompareConfig),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct PhasesConfig {
     pub token_pattern: String,
+    pub align_boundaries: bool,
+    pub phase_timings: Option<String>,
+    pub inner_only: bool,
+    pub describe_phases: bool,
+    pub max_output_bytes: Option<usize>,
+    pub pty: bool,
+    pub live: bool,
+    pub exit_timeout_s: Option<f64>,
+    pub token_stream: TokenStream,
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrateConfig {
+    /// Polling rates in seconds to compare, `None` meaning no polling
+    pub polling_rates: Vec<Option<f64>>,
+}
+
+/// Command lines measured one after the other by the compare command, run through `sh -c`.
+#[derive(Debug, Clone)]
+pub struct CompareConfig {
+    pub baseline: String,
+    pub candidate: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct ListSensorsConfig {
     pub output_format: OutputFormat,
+    pub file_mode: FileMode,
+    pub rapl_path: Option<String>,
+    pub rapl_backend: RaplBackendKind,
+    pub domain_options: DomainOptions,
+    pub dump_domains: bool,
+    pub explain: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    pub rapl_path: Option<String>,
+    pub rapl_backend: RaplBackendKind,
+    pub domain_options: DomainOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub interval_s: f64,
+    pub file: String,
+    pub duration_s: Option<f64>,
+    pub file_mode: FileMode,
+    pub sockets: Option<HashSet<u32>>,
     pub rapl_path: Option<String>,
+    pub rapl_backend: RaplBackendKind,
+    pub domain_options: DomainOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    pub interval_s: f64,
+    pub sockets: Option<HashSet<u32>>,
+    pub rapl_path: Option<String>,
+    pub rapl_backend: RaplBackendKind,
+    pub domain_options: DomainOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShowConfig {
+    pub file: String,
+    pub precision: usize,
 }
 
 #[derive(Debug, Clone)]
 pub enum Command {
-    Profile(ProfileConfig),
+    Profile(Box<ProfileConfig>),
     ListSensors(ListSensorsConfig),
+    Env(EnvConfig),
+    Show(ShowConfig),
+    Log(LogConfig),
+    Watch(WatchConfig),
 }
 
 #[derive(Debug, Clone)]
@@ -42,72 +377,417 @@ pub struct Config {
     pub mode: Command,
 }
 
-impl From<Cli> for Config {
-    fn from(cli: Cli) -> Self {
-        let sockets = cli.sockets.map(|s| {
-            s.split(',')
-                .filter_map(|x| x.trim().parse::<u32>().ok())
-                .collect()
-        });
+/// Sockets beyond this count are rejected, so that a range cannot exhaust the memory.
+const MAX_SOCKETS: u32 = 1024;
+
+/// Parse a socket specification, a comma list of sockets and ranges (e.g. `0,2-4`).
+pub fn parse_sockets(spec: &str) -> Result<HashSet<u32>, JouleProfilerError> {
+    let invalid =
+        |token: &str| JouleProfilerError::InvalidSocketSpec(format!("{}: {}", spec, token));
+    let socket = |token: &str| {
+        token
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&socket| socket < MAX_SOCKETS)
+            .ok_or_else(|| invalid(token))
+    };
+
+    let mut sockets = HashSet::new();
+    for token in spec.split(',') {
+        match token.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (socket(first)?, socket(last)?);
+                if first > last {
+                    return Err(invalid(token));
+                }
+                sockets.extend(first..=last);
+            }
+            None => {
+                sockets.insert(socket(token)?);
+            }
+        }
+    }
+    Ok(sockets)
+}
+
+impl TryFrom<Cli> for Config {
+    type Error = anyhow::Error;
+
+    fn try_from(cli: Cli) -> Result<Self, Self::Error> {
+        let sockets = cli.sockets.as_deref().map(parse_sockets).transpose()?;
+
+        let domain_options = DomainOptions {
+            assume_max_energy_uj: (cli.assume_max_energy > 0).then_some(cli.assume_max_energy),
+            dedup: cli.dedup_domains,
+            domain_map: cli.domain_map,
+            domains: cli.domains,
+            include: cli.include_sensors,
+            exclude: cli.exclude_sensors,
+        };
 
         let mode = match cli.command {
-            ProfilerCommand::Simple(simple) => {
-                let common = simple.common;
-                let output_format = output_format(common.json, common.csv);
-
-                Command::Profile(ProfileConfig {
-                    iterations: common.iterations.unwrap_or(1),
-                    output_format,
-                    jouleit_file: common.jouleit_file,
-                    output_file: common.output_file,
-                    cmd: common.cmd,
-                    rapl_polling: common.rapl_polling,
-                    rapl_path: cli.rapl_path,
-                    mode: Mode::SimpleMode,
+            ProfilerCommand::Simple(simple) => Command::Profile(Box::new(ProfileConfig {
+                cgroup: simple.cgroup,
+                per_thread: simple.per_thread,
+                subtract_overhead: simple.subtract_overhead,
+                idle_sample_s: simple.idle_sample,
+                debug_diff: simple.debug_diff,
+                raw_counters: simple.raw_counters,
+                trend: simple.trend,
+                what_if_cap_w: simple.what_if_cap,
+                build_mode: simple.build_mode,
+                start_delay_s: simple.start_delay,
+                duration_s: simple.duration,
+                min_duration_s: simple.min_duration,
+                simple_samples: simple.simple_samples.map(usize::from),
+                result_pattern: simple.result_pattern,
+                ops_per_watt: simple.ops_per_watt,
+                remote: simple.remote,
+                print_total: simple.print_total,
+                time_series: simple.time_series,
+                time_series_bin_s: simple.time_series_bin,
+                samples_file: simple.samples_file,
+                detect_turbo: simple.detect_turbo,
+                retries: simple.retries,
+                drop_outliers: simple.drop_outliers,
+                ..ProfileConfig::new(
+                    simple.common,
+                    Mode::SimpleMode,
+                    cli.rapl_path,
+                    cli.rapl_backend,
+                    domain_options,
                     sockets,
-                })
-            }
-            ProfilerCommand::Phases(phases) => {
-                let common = phases.common;
-                let output_format = output_format(common.json, common.csv);
-                Command::Profile(ProfileConfig {
-                    iterations: common.iterations.unwrap_or(1),
-                    output_format,
-                    jouleit_file: common.jouleit_file,
-                    output_file: common.output_file,
-                    cmd: common.cmd,
-                    rapl_polling: common.rapl_polling,
-                    rapl_path: cli.rapl_path,
-                    mode: Mode::PhaseMode(PhasesConfig {
+                    cli.output_mode,
+                )
+            })),
+            ProfilerCommand::Phases(phases) => Command::Profile(Box::new(ProfileConfig {
+                print_total: phases.print_total,
+                trace_events: phases.trace_events,
+                ..ProfileConfig::new(
+                    phases.common,
+                    Mode::PhaseMode(PhasesConfig {
                         token_pattern: phases.token_pattern,
+                        align_boundaries: phases.align_boundaries,
+                        phase_timings: phases.phase_timings,
+                        inner_only: phases.inner_only,
+                        describe_phases: phases.describe_phases,
+                        max_output_bytes: phases.max_output_bytes,
+                        pty: phases.pty,
+                        live: phases.live,
+                        exit_timeout_s: phases.exit_timeout,
+                        token_stream: phases.token_stream,
                     }),
+                    cli.rapl_path,
+                    cli.rapl_backend,
+                    domain_options,
                     sockets,
-                })
+                    cli.output_mode,
+                )
+            })),
+            ProfilerCommand::Calibrate(calibrate) => {
+                Command::Profile(Box::new(ProfileConfig::new(
+                    calibrate.common,
+                    Mode::CalibrateMode(CalibrateConfig {
+                        polling_rates: calibrate.rates,
+                    }),
+                    cli.rapl_path,
+                    cli.rapl_backend,
+                    domain_options,
+                    sockets,
+                    cli.output_mode,
+                )))
             }
 
+            ProfilerCommand::Compare(compare) => Command::Profile(Box::new(ProfileConfig::new(
+                compare.common,
+                Mode::CompareMode(CompareConfig {
+                    baseline: compare.baseline,
+                    candidate: compare.candidate,
+                }),
+                cli.rapl_path,
+                cli.rapl_backend,
+                domain_options,
+                sockets,
+                cli.output_mode,
+            ))),
+
             ProfilerCommand::ListSensors(list) => Command::ListSensors(ListSensorsConfig {
-                output_format: output_format(list.json, list.csv),
+                output_format: output_format(None, list.json, list.csv, false, false, false),
+                file_mode: cli.output_mode,
                 rapl_path: cli.rapl_path,
+                rapl_backend: cli.rapl_backend,
+                domain_options,
+                dump_domains: list.dump_domains,
+                explain: list.explain,
+            }),
+            ProfilerCommand::Env => Command::Env(EnvConfig {
+                rapl_path: cli.rapl_path,
+                rapl_backend: cli.rapl_backend,
+                domain_options,
+            }),
+            ProfilerCommand::Show(show) => Command::Show(ShowConfig {
+                file: show.file,
+                precision: show.precision,
+            }),
+            ProfilerCommand::Log(log) => Command::Log(LogConfig {
+                interval_s: log.interval,
+                file: log.file,
+                duration_s: log.duration,
+                file_mode: cli.output_mode,
+                sockets,
+                rapl_path: cli.rapl_path,
+                rapl_backend: cli.rapl_backend,
+                domain_options,
+            }),
+            ProfilerCommand::Watch(watch) => Command::Watch(WatchConfig {
+                interval_s: watch.interval,
+                sockets,
+                rapl_path: cli.rapl_path,
+                rapl_backend: cli.rapl_backend,
+                domain_options,
             }),
         };
 
-        Config { mode }
+        if let Command::Profile(config) = &mode {
+            config.validate()?;
+        }
+        Ok(Config { mode })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
 pub enum OutputFormat {
+    /// Pretty terminal output
+    #[default]
     Terminal,
+    /// JSON file
     Json,
+    /// CSV file
     Csv,
+    /// No output at all, results are discarded
+    None,
+    /// Parquet file, requires the `parquet` feature
+    Parquet,
+    /// HTML report with a chart, requires the `html` feature
+    #[cfg(feature = "html")]
+    Html,
+    /// MessagePack file, the JSON documents in a binary encoding
+    Msgpack,
+    /// Markdown file with GitHub-flavored tables
+    Markdown,
+    /// InfluxDB line protocol, a line per metric
+    Influx,
+    /// Prometheus text exposition format, for the node_exporter textfile collector
+    Prometheus,
 }
 
-fn output_format(json: bool, csv: bool) -> OutputFormat {
-    if json {
+fn output_format(
+    format: Option<OutputFormat>,
+    json: bool,
+    csv: bool,
+    markdown: bool,
+    influx: bool,
+    prometheus: bool,
+) -> OutputFormat {
+    if let Some(format) = format {
+        format
+    } else if json {
         OutputFormat::Json
     } else if csv {
         OutputFormat::Csv
+    } else if markdown {
+        OutputFormat::Markdown
+    } else if influx {
+        OutputFormat::Influx
+    } else if prometheus {
+        OutputFormat::Prometheus
     } else {
         OutputFormat::Terminal
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sockets(spec: &str) -> Vec<u32> {
+        let mut sockets: Vec<u32> = parse_sockets(spec).unwrap().into_iter().collect();
+        sockets.sort();
+        sockets
+    }
+
+    #[test]
+    fn sysfs_selects_the_powercap_backend() {
+        use clap::Parser;
+
+        let cli =
+            Cli::try_parse_from(["joule-profiler", "--rapl-backend", "sysfs", "env"]).unwrap();
+        assert_eq!(cli.rapl_backend, RaplBackendKind::Powercap);
+    }
+
+    #[test]
+    fn durations_must_be_finite_and_at_least_a_millisecond() {
+        use clap::Parser;
+
+        for value in ["inf", "NaN", "1e-12", "0", "-1", "1e30"] {
+            let timeout = ["joule-profiler", "simple", "--timeout", value, "--", "true"];
+            assert!(Cli::try_parse_from(timeout).is_err(), "{}", value);
+            let min_duration = [
+                "joule-profiler",
+                "simple",
+                "--min-duration",
+                value,
+                "--",
+                "true",
+            ];
+            assert!(Cli::try_parse_from(min_duration).is_err(), "{}", value);
+            assert!(Cli::try_parse_from(["joule-profiler", "watch", "--interval", value]).is_err());
+        }
+
+        assert!(Cli::try_parse_from(["joule-profiler", "watch", "--interval", "0.001"]).is_ok());
+        let timeout = ["joule-profiler", "simple", "--timeout", "1.5", "--", "true"];
+        assert!(Cli::try_parse_from(timeout).is_ok());
+    }
+
+    fn parse(args: &[&str]) -> anyhow::Result<Config> {
+        use clap::Parser;
+
+        Config::try_from(Cli::try_parse_from(args).unwrap())
+    }
+
+    #[test]
+    fn zero_iterations_are_rejected() {
+        let err = parse(&["joule-profiler", "simple", "-n", "0", "--", "true"]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(JouleProfilerError::InvalidIterations(0))
+        ));
+
+        assert!(parse(&["joule-profiler", "simple", "-n", "1", "--", "true"]).is_ok());
+    }
+
+    #[test]
+    fn raw_readings_is_an_alias_of_raw_counters() {
+        for flag in ["--raw-counters", "--raw-readings"] {
+            let config = parse(&["joule-profiler", "simple", flag, "--", "true"]).unwrap();
+            let Command::Profile(config) = config.mode else {
+                panic!("{} is not a profile", flag);
+            };
+            assert!(config.raw_counters, "{}", flag);
+        }
+    }
+
+    #[test]
+    fn non_positive_polling_is_rejected() {
+        for polling in ["--rapl-polling=0", "--rapl-polling=-0.5"] {
+            let err = parse(&["joule-profiler", "simple", polling, "--", "true"]).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref(),
+                Some(JouleProfilerError::InvalidPollingInterval(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn post_hook_needs_a_results_file() {
+        for args in [
+            &["--post-hook", "true"][..],
+            &["--post-hook", "true", "--format", "none"],
+            &["--post-hook", "true", "--json", "--jouleit-file", "-"],
+        ] {
+            let args = [&["joule-profiler", "simple"], args, &["--", "true"]].concat();
+            let err = parse(&args).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref(),
+                Some(JouleProfilerError::PostHookWithoutFile)
+            ));
+        }
+
+        let args = ["joule-profiler", "simple", "--post-hook", "true", "--json"];
+        assert!(parse(&[&args[..], &["--", "true"]].concat()).is_ok());
+    }
+
+    #[test]
+    fn metric_format_needs_the_terminal_output() {
+        let args = [
+            "joule-profiler",
+            "simple",
+            "--metric-format",
+            "PACKAGE-0_0=J:3",
+        ];
+        let err = parse(&[&args[..], &["--json", "--", "true"]].concat()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(JouleProfilerError::MetricFormatWithoutTerminal)
+        ));
+
+        assert!(parse(&[&args[..], &["--", "true"]].concat()).is_ok());
+        let also_terminal = ["--json", "--also-terminal", "--", "true"];
+        assert!(parse(&[&args[..], &also_terminal].concat()).is_ok());
+    }
+
+    #[test]
+    fn also_terminal_needs_a_results_file() {
+        let args = [
+            "joule-profiler",
+            "simple",
+            "--json",
+            "--jouleit-file",
+            "-",
+            "--also-terminal",
+            "--",
+            "true",
+        ];
+        let err = parse(&args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(JouleProfilerError::AlsoTerminalOnStdout)
+        ));
+
+        let args = ["joule-profiler", "simple", "--json", "--also-terminal"];
+        assert!(parse(&[&args[..], &["--", "true"]].concat()).is_ok());
+    }
+
+    #[test]
+    fn single_sockets_are_parsed() {
+        assert_eq!(sockets("0"), [0]);
+        assert_eq!(sockets("0, 1"), [0, 1]);
+    }
+
+    #[test]
+    fn socket_ranges_are_expanded() {
+        assert_eq!(sockets("0-3"), [0, 1, 2, 3]);
+        assert_eq!(sockets("1-1"), [1]);
+    }
+
+    #[test]
+    fn sockets_and_ranges_can_be_mixed() {
+        assert_eq!(sockets("0,2-4"), [0, 2, 3, 4]);
+        assert_eq!(sockets("3-4,0,4"), [0, 3, 4]);
+    }
+
+    #[test]
+    fn malformed_socket_specs_are_rejected() {
+        for spec in ["3-0", "a", "0,,1", "0-", "1-2-3", ""] {
+            let err = parse_sockets(spec).unwrap_err();
+            assert!(
+                matches!(err, JouleProfilerError::InvalidSocketSpec(_)),
+                "{}: {}",
+                spec,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn sockets_beyond_the_maximum_are_rejected() {
+        assert_eq!(sockets("1023").len(), 1);
+        for spec in ["1024", "0-4294967295", "0,1000-2000"] {
+            assert!(matches!(
+                parse_sockets(spec),
+                Err(JouleProfilerError::InvalidSocketSpec(_))
+            ));
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use thiserror::Error;
 
+/// Exit code of the profiler when it fails itself, distinct from the usual exit codes of the
+/// profiled commands (`EX_SOFTWARE` of sysexits.h).
+pub const PROFILER_ERROR_EXIT_CODE: i32 = 70;
+
 #[derive(Debug, Error)]
 pub enum JouleProfilerError {
     #[error("Intel RAPL not available at {0}")]
@@ -14,6 +18,9 @@ pub enum JouleProfilerError {
     #[error("Invalid RAPL domain path: {0}")]
     InvalidRaplPath(String),
 
+    #[error("RAPL path {0} is a file of a powercap zone, use the base directory instead: {1}")]
+    RaplPathIsZoneFile(String, String),
+
     #[error("RAPL counter overflow detected")]
     CounterOverflow,
 
@@ -23,12 +30,21 @@ pub enum JouleProfilerError {
     #[error("Invalid iterations value: {0}. Must be >= 1")]
     InvalidIterations(usize),
 
+    #[error("Invalid RAPL polling interval: {0}s. Must be > 0")]
+    InvalidPollingInterval(f64),
+
     #[error("Cannot use both --json and --csv flags simultaneously")]
     ConflictingOutputFormats,
 
     #[error("Invalid socket specification: {0}")]
     InvalidSocketSpec(String),
 
+    #[error("Domain {0} not found, available domains: {1}")]
+    UnknownDomain(String, String),
+
+    #[error("No domain left by --include-sensors/--exclude-sensors, available domains: {0}")]
+    NoDomainsLeftBySensorFilters(String),
+
     #[error("Socket {0} not found in available RAPL domains")]
     SocketNotFound(u32),
 
@@ -41,6 +57,11 @@ pub enum JouleProfilerError {
     #[error("Command killed by signal: {0}")]
     CommandKilled(i32),
 
+    #[error(
+        "Command exited with code {exit_code} at iteration {iteration}, stopping (--fail-fast)"
+    )]
+    CommandFailed { iteration: usize, exit_code: i32 },
+
     #[error("Token '{0}' not found in program output")]
     TokenNotFound(String),
 
@@ -65,6 +86,13 @@ pub enum JouleProfilerError {
     #[error("Insufficient permissions to access RAPL. Try running with sudo")]
     InsufficientPermissions,
 
+    #[error(
+        "Permission denied reading the energy counter {0}. Run with sudo, make the counters \
+         readable with `sudo chmod -R a+r /sys/class/powercap/` (reset at reboot), or grant \
+         CAP_DAC_READ_SEARCH with `sudo setcap cap_dac_read_search+ep <joule-profiler binary>`"
+    )]
+    EnergyCounterNotReadable(String),
+
     #[error("Failed to get current directory")]
     CurrentDirNotFound,
 
@@ -80,11 +108,63 @@ pub enum JouleProfilerError {
     #[error("Invalid JSON format: {0}")]
     InvalidJsonFormat(String),
 
+    #[error("Invalid domain map: {0}")]
+    InvalidDomainMap(String),
+
+    #[error("Invalid score: {0}")]
+    InvalidScore(String),
+
     #[error("Invalid regex pattern: {0}")]
     InvalidPattern(String),
 
     #[error("Not enough snapshots to retrieve metrics")]
     NotEnoughSnapshots,
+
+    #[error("Trimming {0} samples at each end leaves none of the {1} polling intervals")]
+    AllSamplesTrimmed(usize, u64),
+
+    #[error("Failed to read cgroup CPU usage: {0}")]
+    CgroupReadError(String),
+
+    #[error("Failed to read thread CPU times: {0}")]
+    ProcReadError(String),
+
+    #[error("Failed to read NVML GPU energy: {0}")]
+    NvmlError(String),
+
+    #[error("Energy counters of {0} stopped advancing for {1} polling intervals")]
+    CountersStalled(String, u32),
+
+    #[error("{0} differs between the sources: {1} µJ vs {2} µJ ({3:.1} %)")]
+    SourcesDiverge(String, u64, u64, f64),
+
+    #[error("No metric source completed the measurement: {0}")]
+    NoSourceSucceeded(String),
+
+    #[error("Invalid NUMA node: {0}")]
+    InvalidNumaNode(String),
+
+    #[error("Invalid environment variable: {0}")]
+    InvalidEnvVar(String),
+
+    #[error("Working directory not found: {0}")]
+    WorkdirNotFound(String),
+
+    #[error("Failed to open the stdin file: {0}")]
+    StdinFileNotReadable(String),
+
+    #[error(
+        "--post-hook needs a results file, use a file output (e.g. --json) instead of the terminal"
+    )]
+    PostHookWithoutFile,
+
+    #[error("--also-terminal would mix the terminal report with the results written to stdout")]
+    AlsoTerminalOnStdout,
+
+    #[error(
+        "--metric-format only applies to the terminal output, add --also-terminal to display the results written to a file"
+    )]
+    MetricFormatWithoutTerminal,
 }
 
 impl From<std::io::Error> for JouleProfilerError {
@@ -110,3 +190,12 @@ impl JouleProfilerError {
         Self::SocketNotFound(socket)
     }
 }
+
+/// Exit code of the profiler for an error: that of the command when `--fail-fast` stopped
+/// the iterations, else [`PROFILER_ERROR_EXIT_CODE`].
+pub fn error_exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<JouleProfilerError>() {
+        Some(JouleProfilerError::CommandFailed { exit_code, .. }) => *exit_code,
+        _ => PROFILER_ERROR_EXIT_CODE,
+    }
+}
@@ -1,51 +1,165 @@
+use std::path::Path;
+
 use anyhow::Result;
 use clap::Parser;
 use env_logger::Builder;
 use log::{LevelFilter, debug, info, trace};
 
 use crate::{
-    cli::Cli,
-    command::{list_sensors::run_list_sensors, phases::run_phases, simple::run_simple},
+    cli::{Cli, ColorMode},
+    command::{
+        calibrate::{measure_calibration, run_calibrate},
+        compare::{measure_comparison, run_compare},
+        dry_run::run_dry_run,
+        env::run_env,
+        list_sensors::run_list_sensors,
+        log::run_log,
+        phases::{collect_phases, run_phases},
+        show::run_show,
+        simple::{collect_simple, run_simple},
+        watch::run_watch,
+    },
     config::{Command, Config, ProfileConfig},
+    error::{JouleProfilerError, error_exit_code},
+    measurement::ProfileOutput,
+    util::{
+        governor::{CPU_SYSFS_PATH, stabilize_governor},
+        interrupt::{INTERRUPTED_EXIT_CODE, interrupted, watch_interrupt},
+        sysinfo::{read_sysinfo, warn_variance},
+    },
 };
 
+pub mod builder;
 pub mod cli;
 mod command;
-mod config;
+pub mod config;
 pub mod error;
-mod measurement;
+pub mod measurement;
 mod output;
 pub mod source;
+#[cfg(test)]
+mod test_util;
 mod util;
 
-/// Initialize and run Joule Profiler.
-pub async fn run() -> Result<()> {
+/// Initialize and run Joule Profiler, returning the exit code of the profiled command (130
+/// when interrupted).
+pub async fn run() -> Result<i32> {
     let cli = Cli::try_parse()?;
     init_logging(cli.verbose);
+    init_color(if cli.no_color {
+        ColorMode::Never
+    } else {
+        cli.color
+    });
 
-    let config = Config::from(cli);
+    let config = Config::try_from(cli)?;
 
     info!("Joule Profiler starting");
-    JouleProfiler::run(&config).await
+    let exit_code = match JouleProfiler::run(&config).await {
+        Err(err)
+            if matches!(
+                err.downcast_ref::<JouleProfilerError>(),
+                Some(JouleProfilerError::UnsupportedOS(_))
+            ) =>
+        {
+            eprintln!("{}", unsupported_os_help(std::env::consts::OS));
+            return Ok(error_exit_code(&err));
+        }
+        result => result?,
+    };
+
+    if interrupted() {
+        return Ok(INTERRUPTED_EXIT_CODE);
+    }
+    Ok(exit_code)
 }
 
 pub struct JouleProfiler;
 
 impl JouleProfiler {
-    /// Run Joule Profiler.
-    pub async fn run(config: &Config) -> Result<()> {
+    /// Run Joule Profiler, returning the exit code of the profiled command (0 for the
+    /// commands not running one).
+    pub async fn run(config: &Config) -> Result<i32> {
         match &config.mode {
             Command::Profile(profile_config) => Self::profile(profile_config).await,
-            Command::ListSensors(list_config) => run_list_sensors(list_config),
+            Command::ListSensors(list_config) => run_list_sensors(list_config).map(|_| 0),
+            Command::Env(env_config) => run_env(env_config).map(|_| 0),
+            Command::Show(show_config) => run_show(show_config).map(|_| 0),
+            Command::Log(log_config) => run_log(log_config).await.map(|_| 0),
+            Command::Watch(watch_config) => run_watch(watch_config).await.map(|_| 0),
         }
     }
 
-    pub async fn profile(config: &ProfileConfig) -> Result<()> {
+    /// Measure the command and display the results, those of the completed iterations when
+    /// the profiler is interrupted, returning the exit code of the command.
+    pub async fn profile(config: &ProfileConfig) -> Result<i32> {
+        if config.dry_run {
+            return run_dry_run(config).map(|_| 0);
+        }
+        watch_interrupt();
+
+        // Restores the governors when dropped, after the measurement succeeded or failed
+        let _governor = config
+            .stabilize_governor
+            .then(|| stabilize_governor(Path::new(CPU_SYSFS_PATH)))
+            .flatten();
+        warn_variance(&read_sysinfo(Path::new(CPU_SYSFS_PATH)));
+
         match &config.mode {
             config::Mode::SimpleMode => run_simple(config).await,
             config::Mode::PhaseMode(phases_config) => run_phases(config, phases_config).await,
+            config::Mode::CalibrateMode(calibrate_config) => {
+                run_calibrate(config, calibrate_config).await
+            }
+            config::Mode::CompareMode(compare_config) => run_compare(config, compare_config).await,
         }
     }
+
+    /// Measure the command and return the results instead of displaying them, for the crates
+    /// embedding the profiler. The output options are ignored, only the command output and
+    /// the files of the measurement options (e.g. the time series) are written.
+    pub async fn profile_collect(config: &ProfileConfig) -> Result<ProfileOutput> {
+        let _governor = config
+            .stabilize_governor
+            .then(|| stabilize_governor(Path::new(CPU_SYSFS_PATH)))
+            .flatten();
+        warn_variance(&read_sysinfo(Path::new(CPU_SYSFS_PATH)));
+
+        Ok(match &config.mode {
+            config::Mode::SimpleMode => ProfileOutput::Simple(collect_simple(config).await?),
+            config::Mode::PhaseMode(phases_config) => {
+                ProfileOutput::Phases(collect_phases(config, phases_config).await?)
+            }
+            config::Mode::CalibrateMode(calibrate_config) => {
+                ProfileOutput::Calibration(measure_calibration(config, calibrate_config).await?)
+            }
+            config::Mode::CompareMode(compare_config) => {
+                ProfileOutput::Comparison(measure_comparison(config, compare_config).await?)
+            }
+        })
+    }
+}
+
+/// Explanation printed instead of the error on the platforms without the RAPL counters.
+fn unsupported_os_help(os: &str) -> String {
+    format!(
+        "Energy measurement is not available on {}.\n\n\
+         joule-profiler reads the Intel RAPL energy counters through the Linux powercap \
+         interface (/sys/class/powercap), which only exists on Linux. Run it on Linux, on \
+         bare metal or in a virtual machine exposing RAPL.\n\
+         A backend based on powermetrics is planned to measure on macOS.",
+        os
+    )
+}
+
+/// Selects whether the terminal output is colored, `auto` lets anstream detect it.
+fn init_color(mode: ColorMode) {
+    let choice = match mode {
+        ColorMode::Auto => anstream::ColorChoice::Auto,
+        ColorMode::Always => anstream::ColorChoice::Always,
+        ColorMode::Never => anstream::ColorChoice::Never,
+    };
+    choice.write_global();
 }
 
 /// Initializes the logging system based on verbosity flags.
@@ -66,3 +180,65 @@ pub fn init_logging(level: u8) {
         _ => trace!("Logging initialized at TRACE level"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::FakeMetric,
+        config::{Mode, PhasesConfig},
+    };
+
+    fn config(cmd: &[&str], mode: Mode) -> ProfileConfig {
+        ProfileConfig {
+            iterations: 2,
+            cmd: cmd.iter().map(|arg| arg.to_string()).collect(),
+            fake_source: vec![FakeMetric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 1_000,
+            }],
+            mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unsupported_os_help_explains_the_linux_requirement() {
+        let help = unsupported_os_help("macos");
+
+        assert!(help.starts_with("Energy measurement is not available on macos."));
+        assert!(help.contains("Linux powercap interface (/sys/class/powercap)"));
+        assert!(help.contains("powermetrics"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn simple_results_are_collected_per_iteration() {
+        let output = JouleProfiler::profile_collect(&config(&["true"], Mode::SimpleMode))
+            .await
+            .unwrap();
+
+        let ProfileOutput::Simple(results) = output else {
+            panic!("expected simple results, got {:?}", output);
+        };
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].metrics[0].name, "PACKAGE-0_0");
+        assert_eq!(results[0].metrics[0].value, 1_000);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn phases_results_are_collected_per_iteration() {
+        let phases_config = PhasesConfig {
+            token_pattern: "__[A-Z]+__".to_string(),
+            ..Default::default()
+        };
+        let config = config(&["echo", "__WORK__"], Mode::PhaseMode(phases_config));
+
+        let output = JouleProfiler::profile_collect(&config).await.unwrap();
+
+        let ProfileOutput::Phases(results) = output else {
+            panic!("expected phases results, got {:?}", output);
+        };
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].phases.len(), 2);
+    }
+}
@@ -1,8 +1,16 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::source::Metric;
+use crate::{
+    cli::{OutlierMethod, Rounding, ScoreWeight},
+    error::JouleProfilerError,
+    source::{
+        FailedSource, Metric, PollingStats, PowerRange, PowerSample, RawReading,
+        proc::ThreadCpuTime,
+    },
+    util::stats::linear_regression,
+};
 
 #[derive(Debug, Clone)]
 pub enum PhaseToken {
@@ -30,6 +38,454 @@ impl From<PhaseToken> for Option<String> {
     }
 }
 
+/// Name of the estimated container energy metric.
+pub const CONTAINER_ENERGY: &str = "CONTAINER_ENERGY";
+
+/// Estimate the energy of a container as its share of the packages energy.
+///
+/// This is an approximation assuming the package energy is proportional to the busy
+/// CPU time, idle power and memory or uncore activity are attributed the same way.
+pub fn container_energy(metrics: &[Metric], cpu_share: f64, rounding: Rounding) -> Metric {
+    let package_uj = package_energy_uj(metrics);
+
+    Metric {
+        name: CONTAINER_ENERGY.to_string(),
+        value: round_float(package_uj as f64 * cpu_share, rounding),
+        unit: "µJ".to_string(),
+        source: "cgroup".to_string(),
+    }
+}
+
+/// Prefix of the estimated per-thread energy metrics, followed by the thread id and name.
+pub const THREAD_ENERGY_PREFIX: &str = "THREAD_ENERGY_";
+
+/// Estimate the energy of each thread as its share of the packages energy.
+///
+/// Like the container energy this is an approximation, the packages energy is split
+/// according to the CPU time of the threads only, threads without CPU time are omitted.
+pub fn thread_energy(
+    metrics: &[Metric],
+    threads: &[ThreadCpuTime],
+    rounding: Rounding,
+) -> Vec<Metric> {
+    let total_ticks: u64 = threads.iter().map(|thread| thread.ticks).sum();
+    if total_ticks == 0 {
+        return Vec::new();
+    }
+    let package_uj = package_energy_uj(metrics);
+
+    threads
+        .iter()
+        .filter(|thread| thread.ticks > 0)
+        .map(|thread| Metric {
+            name: format!(
+                "{}{}_{}",
+                THREAD_ENERGY_PREFIX,
+                thread.tid,
+                thread.name.replace(char::is_whitespace, "_")
+            ),
+            value: round_float(
+                package_uj as f64 * thread.ticks as f64 / total_ticks as f64,
+                rounding,
+            ),
+            unit: "µJ".to_string(),
+            source: "proc-estimate".to_string(),
+        })
+        .collect()
+}
+
+/// Metric of two sources whose values differ by more than a tolerance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub name: String,
+    pub reference: u64,
+    pub value: u64,
+    /// Difference relative to the reference value in percent
+    pub deviation_pct: f64,
+}
+
+/// Compare the energy metrics reported under the same name by a reference source and
+/// another one, returning those differing by more than `tolerance_pct`.
+pub fn diverging_metrics(
+    reference: &[Metric],
+    other: &[Metric],
+    tolerance_pct: f64,
+) -> Vec<Divergence> {
+    other
+        .iter()
+        .filter(|metric| metric.unit != RATIO_UNIT)
+        .filter_map(|metric| {
+            let reference = reference.iter().find(|r| r.name == metric.name)?;
+            let deviation_pct = match (reference.value, metric.value) {
+                (0, 0) => 0.0,
+                (0, _) => f64::INFINITY,
+                (r, v) => r.abs_diff(v) as f64 / r as f64 * 100.0,
+            };
+            (deviation_pct > tolerance_pct).then(|| Divergence {
+                name: metric.name.clone(),
+                reference: reference.value,
+                value: metric.value,
+                deviation_pct,
+            })
+        })
+        .collect()
+}
+
+/// Energy in microjoules of all the packages.
+pub fn package_energy_uj(metrics: &[Metric]) -> u64 {
+    metrics
+        .iter()
+        .filter(|metric| metric.unit != RATIO_UNIT && metric.name.starts_with("PACKAGE"))
+        .map(|metric| metric.value)
+        .sum()
+}
+
+/// Whether the metric is an energy, unlike the ratios or the procfs memory and CPU time.
+pub fn is_energy(metric: &Metric) -> bool {
+    matches!(metric.unit.as_str(), "µJ" | "mJ")
+}
+
+/// Energy of a metric in microjoules, `None` for the metrics that are not an energy.
+pub fn energy_uj(metric: &Metric) -> Option<u64> {
+    match metric.unit.as_str() {
+        "µJ" => Some(metric.value),
+        "mJ" => Some(metric.value.saturating_mul(1_000)),
+        _ => None,
+    }
+}
+
+/// Metrics in µJ derived from the measured energy that are not consumed over the run, e.g.
+/// the energy per result, and have no average power.
+const NO_POWER_METRICS: [&str; 3] = [ENERGY_PER_RESULT, SCORE_METRIC, WHAT_IF_CAP_ENERGY];
+
+/// Average power of an energy metric over a duration in watts, `None` for the metrics that
+/// are not an energy (e.g. ratios) or not consumed over the duration (see
+/// [`NO_POWER_METRICS`]) and `0.0` for an empty duration.
+pub fn average_power_w(metric: &Metric, duration_ms: u128) -> Option<f64> {
+    if NO_POWER_METRICS.contains(&metric.name.as_str()) {
+        return None;
+    }
+    let energy_uj = energy_uj(metric)?;
+    if duration_ms == 0 {
+        return Some(0.0);
+    }
+    Some(energy_uj as f64 / 1_000.0 / duration_ms as f64)
+}
+
+/// Average power in watts of each energy metric, by metric name.
+pub fn power_by_metric(metrics: &[Metric], duration_ms: u128) -> BTreeMap<String, f64> {
+    metrics
+        .iter()
+        .filter_map(|metric| {
+            average_power_w(metric, duration_ms).map(|power| (metric.name.clone(), power))
+        })
+        .collect()
+}
+
+/// Name of the energy per command result metric.
+pub const ENERGY_PER_RESULT: &str = "ENERGY_PER_RESULT";
+
+/// Energy in microjoules of the packages and DRAM domains, the other domains being
+/// already included in the packages.
+pub fn total_energy_uj(metrics: &[Metric]) -> u64 {
+    metrics
+        .iter()
+        .filter(|metric| metric.unit != RATIO_UNIT)
+        .filter(|metric| metric.name.starts_with("PACKAGE") || metric.name.starts_with("DRAM"))
+        .map(|metric| metric.value)
+        .sum()
+}
+
+/// Energy consumed per unit of the numeric result reported by the command (e.g. per operation).
+pub fn energy_per_result(metrics: &[Metric], result: f64, rounding: Rounding) -> Option<Metric> {
+    if !result.is_finite() || result <= 0.0 {
+        return None;
+    }
+
+    Some(Metric {
+        name: ENERGY_PER_RESULT.to_string(),
+        value: round_float(total_energy_uj(metrics) as f64 / result, rounding),
+        unit: "µJ".to_string(),
+        source: "derived".to_string(),
+    })
+}
+
+/// Name of the throughput per watt metric, with `--ops-per-watt`.
+pub const OPS_PER_WATT: &str = "OPS_PER_WATT";
+
+/// Throughput reported by the command (e.g. ops/sec) per watt of average power of the
+/// packages and DRAM, `None` without a positive throughput, duration or energy.
+pub fn ops_per_watt_metric(
+    metrics: &[Metric],
+    throughput: f64,
+    duration_ms: u128,
+    rounding: Rounding,
+) -> Option<Metric> {
+    let energy_uj = total_energy_uj(metrics);
+    if !throughput.is_finite() || throughput <= 0.0 || duration_ms == 0 || energy_uj == 0 {
+        return None;
+    }
+
+    let average_power_w = energy_uj as f64 / 1_000.0 / duration_ms as f64;
+    Some(Metric {
+        name: OPS_PER_WATT.to_string(),
+        value: round_float(throughput / average_power_w, rounding),
+        unit: "ops/W".to_string(),
+        source: "derived".to_string(),
+    })
+}
+
+/// Unit of the ratio metrics, in thousandths of the reference energy.
+pub const RATIO_UNIT: &str = "‰";
+
+/// Socket suffix of a metric named `NAME_<socket>`.
+pub fn metric_socket(name: &str) -> Option<&str> {
+    name.rsplit_once('_')
+        .map(|(_, socket)| socket)
+        .filter(|socket| !socket.is_empty() && socket.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Domain type of a metric named `NAME_<socket>`, without its socket numbers (`PACKAGE-0_0`
+/// gives `PACKAGE`).
+fn metric_domain(name: &str) -> Option<&str> {
+    metric_socket(name)?;
+    let (domain, _) = name.rsplit_once('_')?;
+    Some(match domain.rsplit_once('-') {
+        Some((prefix, id)) if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) => prefix,
+        _ => domain,
+    })
+}
+
+/// Sum of the metrics grouped by the name given by `total_name`, in order of first appearance;
+/// the metrics without a total name are skipped.
+fn sum_metrics<'a>(
+    metrics: impl IntoIterator<Item = &'a Metric>,
+    total_name: impl Fn(&Metric) -> Option<String>,
+    source: &str,
+) -> Vec<Metric> {
+    let mut totals: Vec<Metric> = Vec::new();
+
+    for metric in metrics {
+        let Some(name) = total_name(metric) else {
+            continue;
+        };
+        match totals.iter_mut().find(|total| total.name == name) {
+            Some(total) => total.value += metric.value,
+            None => totals.push(Metric {
+                name,
+                value: metric.value,
+                unit: metric.unit.clone(),
+                source: source.to_string(),
+            }),
+        }
+    }
+
+    totals
+}
+
+/// Energy of each domain type summed across the sockets, named `{DOMAIN}_TOTAL`, in order
+/// of first appearance.
+pub fn socket_totals(metrics: &[Metric]) -> Vec<Metric> {
+    sum_metrics(
+        metrics
+            .iter()
+            .filter(|metric| metric.unit != RATIO_UNIT && metric.source != TOTALS_SOURCE),
+        |metric| metric_domain(&metric.name).map(|domain| format!("{}_TOTAL", domain)),
+        "derived",
+    )
+}
+
+/// Source of the totals added with `--totals`.
+pub const TOTALS_SOURCE: &str = "computed";
+
+/// Energy of the packages and DRAM of each socket, named `TOTAL_<socket>`, followed by their
+/// sum across the sockets named `TOTAL`, for `--totals`.
+///
+/// The core and uncore domains are part of the package energy, they are not added again.
+/// Only the metrics of the unit of the first one are summed, so that different units are
+/// never mixed.
+pub fn energy_totals(metrics: &[Metric]) -> Vec<Metric> {
+    let top_level: Vec<&Metric> = metrics
+        .iter()
+        .filter(|metric| {
+            metric.source != TOTALS_SOURCE
+                && (metric.name.starts_with("PACKAGE") || metric.name.starts_with("DRAM"))
+        })
+        .collect();
+    let Some(unit) = top_level.first().map(|metric| metric.unit.clone()) else {
+        return Vec::new();
+    };
+
+    let mut totals = sum_metrics(
+        top_level.into_iter().filter(|metric| metric.unit == unit),
+        |metric| {
+            let socket: u32 = metric_socket(&metric.name)?.parse().ok()?;
+            Some(format!("TOTAL_{}", socket))
+        },
+        TOTALS_SOURCE,
+    );
+    totals.sort_by_key(|total| {
+        metric_socket(&total.name).and_then(|socket| socket.parse::<u32>().ok())
+    });
+    if !totals.is_empty() {
+        totals.push(Metric {
+            name: "TOTAL".to_string(),
+            value: totals.iter().map(|total| total.value).sum(),
+            unit,
+            source: TOTALS_SOURCE.to_string(),
+        });
+    }
+    totals
+}
+
+/// Energy of each metric relative to a reference domain, in thousandths of the reference.
+///
+/// A reference naming an exact metric (`PACKAGE-0_0`) is used for all the metrics, otherwise
+/// it is a domain prefix (`PACKAGE`) and each metric is compared to the reference of its socket.
+/// Metrics whose reference is missing or has no energy are skipped.
+pub fn ratio_metrics(metrics: &[Metric], reference: &str, rounding: Rounding) -> Vec<Metric> {
+    let exact = metrics.iter().find(|metric| metric.name == reference);
+    let reference_of = |metric: &Metric| {
+        exact.or_else(|| {
+            let socket = metric_socket(&metric.name)?;
+            metrics.iter().find(|candidate| {
+                candidate.name.starts_with(reference)
+                    && metric_socket(&candidate.name) == Some(socket)
+            })
+        })
+    };
+
+    metrics
+        .iter()
+        .filter_map(|metric| {
+            let reference = reference_of(metric)?;
+            if reference.name == metric.name || reference.value == 0 {
+                return None;
+            }
+            Some(Metric {
+                name: format!("{}/{}", metric.name, reference.name),
+                value: round_float(
+                    metric.value as f64 * 1_000.0 / reference.value as f64,
+                    rounding,
+                ),
+                unit: RATIO_UNIT.to_string(),
+                source: "ratio".to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Name of the weighted sum of metrics added with `--score`.
+pub const SCORE_METRIC: &str = "SCORE";
+
+/// Weighted sum of metrics in a single `SCORE` metric in µJ, every weighted metric must be
+/// measured and be an energy, converted to µJ so that the units are never mixed.
+pub fn score_metric(
+    metrics: &[Metric],
+    weights: &[ScoreWeight],
+    rounding: Rounding,
+) -> Result<Metric, JouleProfilerError> {
+    let mut score = 0.0;
+    for weight in weights {
+        let metric = metrics
+            .iter()
+            .find(|metric| metric.name == weight.name)
+            .ok_or_else(|| {
+                let names: Vec<_> = metrics.iter().map(|metric| metric.name.as_str()).collect();
+                JouleProfilerError::InvalidScore(format!(
+                    "metric {} not measured (available: {})",
+                    weight.name,
+                    names.join(", ")
+                ))
+            })?;
+        let Some(energy_uj) = energy_uj(metric) else {
+            return Err(JouleProfilerError::InvalidScore(format!(
+                "metric {} is not an energy (unit {})",
+                weight.name, metric.unit
+            )));
+        };
+        score += energy_uj as f64 * weight.weight;
+    }
+
+    Ok(Metric {
+        name: SCORE_METRIC.to_string(),
+        value: round_float(score, rounding),
+        unit: "µJ".to_string(),
+        source: "score".to_string(),
+    })
+}
+
+/// Name of the packages energy estimated at another power cap with `--what-if-cap`.
+pub const WHAT_IF_CAP_ENERGY: &str = "WHAT_IF_CAP_ENERGY";
+
+/// Crude estimate of the packages energy had their power been capped at `cap_w` watts.
+///
+/// Above the cap the energy is scaled by `cap / average power`, as if the run had lasted as
+/// long at the capped power; the slowdown a real cap causes, and the idle energy of the
+/// longer run, are ignored. A cap at or above the average power leaves the energy unchanged.
+pub fn what_if_cap_metric(
+    metrics: &[Metric],
+    duration_ms: u128,
+    cap_w: f64,
+    rounding: Rounding,
+) -> Option<Metric> {
+    let energy_uj = package_energy_uj(metrics);
+    if duration_ms == 0 || energy_uj == 0 {
+        return None;
+    }
+
+    let average_power_w = energy_uj as f64 / 1_000.0 / duration_ms as f64;
+    let scale = (cap_w / average_power_w).min(1.0);
+
+    Some(Metric {
+        name: WHAT_IF_CAP_ENERGY.to_string(),
+        value: round_float(energy_uj as f64 * scale, rounding),
+        unit: "µJ".to_string(),
+        source: "what-if".to_string(),
+    })
+}
+
+/// Name of the machine CPU utilization over the run measured with `--build-mode`.
+pub const CPU_UTILIZATION: &str = "CPU_UTILIZATION";
+
+/// Machine CPU utilization below which the idle time of a build is significant.
+pub const BUILD_MIN_UTILIZATION: f64 = 0.8;
+
+/// Machine CPU utilization in [0, 1] as a metric in thousandths.
+pub fn cpu_utilization_metric(utilization: f64) -> Metric {
+    Metric {
+        name: CPU_UTILIZATION.to_string(),
+        value: (utilization * 1_000.0).round() as u64,
+        unit: RATIO_UNIT.to_string(),
+        source: "proc".to_string(),
+    }
+}
+
+/// Whether the machine was idle for a significant share of a build, its energy then
+/// includes I/O stalls rather than the work of the build processes.
+pub fn is_idle_build(utilization: f64) -> bool {
+    utilization < BUILD_MIN_UTILIZATION
+}
+
+/// Divide an integer total, rounding the quotient with the given mode.
+pub fn divide_rounded(value: u128, divisor: u128, rounding: Rounding) -> u128 {
+    if divisor == 0 {
+        return 0;
+    }
+    match rounding {
+        Rounding::Down => value / divisor,
+        Rounding::Nearest => (value + divisor / 2) / divisor,
+    }
+}
+
+/// Convert a float energy to an integer with the given rounding mode.
+fn round_float(value: f64, rounding: Rounding) -> u64 {
+    match rounding {
+        Rounding::Down => value.trunc() as u64,
+        Rounding::Nearest => value.round() as u64,
+    }
+}
+
 /// Detected token with timestamp
 #[derive(Debug, Clone)]
 pub struct Phase {
@@ -38,7 +494,7 @@ pub struct Phase {
     pub line_number: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PhaseResult {
     pub name: String,
 
@@ -57,6 +513,28 @@ pub struct PhaseResult {
     pub metrics: Vec<Metric>,
 
     pub duration_ms: u128,
+
+    /// Start of the phase in microseconds after the start of the command, for the trace
+    /// events
+    #[serde(skip)]
+    pub offset_us: u128,
+
+    /// Start of the phase in microseconds since the Unix epoch, to correlate it with
+    /// external traces, unknown in the results written without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_us: Option<u128>,
+
+    /// End of the phase in microseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_us: Option<u128>,
+
+    /// Number of times an energy counter wrapped during the phase
+    #[serde(default)]
+    pub counter_wraps: u64,
+
+    /// Lowest and highest power of each RAPL metric over the polling intervals of the phase
+    #[serde(default)]
+    pub power_range: BTreeMap<String, PowerRange>,
 }
 
 impl PhaseResult {
@@ -77,21 +555,81 @@ impl PhaseResult {
             start_line,
             end_line,
             metrics,
+            offset_us: 0,
+            start_us: None,
+            end_us: None,
+            counter_wraps: 0,
+            power_range: BTreeMap::new(),
         }
     }
 
+    /// Set the start of the phase in microseconds after the start of the command.
+    pub fn starting_at(mut self, offset_us: u128) -> Self {
+        self.offset_us = offset_us;
+        self
+    }
+
+    /// Set the start and end of the phase in microseconds since the Unix epoch.
+    pub fn between(mut self, start_us: u128, end_us: u128) -> Self {
+        self.start_us = Some(start_us);
+        self.end_us = Some(end_us);
+        self
+    }
+
+    /// Set the number of energy counter wraps during the phase.
+    pub fn with_counter_wraps(mut self, counter_wraps: u64) -> Self {
+        self.counter_wraps = counter_wraps;
+        self
+    }
+
+    /// Set the power range of the metrics over the phase.
+    pub fn with_power_range(mut self, power_range: BTreeMap<String, PowerRange>) -> Self {
+        self.power_range = power_range;
+        self
+    }
+
     pub fn extract_keys(&self) -> Vec<&String> {
         self.metrics.iter().map(|metric| &metric.name).collect()
     }
+
+    /// Explain how the phase boundaries were derived from the command output.
+    pub fn derivation(&self) -> String {
+        let boundary = |token: &Option<String>, line: Option<usize>, ordinal: &str| match token {
+            Some(token) => match line {
+                Some(line) => format!("{}token '{}' at line {}", ordinal, token, line),
+                None => format!("{}token '{}'", ordinal, token),
+            },
+            None => String::new(),
+        };
+
+        match (&self.start_token, &self.end_token) {
+            (None, None) => "START -> END, no token".to_string(),
+            (None, end) => format!("START -> {}", boundary(end, self.end_line, "first ")),
+            (start, None) => format!("{} -> END", boundary(start, self.start_line, "last ")),
+            (start, end) => format!(
+                "{} -> {}",
+                boundary(start, self.start_line, ""),
+                boundary(end, self.end_line, "")
+            ),
+        }
+    }
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct PhaseMeasurementResult {
     /// The metrics of each phase
     pub phases: Vec<PhaseResult>,
-    /// Duration in milliseconds
+    /// Duration in milliseconds, named `duration` in the JSON iterations written by the
+    /// previous versions
+    #[serde(default, alias = "duration")]
     pub duration_ms: u128,
     /// Command exit code
     pub exit_code: i32,
+    /// Sources that failed during the measurement
+    pub failed_sources: Vec<FailedSource>,
+    /// Effective polling of each source
+    #[serde(default)]
+    pub polling: BTreeMap<String, PollingStats>,
 }
 
 impl PhaseMeasurementResult {
@@ -101,9 +639,107 @@ impl PhaseMeasurementResult {
             .flat_map(|phase| phase.extract_keys())
             .collect()
     }
+
+    /// Tokens detected in the output, in order, each phase boundary being one occurrence.
+    pub fn tokens(&self) -> Vec<&str> {
+        // The phases are contiguous, so every token ends a phase except the first start token
+        // when the phases before it were dropped
+        let first_start = self
+            .phases
+            .first()
+            .and_then(|phase| phase.start_token.as_deref());
+
+        first_start
+            .into_iter()
+            .chain(
+                self.phases
+                    .iter()
+                    .filter_map(|phase| phase.end_token.as_deref()),
+            )
+            .collect()
+    }
+
+    /// Share of each phase in the energy of the iteration, in percent of each energy metric.
+    ///
+    /// The whole-run START -> END phase is left out of the total, so that the shares of the
+    /// phases between the tokens sum to 100%, unless it is the only phase. A metric without
+    /// energy in any phase has a 0% share.
+    pub fn energy_percents(&self) -> Vec<BTreeMap<String, f64>> {
+        let is_global =
+            |phase: &PhaseResult| phase.start_token.is_none() && phase.end_token.is_none();
+        let single_global = self.phases.iter().all(is_global);
+
+        let mut totals: BTreeMap<&str, u64> = BTreeMap::new();
+        for phase in self
+            .phases
+            .iter()
+            .filter(|phase| single_global || !is_global(phase))
+        {
+            for metric in phase.metrics.iter().filter(|metric| is_energy(metric)) {
+                *totals.entry(&metric.name).or_default() += metric.value;
+            }
+        }
+
+        self.phases
+            .iter()
+            .map(|phase| {
+                phase
+                    .metrics
+                    .iter()
+                    .filter_map(|metric| {
+                        let total = *totals.get(metric.name.as_str())?;
+                        let percent = if total == 0 {
+                            0.0
+                        } else {
+                            metric.value as f64 * 100.0 / total as f64
+                        };
+                        Some((metric.name.clone(), percent))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Number of occurrences of a phase token in each iteration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenCount {
+    pub token: String,
+    /// Occurrences of the token per iteration, in order of the iterations
+    pub counts: Vec<usize>,
+}
+
+impl TokenCount {
+    /// Count the occurrences of each token per iteration, tokens in order of first appearance.
+    pub fn from_iterations(results: &[PhaseMeasurementResult]) -> Vec<Self> {
+        let mut counts: Vec<Self> = Vec::new();
+
+        for (idx, result) in results.iter().enumerate() {
+            for token in result.tokens() {
+                let count = match counts.iter_mut().find(|count| count.token == token) {
+                    Some(count) => count,
+                    None => {
+                        counts.push(Self {
+                            token: token.to_string(),
+                            counts: vec![0; results.len()],
+                        });
+                        counts.last_mut().expect("a count was just pushed")
+                    }
+                };
+                count.counts[idx] += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Whether the token was not emitted the same number of times in every iteration.
+    pub fn is_variable(&self) -> bool {
+        self.counts.windows(2).any(|pair| pair[0] != pair[1])
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MeasurementResult {
     /// Metrics measured
     pub metrics: Vec<Metric>,
@@ -111,14 +747,1381 @@ pub struct MeasurementResult {
     pub duration_ms: u128,
     /// Command exit code
     pub exit_code: i32,
+    /// Runs of the command for this iteration, above 1 when failed runs were retried
+    #[serde(default = "first_attempt")]
+    pub attempts: u32,
+    /// Runs of the command back to back in the measurement with `--min-duration`, the
+    /// energy metrics and the duration being those of one run
+    #[serde(default = "single_run")]
+    pub repetitions: u32,
     /// The number of measures made by the sources
     pub measure_count: u64,
 
     pub measure_delta: u128,
+    /// Largest time taken to read all the domains of one measure in microseconds
+    #[serde(default)]
+    pub read_spread_us: u128,
+    /// Sources that failed during the measurement
+    pub failed_sources: Vec<FailedSource>,
+    /// Numeric result extracted from the command output with `--result-pattern`
+    pub result_value: Option<f64>,
+    /// Effective polling of each source
+    #[serde(default)]
+    pub polling: BTreeMap<String, PollingStats>,
+    /// Lowest and highest power of each metric over the polling intervals
+    #[serde(default)]
+    pub power_range: BTreeMap<String, PowerRange>,
+    /// First and last counter readings of each domain path with `--raw-counters`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub raw: BTreeMap<String, RawReading>,
+    /// Energy over the equal intervals of the run with `--simple-samples`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub intervals: Vec<IntervalEnergy>,
+    /// Number of times an energy counter wrapped during the measurement, the range being
+    /// added to its energy at each wrap
+    #[serde(default)]
+    pub counter_wraps: u64,
+    /// Power levels of each package metric with steps between them, with `--detect-turbo`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub power_states: BTreeMap<String, PowerStates>,
+}
+
+/// Low and high power levels of a metric over the time series bins of all the iterations,
+/// and the time of one iteration in the high-power state.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PowerStates {
+    pub low_power_w: f64,
+    pub high_power_w: f64,
+    /// Fraction of the bins of the iteration in the high-power state
+    pub high_fraction: f64,
+    /// Number of switches between the two states in the iteration
+    pub steps: usize,
+}
+
+/// Energy of the metrics over one of the equal intervals of a run.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IntervalEnergy {
+    /// Start of the interval after the first measure, in microseconds
+    pub start_us: u128,
+    pub duration_us: u128,
+    pub metrics: Vec<Metric>,
+}
+
+/// Energy of a metric over a sample as `(start, end, energy)`, in µs after the first measure.
+type EnergySpan = (f64, f64, u64);
+
+/// Split the energy of the polling samples of a run into `count` intervals of equal
+/// duration, the energy of a sample straddling two intervals being shared in proportion to
+/// its time in each. The intervals of a metric sum to its energy over the samples.
+pub fn resample_intervals(samples: &[PowerSample], count: usize) -> Vec<IntervalEnergy> {
+    let end_us = samples
+        .iter()
+        .map(|sample| sample.elapsed_us)
+        .max()
+        .unwrap_or(0);
+    if count == 0 || end_us == 0 {
+        return Vec::new();
+    }
+
+    let mut spans: BTreeMap<&str, (&Metric, Vec<EnergySpan>)> = BTreeMap::new();
+    for sample in samples {
+        let end = sample.elapsed_us as f64;
+        let start = end - sample.interval_us as f64;
+        for metric in &sample.metrics {
+            spans
+                .entry(&metric.name)
+                .or_insert_with(|| (metric, Vec::new()))
+                .1
+                .push((start, end, metric.value));
+        }
+    }
+
+    let width = end_us as f64 / count as f64;
+    let boundary_us = |idx: usize| (idx as f64 * width).round() as u128;
+    let mut intervals: Vec<IntervalEnergy> = (0..count)
+        .map(|idx| IntervalEnergy {
+            start_us: boundary_us(idx),
+            duration_us: boundary_us(idx + 1) - boundary_us(idx),
+            metrics: Vec::new(),
+        })
+        .collect();
+
+    for (metric, spans) in spans.values() {
+        // Energy consumed before the time, interpolated inside the spans
+        let cumulative = |time: f64| -> f64 {
+            spans
+                .iter()
+                .map(|&(start, end, energy)| {
+                    if time >= end {
+                        energy as f64
+                    } else if time <= start {
+                        0.0
+                    } else {
+                        energy as f64 * (time - start) / (end - start)
+                    }
+                })
+                .sum()
+        };
+        let total: u64 = spans.iter().map(|&(_, _, energy)| energy).sum();
+
+        // The rounded cumulative energies are differentiated, so no energy is lost
+        let mut consumed = 0;
+        for (idx, interval) in intervals.iter_mut().enumerate() {
+            let until = if idx + 1 == count {
+                total
+            } else {
+                (cumulative((idx + 1) as f64 * width).round() as u64).min(total)
+            };
+            interval.metrics.push(Metric {
+                value: until - consumed,
+                ..(*metric).clone()
+            });
+            consumed = until;
+        }
+    }
+
+    intervals
+}
+
+/// Attempts of the results written before `--retries` existed.
+fn first_attempt() -> u32 {
+    1
+}
+
+/// Repetitions of the results written before `--min-duration` existed.
+fn single_run() -> u32 {
+    1
+}
+
+/// An empty measurement of a single run, as for the results written without the optional
+/// fields.
+impl Default for MeasurementResult {
+    fn default() -> Self {
+        Self {
+            metrics: Vec::new(),
+            duration_ms: 0,
+            exit_code: 0,
+            attempts: first_attempt(),
+            repetitions: single_run(),
+            measure_count: 0,
+            measure_delta: 0,
+            read_spread_us: 0,
+            failed_sources: Vec::new(),
+            result_value: None,
+            polling: BTreeMap::new(),
+            power_range: BTreeMap::new(),
+            raw: BTreeMap::new(),
+            intervals: Vec::new(),
+            counter_wraps: 0,
+            power_states: BTreeMap::new(),
+        }
+    }
 }
 
 impl MeasurementResult {
     pub fn extract_keys(&self) -> Vec<&String> {
         self.metrics.iter().map(|metric| &metric.name).collect()
     }
+
+    /// Energy of the whole run in microjoules, summing the packages and DRAM domains
+    /// only since the other domains are already included in the packages.
+    pub fn total_energy_uj(&self) -> u64 {
+        total_energy_uj(&self.metrics)
+    }
+
+    /// Average power of a metric over the run in watts, see [`average_power_w`].
+    pub fn average_power_w(&self, metric: &Metric) -> Option<f64> {
+        average_power_w(metric, self.duration_ms)
+    }
+
+    /// Energy-delay product of the run in joule seconds.
+    pub fn energy_delay_product(&self) -> f64 {
+        self.total_energy_uj() as f64 / 1_000_000.0 * self.duration_ms as f64 / 1_000.0
+    }
+}
+
+/// Coefficient of variation above which a metric is reported as unstable.
+pub const CV_WARNING_THRESHOLD: f64 = 0.05;
+
+/// Statistics of one metric over the iterations of a simple mode run.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSummary {
+    pub name: String,
+    pub unit: String,
+    pub mean: f64,
+    /// Sample standard deviation, zero with a single iteration
+    pub stddev: f64,
+    /// Standard error of the mean (stddev / sqrt(n)), omitted with less than two iterations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sem: Option<f64>,
+    /// Coefficient of variation (stddev / mean), zero when the mean is zero
+    pub cv: f64,
+    /// Whether the coefficient of variation exceeds [`CV_WARNING_THRESHOLD`]
+    pub unstable: bool,
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+    /// Iterations the statistics are computed over
+    pub samples: usize,
+    /// Outlier iterations left out of the statistics, with `--drop-outliers`
+    pub dropped: usize,
+}
+
+impl MetricSummary {
+    /// Summarize the metrics of the iterations, in order of first appearance.
+    pub fn from_iterations(results: &[MeasurementResult]) -> Vec<Self> {
+        Self::from_iterations_without_outliers(results, None)
+    }
+
+    /// Summarize the metrics of the iterations, the outliers of each metric being dropped
+    /// with the method.
+    pub fn from_iterations_without_outliers(
+        results: &[MeasurementResult],
+        outliers: Option<OutlierMethod>,
+    ) -> Vec<Self> {
+        samples_by_metric(results.iter().map(|result| result.metrics.as_slice()))
+            .into_iter()
+            .map(|(metric, samples)| {
+                let kept = match outliers {
+                    Some(method) => drop_outliers(&samples, method),
+                    None => samples.clone(),
+                };
+                Self {
+                    dropped: samples.len() - kept.len(),
+                    ..Self::new(metric, &kept)
+                }
+            })
+            .collect()
+    }
+
+    /// Summarize sets of metrics, a metric missing from some sets is summarized over the
+    /// sets reporting it instead of being counted as zero.
+    fn from_metrics<'a>(sets: impl IntoIterator<Item = &'a [Metric]>) -> Vec<Self> {
+        samples_by_metric(sets)
+            .into_iter()
+            .map(|(metric, samples)| Self::new(metric, &samples))
+            .collect()
+    }
+
+    fn new(metric: &Metric, samples: &[f64]) -> Self {
+        let count = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let stddev = if samples.len() > 1 {
+            let variance = samples.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1.0);
+            variance.sqrt()
+        } else {
+            0.0
+        };
+        let sem = (samples.len() > 1).then(|| stddev / count.sqrt());
+        let cv = if mean == 0.0 { 0.0 } else { stddev / mean };
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        };
+
+        Self {
+            name: metric.name.clone(),
+            unit: metric.unit.clone(),
+            mean,
+            stddev,
+            sem,
+            cv,
+            unstable: cv > CV_WARNING_THRESHOLD,
+            min: sorted[0],
+            median,
+            max: sorted[sorted.len() - 1],
+            samples: samples.len(),
+            dropped: 0,
+        }
+    }
+}
+
+/// Values of each metric in the sets, in order of first appearance.
+fn samples_by_metric<'a>(
+    sets: impl IntoIterator<Item = &'a [Metric]>,
+) -> Vec<(&'a Metric, Vec<f64>)> {
+    let mut values: Vec<(&Metric, Vec<f64>)> = Vec::new();
+    for metrics in sets {
+        for metric in metrics {
+            match values.iter_mut().find(|(m, _)| m.name == metric.name) {
+                Some((_, samples)) => samples.push(metric.value as f64),
+                None => values.push((metric, vec![metric.value as f64])),
+            }
+        }
+    }
+    values
+}
+
+/// Fewer samples are too few to tell the outliers apart, nothing is dropped.
+pub const MIN_OUTLIER_SAMPLES: usize = 4;
+
+/// Modified z-score above which a sample is an outlier with [`OutlierMethod::Mad`].
+const MAD_Z_SCORE_THRESHOLD: f64 = 3.5;
+
+/// Keep the samples which are not outliers according to the method, in their order.
+pub fn drop_outliers(samples: &[f64], method: OutlierMethod) -> Vec<f64> {
+    if samples.len() < MIN_OUTLIER_SAMPLES {
+        return samples.to_vec();
+    }
+
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let (low, high) = match method {
+        OutlierMethod::Iqr => {
+            let (q1, q3) = (quantile(&sorted, 0.25), quantile(&sorted, 0.75));
+            let fence = 1.5 * (q3 - q1);
+            (q1 - fence, q3 + fence)
+        }
+        OutlierMethod::Mad => {
+            let median = quantile(&sorted, 0.5);
+            let mut deviations: Vec<f64> = sorted.iter().map(|v| (v - median).abs()).collect();
+            deviations.sort_by(f64::total_cmp);
+            let mad = quantile(&deviations, 0.5);
+            if mad == 0.0 {
+                // More than half the samples are equal, the deviation cannot scale the others
+                return samples.to_vec();
+            }
+            // 0.6745 makes the deviation consistent with the standard deviation of a normal
+            let fence = MAD_Z_SCORE_THRESHOLD * mad / 0.6745;
+            (median - fence, median + fence)
+        }
+    };
+
+    samples
+        .iter()
+        .copied()
+        .filter(|value| (low..=high).contains(value))
+        .collect()
+}
+
+/// Quantile of sorted samples, linearly interpolated between the closest ranks.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+/// Statistics of the metrics of one phase over the iterations of a phases mode run.
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseSummary {
+    pub name: String,
+    pub metrics: Vec<MetricSummary>,
+}
+
+impl PhaseSummary {
+    /// Summarize the phases by name in order of first appearance, every occurrence of a
+    /// phase repeated within an iteration being one sample.
+    pub fn from_iterations(results: &[PhaseMeasurementResult]) -> Vec<Self> {
+        let mut phases: Vec<(&str, Vec<&[Metric]>)> = Vec::new();
+        for phase in results.iter().flat_map(|result| &result.phases) {
+            match phases.iter_mut().find(|(name, _)| *name == phase.name) {
+                Some((_, sets)) => sets.push(&phase.metrics),
+                None => phases.push((&phase.name, vec![&phase.metrics])),
+            }
+        }
+
+        phases
+            .into_iter()
+            .map(|(name, sets)| Self {
+                name: name.to_string(),
+                metrics: MetricSummary::from_metrics(sets),
+            })
+            .collect()
+    }
+}
+
+/// Coefficient of determination above which the energy trend over the iterations is reported
+/// as a drift, together with [`TREND_DRIFT_THRESHOLD`].
+pub const TREND_R2_THRESHOLD: f64 = 0.5;
+
+/// Change of the total energy between the first and the last iteration, relative to the mean,
+/// above which the trend is reported as a drift.
+pub const TREND_DRIFT_THRESHOLD: f64 = 0.02;
+
+/// Linear trend of the total energy (packages and DRAM) versus the iteration index.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnergyTrend {
+    /// Energy change per iteration in µJ
+    pub slope_uj: f64,
+    pub r_squared: f64,
+    /// Energy change over the run relative to the mean total energy
+    pub relative_drift: f64,
+    /// Whether the trend is significant, e.g. a thermal drift
+    pub drifting: bool,
+}
+
+impl EnergyTrend {
+    /// Fit the total energy of the iterations, `None` with less than two iterations.
+    pub fn from_iterations(results: &[MeasurementResult]) -> Option<Self> {
+        let points: Vec<_> = results
+            .iter()
+            .enumerate()
+            .map(|(idx, result)| ((idx + 1) as f64, result.total_energy_uj() as f64))
+            .collect();
+        let fit = linear_regression(&points)?;
+
+        let mean = points.iter().map(|(_, energy)| energy).sum::<f64>() / points.len() as f64;
+        let relative_drift = if mean == 0.0 {
+            0.0
+        } else {
+            fit.slope * (points.len() - 1) as f64 / mean
+        };
+
+        Some(Self {
+            slope_uj: fit.slope,
+            r_squared: fit.r_squared,
+            relative_drift,
+            drifting: fit.r_squared >= TREND_R2_THRESHOLD
+                && relative_drift.abs() >= TREND_DRIFT_THRESHOLD,
+        })
+    }
+}
+
+/// Limit which ended the iterations, see [`StopCondition`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Iterations,
+    TimeBudget,
+    EnergyBudget,
+    Interrupted,
+    CommandFailed,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Iterations => f.write_str("iteration count"),
+            StopReason::TimeBudget => f.write_str("time budget"),
+            StopReason::EnergyBudget => f.write_str("energy budget"),
+            StopReason::Interrupted => f.write_str("interrupted"),
+            StopReason::CommandFailed => f.write_str("the command failed"),
+        }
+    }
+}
+
+/// Stops the iterations when the first of the limits is reached: the iteration count, the
+/// wall time or the total energy (packages and DRAM) spent by the iterations, or when the
+/// profiler is interrupted or, with `--fail-fast`, the command failed.
+#[derive(Debug, Clone)]
+pub struct StopCondition {
+    max_iterations: usize,
+    time_budget_ms: Option<u128>,
+    energy_budget_uj: Option<u64>,
+    iterations: usize,
+    elapsed_ms: u128,
+    energy_uj: u64,
+    interrupted: bool,
+    failed: bool,
+}
+
+impl StopCondition {
+    pub fn new(
+        max_iterations: usize,
+        time_budget_s: Option<f64>,
+        energy_budget_j: Option<f64>,
+    ) -> Self {
+        Self {
+            max_iterations,
+            time_budget_ms: time_budget_s.map(|budget_s| (budget_s * 1_000.0) as u128),
+            energy_budget_uj: energy_budget_j.map(|budget_j| (budget_j * 1_000_000.0) as u64),
+            iterations: 0,
+            elapsed_ms: 0,
+            energy_uj: 0,
+            interrupted: false,
+            failed: false,
+        }
+    }
+
+    /// Account a completed iteration, with its wall time and energy.
+    pub fn record(&mut self, elapsed_ms: u128, energy_uj: u64) {
+        self.iterations += 1;
+        self.elapsed_ms += elapsed_ms;
+        self.energy_uj = self.energy_uj.saturating_add(energy_uj);
+    }
+
+    /// Stop after the current iteration, whatever the limits.
+    pub fn interrupt(&mut self) {
+        self.interrupted = true;
+    }
+
+    /// Stop after the iteration whose command exited with a nonzero code.
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// The limit reached by the iterations so far, `None` to run another iteration.
+    pub fn reached(&self) -> Option<StopReason> {
+        if self.failed {
+            Some(StopReason::CommandFailed)
+        } else if self.interrupted {
+            Some(StopReason::Interrupted)
+        } else if self.iterations >= self.max_iterations {
+            Some(StopReason::Iterations)
+        } else if self
+            .time_budget_ms
+            .is_some_and(|budget_ms| self.elapsed_ms >= budget_ms)
+        {
+            Some(StopReason::TimeBudget)
+        } else if self
+            .energy_budget_uj
+            .is_some_and(|budget_uj| self.energy_uj >= budget_uj)
+        {
+            Some(StopReason::EnergyBudget)
+        } else {
+            None
+        }
+    }
+
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+}
+
+/// Aggregated measurements of the command for one polling rate.
+#[derive(Debug, Clone, Serialize)]
+pub struct CalibrationResult {
+    /// Polling rate in seconds, `None` when polling is disabled
+    pub polling_rate_s: Option<f64>,
+    /// Metrics averaged over the iterations
+    pub metrics: Vec<Metric>,
+    /// Average duration in milliseconds
+    pub duration_ms: u128,
+    /// Average number of measures made by the sources
+    pub measure_count: u64,
+    /// Average delta between two measures in microseconds
+    pub measure_delta: u128,
+}
+
+impl CalibrationResult {
+    /// Average the iterations measured with the given polling rate.
+    pub fn from_iterations(
+        polling_rate_s: Option<f64>,
+        results: &[MeasurementResult],
+        rounding: Rounding,
+    ) -> Self {
+        let count = results.len().max(1) as u128;
+        let average = |total: u128| divide_rounded(total, count, rounding);
+
+        let mut metrics: Vec<Metric> = Vec::new();
+        for result in results {
+            for metric in &result.metrics {
+                match metrics.iter_mut().find(|m| m.name == metric.name) {
+                    Some(existing) => existing.value += metric.value,
+                    None => metrics.push(metric.clone()),
+                }
+            }
+        }
+        for metric in &mut metrics {
+            metric.value = average(metric.value as u128) as u64;
+        }
+        metrics.sort_by_key(|metric| metric.name.clone());
+
+        Self {
+            polling_rate_s,
+            metrics,
+            duration_ms: average(results.iter().map(|r| r.duration_ms).sum()),
+            measure_count: average(results.iter().map(|r| r.measure_count as u128).sum()) as u64,
+            measure_delta: average(results.iter().map(|r| r.measure_delta).sum()),
+        }
+    }
+
+    /// Average energy of an iteration in microjoules, see [`total_energy_uj`].
+    pub fn total_energy_uj(&self) -> u64 {
+        total_energy_uj(&self.metrics)
+    }
+
+    /// Relative energy deviation in percent compared to a baseline calibration.
+    pub fn energy_deviation_pct(&self, baseline: &CalibrationResult) -> f64 {
+        let base = baseline.total_energy_uj();
+        if base == 0 {
+            return 0.0;
+        }
+        (self.total_energy_uj() as f64 - base as f64) / base as f64 * 100.0
+    }
+
+    pub fn extract_keys(&self) -> Vec<&String> {
+        self.metrics.iter().map(|metric| &metric.name).collect()
+    }
+}
+
+/// Mean of a metric over the iterations of the baseline and candidate commands of a
+/// comparison.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricComparison {
+    pub name: String,
+    pub unit: String,
+    pub baseline: f64,
+    pub candidate: f64,
+    /// Candidate mean minus the baseline mean
+    pub delta: f64,
+    /// Change from the baseline mean in percent, `None` when the baseline mean is zero
+    pub change_pct: Option<f64>,
+}
+
+impl MetricComparison {
+    /// Compare the mean of each metric over the iterations of both commands, in order of first
+    /// appearance in the baseline; the metrics reported by only one command are left out.
+    pub fn from_iterations(
+        baseline: &[MeasurementResult],
+        candidate: &[MeasurementResult],
+    ) -> Vec<Self> {
+        let candidate = MetricSummary::from_iterations(candidate);
+
+        MetricSummary::from_iterations(baseline)
+            .into_iter()
+            .filter_map(|base| {
+                let other = candidate
+                    .iter()
+                    .find(|metric| metric.name == base.name && metric.unit == base.unit)?;
+                let delta = other.mean - base.mean;
+                Some(Self {
+                    change_pct: (base.mean != 0.0).then(|| delta / base.mean * 100.0),
+                    delta,
+                    baseline: base.mean,
+                    candidate: other.mean,
+                    name: base.name,
+                    unit: base.unit,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Results of a profiling run, one entry per iteration (per polling rate when calibrating,
+/// per metric when comparing).
+#[derive(Debug, Clone)]
+pub enum ProfileOutput {
+    Simple(Vec<MeasurementResult>),
+    Phases(Vec<PhaseMeasurementResult>),
+    Calibration(Vec<CalibrationResult>),
+    Comparison(Vec<MetricComparison>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::cgroup::{cpu_utilization, parse_proc_stat_ticks};
+    use crate::test_util::{metric, metric_in};
+
+    #[test]
+    fn samples_are_split_in_equal_intervals() {
+        let sample = |elapsed_us, interval_us, value| PowerSample {
+            elapsed_us,
+            interval_us,
+            metrics: vec![metric("PACKAGE-0_0", value)],
+        };
+        let samples = [sample(100, 100, 100), sample(400, 300, 300)];
+        let values = |intervals: &[IntervalEnergy]| -> Vec<u64> {
+            intervals
+                .iter()
+                .map(|interval| interval.metrics[0].value)
+                .collect()
+        };
+
+        let intervals = resample_intervals(&samples, 2);
+        assert_eq!(values(&intervals), [200, 200]);
+        assert_eq!(
+            (intervals[1].start_us, intervals[1].duration_us),
+            (200, 200)
+        );
+
+        // The rounding of the shared sample does not lose energy
+        let intervals = resample_intervals(&samples, 3);
+        assert_eq!(values(&intervals), [133, 134, 133]);
+        assert_eq!(intervals[2].start_us + intervals[2].duration_us, 400);
+        assert_eq!(intervals[0].metrics[0].name, "PACKAGE-0_0");
+
+        assert!(resample_intervals(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn phase_derivation_describes_boundaries() {
+        let token = |name: &str| PhaseToken::Token(name.to_string());
+        let phase =
+            |start: &PhaseToken, end: &PhaseToken, lines: (Option<usize>, Option<usize>)| {
+                PhaseResult::new(start, end, lines.0, lines.1, Vec::new(), 0)
+            };
+
+        assert_eq!(
+            phase(&PhaseToken::Start, &token("__PARSE__"), (None, Some(12))).derivation(),
+            "START -> first token '__PARSE__' at line 12"
+        );
+        assert_eq!(
+            phase(&token("__PARSE__"), &token("__RUN__"), (Some(12), Some(40))).derivation(),
+            "token '__PARSE__' at line 12 -> token '__RUN__' at line 40"
+        );
+        assert_eq!(
+            phase(&token("__RUN__"), &PhaseToken::End, (Some(40), None)).derivation(),
+            "last token '__RUN__' at line 40 -> END"
+        );
+        assert_eq!(
+            phase(&PhaseToken::Start, &PhaseToken::End, (None, None)).derivation(),
+            "START -> END, no token"
+        );
+    }
+
+    #[test]
+    fn ratio_to_package_is_computed_per_socket() {
+        let metrics = vec![
+            metric("PACKAGE-0_0", 2_000),
+            metric("DRAM_0", 300),
+            metric("PACKAGE-1_1", 1_000),
+            metric("DRAM_1", 500),
+        ];
+
+        let ratios = ratio_metrics(&metrics, "PACKAGE", Rounding::Down);
+
+        assert_eq!(ratios.len(), 2);
+        assert_eq!(ratios[0].name, "DRAM_0/PACKAGE-0_0");
+        assert_eq!(ratios[0].value, 150);
+        assert_eq!(ratios[0].unit, RATIO_UNIT);
+        assert_eq!(ratios[1].name, "DRAM_1/PACKAGE-1_1");
+        assert_eq!(ratios[1].value, 500);
+
+        // Ratios are not energies and stay out of the totals
+        let all: Vec<Metric> = metrics.iter().cloned().chain(ratios).collect();
+        assert_eq!(total_energy_uj(&all), total_energy_uj(&metrics));
+    }
+
+    #[test]
+    fn ratio_to_exact_reference_skips_zero_reference() {
+        let metrics = vec![metric("PACKAGE-0_0", 2_000), metric("DRAM_0", 300)];
+        let ratios = ratio_metrics(&metrics, "PACKAGE-0_0", Rounding::Down);
+        assert_eq!(ratios.len(), 1);
+        assert_eq!(ratios[0].value, 150);
+
+        let idle = vec![metric("PACKAGE-0_0", 0), metric("DRAM_0", 300)];
+        assert!(ratio_metrics(&idle, "PACKAGE-0_0", Rounding::Down).is_empty());
+    }
+
+    #[test]
+    fn score_is_the_weighted_sum_of_metrics() {
+        let metrics = vec![metric("PACKAGE-0_0", 2_000), metric("DRAM_0", 301)];
+        let weights = |pairs: &[(&str, f64)]| -> Vec<ScoreWeight> {
+            pairs
+                .iter()
+                .map(|(name, weight)| ScoreWeight {
+                    name: name.to_string(),
+                    weight: *weight,
+                })
+                .collect()
+        };
+
+        let score = score_metric(
+            &metrics,
+            &weights(&[("PACKAGE-0_0", 1.0), ("DRAM_0", 0.5)]),
+            Rounding::Nearest,
+        )
+        .unwrap();
+        assert_eq!(score.name, SCORE_METRIC);
+        assert_eq!(score.value, 2_151);
+
+        let down = score_metric(&metrics, &weights(&[("DRAM_0", 0.5)]), Rounding::Down);
+        assert_eq!(down.unwrap().value, 150);
+
+        let missing = score_metric(&metrics, &weights(&[("GPU_0", 1.0)]), Rounding::Down);
+        assert!(matches!(missing, Err(JouleProfilerError::InvalidScore(_))));
+
+        // The energies are converted to µJ, the other metrics rejected
+        let mut metrics = metrics;
+        metrics.push(Metric {
+            name: "GPU_0".to_string(),
+            unit: "mJ".to_string(),
+            ..metric("", 3)
+        });
+        metrics.push(Metric {
+            name: "PEAK_RSS_KB".to_string(),
+            unit: "kB".to_string(),
+            ..metric("", 1_000)
+        });
+        let mixed = score_metric(
+            &metrics,
+            &weights(&[("PACKAGE-0_0", 1.0), ("GPU_0", 1.0)]),
+            Rounding::Down,
+        );
+        assert_eq!(mixed.unwrap().value, 5_000);
+        let memory = score_metric(&metrics, &weights(&[("PEAK_RSS_KB", 1.0)]), Rounding::Down);
+        assert!(matches!(memory, Err(JouleProfilerError::InvalidScore(_))));
+    }
+
+    #[test]
+    fn average_power_is_the_energy_over_the_duration() {
+        let metrics = vec![
+            metric("PACKAGE-0_0", 3_000_000),
+            Metric {
+                name: "GPU_0".to_string(),
+                value: 500,
+                unit: "mJ".to_string(),
+                source: "nvml".to_string(),
+            },
+            Metric {
+                name: "DRAM_0_RATIO".to_string(),
+                value: 100,
+                unit: RATIO_UNIT.to_string(),
+                source: "derived".to_string(),
+            },
+        ];
+
+        let power = power_by_metric(&metrics, 2_000);
+        assert_eq!(power.len(), 2);
+        assert_eq!(power["PACKAGE-0_0"], 1.5);
+        assert_eq!(power["GPU_0"], 0.25);
+
+        assert_eq!(average_power_w(&metrics[0], 0), Some(0.0));
+
+        // The energy per result is not consumed over the run
+        let per_result = Metric {
+            name: ENERGY_PER_RESULT.to_string(),
+            ..metric("", 2_000)
+        };
+        assert_eq!(average_power_w(&per_result, 2_000), None);
+    }
+
+    #[test]
+    fn build_utilization_flags_the_idle_builds() {
+        let begin = parse_proc_stat_ticks("cpu  1000 0 0 1000 0 0 0 0 0 0\n").unwrap();
+        let busy = parse_proc_stat_ticks("cpu  1900 0 50 1050 0 0 0 0 0 0\n").unwrap();
+        // Waiting on the disk: iowait counts as idle
+        let stalled = parse_proc_stat_ticks("cpu  1300 0 100 1100 500 0 0 0 0 0\n").unwrap();
+
+        let utilization = cpu_utilization(&begin, &busy);
+        assert_eq!(cpu_utilization_metric(utilization).value, 950);
+        assert_eq!(cpu_utilization_metric(utilization).unit, RATIO_UNIT);
+        assert!(!is_idle_build(utilization));
+
+        let utilization = cpu_utilization(&begin, &stalled);
+        assert_eq!(cpu_utilization_metric(utilization).value, 400);
+        assert!(is_idle_build(utilization));
+    }
+
+    #[test]
+    fn what_if_cap_scales_the_energy_above_the_cap() {
+        // 10 J over 1 s is an average power of 10 W
+        let metrics = vec![
+            metric("PACKAGE-0_0", 6_000_000),
+            metric("PACKAGE-0_1", 4_000_000),
+        ];
+
+        let capped = what_if_cap_metric(&metrics, 1_000, 4.0, Rounding::Down).unwrap();
+        assert_eq!(capped.name, WHAT_IF_CAP_ENERGY);
+        assert_eq!(capped.value, 4_000_000);
+
+        let above = what_if_cap_metric(&metrics, 1_000, 25.0, Rounding::Down).unwrap();
+        assert_eq!(above.value, 10_000_000);
+
+        assert!(what_if_cap_metric(&metrics, 0, 4.0, Rounding::Down).is_none());
+    }
+
+    #[test]
+    fn ops_per_watt_divides_the_throughput_by_the_average_power() {
+        // 30 J over 2 s is an average power of 15 W
+        let metrics = vec![
+            metric("PACKAGE-0_0", 25_000_000),
+            metric("DRAM_0", 5_000_000),
+            metric("CORE_0", 20_000_000),
+        ];
+
+        let metric = ops_per_watt_metric(&metrics, 4_500.0, 2_000, Rounding::Nearest).unwrap();
+        assert_eq!(metric.name, OPS_PER_WATT);
+        assert_eq!(metric.value, 300);
+        assert_eq!(metric.unit, "ops/W");
+
+        assert!(ops_per_watt_metric(&metrics, 0.0, 2_000, Rounding::Nearest).is_none());
+        assert!(ops_per_watt_metric(&metrics, 4_500.0, 0, Rounding::Nearest).is_none());
+        assert!(ops_per_watt_metric(&[], 4_500.0, 2_000, Rounding::Nearest).is_none());
+    }
+
+    fn iteration(metrics: Vec<Metric>) -> MeasurementResult {
+        MeasurementResult {
+            metrics,
+            duration_ms: 10,
+            measure_count: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn increasing_energy_is_reported_as_a_drift() {
+        let results: Vec<_> = (0..10)
+            .map(|idx| {
+                let package = 1_000 + idx * 20 + idx % 3;
+                iteration(vec![metric("PACKAGE-0_0", package), metric("DRAM_0", 100)])
+            })
+            .collect();
+
+        let trend = EnergyTrend::from_iterations(&results).unwrap();
+
+        assert!(trend.slope_uj > 19.0 && trend.slope_uj < 21.0);
+        assert!(trend.r_squared > 0.99);
+        assert!(trend.drifting);
+
+        let stable: Vec<_> = [1_000, 1_003, 998, 1_001]
+            .into_iter()
+            .map(|package| iteration(vec![metric("PACKAGE-0_0", package)]))
+            .collect();
+        assert!(!EnergyTrend::from_iterations(&stable).unwrap().drifting);
+        assert!(EnergyTrend::from_iterations(&stable[..1]).is_none());
+    }
+
+    #[test]
+    fn summary_computes_cv_and_flags_high_variance() {
+        let results = vec![
+            iteration(vec![metric("PACKAGE-0_0", 100), metric("DRAM_0", 50)]),
+            iteration(vec![metric("PACKAGE-0_0", 102), metric("DRAM_0", 80)]),
+            iteration(vec![metric("PACKAGE-0_0", 98), metric("DRAM_0", 20)]),
+        ];
+
+        let summary = MetricSummary::from_iterations(&results);
+
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].name, "PACKAGE-0_0");
+        assert_eq!(summary[0].mean, 100.0);
+        assert_eq!(summary[0].stddev, 2.0);
+        assert_eq!(summary[0].cv, 0.02);
+        assert!(!summary[0].unstable);
+
+        assert_eq!(summary[0].min, 98.0);
+        assert_eq!(summary[0].median, 100.0);
+        assert_eq!(summary[0].max, 102.0);
+
+        assert_eq!(summary[1].name, "DRAM_0");
+        assert_eq!(summary[1].stddev, 30.0);
+        assert_eq!(summary[1].cv, 0.6);
+        assert!(summary[1].unstable);
+    }
+
+    #[test]
+    fn summary_skips_the_iterations_missing_a_metric() {
+        let results = vec![
+            iteration(vec![metric("PACKAGE-0_0", 100), metric("DRAM_0", 10)]),
+            iteration(vec![metric("PACKAGE-0_0", 300)]),
+            iteration(vec![metric("PACKAGE-0_0", 200), metric("DRAM_0", 30)]),
+            iteration(vec![metric("PACKAGE-0_0", 400)]),
+        ];
+
+        let summary = MetricSummary::from_iterations(&results);
+
+        assert_eq!(summary[0].median, 250.0);
+        assert_eq!(summary[1].name, "DRAM_0");
+        assert_eq!(summary[1].mean, 20.0);
+        assert_eq!(summary[1].min, 10.0);
+    }
+
+    #[test]
+    fn summary_sem_is_stddev_over_sqrt_count() {
+        let results: Vec<_> = [2, 4, 4, 4, 5, 5, 7, 9]
+            .into_iter()
+            .map(|value| iteration(vec![metric("PACKAGE-0_0", value)]))
+            .collect();
+
+        let summary = MetricSummary::from_iterations(&results);
+
+        let stddev = (32.0f64 / 7.0).sqrt();
+        assert!((summary[0].stddev - stddev).abs() < 1e-12);
+        let sem = summary[0].sem.unwrap();
+        assert!((sem - stddev / 8f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn outliers_are_dropped_with_enough_samples() {
+        let samples = [100.0, 102.0, 98.0, 101.0, 99.0, 500.0];
+        assert_eq!(
+            drop_outliers(&samples, OutlierMethod::Iqr),
+            [100.0, 102.0, 98.0, 101.0, 99.0]
+        );
+        assert_eq!(
+            drop_outliers(&samples, OutlierMethod::Mad),
+            [100.0, 102.0, 98.0, 101.0, 99.0]
+        );
+
+        // Too few samples to tell an outlier
+        assert_eq!(drop_outliers(&samples[3..], OutlierMethod::Iqr).len(), 3);
+        // Constant samples, no deviation to scale with
+        assert_eq!(drop_outliers(&[5.0; 5], OutlierMethod::Mad).len(), 5);
+    }
+
+    #[test]
+    fn summary_reports_the_dropped_outliers() {
+        let results: Vec<_> = [100, 102, 98, 101, 500]
+            .into_iter()
+            .map(|value| iteration(vec![metric("PACKAGE-0_0", value)]))
+            .collect();
+
+        let summary =
+            MetricSummary::from_iterations_without_outliers(&results, Some(OutlierMethod::Iqr));
+        assert_eq!(summary[0].samples, 4);
+        assert_eq!(summary[0].dropped, 1);
+        assert_eq!(summary[0].max, 102.0);
+
+        let summary = MetricSummary::from_iterations(&results);
+        assert_eq!((summary[0].samples, summary[0].dropped), (5, 0));
+    }
+
+    #[test]
+    fn summary_of_single_or_zero_iterations_is_stable() {
+        let summary = MetricSummary::from_iterations(&[iteration(vec![metric("CORE_0", 0)])]);
+        assert_eq!(summary[0].stddev, 0.0);
+        assert_eq!(summary[0].sem, None);
+        assert_eq!(summary[0].cv, 0.0);
+        assert!(!summary[0].unstable);
+    }
+
+    fn phases(tokens: &[&str]) -> PhaseMeasurementResult {
+        let mut boundaries = vec![PhaseToken::Start];
+        boundaries.extend(tokens.iter().map(|t| PhaseToken::Token(t.to_string())));
+        boundaries.push(PhaseToken::End);
+
+        PhaseMeasurementResult {
+            phases: boundaries
+                .windows(2)
+                .map(|pair| PhaseResult::new(&pair[0], &pair[1], None, None, Vec::new(), 1))
+                .collect(),
+            duration_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn token_counts_per_iteration() {
+        let results = vec![
+            phases(&["__INIT__", "__LOOP__", "__LOOP__"]),
+            phases(&["__INIT__", "__LOOP__"]),
+        ];
+
+        let counts = TokenCount::from_iterations(&results);
+
+        assert_eq!(
+            counts,
+            vec![
+                TokenCount {
+                    token: "__INIT__".to_string(),
+                    counts: vec![1, 1],
+                },
+                TokenCount {
+                    token: "__LOOP__".to_string(),
+                    counts: vec![2, 1],
+                },
+            ]
+        );
+        assert!(!counts[0].is_variable());
+        assert!(counts[1].is_variable());
+    }
+
+    #[test]
+    fn phase_summary_groups_the_phases_by_name() {
+        let with_energy = |values: [u64; 2]| {
+            let mut result = phases(&["__WORK__"]);
+            for (phase, value) in result.phases.iter_mut().zip(values) {
+                phase.metrics = vec![metric("PACKAGE-0_0", value)];
+            }
+            result
+        };
+        let results = vec![with_energy([10, 100]), with_energy([20, 300])];
+
+        let summary = PhaseSummary::from_iterations(&results);
+
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].name, "START -> __WORK__");
+        assert_eq!(summary[0].metrics[0].mean, 15.0);
+        assert_eq!(summary[1].name, "__WORK__ -> END");
+        assert_eq!(summary[1].metrics[0].max, 300.0);
+    }
+
+    #[test]
+    fn phase_energy_percents_leave_out_the_whole_run() {
+        let mut result = phases(&["__A__"]);
+        result.phases[0].metrics = vec![metric("PACKAGE-0_0", 300), metric("DRAM_0", 0)];
+        result.phases[1].metrics = vec![metric("PACKAGE-0_0", 100), metric("DRAM_0", 0)];
+        let mut global = phases(&[]).phases.remove(0);
+        global.metrics = vec![metric("PACKAGE-0_0", 400), metric("DRAM_0", 0)];
+        result.phases.push(global);
+
+        let percents = result.energy_percents();
+        assert_eq!(percents[0]["PACKAGE-0_0"], 75.0);
+        assert_eq!(percents[1]["PACKAGE-0_0"], 25.0);
+        assert_eq!(percents[2]["PACKAGE-0_0"], 100.0);
+        assert_eq!(percents[0]["DRAM_0"], 0.0);
+
+        // Without tokens, the whole run is the single phase
+        let mut result = phases(&[]);
+        let gpu = Metric {
+            name: "GPU_0".to_string(),
+            unit: "mJ".to_string(),
+            ..metric("", 20)
+        };
+        let ratio = Metric {
+            name: "DRAM_0_RATIO".to_string(),
+            unit: RATIO_UNIT.to_string(),
+            ..metric("", 100)
+        };
+        result.phases[0].metrics = vec![metric("PACKAGE-0_0", 50), gpu, ratio];
+        let percents = result.energy_percents();
+        assert_eq!(percents[0]["PACKAGE-0_0"], 100.0);
+        // Every energy has a share, whatever its unit, but not the ratios
+        assert_eq!(percents[0]["GPU_0"], 100.0);
+        assert!(!percents[0].contains_key("DRAM_0_RATIO"));
+    }
+
+    #[test]
+    fn tokens_include_first_start_token_of_inner_phases() {
+        let mut result = phases(&["__A__", "__B__"]);
+        // Only the inner phase is kept with --inner-only
+        result.phases = vec![result.phases[1].clone()];
+        assert_eq!(result.tokens(), ["__A__", "__B__"]);
+    }
+
+    #[test]
+    fn socket_totals_sum_each_domain_across_sockets() {
+        let metrics = vec![
+            metric("PACKAGE-0_0", 1_000),
+            metric("DRAM_0", 100),
+            metric("PACKAGE-1_1", 1_500),
+            metric("DRAM_1", 150),
+            metric(CONTAINER_ENERGY, 10),
+        ];
+
+        let totals = socket_totals(&metrics);
+
+        let totals: Vec<_> = totals.iter().map(|m| (m.name.as_str(), m.value)).collect();
+        assert_eq!(totals, [("PACKAGE_TOTAL", 2_500), ("DRAM_TOTAL", 250)]);
+    }
+
+    #[test]
+    fn container_energy_is_share_of_packages() {
+        let metrics = vec![
+            metric("PACKAGE-0_0", 3_000_000),
+            metric("PACKAGE-1_1", 1_000_000),
+            metric("DRAM_0", 500_000),
+        ];
+
+        let container = container_energy(&metrics, 0.25, Rounding::Down);
+
+        assert_eq!(container.name, CONTAINER_ENERGY);
+        assert_eq!(container.value, 1_000_000);
+        assert_eq!(container.source, "cgroup");
+    }
+
+    #[test]
+    fn thread_energy_is_proportional_to_cpu_time() {
+        let metrics = vec![
+            metric("PACKAGE-0_0", 800_000),
+            metric("PACKAGE-1_1", 200_000),
+            metric("DRAM_0", 500_000),
+        ];
+        let thread = |tid, name: &str, ticks| ThreadCpuTime {
+            tid,
+            name: name.to_string(),
+            ticks,
+        };
+        let threads = [
+            thread(100, "main", 60),
+            thread(101, "worker 1", 30),
+            thread(102, "worker 2", 10),
+            thread(103, "idle", 0),
+        ];
+
+        let energy: Vec<_> = thread_energy(&metrics, &threads, Rounding::Down)
+            .into_iter()
+            .map(|metric| (metric.name, metric.value, metric.source))
+            .collect();
+
+        let estimate = |name: &str, value| (name.to_string(), value, "proc-estimate".to_string());
+        assert_eq!(
+            energy,
+            [
+                estimate("THREAD_ENERGY_100_main", 600_000),
+                estimate("THREAD_ENERGY_101_worker_1", 300_000),
+                estimate("THREAD_ENERGY_102_worker_2", 100_000),
+            ]
+        );
+        assert!(thread_energy(&metrics, &[thread(1, "idle", 0)], Rounding::Down).is_empty());
+    }
+
+    #[test]
+    fn metrics_diverging_beyond_tolerance_are_reported() {
+        let reference = vec![
+            metric("PACKAGE-0_0", 1_000),
+            metric("DRAM_0", 100),
+            metric("CORE_0", 0),
+        ];
+        let other = vec![
+            metric("PACKAGE-0_0", 1_040),
+            metric("DRAM_0", 150),
+            metric("CORE_0", 5),
+            metric("PSYS_0", 900),
+        ];
+
+        let divergences = diverging_metrics(&reference, &other, 5.0);
+
+        let names: Vec<_> = divergences.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["DRAM_0", "CORE_0"]);
+        assert_eq!(divergences[0].deviation_pct, 50.0);
+        assert!(divergences[1].deviation_pct.is_infinite());
+    }
+
+    #[test]
+    fn divide_rounded_nearest_differs_from_down_on_boundary() {
+        assert_eq!(divide_rounded(5, 2, Rounding::Down), 2);
+        assert_eq!(divide_rounded(5, 2, Rounding::Nearest), 3);
+        assert_eq!(divide_rounded(7, 3, Rounding::Nearest), 2);
+        assert_eq!(divide_rounded(7, 0, Rounding::Nearest), 0);
+    }
+
+    #[test]
+    fn container_energy_applies_rounding() {
+        let metrics = vec![metric("PACKAGE-0_0", 3)];
+
+        assert_eq!(container_energy(&metrics, 0.5, Rounding::Down).value, 1);
+        assert_eq!(container_energy(&metrics, 0.5, Rounding::Nearest).value, 2);
+    }
+
+    #[test]
+    fn calibration_average_uses_rounding() {
+        let result = |value| MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", value)],
+            duration_ms: 1,
+            measure_count: 2,
+            ..Default::default()
+        };
+        let results = [result(10), result(11)];
+
+        let down = CalibrationResult::from_iterations(None, &results, Rounding::Down);
+        let nearest = CalibrationResult::from_iterations(None, &results, Rounding::Nearest);
+
+        assert_eq!(down.metrics[0].value, 10);
+        assert_eq!(nearest.metrics[0].value, 11);
+    }
+
+    #[test]
+    fn calibration_deviation_only_sums_the_packages_and_dram() {
+        let calibration = |package, core| CalibrationResult {
+            polling_rate_s: None,
+            metrics: vec![
+                metric("PACKAGE-0_0", package),
+                metric("CORE_0", core),
+                metric("DRAM_0", 100),
+                metric_in("DRAM_0_RATIO", 900, RATIO_UNIT),
+            ],
+            duration_ms: 1,
+            measure_count: 2,
+            measure_delta: 0,
+        };
+
+        let baseline = calibration(900, 800);
+        let candidate = calibration(1_100, 100);
+
+        assert_eq!(baseline.total_energy_uj(), 1_000);
+        assert_eq!(candidate.energy_deviation_pct(&baseline), 20.0);
+    }
+
+    #[test]
+    fn iteration_count_stops_first_without_budget() {
+        let mut stop = StopCondition::new(3, None, None);
+        for _ in 0..2 {
+            stop.record(1_000_000, u64::MAX / 4);
+            assert_eq!(stop.reached(), None);
+        }
+        stop.record(1_000_000, 0);
+        assert_eq!(stop.reached(), Some(StopReason::Iterations));
+        assert_eq!(stop.iterations(), 3);
+    }
+
+    #[test]
+    fn time_budget_stops_before_the_iteration_count() {
+        let mut stop = StopCondition::new(10, Some(2.5), Some(100.0));
+        stop.record(1_200, 1_000);
+        stop.record(1_200, 1_000);
+        assert_eq!(stop.reached(), None);
+        stop.record(1_200, 1_000);
+        assert_eq!(stop.reached(), Some(StopReason::TimeBudget));
+    }
+
+    #[test]
+    fn energy_budget_stops_before_the_iteration_count() {
+        let mut stop = StopCondition::new(10, Some(60.0), Some(1.5));
+        stop.record(10, 1_000_000);
+        assert_eq!(stop.reached(), None);
+        stop.record(10, 600_000);
+        assert_eq!(stop.reached(), Some(StopReason::EnergyBudget));
+        assert_eq!(stop.iterations(), 2);
+    }
+
+    #[test]
+    fn interrupt_stops_before_the_limits() {
+        let mut stop = StopCondition::new(100, None, None);
+        stop.record(10, 1_000);
+        assert_eq!(stop.reached(), None);
+        stop.interrupt();
+        assert_eq!(stop.reached(), Some(StopReason::Interrupted));
+        assert_eq!(stop.iterations(), 1);
+
+        stop.fail();
+        assert_eq!(stop.reached(), Some(StopReason::CommandFailed));
+    }
+
+    #[test]
+    fn totals_sum_the_packages_and_dram_per_socket() {
+        let metric = |name: &str, value, unit: &str| Metric {
+            name: name.to_string(),
+            value,
+            unit: unit.to_string(),
+            source: "powercap".to_string(),
+        };
+        let metrics = [
+            metric("PACKAGE-0_0", 1_000, "µJ"),
+            metric("CORE_0", 600, "µJ"),
+            metric("DRAM_0", 200, "µJ"),
+            metric("PACKAGE-1_1", 900, "µJ"),
+            metric("DRAM_1", 100, "µJ"),
+            metric("DRAM_2", 7, "mJ"),
+            metric("PACKAGE-0_0_RATIO", 500, RATIO_UNIT),
+        ];
+
+        let totals = energy_totals(&metrics);
+
+        let values: Vec<(&str, u64)> = totals
+            .iter()
+            .map(|total| (total.name.as_str(), total.value))
+            .collect();
+        assert_eq!(
+            values,
+            [("TOTAL_0", 1_200), ("TOTAL_1", 1_000), ("TOTAL", 2_200)]
+        );
+        assert!(
+            totals
+                .iter()
+                .all(|total| total.unit == "µJ" && total.source == TOTALS_SOURCE)
+        );
+        assert!(energy_totals(&[metric("CORE_0", 600, "µJ")]).is_empty());
+    }
+
+    #[test]
+    fn comparison_reports_the_delta_of_the_means() {
+        let metric = |name: &str, value| Metric {
+            name: name.to_string(),
+            value,
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        };
+        let result =
+            |package, dram| iteration(vec![metric("PACKAGE-0_0", package), metric("DRAM_0", dram)]);
+
+        let comparison = MetricComparison::from_iterations(
+            &[result(1_000, 0), result(3_000, 0)],
+            &[result(1_500, 10)],
+        );
+
+        assert_eq!(comparison.len(), 2);
+        assert_eq!(comparison[0].name, "PACKAGE-0_0");
+        assert_eq!(comparison[0].baseline, 2_000.0);
+        assert_eq!(comparison[0].candidate, 1_500.0);
+        assert_eq!(comparison[0].delta, -500.0);
+        assert_eq!(comparison[0].change_pct, Some(-25.0));
+        assert_eq!(comparison[1].change_pct, None);
+    }
 }
@@ -1,17 +1,29 @@
-use std::collections::HashSet;
-use std::fs::File;
+use std::collections::{BTreeMap, HashSet};
 use std::io::Write;
 
 use anyhow::Result;
 use log::{debug, info, trace, warn};
 
+use crate::cli::EnergyUnit;
 use crate::config::{ListSensorsConfig, ProfileConfig};
-use crate::measurement::{MeasurementResult, PhaseMeasurementResult, PhaseResult};
+use crate::measurement::{
+    CalibrationResult, MeasurementResult, MetricSummary, PhaseMeasurementResult, PhaseResult,
+    average_power_w, socket_totals,
+};
 use crate::output::OutputFormatTrait;
-use crate::source::Sensor;
-use crate::util::file::{create_file_with_user_permissions, get_absolute_path};
+use crate::source::{Metric, Sensor};
+use crate::util::file::FileMode;
 
-use super::default_iterations_filename;
+use super::{ENERGY_UNIT, FieldProjection, OutputSink, format_float, report_written};
+
+/// Statistic of a metric written in a summary row.
+type Statistic = fn(&MetricSummary) -> f64;
+
+/// Field separator used without `--csv-delimiter`.
+pub const DEFAULT_DELIMITER: char = ';';
+
+/// Columns of the start and end of the phases since the Unix epoch, with `--csv-timestamps`.
+const TIMESTAMP_COLUMNS: [&str; 2] = ["start_us", "end_us"];
 
 /// Data for a phase row in CSV output
 struct PhaseRowData<'a> {
@@ -40,18 +52,49 @@ impl<'a> PhaseRowData<'a> {
     }
 }
 
+/// Metric columns of the phase rows
+struct PhaseKeys<'a> {
+    /// Metrics of the phases, which also get a `<NAME>_percent` column
+    metrics: &'a [&'a String],
+    /// Energy metrics, which get a `<NAME>_power_w` column
+    power: &'a [&'a String],
+}
+
 pub struct CsvOutput {
-    file: File,
+    file: OutputSink,
     filename: String,
+    delimiter: char,
+    fields: FieldProjection,
+    /// Whether each column of the current header is written, all of them when empty
+    columns: Vec<bool>,
+    /// Skip the next header, already written by a previous run to the appended file
+    skip_header: bool,
+    /// Unit of the energy metrics, µJ if not set
+    energy_unit: Option<EnergyUnit>,
 }
 
 impl OutputFormatTrait for CsvOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
     fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
         debug!("Formatting simple single measurement for CSV");
         let keys: Vec<&String> = result.metrics.iter().map(|metric| &metric.name).collect();
+        let iteration = config.always_iteration_column.then_some(0);
+        let totals = total_keys(config, result);
+
+        let power = power_keys(std::slice::from_ref(result));
 
-        self.write_header(&keys, false, false)?;
-        self.write_row(&config.cmd, result, None)?;
+        self.write_header(
+            &keys,
+            iteration.is_some(),
+            false,
+            &power,
+            config.derived,
+            &totals,
+        )?;
+        self.write_row(config, result, iteration, &keys, &power)?;
 
         self.finalize();
         Ok(())
@@ -70,16 +113,27 @@ impl OutputFormatTrait for CsvOutput {
         }
 
         let first = &results[0];
-        let keys: Vec<&String> = first.extract_keys();
+        let keys = iteration_keys(results);
 
-        debug!("CSV will contain {} metrics", first.metrics.len());
+        debug!("CSV will contain {} metrics", keys.len());
 
-        self.write_header(&keys, true, false)?;
+        let totals = total_keys(config, first);
+        let power = power_keys(results);
+
+        self.write_header(&keys, true, false, &power, config.derived, &totals)?;
 
         for (idx, res) in results.iter().enumerate() {
-            self.write_row(&config.cmd, &res, Some(idx))?;
+            self.write_row(config, res, Some(idx), &keys, &power)?;
         }
 
+        let other_columns = 4 + power.len() + if config.derived { 2 } else { 0 } + totals.len();
+        self.write_summary_rows(
+            config,
+            &keys,
+            &MetricSummary::from_iterations_without_outliers(results, config.drop_outliers),
+            other_columns,
+        )?;
+
         self.finalize();
         Ok(())
     }
@@ -99,10 +153,18 @@ impl OutputFormatTrait for CsvOutput {
             return Ok(());
         }
 
-        let keys: Vec<&String> = result.extract_keys();
-        self.write_header(&keys, false, true)?;
+        let keys = unique_keys(std::slice::from_ref(result));
+        let power = phase_power_keys(&keys, &result.phases);
+        self.write_header(
+            &keys,
+            false,
+            true,
+            &power,
+            false,
+            &phase_columns(config, &keys),
+        )?;
 
-        for phase in &result.phases {
+        for (phase, percents) in result.phases.iter().zip(result.energy_percents()) {
             trace!("Writing phase: {}", phase.name);
 
             let phase_data = PhaseRowData::new(
@@ -113,7 +175,11 @@ impl OutputFormatTrait for CsvOutput {
                 phase.end_line,
             );
 
-            self.write_row_phase(&config.cmd, &phase, None, &phase_data)?;
+            let phase_keys = PhaseKeys {
+                metrics: &keys,
+                power: &power,
+            };
+            self.write_row_phase(config, phase, None, &phase_data, &phase_keys, &percents)?;
         }
 
         self.finalize();
@@ -139,18 +205,23 @@ impl OutputFormatTrait for CsvOutput {
             return Ok(());
         }
 
-        let mut keys: HashSet<&String> = HashSet::new();
-        for phase_result in results {
-            keys.extend(phase_result.extract_keys());
-        }
-        let keys_vec: Vec<&String> = keys.into_iter().collect();
+        let keys_vec = unique_keys(results);
 
         debug!("CSV will contain {} metrics", keys_vec.len());
 
-        self.write_header(&keys_vec, true, true)?;
+        let power = phase_power_keys(&keys_vec, results.iter().flat_map(|result| &result.phases));
+        self.write_header(
+            &keys_vec,
+            true,
+            true,
+            &power,
+            false,
+            &phase_columns(config, &keys_vec),
+        )?;
 
         for (idx, iteration_results) in results.iter().enumerate() {
-            for phase in &iteration_results.phases {
+            let percents = iteration_results.energy_percents();
+            for (phase, percents) in iteration_results.phases.iter().zip(&percents) {
                 trace!("Writing iteration {} phase: {}", idx + 1, phase.name);
 
                 let phase_data = PhaseRowData::new(
@@ -161,72 +232,335 @@ impl OutputFormatTrait for CsvOutput {
                     phase.end_line,
                 );
 
-                self.write_row_phase(&config.cmd, &phase, Some(idx), &phase_data)?;
+                let phase_keys = PhaseKeys {
+                    metrics: &keys_vec,
+                    power: &power,
+                };
+                self.write_row_phase(config, phase, Some(idx), &phase_data, &phase_keys, percents)?;
+            }
+        }
+
+        self.finalize();
+        Ok(())
+    }
+
+    fn calibration(&mut self, config: &ProfileConfig, results: &[CalibrationResult]) -> Result<()> {
+        info!("Formatting {} calibration rates for CSV", results.len());
+
+        let Some(baseline) = results.first() else {
+            warn!("No calibration results to write to CSV");
+            return Ok(());
+        };
+
+        let mut keys: Vec<&String> = Vec::new();
+        for key in results.iter().flat_map(|result| result.extract_keys()) {
+            if !keys.contains(&key) {
+                keys.push(key);
             }
         }
 
+        let mut header = vec!["command".to_string(), "polling_rate_s".to_string()];
+        header.extend(keys.iter().map(|key| key.to_string()));
+        header.extend(
+            [
+                "duration_ms",
+                "measure_count",
+                "measure_delta",
+                "energy_deviation_pct",
+            ]
+            .map(String::from),
+        );
+        self.write_header_record(&header)?;
+
+        for result in results {
+            let mut fields = vec![
+                config.cmd.join(" "),
+                result
+                    .polling_rate_s
+                    .map(|rate| rate.to_string())
+                    .unwrap_or_default(),
+            ];
+            fields.extend(self.metric_cells(&result.metrics, &keys, config.precision));
+            fields.extend([
+                result.duration_ms.to_string(),
+                result.measure_count.to_string(),
+                result.measure_delta.to_string(),
+                format_float(result.energy_deviation_pct(baseline), config.precision),
+            ]);
+            self.write_record(&fields)?;
+        }
+
         self.finalize();
         Ok(())
     }
 
     fn list_sensors(&mut self, _config: &ListSensorsConfig, sensors: &[Sensor]) -> Result<()> {
-        write!(self.file, "sensor;unit;source")?;
+        self.write_record(&["sensor", "unit", "source"].map(String::from))?;
         for sensor in sensors {
-            write!(
-                self.file,
-                "{};{};{}",
-                sensor.name, sensor.unit, sensor.source
-            )?;
+            self.write_record(&[
+                sensor.name.clone(),
+                sensor.unit.clone(),
+                sensor.source.clone(),
+            ])?;
         }
         Ok(())
     }
 }
 
+/// Names of the metrics of the iterations once each, in order of first appearance, a metric
+/// missing from an iteration (e.g. of a failed source) getting an empty cell.
+fn iteration_keys(results: &[MeasurementResult]) -> Vec<&String> {
+    let mut keys: Vec<&String> = Vec::new();
+    for key in results.iter().flat_map(|result| result.extract_keys()) {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// Names of the energy metrics, which get a `<NAME>_power_w` column.
+fn power_keys(results: &[MeasurementResult]) -> Vec<&String> {
+    let mut keys: Vec<&String> = Vec::new();
+    for result in results {
+        for metric in &result.metrics {
+            if average_power_w(metric, result.duration_ms).is_some()
+                && !keys.contains(&&metric.name)
+            {
+                keys.push(&metric.name);
+            }
+        }
+    }
+    keys
+}
+
+/// Names of the metrics of the phases once each, in order of first appearance, the phases
+/// having the same metrics.
+fn unique_keys(results: &[PhaseMeasurementResult]) -> Vec<&String> {
+    let mut keys: Vec<&String> = Vec::new();
+    for key in results.iter().flat_map(|result| result.extract_keys()) {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// Names among the keys of the energy metrics of the phases, which get a `<NAME>_power_w`
+/// column.
+fn phase_power_keys<'a, 'b>(
+    keys: &[&'a String],
+    phases: impl IntoIterator<Item = &'b PhaseResult>,
+) -> Vec<&'a String> {
+    let energy: HashSet<&String> = phases
+        .into_iter()
+        .flat_map(|phase| {
+            phase
+                .metrics
+                .iter()
+                .filter(|metric| average_power_w(metric, phase.duration_ms).is_some())
+                .map(|metric| &metric.name)
+        })
+        .collect();
+    keys.iter()
+        .filter(|key| energy.contains(**key))
+        .copied()
+        .collect()
+}
+
+/// Average power in watts of the metric of each power key, empty for a missing metric.
+fn power_cells(
+    metrics: &[Metric],
+    duration_ms: u128,
+    power: &[&String],
+    precision: usize,
+) -> Vec<String> {
+    power
+        .iter()
+        .map(|key| {
+            metrics
+                .iter()
+                .find(|metric| &metric.name == *key)
+                .and_then(|metric| average_power_w(metric, duration_ms))
+                .map(|power| format_float(power, precision))
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Names of the `{DOMAIN}_TOTAL` columns written with `--csv-include-total`.
+fn total_keys(config: &ProfileConfig, result: &MeasurementResult) -> Vec<String> {
+    if !config.csv_include_total {
+        return Vec::new();
+    }
+    socket_totals(&result.metrics)
+        .into_iter()
+        .map(|total| total.name)
+        .collect()
+}
+
 impl CsvOutput {
-    pub fn new(output_file: Option<String>) -> Result<Self> {
-        let filename = output_file
-            .clone()
-            .unwrap_or(default_iterations_filename("csv"));
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let (file, filename) = OutputSink::open(output_file, "CSV", "csv", file_mode)?;
 
-        let absolute_path = get_absolute_path(&filename)?;
-        info!("Creating CSV output file: {}", absolute_path);
+        Ok(Self {
+            file,
+            filename,
+            delimiter: DEFAULT_DELIMITER,
+            fields: FieldProjection::default(),
+            columns: Vec::new(),
+            skip_header: false,
+            energy_unit: None,
+        })
+    }
 
-        let file = create_file_with_user_permissions(&absolute_path)?;
+    /// Creates a CSV writer adding the rows to the end of the file, the header only being
+    /// written if the file is empty.
+    pub fn appending(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let (file, filename) = OutputSink::open_append(output_file, "CSV", "csv", file_mode)?;
+        let skip_header = file.has_content()?;
 
         Ok(Self {
             file,
-            filename: absolute_path,
+            filename,
+            delimiter: DEFAULT_DELIMITER,
+            fields: FieldProjection::default(),
+            columns: Vec::new(),
+            skip_header,
+            energy_unit: None,
         })
     }
 
+    /// Write the energy metrics in this unit instead of microjoules.
+    pub fn set_energy_unit(&mut self, unit: EnergyUnit) {
+        self.energy_unit = Some(unit);
+    }
+
+    /// Value of a metric, the energy ones being converted to the `--unit`.
+    fn metric_cell(&self, metric: &Metric, precision: usize) -> String {
+        match self.energy_unit {
+            Some(unit) if metric.unit == ENERGY_UNIT => {
+                format_float(unit.from_uj(metric.value as f64), precision)
+            }
+            _ => metric.value.to_string(),
+        }
+    }
+
+    /// Value of the metric of each key, empty for a metric missing from the result so that
+    /// the cells stay aligned with the header.
+    fn metric_cells(&self, metrics: &[Metric], keys: &[&String], precision: usize) -> Vec<String> {
+        keys.iter()
+            .map(|key| {
+                metrics
+                    .iter()
+                    .find(|metric| &metric.name == *key)
+                    .map(|metric| self.metric_cell(metric, precision))
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Statistic of a metric summary, converted to the `--unit` for the energy ones.
+    fn summary_cell(&self, metric: &MetricSummary, value: f64, precision: usize) -> String {
+        match self.energy_unit {
+            Some(unit) if metric.unit == ENERGY_UNIT => {
+                format_float(unit.from_uj(value), precision)
+            }
+            _ => format_float(value, precision),
+        }
+    }
+
+    /// Separate the fields with another character than `;`.
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = delimiter;
+    }
+
+    /// Only write the selected columns of [`OUTPUT_FIELDS`](crate::output::OUTPUT_FIELDS).
+    pub fn set_fields(&mut self, fields: FieldProjection) {
+        self.fields = fields;
+    }
+
+    /// Write the header, selecting the columns written in it and the following rows.
+    fn write_header_record(&mut self, header: &[String]) -> Result<()> {
+        self.columns = header
+            .iter()
+            .map(|column| self.fields.keeps(column))
+            .collect();
+        if self.skip_header {
+            debug!("Skipping the CSV header, already in the appended file");
+            self.skip_header = false;
+            return Ok(());
+        }
+        self.write_record(header)
+    }
+
+    /// Quote a field containing the delimiter, a double quote or a line break (RFC 4180),
+    /// its double quotes being doubled.
+    fn escape(&self, field: &str) -> String {
+        if field.contains([self.delimiter, '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    /// Write the escaped fields of the selected columns as one line.
+    fn write_record(&mut self, fields: &[String]) -> Result<()> {
+        let line = fields
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.columns.get(*idx).copied().unwrap_or(true))
+            .map(|(_, field)| self.escape(field))
+            .collect::<Vec<_>>()
+            .join(&self.delimiter.to_string());
+        writeln!(self.file, "{}", line)?;
+        Ok(())
+    }
+
     fn write_header(
         &mut self,
         keys: &[&String],
         include_iteration: bool,
         include_phase: bool,
+        power: &[&String],
+        derived: bool,
+        totals: &[String],
     ) -> Result<()> {
         trace!("Writing CSV header with {} metrics", keys.len());
 
-        write!(self.file, "command;")?;
+        let mut fields = vec!["command".to_string()];
 
         if include_iteration {
-            write!(self.file, "iteration;")?;
+            fields.push("iteration".to_string());
         }
 
         if include_phase {
-            write!(
-                self.file,
-                "phase_name;start_token;end_token;start_line;end_line;"
-            )?;
+            fields.extend(
+                [
+                    "phase_name",
+                    "start_token",
+                    "end_token",
+                    "start_line",
+                    "end_line",
+                ]
+                .map(String::from),
+            );
         }
 
-        for key in keys {
-            write!(self.file, "{};", key)?;
+        fields.extend(keys.iter().map(|key| key.to_string()));
+        fields.extend(
+            ["duration_ms", "measure_count", "measure_delta", "exit_code"].map(String::from),
+        );
+
+        // Derived columns come after the raw ones so the existing columns keep their position,
+        // the power columns being named as the other derived ones with `--derived`
+        let power_suffix = if derived { "AVG_POWER_W" } else { "power_w" };
+        fields.extend(power.iter().map(|key| format!("{}_{}", key, power_suffix)));
+        if derived {
+            fields.extend(["TOTAL_ENERGY_UJ", "EDP_J_S"].map(String::from));
         }
-        writeln!(
-            self.file,
-            "duration_ms;measure_count;measure_delta;exit_code"
-        )?;
+        fields.extend(totals.iter().cloned());
+        self.write_header_record(&fields)?;
 
         debug!("CSV header written");
         Ok(())
@@ -234,69 +568,630 @@ impl CsvOutput {
 
     fn write_row(
         &mut self,
-        command: &[String],
+        config: &ProfileConfig,
         result: &MeasurementResult,
         iteration: Option<usize>,
+        keys: &[&String],
+        power: &[&String],
     ) -> Result<()> {
-        write!(self.file, "'{}';", command.join(" "))?;
+        let mut fields = vec![config.cmd.join(" ")];
 
         if let Some(idx) = iteration {
             trace!("Writing CSV row for iteration {}", idx);
-            write!(self.file, "{};", idx)?;
+            fields.push(idx.to_string());
         }
 
-        for metric in &result.metrics {
-            write!(self.file, "{};", metric.value)?;
+        fields.extend(self.metric_cells(&result.metrics, keys, config.precision));
+        fields.extend([
+            result.duration_ms.to_string(),
+            result.measure_count.to_string(),
+            result.measure_delta.to_string(),
+            result.exit_code.to_string(),
+        ]);
+
+        fields.extend(power_cells(
+            &result.metrics,
+            result.duration_ms,
+            power,
+            config.precision,
+        ));
+        if config.derived {
+            fields.push(result.total_energy_uj().to_string());
+            fields.push(format_float(
+                result.energy_delay_product(),
+                config.precision,
+            ));
+        }
+        if config.csv_include_total {
+            fields.extend(
+                socket_totals(&result.metrics)
+                    .into_iter()
+                    .map(|total| self.metric_cell(&total, config.precision)),
+            );
         }
 
-        write!(
-            self.file,
-            "{};{};{};{};",
-            result.duration_ms, result.measure_count, result.measure_delta, result.exit_code
-        )?;
+        self.write_record(&fields)
+    }
+
+    /// Write one row per statistic of the metrics over the iterations, named in the iteration
+    /// column, the columns other than the metrics being left empty.
+    fn write_summary_rows(
+        &mut self,
+        config: &ProfileConfig,
+        keys: &[&String],
+        summary: &[MetricSummary],
+        other_columns: usize,
+    ) -> Result<()> {
+        let statistics: [(&str, Statistic); 5] = [
+            ("mean", |metric| metric.mean),
+            ("stddev", |metric| metric.stddev),
+            ("min", |metric| metric.min),
+            ("median", |metric| metric.median),
+            ("max", |metric| metric.max),
+        ];
+
+        for (name, statistic) in statistics {
+            let mut fields = vec![config.cmd.join(" "), name.to_string()];
+            // A metric missing from every iteration has no statistics
+            fields.extend(keys.iter().map(|key| {
+                summary
+                    .iter()
+                    .find(|metric| &metric.name == *key)
+                    .map(|metric| self.summary_cell(metric, statistic(metric), config.precision))
+                    .unwrap_or_default()
+            }));
+            fields.extend(std::iter::repeat_n(String::new(), other_columns));
+            self.write_record(&fields)?;
+        }
 
         Ok(())
     }
 
     fn write_row_phase(
         &mut self,
-        command: &[String],
+        config: &ProfileConfig,
         result: &PhaseResult,
         iteration: Option<usize>,
         phase: &PhaseRowData,
+        keys: &PhaseKeys,
+        percents: &BTreeMap<String, f64>,
     ) -> Result<()> {
-        write!(self.file, "'{}';", command.join(" "))?;
+        let mut fields = vec![config.cmd.join(" ")];
 
         if let Some(idx) = iteration {
             trace!("Writing CSV row for iteration {}", idx);
-            write!(self.file, "{};", idx)?;
+            fields.push(idx.to_string());
         }
 
-        write!(self.file, "{};", phase.name)?;
-        write!(self.file, "{};", phase.start_token.unwrap_or(""))?;
-        write!(self.file, "{};", phase.end_token.unwrap_or(""))?;
-        write!(
-            self.file,
-            "{};",
-            phase.start_line.map(|l| l.to_string()).unwrap_or_default()
-        )?;
-        write!(
-            self.file,
-            "{};",
-            phase.end_line.map(|l| l.to_string()).unwrap_or_default()
-        )?;
+        fields.extend([
+            phase.name.to_string(),
+            phase.start_token.unwrap_or("").to_string(),
+            phase.end_token.unwrap_or("").to_string(),
+            phase.start_line.map(|l| l.to_string()).unwrap_or_default(),
+            phase.end_line.map(|l| l.to_string()).unwrap_or_default(),
+        ]);
 
-        for metric in &result.metrics {
-            write!(self.file, "{};", metric.value)?;
-        }
+        fields.extend(self.metric_cells(&result.metrics, keys.metrics, config.precision));
+        fields.push(result.duration_ms.to_string());
+        // The measure counts and the exit code are not known per phase, empty so that the
+        // power and percent columns are aligned with their header
+        fields.extend([String::new(), String::new(), String::new()]);
+        fields.extend(power_cells(
+            &result.metrics,
+            result.duration_ms,
+            keys.power,
+            config.precision,
+        ));
 
-        write!(self.file, "{};", result.duration_ms)?;
+        // The share of the phase in the energy of the iteration
+        fields.extend(keys.metrics.iter().map(|key| {
+            percents
+                .get(*key)
+                .map(|percent| format_float(*percent, config.precision))
+                .unwrap_or_default()
+        }));
+        if config.csv_timestamps {
+            fields.extend(
+                [result.start_us, result.end_us]
+                    .map(|timestamp| timestamp.map(|t| t.to_string()).unwrap_or_default()),
+            );
+        }
 
-        Ok(())
+        self.write_record(&fields)
     }
 
     fn finalize(&self) {
-        println!("✔ CSV written to: {}", self.filename);
-        info!("CSV output saved to: {}", self.filename);
+        report_written("CSV", &self.filename);
+    }
+}
+
+/// Header of the columns after the phase metrics: the share of the phases in the energy of
+/// each metric, then their timestamps if requested.
+fn phase_columns(config: &ProfileConfig, keys: &[&String]) -> Vec<String> {
+    let mut columns: Vec<String> = keys.iter().map(|key| format!("{}_percent", key)).collect();
+    if config.csv_timestamps {
+        columns.extend(TIMESTAMP_COLUMNS.map(String::from));
+    }
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::PhaseToken;
+    use crate::test_util::metric;
+    use std::fs::read_to_string;
+    use tempfile::tempdir;
+
+    #[test]
+    fn single_run_can_include_iteration_column() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            always_iteration_column: true,
+            precision: 3,
+            ..Default::default()
+        };
+        let result = MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", 1_000)],
+            duration_ms: 10,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.simple_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "command;iteration;PACKAGE-0_0;duration_ms;measure_count;measure_delta;exit_code;\
+             PACKAGE-0_0_power_w"
+        );
+        assert_eq!(lines.next().unwrap(), "./bench;0;1000;10;2;0;0;0.100");
+    }
+
+    #[test]
+    fn calibration_rows_are_aligned_with_the_header() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 1,
+            ..Default::default()
+        };
+        let results = [
+            CalibrationResult {
+                polling_rate_s: None,
+                metrics: vec![metric("PACKAGE-0_0", 1_000)],
+                duration_ms: 10,
+                measure_count: 2,
+                measure_delta: 10_000,
+            },
+            CalibrationResult {
+                polling_rate_s: Some(0.01),
+                metrics: vec![metric("DRAM_0", 100), metric("PACKAGE-0_0", 1_100)],
+                duration_ms: 10,
+                measure_count: 3,
+                measure_delta: 5_000,
+            },
+        ];
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.calibration(&config, &results).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "command;polling_rate_s;PACKAGE-0_0;DRAM_0;duration_ms;measure_count;\
+                 measure_delta;energy_deviation_pct",
+                "./bench;;1000;;10;2;10000;0.0",
+                "./bench;0.01;1100;100;10;3;5000;20.0",
+            ]
+        );
+        let columns = lines[0].split(';').count();
+        assert!(lines.iter().all(|line| line.split(';').count() == columns));
+    }
+
+    #[test]
+    fn derived_columns_are_appended_after_raw_metrics() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            derived: true,
+            precision: 3,
+            ..Default::default()
+        };
+        let result = MeasurementResult {
+            metrics: vec![
+                metric("PACKAGE-0_0", 2_000_000),
+                metric("CORE_0", 1_000_000),
+                metric("DRAM_0", 500_000),
+            ],
+            duration_ms: 1_000,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.simple_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "command;PACKAGE-0_0;CORE_0;DRAM_0;duration_ms;measure_count;measure_delta;exit_code;\
+             PACKAGE-0_0_AVG_POWER_W;CORE_0_AVG_POWER_W;DRAM_0_AVG_POWER_W;TOTAL_ENERGY_UJ;EDP_J_S"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "./bench;2000000;1000000;500000;1000;2;0;0;2.000;1.000;0.500;2500000;2.500"
+        );
+    }
+
+    #[test]
+    fn iterations_are_followed_by_summary_rows() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 1,
+            ..Default::default()
+        };
+        let result = |package: u64| MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", package)],
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output
+            .simple_iterations(&config, &[result(100), result(300), result(200)])
+            .unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[3], "./bench;2;200;0;2;0;0;0.0");
+        assert_eq!(lines[4], "./bench;mean;200.0;;;;;");
+        assert_eq!(lines[5], "./bench;stddev;100.0;;;;;");
+        assert_eq!(lines[7], "./bench;median;200.0;;;;;");
+        assert_eq!(lines[8], "./bench;max;300.0;;;;;");
+    }
+
+    #[test]
+    fn metric_missing_from_an_iteration_has_empty_cells() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 1,
+            ..Default::default()
+        };
+        let result = |metrics| MeasurementResult {
+            metrics,
+            duration_ms: 1_000,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output
+            .simple_iterations(
+                &config,
+                &[
+                    result(vec![metric("DRAM_0", 1_000_000)]),
+                    result(vec![
+                        metric("PACKAGE-0_0", 2_000_000),
+                        metric("DRAM_0", 3_000_000),
+                    ]),
+                ],
+            )
+            .unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines[0],
+            "command;iteration;DRAM_0;PACKAGE-0_0;duration_ms;measure_count;measure_delta;\
+             exit_code;DRAM_0_power_w;PACKAGE-0_0_power_w"
+        );
+        assert_eq!(lines[1], "./bench;0;1000000;;1000;2;0;0;1.0;");
+        assert_eq!(lines[2], "./bench;1;3000000;2000000;1000;2;0;0;3.0;2.0");
+    }
+
+    #[test]
+    fn energy_unit_converts_the_summary_rows() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 1,
+            ..Default::default()
+        };
+        let result = |package: u64| MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", package)],
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.set_energy_unit(EnergyUnit::J);
+        output
+            .simple_iterations(&config, &[result(1_000_000), result(3_000_000)])
+            .unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[3], "./bench;mean;2.0;;;;;");
+        assert_eq!(lines[5], "./bench;min;1.0;;;;;");
+        assert_eq!(lines[7], "./bench;max;3.0;;;;;");
+    }
+
+    #[test]
+    fn fields_with_the_delimiter_or_quotes_are_quoted() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo \"a,b\"".to_string(),
+            ],
+            precision: 3,
+            ..Default::default()
+        };
+        let result = MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", 1_000)],
+            duration_ms: 10,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.set_delimiter(',');
+        output.simple_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "command,PACKAGE-0_0,duration_ms,measure_count,measure_delta,exit_code,\
+             PACKAGE-0_0_power_w"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "\"sh -c echo \"\"a,b\"\"\",1000,10,2,0,0,0.100"
+        );
+    }
+
+    #[test]
+    fn sensors_are_written_one_per_line() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sensors.csv").to_string_lossy().to_string();
+        let sensor = |name: &str| Sensor {
+            name: name.to_string(),
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        };
+        let config = ListSensorsConfig {
+            output_format: Default::default(),
+            file_mode: FileMode::default(),
+            rapl_path: None,
+            rapl_backend: Default::default(),
+            domain_options: Default::default(),
+            dump_domains: false,
+            explain: false,
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output
+            .list_sensors(&config, &[sensor("PACKAGE-0_0"), sensor("ODD;NAME")])
+            .unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "sensor;unit;source\nPACKAGE-0_0;µJ;powercap\n\"ODD;NAME\";µJ;powercap\n"
+        );
+    }
+
+    #[test]
+    fn total_columns_sum_the_sockets() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            csv_include_total: true,
+            precision: 3,
+            ..Default::default()
+        };
+        let result = MeasurementResult {
+            metrics: vec![
+                metric("PACKAGE-0_0", 2_000),
+                metric("PACKAGE-1_1", 3_000),
+                metric("DRAM_0", 400),
+                metric("DRAM_1", 600),
+            ],
+            duration_ms: 10,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.simple_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "command;PACKAGE-0_0;PACKAGE-1_1;DRAM_0;DRAM_1;duration_ms;measure_count;\
+             measure_delta;exit_code;PACKAGE-0_0_power_w;PACKAGE-1_1_power_w;DRAM_0_power_w;\
+             DRAM_1_power_w;PACKAGE_TOTAL;DRAM_TOTAL"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "./bench;2000;3000;400;600;10;2;0;0;0.200;0.300;0.040;0.060;5000;1000"
+        );
+    }
+
+    #[test]
+    fn phase_rows_have_the_share_of_the_energy() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 1,
+            ..Default::default()
+        };
+        let phase = |start: &str, end: &str, value| PhaseResult {
+            name: format!("{} -> {}", start, end),
+            start_token: (start != "START").then(|| start.to_string()),
+            end_token: (end != "END").then(|| end.to_string()),
+            metrics: vec![metric("PACKAGE-0_0", value)],
+            duration_ms: 5,
+            ..Default::default()
+        };
+        let result = PhaseMeasurementResult {
+            phases: vec![phase("START", "__A__", 100), phase("__A__", "END", 200)],
+            duration_ms: 10,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.phases_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].ends_with(";exit_code;PACKAGE-0_0_power_w;PACKAGE-0_0_percent"));
+        assert!(lines[1].ends_with(";100;5;;;;0.0;33.3"));
+        assert!(lines[2].ends_with(";200;5;;;;0.0;66.7"));
+    }
+
+    #[test]
+    fn phase_rows_have_the_power_of_the_energy_metrics() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 2,
+            ..Default::default()
+        };
+        let mut ratio = metric("PACKAGE-0_0_RATIO", 500);
+        ratio.unit = "‰".to_string();
+        let phase = |start: &str, end: &str, value, duration_ms| PhaseResult {
+            name: format!("{} -> {}", start, end),
+            start_token: (start != "START").then(|| start.to_string()),
+            end_token: (end != "END").then(|| end.to_string()),
+            metrics: vec![metric("PACKAGE-0_0", value), ratio.clone()],
+            duration_ms,
+            ..Default::default()
+        };
+        let result = PhaseMeasurementResult {
+            phases: vec![
+                phase("START", "__A__", 3_000_000, 1_000),
+                phase("__A__", "END", 1_000_000, 4_000),
+            ],
+            duration_ms: 5_000,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.phases_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        // The ratio is not an energy and has no power
+        assert!(lines[0].ends_with(
+            ";exit_code;PACKAGE-0_0_power_w;PACKAGE-0_0_percent;PACKAGE-0_0_RATIO_percent"
+        ));
+        assert!(lines[1].ends_with(";3000000;500;1000;;;;3.00;75.00;"));
+        assert!(lines[2].ends_with(";1000000;500;4000;;;;0.25;25.00;"));
+    }
+
+    #[test]
+    fn phase_timestamps_are_appended_when_requested() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 1,
+            csv_timestamps: true,
+            ..Default::default()
+        };
+        let phase = |start_us| {
+            PhaseResult::new(
+                &PhaseToken::Start,
+                &PhaseToken::End,
+                None,
+                None,
+                vec![metric("PACKAGE-0_0", 100)],
+                5,
+            )
+            .between(start_us, start_us + 5_000)
+        };
+        let result = PhaseMeasurementResult {
+            phases: vec![phase(1_700_000_000_000_000), phase(1_700_000_000_005_000)],
+            duration_ms: 10,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.phases_iterations(&config, &[result]).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].ends_with(";PACKAGE-0_0_percent;start_us;end_us"));
+        assert!(lines[1].ends_with(";50.0;1700000000000000;1700000000005000"));
+        assert!(lines[2].ends_with(";50.0;1700000000005000;1700000000010000"));
+    }
+
+    #[test]
+    fn energy_unit_converts_the_energy_cells() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.csv").to_string_lossy().to_string();
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            precision: 3,
+            ..Default::default()
+        };
+        let mut ratio = metric("PACKAGE-0_0_RATIO", 500);
+        ratio.unit = "‰".to_string();
+        let result = MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", 1_500_000), ratio],
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut output = CsvOutput::new(Some(path.clone()), FileMode::default()).unwrap();
+        output.set_energy_unit(EnergyUnit::J);
+        output.simple_single(&config, &result).unwrap();
+
+        let content = read_to_string(&path).unwrap();
+        // The ratio is not an energy and is kept as is
+        assert!(
+            content
+                .lines()
+                .nth(1)
+                .unwrap()
+                .starts_with("./bench;1.500;500;")
+        );
     }
 }
@@ -0,0 +1,306 @@
+use std::{fmt::Write, io::Write as _};
+
+use anyhow::Result;
+use log::{debug, info};
+
+use crate::{
+    config::ProfileConfig,
+    measurement::{MeasurementResult, PhaseMeasurementResult, total_energy_uj},
+    output::{OutputFormatTrait, default_iterations_filename, report_written},
+    util::file::{FileMode, create_file_with_user_permissions, get_absolute_path},
+};
+
+/// Size of the SVG bar chart in pixels.
+const CHART_WIDTH: usize = 640;
+const BAR_HEIGHT: usize = 24;
+const LABEL_WIDTH: usize = 220;
+
+/// One row of the metrics table, `phase` is empty in simple mode.
+struct TableRow {
+    iteration: usize,
+    phase: String,
+    metric: String,
+    value: u64,
+    unit: String,
+}
+
+/// Self-contained HTML report with a metrics table and an inline SVG bar chart, requires
+/// the `html` feature.
+pub struct HtmlOutput {
+    filename: String,
+    file_mode: FileMode,
+}
+
+impl OutputFormatTrait for HtmlOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        self.write(&simple_report(config, std::slice::from_ref(result)))
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        info!("Formatting {} simple iterations for HTML", results.len());
+        self.write(&simple_report(config, results))
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        self.write(&phases_report(config, std::slice::from_ref(result)))
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        info!("Formatting {} phase iterations for HTML", results.len());
+        self.write(&phases_report(config, results))
+    }
+}
+
+impl HtmlOutput {
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let filename = output_file.unwrap_or(default_iterations_filename("html"));
+        let absolute_path = get_absolute_path(&filename)?;
+        info!("Creating HTML output file: {}", absolute_path);
+
+        Ok(Self {
+            filename: absolute_path,
+            file_mode,
+        })
+    }
+
+    fn write(&self, html: &str) -> Result<()> {
+        debug!("Writing HTML report ({} bytes)", html.len());
+
+        let mut file = create_file_with_user_permissions(&self.filename, self.file_mode)?;
+        file.write_all(html.as_bytes())?;
+
+        report_written("HTML", &self.filename);
+        Ok(())
+    }
+}
+
+/// Report of the simple mode, the chart shows the mean of each metric over the iterations.
+fn simple_report(config: &ProfileConfig, results: &[MeasurementResult]) -> String {
+    let mut rows = Vec::new();
+    let mut bars: Vec<(String, u64)> = Vec::new();
+
+    for (idx, result) in results.iter().enumerate() {
+        for metric in &result.metrics {
+            rows.push(TableRow {
+                iteration: idx + 1,
+                phase: String::new(),
+                metric: metric.name.clone(),
+                value: metric.value,
+                unit: metric.unit.clone(),
+            });
+            match bars.iter_mut().find(|(name, _)| *name == metric.name) {
+                Some((_, total)) => *total += metric.value,
+                None => bars.push((metric.name.clone(), metric.value)),
+            }
+        }
+    }
+    let count = results.len().max(1) as u64;
+    for (_, total) in &mut bars {
+        *total /= count;
+    }
+
+    render(config, "Mean energy per metric (µJ)", &bars, &rows)
+}
+
+/// Report of the phases mode, the chart shows the mean total energy of each phase.
+fn phases_report(config: &ProfileConfig, results: &[PhaseMeasurementResult]) -> String {
+    let mut rows = Vec::new();
+    let mut bars: Vec<(String, u64)> = Vec::new();
+
+    for (idx, result) in results.iter().enumerate() {
+        for phase in &result.phases {
+            for metric in &phase.metrics {
+                rows.push(TableRow {
+                    iteration: idx + 1,
+                    phase: phase.name.clone(),
+                    metric: metric.name.clone(),
+                    value: metric.value,
+                    unit: metric.unit.clone(),
+                });
+            }
+            let energy = total_energy_uj(&phase.metrics);
+            match bars.iter_mut().find(|(name, _)| *name == phase.name) {
+                Some((_, total)) => *total += energy,
+                None => bars.push((phase.name.clone(), energy)),
+            }
+        }
+    }
+    let count = results.len().max(1) as u64;
+    for (_, total) in &mut bars {
+        *total /= count;
+    }
+
+    render(config, "Mean total energy per phase (µJ)", &bars, &rows)
+}
+
+fn render(
+    config: &ProfileConfig,
+    chart_title: &str,
+    bars: &[(String, u64)],
+    rows: &[TableRow],
+) -> String {
+    let command = escape(&config.cmd.join(" "));
+    let mut html = String::new();
+
+    let _ = writeln!(html, "<!DOCTYPE html>");
+    let _ = writeln!(html, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(html, "<title>joule-profiler: {}</title>", command);
+    let _ = writeln!(
+        html,
+        "<style>body{{font-family:sans-serif;margin:2em}}\
+         table{{border-collapse:collapse}}td,th{{border:1px solid #ccc;padding:4px 8px}}\
+         td.value{{text-align:right}}</style>"
+    );
+    let _ = writeln!(html, "</head>\n<body>");
+    let _ = writeln!(html, "<h1>{}</h1>", command);
+    let _ = writeln!(html, "<h2>{}</h2>", escape(chart_title));
+    html.push_str(&bar_chart(bars));
+    let _ = writeln!(html, "<h2>Metrics</h2>\n<table>");
+    let _ = writeln!(
+        html,
+        "<tr><th>Iteration</th><th>Phase</th><th>Metric</th><th>Value</th><th>Unit</th></tr>"
+    );
+    for row in rows {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"value\">{}</td><td>{}</td></tr>",
+            row.iteration,
+            escape(&row.phase),
+            escape(&row.metric),
+            row.value,
+            escape(&row.unit)
+        );
+    }
+    let _ = writeln!(html, "</table>\n</body>\n</html>");
+
+    html
+}
+
+/// Horizontal bars scaled on the largest value.
+fn bar_chart(bars: &[(String, u64)]) -> String {
+    let max = bars
+        .iter()
+        .map(|(_, value)| *value)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let bar_area = CHART_WIDTH - LABEL_WIDTH - 100;
+    let height = bars.len().max(1) * BAR_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+        CHART_WIDTH, height
+    );
+    for (idx, (label, value)) in bars.iter().enumerate() {
+        let y = idx * BAR_HEIGHT;
+        let width = (*value as f64 / max as f64 * bar_area as f64).round() as usize;
+        let _ = writeln!(
+            svg,
+            "<text x=\"0\" y=\"{}\" font-size=\"12\">{}</text>\
+             <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#4c78a8\"/>\
+             <text x=\"{}\" y=\"{}\" font-size=\"12\">{}</text>",
+            y + 16,
+            escape(label),
+            LABEL_WIDTH,
+            y + 4,
+            width,
+            BAR_HEIGHT - 8,
+            LABEL_WIDTH + width + 6,
+            y + 16,
+            value
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Escape the HTML special characters of a text.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        measurement::{PhaseResult, PhaseToken},
+        test_util::{config, metric},
+    };
+
+    #[test]
+    fn simple_report_contains_values_and_chart() {
+        let result = MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", 123_456), metric("DRAM_0", 7_890)],
+            duration_ms: 10,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string(), "<fast>".to_string()],
+            ..config()
+        };
+
+        let html = simple_report(&config, &[result]);
+
+        assert!(html.contains("<svg"));
+        assert!(html.contains("<td>PACKAGE-0_0</td><td class=\"value\">123456</td>"));
+        assert!(html.contains("<td>DRAM_0</td><td class=\"value\">7890</td>"));
+        assert!(html.contains("<h1>./bench &lt;fast&gt;</h1>"));
+    }
+
+    #[test]
+    fn phases_report_charts_each_phase() {
+        let parse = PhaseToken::Token("__PARSE__".to_string());
+        let result = PhaseMeasurementResult {
+            phases: vec![
+                PhaseResult::new(
+                    &PhaseToken::Start,
+                    &parse,
+                    None,
+                    Some(1),
+                    vec![metric("PACKAGE-0_0", 100), metric("DRAM_0", 20)],
+                    5,
+                ),
+                PhaseResult::new(
+                    &parse,
+                    &PhaseToken::End,
+                    Some(1),
+                    None,
+                    vec![metric("PACKAGE-0_0", 300)],
+                    5,
+                ),
+            ],
+            duration_ms: 10,
+            ..Default::default()
+        };
+
+        let html = phases_report(&config(), &[result]);
+
+        assert!(html.contains("<svg"));
+        assert!(html.contains("START -&gt; __PARSE__</text>"));
+        assert!(html.contains(">120</text>"));
+        assert!(html.contains("<td class=\"value\">300</td>"));
+    }
+}
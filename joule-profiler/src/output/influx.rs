@@ -0,0 +1,248 @@
+use std::io::Write;
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    config::ProfileConfig,
+    measurement::{MeasurementResult, PhaseMeasurementResult},
+    output::{OutputFormatTrait, OutputSink, report_written},
+    source::Metric,
+    util::{file::FileMode, time::get_timestamp},
+};
+
+/// Measurement name of the lines when `--influx-measurement` is not given.
+pub const DEFAULT_INFLUX_MEASUREMENT: &str = "joule_profiler";
+
+/// InfluxDB line protocol (`measurement,tags fields timestamp`), one line per metric of each
+/// iteration or phase, to ingest the results as a time series.
+///
+/// The command, iteration, phase and metric are tags, the energy, duration and exit code
+/// fields, and every line is timestamped with the time the results are written, in
+/// nanoseconds.
+pub struct InfluxOutput {
+    writer: OutputSink,
+    filename: String,
+    measurement: String,
+}
+
+/// Tags and fields of a line, the metric tags being added to them.
+struct Point<'a> {
+    command: &'a str,
+    iteration: usize,
+    phase: Option<&'a str>,
+    duration_ms: u128,
+    exit_code: i32,
+}
+
+impl OutputFormatTrait for InfluxOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        self.simple_iterations(config, std::slice::from_ref(result))
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        info!(
+            "Formatting {} simple iterations for InfluxDB",
+            results.len()
+        );
+        let command = config.cmd.join(" ");
+        let timestamp_ns = get_timestamp() * 1_000;
+
+        for (idx, result) in results.iter().enumerate() {
+            let point = Point {
+                command: &command,
+                iteration: idx + 1,
+                phase: None,
+                duration_ms: result.duration_ms,
+                exit_code: result.exit_code,
+            };
+            self.write_lines(&point, &result.metrics, timestamp_ns)?;
+        }
+
+        report_written("InfluxDB", &self.filename);
+        Ok(())
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        self.phases_iterations(config, std::slice::from_ref(result))
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        info!("Formatting {} phase iterations for InfluxDB", results.len());
+        let command = config.cmd.join(" ");
+        let timestamp_ns = get_timestamp() * 1_000;
+
+        for (idx, result) in results.iter().enumerate() {
+            for phase in &result.phases {
+                let point = Point {
+                    command: &command,
+                    iteration: idx + 1,
+                    phase: Some(&phase.name),
+                    duration_ms: phase.duration_ms,
+                    exit_code: result.exit_code,
+                };
+                self.write_lines(&point, &phase.metrics, timestamp_ns)?;
+            }
+        }
+
+        report_written("InfluxDB", &self.filename);
+        Ok(())
+    }
+}
+
+/// Escape the commas and spaces of a measurement name.
+fn escape_measurement(name: &str) -> String {
+    name.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(' ', "\\ ")
+}
+
+/// Escape the commas, equal signs and spaces of a tag value, the newlines not being allowed
+/// in a line are replaced by spaces.
+fn escape_tag(value: &str) -> String {
+    escape_measurement(&value.replace('\n', " ")).replace('=', "\\=")
+}
+
+impl InfluxOutput {
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let (writer, filename) = OutputSink::open(output_file, "InfluxDB", "lp", file_mode)?;
+        Ok(Self {
+            writer,
+            filename,
+            measurement: DEFAULT_INFLUX_MEASUREMENT.to_string(),
+        })
+    }
+
+    /// Name the measurement of the lines.
+    pub fn set_measurement(&mut self, measurement: &str) {
+        self.measurement = measurement.to_string();
+    }
+
+    /// Write a line per metric, the empty tag values being left out as InfluxDB rejects them.
+    fn write_lines(&mut self, point: &Point, metrics: &[Metric], timestamp_ns: u128) -> Result<()> {
+        for metric in metrics {
+            let iteration = point.iteration.to_string();
+            let tags = [
+                ("command", point.command),
+                ("iteration", iteration.as_str()),
+                ("phase", point.phase.unwrap_or_default()),
+                ("metric", metric.name.as_str()),
+                ("unit", metric.unit.as_str()),
+                ("source", metric.source.as_str()),
+            ];
+
+            let mut line = escape_measurement(&self.measurement);
+            for (key, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+                line.push_str(&format!(",{}={}", key, escape_tag(value)));
+            }
+            writeln!(
+                self.writer,
+                "{} energy={}i,duration_ms={}i,exit_code={}i {}",
+                line, metric.value, point.duration_ms, point.exit_code, timestamp_ns
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{measurement::PhaseResult, test_util::metric};
+
+    fn write(write: impl FnOnce(&mut InfluxOutput, &ProfileConfig)) -> Vec<String> {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("results.lp");
+        let mut output = InfluxOutput::new(
+            Some(path.to_str().unwrap().to_string()),
+            FileMode::default(),
+        )
+        .unwrap();
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string(), "a,b".to_string()],
+            ..Default::default()
+        };
+
+        write(&mut output, &config);
+        read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                // The timestamp is the time of writing
+                let (line, timestamp) = line.rsplit_once(' ').unwrap();
+                assert!(timestamp.parse::<u128>().unwrap() > 0);
+                line.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simple_iterations_are_a_line_per_metric() {
+        let result = |value| MeasurementResult {
+            duration_ms: 10,
+            measure_count: 2,
+            metrics: vec![metric("PACKAGE-0_0", value), metric("DRAM_0", 20)],
+            ..Default::default()
+        };
+
+        let lines = write(|output, config| {
+            output.set_measurement("energy runs");
+            output
+                .simple_iterations(config, &[result(1_500), result(1_700)])
+                .unwrap()
+        });
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "energy\\ runs,command=./bench\\ a\\,b,iteration=1,metric=PACKAGE-0_0,unit=µJ,\
+             source=powercap energy=1500i,duration_ms=10i,exit_code=0i"
+        );
+        assert!(lines[3].contains(",iteration=2,metric=DRAM_0,"));
+    }
+
+    #[test]
+    fn phases_are_tagged_with_their_name() {
+        let result = PhaseMeasurementResult {
+            phases: vec![PhaseResult {
+                name: "START -> __A__".to_string(),
+                metrics: vec![metric("PACKAGE-0_0", 100)],
+                duration_ms: 5,
+                ..Default::default()
+            }],
+            duration_ms: 5,
+            exit_code: 1,
+            ..Default::default()
+        };
+
+        let lines = write(|output, config| output.phases_single(config, &result).unwrap());
+
+        assert_eq!(
+            lines,
+            [
+                "joule_profiler,command=./bench\\ a\\,b,iteration=1,phase=START\\ ->\\ __A__,\
+              metric=PACKAGE-0_0,unit=µJ,source=powercap energy=100i,duration_ms=5i,exit_code=1i"
+            ]
+        );
+    }
+}
@@ -1,35 +1,96 @@
-use std::fs::File;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
+use std::path::Path;
 
 use anyhow::{Result, bail};
 use log::{info, trace};
+use serde::Serialize;
 use serde_json::json;
 
+use crate::cli::EnergyUnit;
 use crate::config::{ListSensorsConfig, Mode, ProfileConfig};
-use crate::measurement::{MeasurementResult, PhaseMeasurementResult};
-use crate::output::{OutputFormatTrait, default_iterations_filename};
-use crate::source::Sensor;
-use crate::util::file::{create_file_with_user_permissions, get_absolute_path};
+use crate::measurement::{
+    CalibrationResult, EnergyTrend, MeasurementResult, MetricComparison, MetricSummary,
+    PhaseMeasurementResult, PhaseSummary, TokenCount, metric_socket,
+};
+use crate::output::{
+    ENERGY_UNIT, FieldProjection, OutputFormatTrait, OutputSink,
+    model::{
+        CalibrationRate, CalibrationReport, ComparisonReport, PhasesIteration,
+        PhasesIterationsReport, PhasesReport, PhasesRun, ReportMode, SimpleIteration,
+        SimpleIterationsReport, SimpleReport, SimpleRun,
+    },
+    report_written, round_float,
+};
+use crate::source::{Metric, Sensor, rapl::domain::rapl_base_path};
+use crate::util::{
+    file::FileMode,
+    governor::CPU_SYSFS_PATH,
+    sysinfo::{Metadata, PROC_PATH, SysInfo, read_metadata, read_sysinfo},
+};
 
-/// JSON output writer to file.
+/// Fields depending on the timing of the run, removed from the canonical JSON.
+const VOLATILE_FIELDS: [&str; 14] = [
+    "duration_ms",
+    "start_us",
+    "end_us",
+    "measure_count",
+    "measure_delta",
+    "read_spread_us",
+    "polling",
+    "power_w",
+    "power_range",
+    "power_states",
+    "raw",
+    "counter_wraps",
+    "overflow_detected",
+    "timestamp",
+];
+
+/// Key of the metrics without a socket suffix when they are grouped by socket.
+const NO_SOCKET_KEY: &str = "other";
+
+/// JSON output writer to a file or stdout.
 pub struct JsonOutput {
-    writer: File,
+    writer: OutputSink,
     filename: String,
+    encoding: Encoding,
+    /// Write the canonical form of the documents, see [`canonicalize`]
+    canonical: bool,
+    /// Nest the metrics under their socket, see [`group_by_socket`]
+    group_by_socket: bool,
+    /// Keys written in the documents and their iterations and phases
+    fields: FieldProjection,
+    /// Write each document on a single line (JSON Lines), to append them to a file
+    lines: bool,
+    /// Unit and precision of the energy metrics, see [`convert_energy`]
+    energy_unit: Option<(EnergyUnit, usize)>,
+}
+
+/// Encoding of the JSON documents written to the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Pretty printed JSON, one document per line
+    Json,
+    /// MessagePack, the documents are concatenated
+    Msgpack,
 }
 
 impl OutputFormatTrait for JsonOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
     fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
-        let obj = json!({
-            "command": config.cmd.join(" "),
-            "mode": "simple",
-            "metrics": result.metrics,
-            "duration_ms": result.duration_ms,
-            "exit_code": result.exit_code,
-            "measure_count": result.measure_count,
-            "measure_delta": result.measure_delta,
-        });
+        let report = SimpleReport {
+            command: config.cmd.join(" "),
+            mode: ReportMode::Simple,
+            metadata: metadata_value(config, metric_names(&result.metrics)),
+            system: system_value(),
+            run: SimpleRun::new(result, config.precision),
+        };
 
-        self.write_json(&obj)
+        self.write_json(&report)
     }
 
     fn simple_iterations(
@@ -39,29 +100,32 @@ impl OutputFormatTrait for JsonOutput {
     ) -> Result<()> {
         info!("Formatting {} simple iterations", results.len());
 
-        let iters: Vec<_> = results
+        let iterations = results
             .iter()
             .enumerate()
             .map(|(idx, result)| {
                 trace!("Formatting iteration {}", idx + 1);
-                json!({
-                    "iteration": idx + 1,
-                    "metrics": result.metrics,
-                    "duration_ms": result.duration_ms,
-                    "exit_code": result.exit_code,
-                    "measure_count": result.measure_count,
-                    "measure_delta": result.measure_delta,
-                })
+                SimpleIteration {
+                    iteration: idx + 1,
+                    run: SimpleRun::new(result, config.precision),
+                }
             })
             .collect();
 
-        let root = json!({
-            "command": config.cmd.join(" "),
-            "mode": "simple-iterations",
-            "iterations": iters
-        });
+        let report = SimpleIterationsReport {
+            command: config.cmd.join(" "),
+            mode: ReportMode::SimpleIterations,
+            metadata: metadata_value(
+                config,
+                metric_names(results.iter().flat_map(|result| &result.metrics)),
+            ),
+            system: system_value(),
+            iterations,
+            summary: MetricSummary::from_iterations_without_outliers(results, config.drop_outliers),
+            trend: config.trend.then(|| EnergyTrend::from_iterations(results)),
+        };
 
-        self.write_json(&root)
+        self.write_json(&report)
     }
 
     fn phases_single(
@@ -69,21 +133,24 @@ impl OutputFormatTrait for JsonOutput {
         config: &ProfileConfig,
         result: &PhaseMeasurementResult,
     ) -> Result<()> {
-        let phases_value = serde_json::to_value(result.phases.clone())?;
         let phases_config = match &config.mode {
-            Mode::SimpleMode => bail!("Invalid configuration mode."),
             Mode::PhaseMode(phases_config) => phases_config,
+            _ => bail!("Invalid configuration mode."),
         };
 
-        let obj = json!({
-            "command": config.cmd.join(" "),
-            "mode": "phases",
-            "token_pattern": phases_config.token_pattern,
-            "exit_code": result.exit_code,
-            "phases": phases_value
-        });
+        let report = PhasesReport {
+            command: config.cmd.join(" "),
+            mode: ReportMode::Phases,
+            metadata: metadata_value(
+                config,
+                metric_names(result.phases.iter().flat_map(|phase| &phase.metrics)),
+            ),
+            system: system_value(),
+            token_pattern: &phases_config.token_pattern,
+            run: PhasesRun::new(result, phases_config.describe_phases, config.precision),
+        };
 
-        self.write_json(&obj)
+        self.write_json(&report)
     }
 
     fn phases_iterations(
@@ -93,63 +160,325 @@ impl OutputFormatTrait for JsonOutput {
     ) -> Result<()> {
         info!("Formatting {} phase iterations", results.len());
         let phases_config = match &config.mode {
-            Mode::SimpleMode => bail!("Invalid configuration mode."),
             Mode::PhaseMode(phases_config) => phases_config,
+            _ => bail!("Invalid configuration mode."),
         };
 
-        let iters: Vec<_> = results
+        let iterations = results
             .iter()
             .enumerate()
-            .map(|(idx, result)| {
-                json!({
-                    "iteration": idx + 1,
-                    "exit_code": result.exit_code,
-                    "duration": result.duration_ms,
-                    "phases": result.phases,
-                })
+            .map(|(idx, result)| PhasesIteration {
+                iteration: idx + 1,
+                run: PhasesRun::new(result, phases_config.describe_phases, config.precision),
             })
             .collect();
 
-        let root = json!({
-            "command": config.cmd.join(" "),
-            "mode": "phases-iterations",
-            "token_pattern": phases_config.token_pattern,
-            "iterations": iters
-        });
+        let report = PhasesIterationsReport {
+            command: config.cmd.join(" "),
+            mode: ReportMode::PhasesIterations,
+            metadata: metadata_value(
+                config,
+                metric_names(
+                    results
+                        .iter()
+                        .flat_map(|result| &result.phases)
+                        .flat_map(|phase| &phase.metrics),
+                ),
+            ),
+            system: system_value(),
+            token_pattern: &phases_config.token_pattern,
+            iterations,
+            token_counts: TokenCount::from_iterations(results),
+            summary: PhaseSummary::from_iterations(results),
+        };
 
-        self.write_json(&root)
+        self.write_json(&report)
+    }
+
+    fn calibration(&mut self, config: &ProfileConfig, results: &[CalibrationResult]) -> Result<()> {
+        info!("Formatting {} calibration rates", results.len());
+
+        let rates = match results.first() {
+            Some(baseline) => results
+                .iter()
+                .map(|result| CalibrationRate::new(result, baseline, config.precision))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let report = CalibrationReport {
+            command: config.cmd.join(" "),
+            mode: ReportMode::Calibrate,
+            metadata: metadata_value(
+                config,
+                metric_names(results.iter().flat_map(|result| &result.metrics)),
+            ),
+            rates,
+        };
+
+        self.write_json(&report)
+    }
+
+    fn comparison(
+        &mut self,
+        config: &ProfileConfig,
+        comparison: &[MetricComparison],
+    ) -> Result<()> {
+        let compare_config = match &config.mode {
+            Mode::CompareMode(compare_config) => compare_config,
+            _ => bail!("Invalid configuration mode."),
+        };
+
+        let report = ComparisonReport {
+            mode: ReportMode::Compare,
+            metadata: metadata_value(config, comparison.iter().map(|metric| metric.name.as_str())),
+            system: system_value(),
+            baseline: &compare_config.baseline,
+            candidate: &compare_config.candidate,
+            iterations: config.iterations,
+            metrics: comparison,
+        };
+
+        self.write_json(&report)
     }
 
     fn list_sensors(&mut self, _config: &ListSensorsConfig, sensors: &[Sensor]) -> Result<()> {
-        self.write_json(&serde_json::to_value(sensors)?)
+        self.write_json(&sensors)
+    }
+}
+
+impl Encoding {
+    fn label(self) -> &'static str {
+        match self {
+            Encoding::Json => "JSON",
+            Encoding::Msgpack => "MessagePack",
+        }
+    }
+}
+
+/// Make a document comparable between runs: the volatile fields are removed and the metrics
+/// sorted by name, the object keys being already sorted by serde_json.
+fn canonicalize(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            object.retain(|key, _| !VOLATILE_FIELDS.contains(&key.as_str()));
+            if let Some(serde_json::Value::Array(metrics)) = object.get_mut("metrics") {
+                metrics.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+            }
+            object.values_mut().for_each(canonicalize);
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(canonicalize),
+        _ => {}
     }
 }
 
+/// Energy keys of the metrics and of their summaries, see [`MetricSummary`].
+const ENERGY_KEYS: [&str; 7] = ["value", "mean", "stddev", "sem", "min", "median", "max"];
+
+/// Convert the value of the energy metrics of every metrics and summary array from
+/// microjoules to the unit, rounded to the precision, their `unit` being replaced.
+fn convert_energy(value: &mut serde_json::Value, unit: EnergyUnit, precision: usize) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for key in ["metrics", "summary"] {
+                let Some(serde_json::Value::Array(metrics)) = object.get_mut(key) else {
+                    continue;
+                };
+                for metric in metrics.iter_mut() {
+                    if metric["unit"] != ENERGY_UNIT {
+                        continue;
+                    }
+                    for key in ENERGY_KEYS {
+                        if let Some(energy_uj) = metric.get(key).and_then(|value| value.as_f64()) {
+                            metric[key] = json!(round_float(unit.from_uj(energy_uj), precision));
+                        }
+                    }
+                    metric["unit"] = json!(unit.symbol());
+                }
+            }
+            object
+                .values_mut()
+                .for_each(|value| convert_energy(value, unit, precision));
+        }
+        serde_json::Value::Array(array) => array
+            .iter_mut()
+            .for_each(|value| convert_energy(value, unit, precision)),
+        _ => {}
+    }
+}
+
+/// Replace every metrics array by an object of the metrics of each socket, parsed from the
+/// metric names (`{"0": [...], "1": [...]}`), the metrics without a socket going under
+/// `other`.
+fn group_by_socket(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            object.values_mut().for_each(group_by_socket);
+            if let Some(serde_json::Value::Array(metrics)) = object.get_mut("metrics") {
+                let mut sockets: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+                for metric in metrics.drain(..) {
+                    let socket = metric["name"]
+                        .as_str()
+                        .and_then(metric_socket)
+                        .unwrap_or(NO_SOCKET_KEY)
+                        .to_string();
+                    sockets.entry(socket).or_default().push(metric);
+                }
+                object["metrics"] = json!(sockets);
+            }
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(group_by_socket),
+        _ => {}
+    }
+}
+
+/// Remove the keys not selected by the projection from the document and from its
+/// iterations, phases and calibration rates.
+fn project(value: &mut serde_json::Value, fields: &FieldProjection) {
+    if let serde_json::Value::Object(object) = value {
+        object.retain(|key, _| fields.keeps(key));
+        for key in ["iterations", "phases", "rates"] {
+            if let Some(serde_json::Value::Array(items)) = object.get_mut(key) {
+                items.iter_mut().for_each(|item| project(item, fields));
+            }
+        }
+    }
+}
+
+/// Machine that produced the results, the sockets being counted from the metrics and the
+/// RAPL path only reported when the RAPL source is used.
+fn metadata_value<'a>(
+    config: &ProfileConfig,
+    metric_names: impl IntoIterator<Item = &'a str>,
+) -> Metadata {
+    let sockets: BTreeSet<&str> = metric_names.into_iter().filter_map(metric_socket).collect();
+    let rapl_path = config
+        .fake_source
+        .is_empty()
+        .then(|| rapl_base_path(config.rapl_path.as_deref()));
+
+    read_metadata(
+        Path::new(PROC_PATH),
+        (!sockets.is_empty()).then_some(sockets.len()),
+        rapl_path,
+    )
+}
+
+/// Names of the metrics, to count their sockets.
+fn metric_names<'a>(
+    metrics: impl IntoIterator<Item = &'a Metric>,
+) -> impl Iterator<Item = &'a str> {
+    metrics.into_iter().map(|metric| metric.name.as_str())
+}
+
+/// State of the CPUs affecting the energy (turbo, SMT, governor), read when the results are
+/// written.
+fn system_value() -> SysInfo {
+    read_sysinfo(Path::new(CPU_SYSFS_PATH))
+}
+
 impl JsonOutput {
     /// Creates a JSON output writer to a file.
-    pub fn new(output_file: Option<String>) -> Result<Self> {
-        let filename = output_file
-            .clone()
-            .unwrap_or(default_iterations_filename("json"));
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        Self::with_encoding(output_file, Encoding::Json, file_mode)
+    }
+
+    /// Creates a writer of the JSON documents to a file in the given encoding.
+    pub fn with_encoding(
+        output_file: Option<String>,
+        encoding: Encoding,
+        file_mode: FileMode,
+    ) -> Result<Self> {
+        let extension = match encoding {
+            Encoding::Json => "json",
+            Encoding::Msgpack => "msgpack",
+        };
+        let (writer, filename) =
+            OutputSink::open(output_file, encoding.label(), extension, file_mode)?;
 
-        let absolute_path = get_absolute_path(&filename)?;
-        info!("Creating JSON output file: {}", absolute_path);
+        Ok(Self {
+            writer,
+            filename,
+            encoding,
+            canonical: false,
+            group_by_socket: false,
+            fields: FieldProjection::default(),
+            lines: false,
+            energy_unit: None,
+        })
+    }
 
-        let file = create_file_with_user_permissions(&absolute_path)?;
+    /// Creates a JSON writer adding the documents to the end of the file, one per line so
+    /// that the file of several runs is valid newline-delimited JSON.
+    pub fn appending(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let (writer, filename) =
+            OutputSink::open_append(output_file, Encoding::Json.label(), "json", file_mode)?;
 
         Ok(Self {
-            writer: file,
-            filename: absolute_path,
+            writer,
+            filename,
+            encoding: Encoding::Json,
+            canonical: false,
+            group_by_socket: false,
+            fields: FieldProjection::default(),
+            lines: true,
+            energy_unit: None,
         })
     }
 
-    fn write_json(&mut self, value: &serde_json::Value) -> Result<()> {
-        let json_str = serde_json::to_string_pretty(value)?;
-        trace!("Writing JSON output ({} bytes)", json_str.len());
-        writeln!(self.writer, "{}", json_str)?;
+    /// Write the canonical form of the documents, for golden comparisons.
+    pub fn set_canonical(&mut self, canonical: bool) {
+        self.canonical = canonical;
+    }
+
+    /// Nest the metrics of the documents under their socket.
+    pub fn set_group_by_socket(&mut self, group_by_socket: bool) {
+        self.group_by_socket = group_by_socket;
+    }
+
+    /// Write the energy metrics in this unit instead of microjoules.
+    pub fn set_energy_unit(&mut self, unit: EnergyUnit, precision: usize) {
+        self.energy_unit = Some((unit, precision));
+    }
+
+    /// Only write the selected keys of [`OUTPUT_FIELDS`](crate::output::OUTPUT_FIELDS).
+    pub fn set_fields(&mut self, fields: FieldProjection) {
+        self.fields = fields;
+    }
+
+    fn write_json(&mut self, report: &impl Serialize) -> Result<()> {
+        let mut value = serde_json::to_value(report)?;
+        if self.fields.is_restricted() {
+            project(&mut value, &self.fields);
+        }
+        if let Some((unit, precision)) = self.energy_unit {
+            convert_energy(&mut value, unit, precision);
+        }
+        if self.canonical {
+            canonicalize(&mut value);
+        }
+        if self.group_by_socket {
+            group_by_socket(&mut value);
+        }
+
+        match self.encoding {
+            Encoding::Json => {
+                let json_str = if self.lines {
+                    serde_json::to_string(&value)?
+                } else {
+                    serde_json::to_string_pretty(&value)?
+                };
+                trace!("Writing JSON output ({} bytes)", json_str.len());
+                writeln!(self.writer, "{}", json_str)?;
+            }
+            Encoding::Msgpack => {
+                let bytes = rmp_serde::to_vec_named(&value)?;
+                trace!("Writing MessagePack output ({} bytes)", bytes.len());
+                self.writer.write_all(&bytes)?;
+            }
+        }
 
-        println!("✔ JSON written to: {}", self.filename);
-        info!("JSON output saved to: {}", self.filename);
+        report_written(self.encoding.label(), &self.filename);
 
         Ok(())
     }
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    config::ProfileConfig,
+    measurement::{MeasurementResult, PhaseMeasurementResult},
+    output::{OutputFormatTrait, OutputSink, report_written},
+    source::Metric,
+    util::file::FileMode,
+};
+
+/// GitHub-flavored Markdown tables, one row per iteration or phase and one column per
+/// metric, e.g. to paste the results in a pull request.
+pub struct MarkdownOutput {
+    writer: OutputSink,
+    filename: String,
+}
+
+impl OutputFormatTrait for MarkdownOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        self.simple_iterations(config, std::slice::from_ref(result))
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        info!(
+            "Formatting {} simple iterations for Markdown",
+            results.len()
+        );
+        let columns = metric_columns(results.iter().map(|result| result.metrics.as_slice()));
+
+        let mut header = vec!["Iteration".to_string()];
+        header.extend(
+            columns
+                .iter()
+                .map(|(name, unit)| format!("{} ({})", name, unit)),
+        );
+        header.extend(["Duration (ms)".to_string(), "Exit code".to_string()]);
+
+        let rows = results
+            .iter()
+            .enumerate()
+            .map(|(idx, result)| {
+                let mut row = vec![(idx + 1).to_string()];
+                row.extend(metric_cells(&columns, &result.metrics));
+                row.extend([result.duration_ms.to_string(), result.exit_code.to_string()]);
+                row
+            })
+            .collect::<Vec<_>>();
+
+        self.write_table(config, 1, &header, &rows)
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        self.phases_iterations(config, std::slice::from_ref(result))
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        info!("Formatting {} phase iterations for Markdown", results.len());
+        let columns = metric_columns(
+            results
+                .iter()
+                .flat_map(|result| &result.phases)
+                .map(|phase| phase.metrics.as_slice()),
+        );
+
+        let mut header = vec!["Iteration".to_string(), "Phase".to_string()];
+        header.extend(
+            columns
+                .iter()
+                .map(|(name, unit)| format!("{} ({})", name, unit)),
+        );
+        header.extend(["Duration (ms)".to_string(), "Exit code".to_string()]);
+
+        let mut rows = Vec::new();
+        for (idx, result) in results.iter().enumerate() {
+            for phase in &result.phases {
+                // As code so that the underscores of the tokens are not rendered as emphasis
+                let mut row = vec![(idx + 1).to_string(), format!("`{}`", phase.name)];
+                row.extend(metric_cells(&columns, &phase.metrics));
+                row.extend([phase.duration_ms.to_string(), result.exit_code.to_string()]);
+                rows.push(row);
+            }
+        }
+
+        self.write_table(config, 2, &header, &rows)
+    }
+}
+
+/// Name and unit of every metric of the rows, sorted by name.
+fn metric_columns<'a>(rows: impl Iterator<Item = &'a [Metric]>) -> BTreeMap<String, String> {
+    rows.flatten()
+        .map(|metric| (metric.name.clone(), metric.unit.clone()))
+        .collect()
+}
+
+/// Values of the metrics in the order of the columns, empty for the missing ones.
+fn metric_cells(columns: &BTreeMap<String, String>, metrics: &[Metric]) -> Vec<String> {
+    columns
+        .keys()
+        .map(|name| {
+            metrics
+                .iter()
+                .find(|metric| &metric.name == name)
+                .map(|metric| metric.value.to_string())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Escape the characters ending a cell or a row of a table.
+fn escape(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Format a table row, the cells being escaped.
+fn table_row<S: AsRef<str>>(cells: &[S]) -> String {
+    let cells: Vec<String> = cells.iter().map(|cell| escape(cell.as_ref())).collect();
+    format!("| {} |", cells.join(" | "))
+}
+
+impl MarkdownOutput {
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let (writer, filename) = OutputSink::open(output_file, "Markdown", "md", file_mode)?;
+        Ok(Self { writer, filename })
+    }
+
+    /// Write the command followed by the table, the first `labels` columns left-aligned and
+    /// the numeric ones right-aligned.
+    fn write_table(
+        &mut self,
+        config: &ProfileConfig,
+        labels: usize,
+        header: &[String],
+        rows: &[Vec<String>],
+    ) -> Result<()> {
+        writeln!(self.writer, "**Command:** `{}`", config.cmd.join(" "))?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "{}", table_row(header))?;
+
+        let alignments: Vec<&str> = (0..header.len())
+            .map(|idx| if idx < labels { ":---" } else { "---:" })
+            .collect();
+        writeln!(self.writer, "{}", table_row(&alignments))?;
+        for row in rows {
+            writeln!(self.writer, "{}", table_row(row))?;
+        }
+
+        report_written("Markdown", &self.filename);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{measurement::PhaseResult, test_util::metric};
+
+    fn write(write: impl FnOnce(&mut MarkdownOutput, &ProfileConfig)) -> String {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("results.md");
+        let mut output = MarkdownOutput::new(
+            Some(path.to_str().unwrap().to_string()),
+            FileMode::default(),
+        )
+        .unwrap();
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string(), "a|b".to_string()],
+            ..Default::default()
+        };
+
+        write(&mut output, &config);
+        read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn simple_iterations_are_one_row_each() {
+        let result = |value, duration_ms| MeasurementResult {
+            duration_ms,
+            measure_count: 2,
+            metrics: vec![metric("DRAM_0", 20), metric("PACKAGE-0_0", value)],
+            ..Default::default()
+        };
+
+        let markdown = write(|output, config| {
+            output
+                .simple_iterations(config, &[result(1_500, 10), result(1_700, 12)])
+                .unwrap()
+        });
+
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(lines[0], "**Command:** `./bench a|b`");
+        assert_eq!(
+            lines[2],
+            "| Iteration | DRAM_0 (µJ) | PACKAGE-0_0 (µJ) | Duration (ms) | Exit code |"
+        );
+        assert_eq!(lines[3], "| :--- | ---: | ---: | ---: | ---: |");
+        assert_eq!(lines[4], "| 1 | 20 | 1500 | 10 | 0 |");
+        assert_eq!(lines[5], "| 2 | 20 | 1700 | 12 | 0 |");
+    }
+
+    #[test]
+    fn phases_are_one_row_each_with_missing_metrics_left_empty() {
+        let phase = |name: &str, metrics| PhaseResult {
+            name: name.to_string(),
+            metrics,
+            duration_ms: 5,
+            ..Default::default()
+        };
+        let result = PhaseMeasurementResult {
+            phases: vec![
+                phase("START -> __A__", vec![metric("PACKAGE-0_0", 100)]),
+                phase(
+                    "__A__ -> END",
+                    vec![metric("DRAM_0", 3), metric("PACKAGE-0_0", 200)],
+                ),
+            ],
+            duration_ms: 10,
+            exit_code: 1,
+            ..Default::default()
+        };
+
+        let markdown = write(|output, config| output.phases_single(config, &result).unwrap());
+
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(
+            lines[2],
+            "| Iteration | Phase | DRAM_0 (µJ) | PACKAGE-0_0 (µJ) | Duration (ms) | Exit code |"
+        );
+        assert_eq!(lines[3], "| :--- | :--- | ---: | ---: | ---: | ---: |");
+        assert_eq!(lines[4], "| 1 | `START -> __A__` |  | 100 | 5 | 1 |");
+        assert_eq!(lines[5], "| 1 | `__A__ -> END` | 3 | 200 | 5 | 1 |");
+    }
+}
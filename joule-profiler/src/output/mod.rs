@@ -1,32 +1,96 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs::File,
+    io::{Stdout, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Result;
 use enum_dispatch::enum_dispatch;
-use log::error;
+use log::{error, info, warn};
 
 use crate::{
+    cli::{EnergyUnit, MetricFormat},
     config::{ListSensorsConfig, OutputFormat, ProfileConfig},
-    measurement::{MeasurementResult, PhaseMeasurementResult},
-    output::{csv::CsvOutput, json::JsonOutput, terminal::TerminalOutput},
-    source::Sensor,
+    measurement::{CalibrationResult, MeasurementResult, MetricComparison, PhaseMeasurementResult},
+    output::{
+        csv::{CsvOutput, DEFAULT_DELIMITER},
+        influx::InfluxOutput,
+        json::JsonOutput,
+        markdown::MarkdownOutput,
+        msgpack::MsgpackOutput,
+        null::NullOutput,
+        parquet::ParquetOutput,
+        prometheus::PrometheusOutput,
+        terminal::{SUCCESS_STYLE, TerminalOutput, paint},
+    },
+    source::{Metric, Sensor},
+    util::{
+        file::{
+            FileMode, append_file_with_user_permissions, create_file_with_user_permissions,
+            get_absolute_path,
+        },
+        glob::glob_matches,
+    },
 };
 
 mod csv;
+#[cfg(feature = "html")]
+mod html;
+pub mod influx;
 mod json;
-mod terminal;
+mod markdown;
+mod model;
+mod msgpack;
+mod null;
+pub mod otlp;
+mod parquet;
+mod prometheus;
+pub mod terminal;
+pub mod time_series;
+pub mod trace_events;
 
 #[enum_dispatch]
-pub enum Displayer {
+pub enum Output {
     Terminal(TerminalOutput),
     Json(JsonOutput),
     Csv(CsvOutput),
+    Null(NullOutput),
+    Parquet(ParquetOutput),
+    #[cfg(feature = "html")]
+    Html(html::HtmlOutput),
+    Msgpack(MsgpackOutput),
+    Markdown(MarkdownOutput),
+    Influx(InfluxOutput),
+    Prometheus(PrometheusOutput),
+}
+
+/// Writes the results to the configured output, keeping only the metrics matching `--metrics`.
+pub struct Displayer {
+    output: Output,
+    /// Terminal output also showing the results written to a file, with `--also-terminal`
+    terminal: Option<TerminalOutput>,
 }
 
 impl TryFrom<&ProfileConfig> for Displayer {
     type Error = anyhow::Error;
 
     fn try_from(config: &ProfileConfig) -> Result<Self, Self::Error> {
-        Displayer::new(&config.output_format, config.jouleit_file.as_ref())
+        Ok(Displayer::new(
+            &config.output_format,
+            config.jouleit_file.as_ref(),
+            config.file_mode,
+            config.append,
+        )?
+        .with_also_terminal(config.also_terminal)
+        .with_metric_formats(&config.metric_formats)
+        .with_energy_unit(config.energy_unit, config.precision)
+        .with_csv_delimiter(config.csv_delimiter.unwrap_or(DEFAULT_DELIMITER))
+        .with_canonical_json(config.canonical_json)
+        .with_json_group_by_socket(config.json_group_by_socket)
+        .with_fields(&config.fields)
+        .with_influx_measurement(&config.influx_measurement))
     }
 }
 
@@ -34,21 +98,296 @@ impl TryFrom<&ListSensorsConfig> for Displayer {
     type Error = anyhow::Error;
 
     fn try_from(config: &ListSensorsConfig) -> Result<Self, Self::Error> {
-        Displayer::new(&config.output_format, None)
+        Displayer::new(&config.output_format, None, config.file_mode, false)
     }
 }
 
 impl Displayer {
-    pub fn new(output_format: &OutputFormat, jouleit_file: Option<&String>) -> Result<Self> {
-        Ok(match output_format {
-            OutputFormat::Terminal => Self::Terminal(TerminalOutput),
-            OutputFormat::Json => Self::Json(JsonOutput::new(jouleit_file.cloned())?),
-            OutputFormat::Csv => Self::Csv(CsvOutput::new(jouleit_file.cloned())?),
+    /// Create the output of the format, `append` adding the CSV and JSON results to the end
+    /// of an existing file.
+    pub fn new(
+        output_format: &OutputFormat,
+        jouleit_file: Option<&String>,
+        file_mode: FileMode,
+        append: bool,
+    ) -> Result<Self> {
+        let file = jouleit_file.cloned();
+        if append && !matches!(output_format, OutputFormat::Json | OutputFormat::Csv) {
+            warn!("--append only applies to the CSV and JSON outputs, the file is overwritten");
+        }
+        let output = match output_format {
+            OutputFormat::Terminal => Output::Terminal(TerminalOutput::default()),
+            OutputFormat::Json if append => Output::Json(JsonOutput::appending(file, file_mode)?),
+            OutputFormat::Json => Output::Json(JsonOutput::new(file, file_mode)?),
+            OutputFormat::Csv if append => Output::Csv(CsvOutput::appending(file, file_mode)?),
+            OutputFormat::Csv => Output::Csv(CsvOutput::new(file, file_mode)?),
+            OutputFormat::None => Output::Null(NullOutput),
+            OutputFormat::Parquet => Output::Parquet(ParquetOutput::new(file, file_mode)?),
+            #[cfg(feature = "html")]
+            OutputFormat::Html => Output::Html(html::HtmlOutput::new(file, file_mode)?),
+            OutputFormat::Msgpack => Output::Msgpack(MsgpackOutput::new(file, file_mode)?),
+            OutputFormat::Markdown => Output::Markdown(MarkdownOutput::new(file, file_mode)?),
+            OutputFormat::Influx => Output::Influx(InfluxOutput::new(file, file_mode)?),
+            OutputFormat::Prometheus => Output::Prometheus(PrometheusOutput::new(file, file_mode)?),
+        };
+        Ok(Self {
+            output,
+            terminal: None,
         })
     }
+
+    /// Also display the results in the terminal when they are written to a file.
+    pub fn with_also_terminal(mut self, enabled: bool) -> Self {
+        if enabled && !matches!(self.output, Output::Terminal(_)) {
+            self.terminal = Some(TerminalOutput::default());
+        }
+        self
+    }
+
+    /// Display the metrics with the given unit and precision, only the terminal output is
+    /// formatted, the other outputs keep the raw values.
+    pub fn with_metric_formats(mut self, formats: &[MetricFormat]) -> Self {
+        if let Output::Terminal(terminal) = &mut self.output {
+            terminal.set_metric_formats(formats);
+        }
+        if let Some(terminal) = &mut self.terminal {
+            terminal.set_metric_formats(formats);
+        }
+        self
+    }
+
+    /// Write the energy metrics of the terminal, JSON and CSV outputs in this unit, with the
+    /// given precision.
+    pub fn with_energy_unit(mut self, unit: Option<EnergyUnit>, precision: usize) -> Self {
+        let Some(unit) = unit else {
+            return self;
+        };
+        match &mut self.output {
+            Output::Terminal(terminal) => terminal.set_energy_unit(unit),
+            Output::Json(json) => json.set_energy_unit(unit, precision),
+            Output::Csv(csv) => csv.set_energy_unit(unit),
+            Output::Null(_) => {}
+            _ => warn!("--unit does not apply to this output, the energy is written in µJ"),
+        }
+        if let Some(terminal) = &mut self.terminal {
+            terminal.set_energy_unit(unit);
+        }
+        self
+    }
+
+    /// Write the JSON output in its canonical form, without the volatile fields.
+    pub fn with_canonical_json(mut self, canonical: bool) -> Self {
+        if let Output::Json(json) = &mut self.output {
+            json.set_canonical(canonical);
+        }
+        self
+    }
+
+    /// Nest the metrics of the JSON output under their socket.
+    pub fn with_json_group_by_socket(mut self, group_by_socket: bool) -> Self {
+        if let Output::Json(json) = &mut self.output {
+            json.set_group_by_socket(group_by_socket);
+        }
+        self
+    }
+
+    /// Only write these non-metric fields in the CSV and JSON outputs, see [`OUTPUT_FIELDS`].
+    pub fn with_fields(mut self, fields: &[String]) -> Self {
+        match &mut self.output {
+            Output::Json(json) => json.set_fields(FieldProjection::new(fields)),
+            Output::Csv(csv) => csv.set_fields(FieldProjection::new(fields)),
+            _ => {}
+        }
+        self
+    }
+
+    /// Name the measurement of the InfluxDB lines.
+    pub fn with_influx_measurement(mut self, measurement: &str) -> Self {
+        if let Output::Influx(influx) = &mut self.output {
+            influx.set_measurement(measurement);
+        }
+        self
+    }
+
+    /// Separate the fields of the CSV output with this character.
+    pub fn with_csv_delimiter(mut self, delimiter: char) -> Self {
+        if let Output::Csv(csv) = &mut self.output {
+            csv.set_delimiter(delimiter);
+        }
+        self
+    }
+}
+
+impl OutputFormatTrait for Displayer {
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        let result = match &config.metrics {
+            Some(glob) => {
+                let mut result = result.clone();
+                result.metrics = filter_metrics(&result.metrics, glob);
+                warn_if_empty(glob, std::slice::from_ref(&result.metrics));
+                Cow::Owned(result)
+            }
+            None => Cow::Borrowed(result),
+        };
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal.simple_single(config, &result)?;
+        }
+        self.output.simple_single(config, &result)
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        let results = match &config.metrics {
+            Some(glob) => {
+                let mut results = results.to_vec();
+                for result in &mut results {
+                    result.metrics = filter_metrics(&result.metrics, glob);
+                }
+                let metrics: Vec<_> = results
+                    .iter()
+                    .map(|result| result.metrics.clone())
+                    .collect();
+                warn_if_empty(glob, &metrics);
+                Cow::Owned(results)
+            }
+            None => Cow::Borrowed(results),
+        };
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal.simple_iterations(config, &results)?;
+        }
+        self.output.simple_iterations(config, &results)
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        let result = match &config.metrics {
+            Some(glob) => Cow::Owned(filter_phases(result, glob)),
+            None => Cow::Borrowed(result),
+        };
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal.phases_single(config, &result)?;
+        }
+        self.output.phases_single(config, &result)
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        let results = match &config.metrics {
+            Some(glob) => Cow::Owned(
+                results
+                    .iter()
+                    .map(|result| filter_phases(result, glob))
+                    .collect::<Vec<_>>(),
+            ),
+            None => Cow::Borrowed(results),
+        };
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal.phases_iterations(config, &results)?;
+        }
+        self.output.phases_iterations(config, &results)
+    }
+
+    fn calibration(&mut self, config: &ProfileConfig, results: &[CalibrationResult]) -> Result<()> {
+        let results = match &config.metrics {
+            Some(glob) => {
+                let mut results = results.to_vec();
+                for result in &mut results {
+                    result.metrics = filter_metrics(&result.metrics, glob);
+                }
+                let metrics: Vec<_> = results
+                    .iter()
+                    .map(|result| result.metrics.clone())
+                    .collect();
+                warn_if_empty(glob, &metrics);
+                Cow::Owned(results)
+            }
+            None => Cow::Borrowed(results),
+        };
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal.calibration(config, &results)?;
+        }
+        self.output.calibration(config, &results)
+    }
+
+    fn comparison(
+        &mut self,
+        config: &ProfileConfig,
+        comparison: &[MetricComparison],
+    ) -> Result<()> {
+        let comparison = match &config.metrics {
+            Some(glob) => {
+                let comparison: Vec<_> = comparison
+                    .iter()
+                    .filter(|metric| glob_matches(glob, &metric.name))
+                    .cloned()
+                    .collect();
+                if comparison.is_empty() {
+                    warn!("No metric matches '{}', the output has no metrics", glob);
+                }
+                Cow::Owned(comparison)
+            }
+            None => Cow::Borrowed(comparison),
+        };
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal.comparison(config, &comparison)?;
+        }
+        self.output.comparison(config, &comparison)
+    }
+
+    fn list_sensors(&mut self, config: &ListSensorsConfig, sensors: &[Sensor]) -> Result<()> {
+        self.output.list_sensors(config, sensors)
+    }
+
+    fn results_path(&self) -> Option<&str> {
+        self.output.results_path()
+    }
 }
 
-#[enum_dispatch(Displayer)]
+/// Keep the metrics whose name matches the glob.
+fn filter_metrics(metrics: &[Metric], glob: &str) -> Vec<Metric> {
+    metrics
+        .iter()
+        .filter(|metric| glob_matches(glob, &metric.name))
+        .cloned()
+        .collect()
+}
+
+fn filter_phases(result: &PhaseMeasurementResult, glob: &str) -> PhaseMeasurementResult {
+    let mut result = result.clone();
+    for phase in &mut result.phases {
+        phase.metrics = filter_metrics(&phase.metrics, glob);
+    }
+    let metrics: Vec<_> = result
+        .phases
+        .iter()
+        .map(|phase| phase.metrics.clone())
+        .collect();
+    warn_if_empty(glob, &metrics);
+    result
+}
+
+fn warn_if_empty(glob: &str, metrics: &[Vec<Metric>]) {
+    if metrics.iter().all(Vec::is_empty) {
+        warn!("No metric matches '{}', the output has no metrics", glob);
+    }
+}
+
+#[enum_dispatch(Output)]
 pub trait OutputFormatTrait {
     fn simple_single(&mut self, _config: &ProfileConfig, _result: &MeasurementResult)
     -> Result<()>;
@@ -80,10 +419,33 @@ pub trait OutputFormatTrait {
         anyhow::bail!("Phases iterations not implemented for this format");
     }
 
+    fn calibration(
+        &mut self,
+        _config: &ProfileConfig,
+        _results: &[CalibrationResult],
+    ) -> Result<()> {
+        error!("Calibration not implemented for this format");
+        anyhow::bail!("Calibration not implemented for this format");
+    }
+
+    fn comparison(
+        &mut self,
+        _config: &ProfileConfig,
+        _comparison: &[MetricComparison],
+    ) -> Result<()> {
+        error!("Comparison not implemented for this format");
+        anyhow::bail!("Comparison not implemented for this format");
+    }
+
     fn list_sensors(&mut self, _config: &ListSensorsConfig, _sensors: &[Sensor]) -> Result<()> {
         error!("List sensors not implemented for this format");
         anyhow::bail!("List sensors not implemented for this format");
     }
+
+    /// Path of the file the results are written to, `None` for the outputs without a file.
+    fn results_path(&self) -> Option<&str> {
+        None
+    }
 }
 
 fn default_iterations_filename(ext: &str) -> String {
@@ -93,3 +455,296 @@ fn default_iterations_filename(ext: &str) -> String {
         .as_secs();
     format!("data{}.{}", ts, ext)
 }
+
+/// Marker printed before the confirmation of a written output file.
+pub const SUCCESS_MARKER: char = '\u{2714}';
+
+/// Message confirming that an output file was written, its marker colored.
+fn written_message(format: &str, filename: &str) -> String {
+    format!(
+        "{} {} written to: {}",
+        paint(SUCCESS_STYLE, &SUCCESS_MARKER.to_string()),
+        format,
+        filename
+    )
+}
+
+/// Output file selecting the standard output, with `--jouleit-file -`.
+pub const STDOUT_PATH: &str = "-";
+
+/// Non-metric CSV columns and JSON keys that `--fields` selects, the metrics and the nested
+/// results (iterations, phases, summaries) are always written.
+pub const OUTPUT_FIELDS: [&str; 30] = [
+    "command",
+    "mode",
+    "metadata",
+    "system",
+    "iteration",
+    "phase_name",
+    "start_token",
+    "end_token",
+    "start_line",
+    "end_line",
+    "start_us",
+    "end_us",
+    "duration_ms",
+    "exit_code",
+    "attempts",
+    "repetitions",
+    "measure_count",
+    "measure_delta",
+    "read_spread_us",
+    "power_w",
+    "polling",
+    "power_range",
+    "power_states",
+    "raw",
+    "counter_wraps",
+    "overflow_detected",
+    "failed_sources",
+    "token_pattern",
+    "polling_rate_s",
+    "energy_deviation_pct",
+];
+
+/// Non-metric fields written by the CSV and JSON outputs, all of them by default.
+#[derive(Debug, Clone, Default)]
+pub struct FieldProjection {
+    fields: Option<HashSet<String>>,
+}
+
+impl FieldProjection {
+    /// Keep only these fields, all of them when empty.
+    pub fn new(fields: &[String]) -> Self {
+        Self {
+            fields: (!fields.is_empty()).then(|| fields.iter().cloned().collect()),
+        }
+    }
+
+    /// Whether only some of the fields are written.
+    pub fn is_restricted(&self) -> bool {
+        self.fields.is_some()
+    }
+
+    /// Whether the column or key is written, the ones outside [`OUTPUT_FIELDS`] always are.
+    pub fn keeps(&self, name: &str) -> bool {
+        match &self.fields {
+            Some(fields) => !OUTPUT_FIELDS.contains(&name) || fields.contains(name),
+            None => true,
+        }
+    }
+}
+
+/// Unit of the measured energy metrics, converted with `--unit`.
+pub const ENERGY_UNIT: &str = "µJ";
+
+/// Destination of the JSON and CSV outputs, a file or the standard output.
+pub enum OutputSink {
+    Stdout(Stdout),
+    File(File),
+}
+
+impl OutputSink {
+    /// Create the output file, a timestamped one if not given, or select the standard output
+    /// for `-`. Returns the sink with the name reported once written.
+    pub fn open(
+        output_file: Option<String>,
+        format: &str,
+        extension: &str,
+        file_mode: FileMode,
+    ) -> Result<(Self, String)> {
+        Self::open_with(output_file, format, extension, file_mode, false)
+    }
+
+    /// Open the output file as with [`OutputSink::open`], the results being written after
+    /// the ones of the previous runs, with `--append`.
+    pub fn open_append(
+        output_file: Option<String>,
+        format: &str,
+        extension: &str,
+        file_mode: FileMode,
+    ) -> Result<(Self, String)> {
+        Self::open_with(output_file, format, extension, file_mode, true)
+    }
+
+    /// Whether the file already had results when it was opened, never for the standard output.
+    pub fn has_content(&self) -> Result<bool> {
+        match self {
+            OutputSink::Stdout(_) => Ok(false),
+            OutputSink::File(file) => Ok(file.metadata()?.len() > 0),
+        }
+    }
+
+    fn open_with(
+        output_file: Option<String>,
+        format: &str,
+        extension: &str,
+        file_mode: FileMode,
+        append: bool,
+    ) -> Result<(Self, String)> {
+        if output_file.as_deref() == Some(STDOUT_PATH) {
+            info!("Writing the {} output to stdout", format);
+            return Ok((Self::Stdout(std::io::stdout()), "stdout".to_string()));
+        }
+
+        let filename = output_file.unwrap_or(default_iterations_filename(extension));
+        let absolute_path = get_absolute_path(&filename)?;
+        info!("Creating {} output file: {}", format, absolute_path);
+
+        let file = if append {
+            append_file_with_user_permissions(&absolute_path, file_mode)?
+        } else {
+            create_file_with_user_permissions(&absolute_path, file_mode)?
+        };
+        Ok((Self::File(file), absolute_path))
+    }
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputSink::Stdout(stdout) => stdout.write(buf),
+            OutputSink::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputSink::Stdout(stdout) => stdout.flush(),
+            OutputSink::File(file) => file.flush(),
+        }
+    }
+}
+
+/// Report that an output file was written, shared by the file outputs.
+///
+/// The message goes to stderr so that stdout only holds the command output, or the results
+/// written to stdout.
+pub fn report_written(format: &str, filename: &str) {
+    anstream::eprintln!("{}", written_message(format, filename));
+    info!("{} output saved to: {}", format, filename);
+}
+
+/// Format a float value with the given number of decimal places.
+pub fn format_float(value: f64, precision: usize) -> String {
+    format!("{:.*}", precision, value)
+}
+
+/// Round a float value to the given number of decimal places.
+pub fn round_float(value: f64, precision: usize) -> f64 {
+    let factor = 10f64.powi(precision as i32);
+    (value * factor).round() / factor
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        cli::Cli,
+        config::{Command, Config},
+    };
+
+    fn decimals(formatted: &str) -> usize {
+        formatted.split('.').nth(1).map(str::len).unwrap_or(0)
+    }
+
+    #[test]
+    fn success_marker_is_a_single_checkmark() {
+        let message = written_message("CSV", "out.csv");
+        let message = anstream::adapter::strip_str(&message).to_string();
+        assert_eq!(SUCCESS_MARKER.len_utf8(), 3);
+        assert!(message.starts_with("\u{2714} CSV written to:"));
+        assert!(!message.contains('\u{00e2}'));
+    }
+
+    fn metric(name: &str) -> Metric {
+        Metric {
+            name: name.to_string(),
+            value: 1,
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        }
+    }
+
+    #[test]
+    fn metrics_glob_keeps_package_metrics_only() {
+        let metrics = vec![
+            metric("PACKAGE-0_0"),
+            metric("CORE_0"),
+            metric("DRAM_0"),
+            metric("PACKAGE-1_1"),
+            metric("DRAM_1"),
+        ];
+
+        let names: Vec<_> = filter_metrics(&metrics, "PACKAGE*")
+            .into_iter()
+            .map(|metric| metric.name)
+            .collect();
+        assert_eq!(names, ["PACKAGE-0_0", "PACKAGE-1_1"]);
+
+        assert!(filter_metrics(&metrics, "GPU*").is_empty());
+    }
+
+    #[test]
+    fn format_float_uses_precision_decimals() {
+        assert_eq!(decimals(&format_float(1.234567, 3)), 3);
+        assert_eq!(decimals(&format_float(1.0, 5)), 5);
+        assert_eq!(format_float(2.5, 0), "2");
+    }
+
+    #[test]
+    fn round_float_rounds_to_precision() {
+        assert_eq!(round_float(1.23456, 2), 1.23);
+        assert_eq!(round_float(1.23556, 3), 1.236);
+    }
+
+    #[test]
+    fn also_terminal_writes_the_file_and_displays_the_results() {
+        let dir = tempdir().unwrap();
+        let jouleit_file = dir.path().join("results.json");
+
+        let cli = Cli::try_parse_from([
+            "joule-profiler",
+            "simple",
+            "--json",
+            "--also-terminal",
+            "--jouleit-file",
+            jouleit_file.to_str().unwrap(),
+            "--",
+            "true",
+        ])
+        .unwrap();
+        let Command::Profile(config) = Config::try_from(cli).unwrap().mode else {
+            panic!("Expected a profiling configuration");
+        };
+
+        let mut displayer = Displayer::try_from(config.as_ref()).unwrap();
+        assert!(matches!(displayer.output, Output::Json(_)));
+        assert!(displayer.terminal.is_some());
+
+        let result = MeasurementResult {
+            metrics: vec![Metric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 1_234,
+                unit: "µJ".to_string(),
+                source: "powercap".to_string(),
+            }],
+            duration_ms: 5,
+            measure_count: 2,
+            ..Default::default()
+        };
+        displayer.simple_single(&config, &result).unwrap();
+
+        let written = std::fs::read_to_string(&jouleit_file).unwrap();
+        assert!(written.contains("\"PACKAGE-0_0\""));
+
+        // Already displayed in the terminal, no second terminal output
+        let terminal = Displayer::new(&OutputFormat::Terminal, None, FileMode::default(), false)
+            .unwrap()
+            .with_also_terminal(true);
+        assert!(terminal.terminal.is_none());
+    }
+}
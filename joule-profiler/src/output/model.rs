@@ -0,0 +1,348 @@
+//! Documents written by the JSON and MessagePack outputs.
+//!
+//! Every mode serializes one of these reports, so that the keys shared by the modes keep
+//! the same name and type (e.g. the duration is always `duration_ms`). Their field order is
+//! not kept, the keys of the documents being sorted.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    measurement::{
+        CalibrationResult, EnergyTrend, IntervalEnergy, MeasurementResult, MetricComparison,
+        MetricSummary, PhaseMeasurementResult, PhaseResult, PhaseSummary, PowerStates, TokenCount,
+        power_by_metric,
+    },
+    output::round_float,
+    source::{FailedSource, Metric, PollingStats, PowerRange, RawReading},
+    util::sysinfo::{Metadata, SysInfo},
+};
+
+/// Mode of a document, its `mode` key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportMode {
+    Simple,
+    SimpleIterations,
+    Phases,
+    PhasesIterations,
+    Calibrate,
+    Compare,
+}
+
+/// Results of a simple mode run, alone or as an iteration.
+#[derive(Debug, Serialize)]
+pub struct SimpleRun<'a> {
+    pub metrics: &'a [Metric],
+    pub duration_ms: u128,
+    pub exit_code: i32,
+    pub attempts: u32,
+    pub repetitions: u32,
+    pub measure_count: u64,
+    pub measure_delta: u128,
+    pub read_spread_us: u128,
+    /// Average power in watts of each energy metric
+    pub power_w: BTreeMap<String, f64>,
+    pub polling: &'a BTreeMap<String, PollingStats>,
+    pub power_range: &'a BTreeMap<String, PowerRange>,
+    pub failed_sources: &'a [FailedSource],
+    /// Counter readings of `--raw-counters`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub raw: &'a BTreeMap<String, RawReading>,
+    /// Energy over the equal intervals of `--simple-samples`
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub intervals: &'a [IntervalEnergy],
+    pub counter_wraps: u64,
+    /// Whether an energy counter wrapped, to filter the runs whose energy was corrected
+    pub overflow_detected: bool,
+    /// Power levels of `--detect-turbo`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub power_states: &'a BTreeMap<String, PowerStates>,
+}
+
+impl<'a> SimpleRun<'a> {
+    pub fn new(result: &'a MeasurementResult, precision: usize) -> Self {
+        Self {
+            metrics: &result.metrics,
+            duration_ms: result.duration_ms,
+            exit_code: result.exit_code,
+            attempts: result.attempts,
+            repetitions: result.repetitions,
+            measure_count: result.measure_count,
+            measure_delta: result.measure_delta,
+            read_spread_us: result.read_spread_us,
+            power_w: power_by_metric_rounded(&result.metrics, result.duration_ms, precision),
+            polling: &result.polling,
+            power_range: &result.power_range,
+            failed_sources: &result.failed_sources,
+            raw: &result.raw,
+            intervals: &result.intervals,
+            counter_wraps: result.counter_wraps,
+            overflow_detected: result.counter_wraps > 0,
+            power_states: &result.power_states,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimpleReport<'a> {
+    pub command: String,
+    pub mode: ReportMode,
+    pub metadata: Metadata,
+    pub system: SysInfo,
+    #[serde(flatten)]
+    pub run: SimpleRun<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimpleIteration<'a> {
+    /// Index of the iteration, from 1
+    pub iteration: usize,
+    #[serde(flatten)]
+    pub run: SimpleRun<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimpleIterationsReport<'a> {
+    pub command: String,
+    pub mode: ReportMode,
+    pub metadata: Metadata,
+    pub system: SysInfo,
+    pub iterations: Vec<SimpleIteration<'a>>,
+    pub summary: Vec<MetricSummary>,
+    /// Only written with `--trend`, `null` when there are too few iterations for a trend
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trend: Option<Option<EnergyTrend>>,
+}
+
+/// A phase with its average power and its share of the energy of the run.
+#[derive(Debug, Serialize)]
+pub struct PhaseReport<'a> {
+    #[serde(flatten)]
+    pub phase: &'a PhaseResult,
+    pub power_w: BTreeMap<String, f64>,
+    /// Share of the phase in the energy of each metric, in percent
+    pub percent: BTreeMap<String, f64>,
+    /// How the phase boundaries were found, with `--describe-phases`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derivation: Option<String>,
+    /// Whether an energy counter wrapped during the phase
+    pub overflow_detected: bool,
+}
+
+/// Results of a phases mode run, alone or as an iteration.
+#[derive(Debug, Serialize)]
+pub struct PhasesRun<'a> {
+    pub exit_code: i32,
+    pub duration_ms: u128,
+    pub phases: Vec<PhaseReport<'a>>,
+    pub failed_sources: &'a [FailedSource],
+    pub polling: &'a BTreeMap<String, PollingStats>,
+}
+
+impl<'a> PhasesRun<'a> {
+    pub fn new(result: &'a PhaseMeasurementResult, describe: bool, precision: usize) -> Self {
+        let phases = result
+            .phases
+            .iter()
+            .zip(result.energy_percents())
+            .map(|(phase, percents)| PhaseReport {
+                phase,
+                power_w: power_by_metric_rounded(&phase.metrics, phase.duration_ms, precision),
+                percent: percents
+                    .into_iter()
+                    .map(|(name, percent)| (name, round_float(percent, precision)))
+                    .collect(),
+                derivation: describe.then(|| phase.derivation()),
+                overflow_detected: phase.counter_wraps > 0,
+            })
+            .collect();
+
+        Self {
+            exit_code: result.exit_code,
+            duration_ms: result.duration_ms,
+            phases,
+            failed_sources: &result.failed_sources,
+            polling: &result.polling,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhasesReport<'a> {
+    pub command: String,
+    pub mode: ReportMode,
+    pub metadata: Metadata,
+    pub system: SysInfo,
+    pub token_pattern: &'a str,
+    #[serde(flatten)]
+    pub run: PhasesRun<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhasesIteration<'a> {
+    /// Index of the iteration, from 1
+    pub iteration: usize,
+    #[serde(flatten)]
+    pub run: PhasesRun<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhasesIterationsReport<'a> {
+    pub command: String,
+    pub mode: ReportMode,
+    pub metadata: Metadata,
+    pub system: SysInfo,
+    pub token_pattern: &'a str,
+    pub iterations: Vec<PhasesIteration<'a>>,
+    pub token_counts: Vec<TokenCount>,
+    pub summary: Vec<PhaseSummary>,
+}
+
+/// Measurements of one polling rate of the calibration.
+#[derive(Debug, Serialize)]
+pub struct CalibrationRate<'a> {
+    pub polling_rate_s: Option<f64>,
+    pub metrics: &'a [Metric],
+    pub duration_ms: u128,
+    pub measure_count: u64,
+    pub measure_delta: u128,
+    /// Deviation of the energy from the first rate, in percent
+    pub energy_deviation_pct: f64,
+}
+
+impl<'a> CalibrationRate<'a> {
+    pub fn new(
+        result: &'a CalibrationResult,
+        baseline: &CalibrationResult,
+        precision: usize,
+    ) -> Self {
+        Self {
+            polling_rate_s: result.polling_rate_s,
+            metrics: &result.metrics,
+            duration_ms: result.duration_ms,
+            measure_count: result.measure_count,
+            measure_delta: result.measure_delta,
+            energy_deviation_pct: round_float(result.energy_deviation_pct(baseline), precision),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalibrationReport<'a> {
+    pub command: String,
+    pub mode: ReportMode,
+    pub metadata: Metadata,
+    pub rates: Vec<CalibrationRate<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComparisonReport<'a> {
+    pub mode: ReportMode,
+    pub metadata: Metadata,
+    pub system: SysInfo,
+    pub baseline: &'a str,
+    pub candidate: &'a str,
+    pub iterations: usize,
+    pub metrics: &'a [MetricComparison],
+}
+
+/// Average power in watts of each energy metric, rounded to the output precision.
+fn power_by_metric_rounded(
+    metrics: &[Metric],
+    duration_ms: u128,
+    precision: usize,
+) -> BTreeMap<String, f64> {
+    power_by_metric(metrics, duration_ms)
+        .into_iter()
+        .map(|(name, power)| (name, round_float(power, precision)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::test_util::metric;
+
+    fn keys(value: &serde_json::Value) -> BTreeSet<&str> {
+        value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn iterations_of_both_modes_have_their_duration_in_ms() {
+        let simple = MeasurementResult {
+            duration_ms: 10,
+            measure_count: 2,
+            metrics: vec![metric("PACKAGE-0_0", 1_000)],
+            ..Default::default()
+        };
+        let phases = PhaseMeasurementResult {
+            phases: vec![PhaseResult {
+                name: "START -> END".to_string(),
+                metrics: vec![metric("PACKAGE-0_0", 1_000)],
+                duration_ms: 10,
+                counter_wraps: 2,
+                ..Default::default()
+            }],
+            duration_ms: 10,
+            ..Default::default()
+        };
+
+        let simple = serde_json::to_value(SimpleIteration {
+            iteration: 1,
+            run: SimpleRun::new(&simple, 2),
+        })
+        .unwrap();
+        let phases = serde_json::to_value(PhasesIteration {
+            iteration: 1,
+            run: PhasesRun::new(&phases, false, 2),
+        })
+        .unwrap();
+
+        assert_eq!(phases["duration_ms"], 10);
+        assert!(phases.get("duration").is_none());
+        // Unknown timestamps are left out
+        assert!(phases["phases"][0].get("start_us").is_none());
+        let shared: BTreeSet<&str> = keys(&simple)
+            .intersection(&keys(&phases))
+            .copied()
+            .collect();
+        assert_eq!(
+            shared,
+            BTreeSet::from([
+                "duration_ms",
+                "exit_code",
+                "failed_sources",
+                "iteration",
+                "polling"
+            ])
+        );
+        assert_eq!(simple["power_w"]["PACKAGE-0_0"], 0.1);
+        assert_eq!(phases["phases"][0]["power_w"]["PACKAGE-0_0"], 0.1);
+        assert_eq!(phases["phases"][0]["percent"]["PACKAGE-0_0"], 100.0);
+        assert!(phases["phases"][0].get("derivation").is_none());
+        assert_eq!(simple["overflow_detected"], false);
+        assert_eq!(phases["phases"][0]["counter_wraps"], 2);
+        assert_eq!(phases["phases"][0]["overflow_detected"], true);
+    }
+
+    #[test]
+    fn modes_are_kebab_case() {
+        assert_eq!(
+            serde_json::to_value(ReportMode::PhasesIterations).unwrap(),
+            "phases-iterations"
+        );
+        assert_eq!(
+            serde_json::to_value(ReportMode::Calibrate).unwrap(),
+            "calibrate"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+
+use crate::{
+    config::{ListSensorsConfig, ProfileConfig},
+    measurement::{CalibrationResult, MeasurementResult, PhaseMeasurementResult},
+    output::{
+        OutputFormatTrait,
+        json::{Encoding, JsonOutput},
+    },
+    source::Sensor,
+    util::file::FileMode,
+};
+
+/// MessagePack output writer to file, the same documents as the JSON output in a compact
+/// binary encoding.
+pub struct MsgpackOutput {
+    json: JsonOutput,
+}
+
+impl OutputFormatTrait for MsgpackOutput {
+    fn results_path(&self) -> Option<&str> {
+        self.json.results_path()
+    }
+
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        self.json.simple_single(config, result)
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        self.json.simple_iterations(config, results)
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        self.json.phases_single(config, result)
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        self.json.phases_iterations(config, results)
+    }
+
+    fn calibration(&mut self, config: &ProfileConfig, results: &[CalibrationResult]) -> Result<()> {
+        self.json.calibration(config, results)
+    }
+
+    fn list_sensors(&mut self, config: &ListSensorsConfig, sensors: &[Sensor]) -> Result<()> {
+        self.json.list_sensors(config, sensors)
+    }
+}
+
+impl MsgpackOutput {
+    /// Creates a MessagePack output writer to a file.
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        Ok(Self {
+            json: JsonOutput::with_encoding(output_file, Encoding::Msgpack, file_mode)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::source::Metric;
+
+    #[test]
+    fn measurement_result_round_trips_through_msgpack() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("result.msgpack");
+        let config = ProfileConfig {
+            cmd: vec!["./bench".to_string()],
+            ..Default::default()
+        };
+        let result = MeasurementResult {
+            metrics: vec![Metric {
+                name: "PACKAGE-0_0".to_string(),
+                value: 123_456_789,
+                unit: "µJ".to_string(),
+                source: "powercap".to_string(),
+            }],
+            duration_ms: 1_500,
+            exit_code: 2,
+            measure_count: 30,
+            measure_delta: 50_000,
+            read_spread_us: 12,
+            ..Default::default()
+        };
+
+        let mut output = MsgpackOutput::new(
+            Some(path.to_string_lossy().to_string()),
+            FileMode::default(),
+        )
+        .unwrap();
+        output.simple_single(&config, &result).unwrap();
+
+        let decoded: MeasurementResult = rmp_serde::from_slice(&read(&path).unwrap()).unwrap();
+        assert_eq!(decoded.metrics.len(), 1);
+        assert_eq!(decoded.metrics[0].name, "PACKAGE-0_0");
+        assert_eq!(decoded.metrics[0].value, 123_456_789);
+        assert_eq!(decoded.metrics[0].unit, "µJ");
+        assert_eq!(decoded.duration_ms, 1_500);
+        assert_eq!(decoded.exit_code, 2);
+        assert_eq!(decoded.measure_count, 30);
+        assert_eq!(decoded.measure_delta, 50_000);
+        assert_eq!(decoded.read_spread_us, 12);
+    }
+}
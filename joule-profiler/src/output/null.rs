@@ -0,0 +1,114 @@
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    config::{ListSensorsConfig, ProfileConfig},
+    measurement::{CalibrationResult, MeasurementResult, PhaseMeasurementResult},
+    output::OutputFormatTrait,
+    source::Sensor,
+};
+
+/// Output discarding all the results, neither printing nor writing files.
+#[derive(Debug, Clone, Default)]
+pub struct NullOutput;
+
+impl OutputFormatTrait for NullOutput {
+    fn simple_single(
+        &mut self,
+        _config: &ProfileConfig,
+        _result: &MeasurementResult,
+    ) -> Result<()> {
+        debug!("Discarding simple measurement");
+        Ok(())
+    }
+
+    fn simple_iterations(
+        &mut self,
+        _config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        debug!("Discarding {} simple iterations", results.len());
+        Ok(())
+    }
+
+    fn phases_single(
+        &mut self,
+        _config: &ProfileConfig,
+        _result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        debug!("Discarding phases measurement");
+        Ok(())
+    }
+
+    fn phases_iterations(
+        &mut self,
+        _config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        debug!("Discarding {} phase iterations", results.len());
+        Ok(())
+    }
+
+    fn calibration(
+        &mut self,
+        _config: &ProfileConfig,
+        results: &[CalibrationResult],
+    ) -> Result<()> {
+        debug!("Discarding {} calibration rates", results.len());
+        Ok(())
+    }
+
+    fn list_sensors(&mut self, _config: &ListSensorsConfig, sensors: &[Sensor]) -> Result<()> {
+        debug!("Discarding {} sensors", sensors.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::Cli,
+        config::{Command, Config, OutputFormat},
+        output::{Displayer, Output},
+    };
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    #[test]
+    fn format_none_discards_results_without_files() {
+        let dir = tempdir().unwrap();
+        let jouleit_file = dir.path().join("results.json");
+
+        let cli = Cli::try_parse_from([
+            "joule-profiler",
+            "simple",
+            "--format",
+            "none",
+            "--jouleit-file",
+            jouleit_file.to_str().unwrap(),
+            "--",
+            "true",
+        ])
+        .unwrap();
+        let Command::Profile(config) = Config::try_from(cli).unwrap().mode else {
+            panic!("Expected a profiling configuration");
+        };
+        assert_eq!(config.output_format, OutputFormat::None);
+
+        let mut displayer = Displayer::try_from(config.as_ref()).unwrap();
+        assert!(matches!(displayer.output, Output::Null(_)));
+
+        let result = MeasurementResult {
+            metrics: Vec::new(),
+            ..Default::default()
+        };
+        displayer.simple_single(&config, &result).unwrap();
+        displayer
+            .simple_iterations(&config, &[result.clone(), result])
+            .unwrap();
+
+        assert!(!jouleit_file.exists());
+        assert_eq!(dir.path().read_dir().unwrap().count(), 0);
+    }
+}
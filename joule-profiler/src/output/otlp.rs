@@ -0,0 +1,271 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::{
+    config::ProfileConfig,
+    measurement::{MeasurementResult, PhaseMeasurementResult},
+    source::Metric,
+};
+
+/// Prefix of the exported gauges names.
+const GAUGE_PREFIX: &str = "joule_profiler.energy";
+
+/// One gauge value to export, with its attributes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaugePoint {
+    pub name: String,
+    pub value: u64,
+    pub unit: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+/// Destination of the exported gauges.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+pub trait GaugeExporter {
+    fn export(&mut self, points: &[GaugePoint]) -> Result<()>;
+}
+
+/// Export the gauges, an unreachable collector only produces a warning.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+pub fn export_points(exporter: &mut impl GaugeExporter, points: &[GaugePoint]) {
+    match exporter.export(points) {
+        Ok(()) => info!("Exported {} gauges to the OTLP collector", points.len()),
+        Err(e) => warn!("Failed to export metrics to the OTLP collector: {:#}", e),
+    }
+}
+
+/// Build one gauge per domain for each simple mode iteration.
+pub fn simple_points(config: &ProfileConfig, results: &[MeasurementResult]) -> Vec<GaugePoint> {
+    results
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, result)| {
+            let attributes = base_attributes(config, "simple", idx, results.len());
+            result
+                .metrics
+                .iter()
+                .map(move |metric| gauge_point(metric, attributes.clone()))
+        })
+        .collect()
+}
+
+/// Build one gauge per domain for each phase of each iteration.
+pub fn phases_points(
+    config: &ProfileConfig,
+    results: &[PhaseMeasurementResult],
+) -> Vec<GaugePoint> {
+    let mut points = Vec::new();
+
+    for (idx, result) in results.iter().enumerate() {
+        for phase in &result.phases {
+            let mut attributes = base_attributes(config, "phases", idx, results.len());
+            attributes.push(("phase".to_string(), phase.name.clone()));
+
+            points.extend(
+                phase
+                    .metrics
+                    .iter()
+                    .map(|metric| gauge_point(metric, attributes.clone())),
+            );
+        }
+    }
+
+    points
+}
+
+fn base_attributes(
+    config: &ProfileConfig,
+    mode: &str,
+    iteration: usize,
+    iterations: usize,
+) -> Vec<(String, String)> {
+    let mut attributes = vec![
+        ("command".to_string(), config.cmd.join(" ")),
+        ("mode".to_string(), mode.to_string()),
+    ];
+    if iterations > 1 {
+        attributes.push(("iteration".to_string(), (iteration + 1).to_string()));
+    }
+    attributes
+}
+
+fn gauge_point(metric: &Metric, mut attributes: Vec<(String, String)>) -> GaugePoint {
+    attributes.push(("source".to_string(), metric.source.clone()));
+    GaugePoint {
+        name: format!("{}.{}", GAUGE_PREFIX, metric.name.to_lowercase()),
+        value: metric.value,
+        unit: metric.unit.clone(),
+        attributes,
+    }
+}
+
+/// Export the gauges to the collector configured with `--otlp`, if any.
+pub async fn export_to_collector(config: &ProfileConfig, points: Vec<GaugePoint>) {
+    let Some(endpoint) = config.otlp_endpoint.clone() else {
+        return;
+    };
+
+    #[cfg(feature = "otlp")]
+    {
+        // The blocking HTTP client must not run on the async runtime threads
+        let handle = tokio::task::spawn_blocking(move || {
+            export_points(&mut OtlpExporter::new(endpoint), &points)
+        });
+        if let Err(e) = handle.await {
+            warn!("OTLP export task failed: {}", e);
+        }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = points;
+        warn!(
+            "Cannot export to {}: joule-profiler was built without the `otlp` feature",
+            endpoint
+        );
+    }
+}
+
+/// Exports the gauges to an OpenTelemetry collector using OTLP over HTTP.
+#[cfg(feature = "otlp")]
+pub struct OtlpExporter {
+    endpoint: String,
+}
+
+#[cfg(feature = "otlp")]
+impl OtlpExporter {
+    pub fn new(endpoint: String) -> Self {
+        Self { endpoint }
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl GaugeExporter for OtlpExporter {
+    fn export(&mut self, points: &[GaugePoint]) -> Result<()> {
+        use std::collections::HashMap;
+
+        use opentelemetry::{
+            KeyValue,
+            metrics::{Gauge, MeterProvider},
+        };
+        use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+        use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+
+        let exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(&self.endpoint)
+            .build()?;
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter).build())
+            .build();
+        let meter = provider.meter("joule-profiler");
+
+        let mut gauges: HashMap<&str, Gauge<u64>> = HashMap::new();
+        for point in points {
+            let gauge = gauges.entry(&point.name).or_insert_with(|| {
+                meter
+                    .u64_gauge(point.name.clone())
+                    .with_unit(point.unit.clone())
+                    .build()
+            });
+            let attributes: Vec<KeyValue> = point
+                .attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+                .collect();
+            gauge.record(point.value, &attributes);
+        }
+
+        // Shutting down flushes the recorded gauges to the collector
+        provider.shutdown()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::{PhaseResult, PhaseToken};
+    use crate::test_util::{config, metric};
+
+    #[derive(Default)]
+    struct RecordingExporter {
+        exported: Vec<GaugePoint>,
+        fail: bool,
+    }
+
+    impl GaugeExporter for RecordingExporter {
+        fn export(&mut self, points: &[GaugePoint]) -> Result<()> {
+            if self.fail {
+                anyhow::bail!("connection refused");
+            }
+            self.exported.extend_from_slice(points);
+            Ok(())
+        }
+    }
+
+    fn attribute<'a>(point: &'a GaugePoint, key: &str) -> Option<&'a str> {
+        point
+            .attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn simple_points_build_one_gauge_per_domain() {
+        let result = MeasurementResult {
+            metrics: vec![metric("PACKAGE-0_0", 1_000), metric("CORE_0", 400)],
+            duration_ms: 10,
+            measure_count: 2,
+            ..Default::default()
+        };
+
+        let mut exporter = RecordingExporter::default();
+        export_points(&mut exporter, &simple_points(&config(), &[result]));
+
+        let points = exporter.exported;
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].name, "joule_profiler.energy.package-0_0");
+        assert_eq!(points[0].value, 1_000);
+        assert_eq!(points[0].unit, "µJ");
+        assert_eq!(attribute(&points[0], "command"), Some("./bench --fast"));
+        assert_eq!(attribute(&points[0], "source"), Some("powercap"));
+        assert_eq!(attribute(&points[0], "iteration"), None);
+        assert_eq!(points[1].name, "joule_profiler.energy.core_0");
+    }
+
+    #[test]
+    fn phases_points_carry_phase_and_iteration() {
+        let phase = PhaseResult::new(
+            &PhaseToken::Start,
+            &PhaseToken::End,
+            None,
+            None,
+            vec![metric("PACKAGE-0_0", 10)],
+            1,
+        );
+        let result = |phase: &PhaseResult| PhaseMeasurementResult {
+            phases: vec![phase.clone()],
+            duration_ms: 1,
+            ..Default::default()
+        };
+
+        let points = phases_points(&config(), &[result(&phase), result(&phase)]);
+
+        assert_eq!(points.len(), 2);
+        assert_eq!(attribute(&points[1], "phase"), Some("START -> END"));
+        assert_eq!(attribute(&points[1], "iteration"), Some("2"));
+        assert_eq!(attribute(&points[1], "mode"), Some("phases"));
+    }
+
+    #[test]
+    fn export_failure_is_not_fatal() {
+        let mut exporter = RecordingExporter {
+            fail: true,
+            ..Default::default()
+        };
+        export_points(&mut exporter, &[]);
+        assert!(exporter.exported.is_empty());
+    }
+}
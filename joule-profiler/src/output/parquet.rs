@@ -0,0 +1,295 @@
+use anyhow::Result;
+use log::{debug, info};
+
+use crate::{
+    config::ProfileConfig,
+    measurement::{MeasurementResult, PhaseMeasurementResult},
+    output::{OutputFormatTrait, default_iterations_filename, report_written},
+    util::file::{FileMode, get_absolute_path},
+};
+
+/// One row of the Parquet file, a metric of a phase (or of the whole run) of an iteration.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+struct ParquetRow {
+    command: String,
+    iteration: u32,
+    phase_name: Option<String>,
+    metric_name: String,
+    value_uj: u64,
+    unit: String,
+    duration_ms: u64,
+    exit_code: i32,
+}
+
+/// Columnar output with one row per (iteration, phase, metric), requires the `parquet` feature.
+pub struct ParquetOutput {
+    filename: String,
+    #[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+    file_mode: FileMode,
+}
+
+impl OutputFormatTrait for ParquetOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        self.write(&simple_rows(config, std::slice::from_ref(result)))
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        info!("Formatting {} simple iterations for Parquet", results.len());
+        self.write(&simple_rows(config, results))
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        self.write(&phases_rows(config, std::slice::from_ref(result)))
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        info!("Formatting {} phase iterations for Parquet", results.len());
+        self.write(&phases_rows(config, results))
+    }
+}
+
+impl ParquetOutput {
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        if cfg!(not(feature = "parquet")) {
+            anyhow::bail!(
+                "Parquet output requires joule-profiler built with the `parquet` feature"
+            );
+        }
+
+        let filename = output_file.unwrap_or(default_iterations_filename("parquet"));
+        let absolute_path = get_absolute_path(&filename)?;
+        info!("Creating Parquet output file: {}", absolute_path);
+
+        Ok(Self {
+            filename: absolute_path,
+            file_mode,
+        })
+    }
+
+    fn write(&self, rows: &[ParquetRow]) -> Result<()> {
+        debug!("Writing {} Parquet rows", rows.len());
+
+        #[cfg(feature = "parquet")]
+        {
+            let file = crate::util::file::create_file_with_user_permissions(
+                &self.filename,
+                self.file_mode,
+            )?;
+            write_rows(file, rows)?;
+        }
+
+        report_written("Parquet", &self.filename);
+        Ok(())
+    }
+}
+
+fn simple_rows(config: &ProfileConfig, results: &[MeasurementResult]) -> Vec<ParquetRow> {
+    let command = config.cmd.join(" ");
+    let mut rows = Vec::new();
+
+    for (idx, result) in results.iter().enumerate() {
+        for metric in &result.metrics {
+            rows.push(ParquetRow {
+                command: command.clone(),
+                iteration: idx as u32 + 1,
+                phase_name: None,
+                metric_name: metric.name.clone(),
+                value_uj: metric.value,
+                unit: metric.unit.clone(),
+                duration_ms: result.duration_ms as u64,
+                exit_code: result.exit_code,
+            });
+        }
+    }
+
+    rows
+}
+
+fn phases_rows(config: &ProfileConfig, results: &[PhaseMeasurementResult]) -> Vec<ParquetRow> {
+    let command = config.cmd.join(" ");
+    let mut rows = Vec::new();
+
+    for (idx, result) in results.iter().enumerate() {
+        for phase in &result.phases {
+            for metric in &phase.metrics {
+                rows.push(ParquetRow {
+                    command: command.clone(),
+                    iteration: idx as u32 + 1,
+                    phase_name: Some(phase.name.clone()),
+                    metric_name: metric.name.clone(),
+                    value_uj: metric.value,
+                    unit: metric.unit.clone(),
+                    duration_ms: phase.duration_ms as u64,
+                    exit_code: result.exit_code,
+                });
+            }
+        }
+    }
+
+    rows
+}
+
+#[cfg(feature = "parquet")]
+fn schema() -> arrow_schema::Schema {
+    use arrow_schema::{DataType, Field, Schema};
+
+    Schema::new(vec![
+        Field::new("command", DataType::Utf8, false),
+        Field::new("iteration", DataType::UInt32, false),
+        Field::new("phase_name", DataType::Utf8, true),
+        Field::new("metric_name", DataType::Utf8, false),
+        Field::new("value_uj", DataType::UInt64, false),
+        Field::new("unit", DataType::Utf8, false),
+        Field::new("duration_ms", DataType::UInt64, false),
+        Field::new("exit_code", DataType::Int32, false),
+    ])
+}
+
+#[cfg(feature = "parquet")]
+fn write_rows(file: std::fs::File, rows: &[ParquetRow]) -> Result<()> {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Int32Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+    use parquet::arrow::ArrowWriter;
+
+    let strings = |f: fn(&ParquetRow) -> &str| -> ArrayRef {
+        Arc::new(rows.iter().map(|row| Some(f(row))).collect::<StringArray>())
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        strings(|row| &row.command),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.iteration)
+                .collect::<UInt32Array>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.phase_name.as_deref())
+                .collect::<StringArray>(),
+        ),
+        strings(|row| &row.metric_name),
+        Arc::new(rows.iter().map(|row| row.value_uj).collect::<UInt64Array>()),
+        strings(|row| &row.unit),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.duration_ms)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(rows.iter().map(|row| row.exit_code).collect::<Int32Array>()),
+    ];
+
+    let schema = Arc::new(schema());
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let mut writer = ArrowWriter::try_new(file, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        measurement::{PhaseResult, PhaseToken},
+        test_util::{config, metric},
+    };
+
+    fn phases_result() -> PhaseMeasurementResult {
+        let parse = PhaseToken::Token("__PARSE__".to_string());
+        PhaseMeasurementResult {
+            phases: vec![
+                PhaseResult::new(
+                    &PhaseToken::Start,
+                    &parse,
+                    None,
+                    Some(1),
+                    vec![metric("PACKAGE-0_0", 10), metric("DRAM_0", 2)],
+                    5,
+                ),
+                PhaseResult::new(
+                    &parse,
+                    &PhaseToken::End,
+                    Some(1),
+                    None,
+                    vec![metric("PACKAGE-0_0", 30), metric("DRAM_0", 4)],
+                    7,
+                ),
+            ],
+            duration_ms: 12,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn phases_rows_have_one_row_per_iteration_phase_and_metric() {
+        let rows = phases_rows(&config(), &[phases_result(), phases_result()]);
+
+        assert_eq!(rows.len(), 8);
+        assert_eq!(rows[0].phase_name.as_deref(), Some("START -> __PARSE__"));
+        assert_eq!(rows[0].duration_ms, 5);
+        assert_eq!(rows[7].iteration, 2);
+        assert_eq!(rows[7].metric_name, "DRAM_0");
+        assert_eq!(rows[7].value_uj, 4);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_file_is_read_back() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.parquet");
+
+        let mut output = ParquetOutput::new(
+            Some(path.to_string_lossy().to_string()),
+            FileMode::default(),
+        )
+        .unwrap();
+        output.phases_single(&config(), &phases_result()).unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 4);
+
+        let columns: Vec<&str> = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.name())
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                "command",
+                "iteration",
+                "phase_name",
+                "metric_name",
+                "value_uj",
+                "unit",
+                "duration_ms",
+                "exit_code"
+            ]
+        );
+    }
+}
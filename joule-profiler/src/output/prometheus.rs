@@ -0,0 +1,297 @@
+use std::io::Write;
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    config::ProfileConfig,
+    measurement::{MeasurementResult, PhaseMeasurementResult},
+    output::{ENERGY_UNIT, OutputFormatTrait, OutputSink, report_written},
+    source::Metric,
+    util::file::FileMode,
+};
+
+/// Prometheus text exposition format, for the node_exporter textfile collector which reads
+/// the `.prom` files of a directory.
+///
+/// Every value is a gauge labelled with the command, iteration and phase, the metrics in µJ
+/// going to `joule_profiler_energy_microjoules` and the other ones (ratios, scores...) to
+/// `joule_profiler_metric` with their unit as a label. The samples are not timestamped as
+/// the collector rejects them.
+pub struct PrometheusOutput {
+    writer: OutputSink,
+    filename: String,
+}
+
+/// A family of samples, written after its `# HELP` and `# TYPE` headers.
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    samples: Vec<String>,
+}
+
+impl Family {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            samples: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, labels: &[(&str, &str)], value: impl ToString) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+            .collect();
+        self.samples.push(format!(
+            "{}{{{}}} {}",
+            self.name,
+            labels.join(","),
+            value.to_string()
+        ));
+    }
+}
+
+/// Samples of every family, the families being written one after the other as the format
+/// requires the samples of a family to be grouped.
+struct Families {
+    energy: Family,
+    metric: Family,
+    duration: Family,
+    exit_code: Family,
+}
+
+impl Families {
+    fn new() -> Self {
+        Self {
+            energy: Family::new(
+                "joule_profiler_energy_microjoules",
+                "Energy consumed by the domain during the run, in microjoules",
+            ),
+            metric: Family::new(
+                "joule_profiler_metric",
+                "Value of the metrics not in microjoules, in their unit",
+            ),
+            duration: Family::new(
+                "joule_profiler_duration_milliseconds",
+                "Wall-clock duration of the run, in milliseconds",
+            ),
+            exit_code: Family::new(
+                "joule_profiler_exit_code",
+                "Exit code of the profiled command",
+            ),
+        }
+    }
+
+    /// Add the metrics and the duration of a run (an iteration or one of its phases).
+    fn push_run(&mut self, labels: &[(&str, &str)], metrics: &[Metric], duration_ms: u128) {
+        for metric in metrics {
+            let mut metric_labels = vec![("domain", metric.name.as_str())];
+            metric_labels.extend_from_slice(labels);
+            if metric.unit == ENERGY_UNIT {
+                self.energy.push(&metric_labels, metric.value);
+            } else {
+                metric_labels.push(("unit", metric.unit.as_str()));
+                self.metric.push(&metric_labels, metric.value);
+            }
+        }
+        self.duration.push(labels, duration_ms);
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        for family in [&self.energy, &self.metric, &self.duration, &self.exit_code] {
+            if family.samples.is_empty() {
+                continue;
+            }
+            writeln!(writer, "# HELP {} {}", family.name, family.help)?;
+            writeln!(writer, "# TYPE {} gauge", family.name)?;
+            for sample in &family.samples {
+                writeln!(writer, "{}", sample)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OutputFormatTrait for PrometheusOutput {
+    fn results_path(&self) -> Option<&str> {
+        Some(&self.filename)
+    }
+
+    fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
+        self.simple_iterations(config, std::slice::from_ref(result))
+    }
+
+    fn simple_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[MeasurementResult],
+    ) -> Result<()> {
+        info!(
+            "Formatting {} simple iterations for Prometheus",
+            results.len()
+        );
+        let command = config.cmd.join(" ");
+        let mut families = Families::new();
+
+        for (idx, result) in results.iter().enumerate() {
+            let iteration = (idx + 1).to_string();
+            let labels = [("command", command.as_str()), ("iteration", &iteration)];
+            families.push_run(&labels, &result.metrics, result.duration_ms);
+            families.exit_code.push(&labels, result.exit_code);
+        }
+
+        families.write(&mut self.writer)?;
+        report_written("Prometheus", &self.filename);
+        Ok(())
+    }
+
+    fn phases_single(
+        &mut self,
+        config: &ProfileConfig,
+        result: &PhaseMeasurementResult,
+    ) -> Result<()> {
+        self.phases_iterations(config, std::slice::from_ref(result))
+    }
+
+    fn phases_iterations(
+        &mut self,
+        config: &ProfileConfig,
+        results: &[PhaseMeasurementResult],
+    ) -> Result<()> {
+        info!(
+            "Formatting {} phase iterations for Prometheus",
+            results.len()
+        );
+        let command = config.cmd.join(" ");
+        let mut families = Families::new();
+
+        for (idx, result) in results.iter().enumerate() {
+            let iteration = (idx + 1).to_string();
+            let labels = [("command", command.as_str()), ("iteration", &iteration)];
+            for phase in &result.phases {
+                let mut phase_labels = labels.to_vec();
+                phase_labels.push(("phase", &phase.name));
+                families.push_run(&phase_labels, &phase.metrics, phase.duration_ms);
+            }
+            families.exit_code.push(&labels, result.exit_code);
+        }
+
+        families.write(&mut self.writer)?;
+        report_written("Prometheus", &self.filename);
+        Ok(())
+    }
+}
+
+/// Escape the backslashes, double quotes and newlines of a label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl PrometheusOutput {
+    pub fn new(output_file: Option<String>, file_mode: FileMode) -> Result<Self> {
+        let (writer, filename) = OutputSink::open(output_file, "Prometheus", "prom", file_mode)?;
+        Ok(Self { writer, filename })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        measurement::PhaseResult,
+        test_util::{metric, metric_in},
+    };
+
+    fn write(write: impl FnOnce(&mut PrometheusOutput, &ProfileConfig)) -> String {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("results.prom");
+        let mut output = PrometheusOutput::new(
+            Some(path.to_str().unwrap().to_string()),
+            FileMode::default(),
+        )
+        .unwrap();
+        let config = ProfileConfig {
+            cmd: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo \"a\\b\"".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        write(&mut output, &config);
+        read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\\b \"c\"\nd"), "a\\\\b \\\"c\\\"\\nd");
+    }
+
+    #[test]
+    fn simple_iterations_are_grouped_by_family() {
+        let result = |value| MeasurementResult {
+            duration_ms: 10,
+            measure_count: 2,
+            metrics: vec![metric("PACKAGE-0_0", value), metric_in("SCORE", 3, "ops/W")],
+            ..Default::default()
+        };
+
+        let prom = write(|output, config| {
+            output
+                .simple_iterations(config, &[result(1_500), result(1_700)])
+                .unwrap()
+        });
+
+        let command = r#"command="sh -c echo \"a\\b\"""#;
+        let expected = [
+            "# HELP joule_profiler_energy_microjoules Energy consumed by the domain during the run, in microjoules".to_string(),
+            "# TYPE joule_profiler_energy_microjoules gauge".to_string(),
+            format!("joule_profiler_energy_microjoules{{domain=\"PACKAGE-0_0\",{},iteration=\"1\"}} 1500", command),
+            format!("joule_profiler_energy_microjoules{{domain=\"PACKAGE-0_0\",{},iteration=\"2\"}} 1700", command),
+            "# HELP joule_profiler_metric Value of the metrics not in microjoules, in their unit".to_string(),
+            "# TYPE joule_profiler_metric gauge".to_string(),
+            format!("joule_profiler_metric{{domain=\"SCORE\",{},iteration=\"1\",unit=\"ops/W\"}} 3", command),
+            format!("joule_profiler_metric{{domain=\"SCORE\",{},iteration=\"2\",unit=\"ops/W\"}} 3", command),
+        ];
+        let lines: Vec<&str> = prom.lines().collect();
+        assert_eq!(lines[..expected.len()], expected);
+        assert!(prom.contains(&format!(
+            "joule_profiler_duration_milliseconds{{{},iteration=\"2\"}} 10\n",
+            command
+        )));
+        assert!(prom.contains("# TYPE joule_profiler_exit_code gauge\n"));
+    }
+
+    #[test]
+    fn phases_are_labelled_with_their_name() {
+        let result = PhaseMeasurementResult {
+            phases: vec![PhaseResult {
+                name: "START -> __A__".to_string(),
+                metrics: vec![metric("PACKAGE-0_0", 100)],
+                duration_ms: 5,
+                ..Default::default()
+            }],
+            duration_ms: 5,
+            exit_code: 1,
+            ..Default::default()
+        };
+
+        let prom = write(|output, config| output.phases_single(config, &result).unwrap());
+
+        assert!(prom.contains(",iteration=\"1\",phase=\"START -> __A__\"} 100\n"));
+        assert!(prom.contains(",phase=\"START -> __A__\"} 5\n"));
+        // The exit code is the one of the whole iteration
+        assert!(prom.contains(",iteration=\"1\"} 1\n"));
+        assert!(!prom.contains("joule_profiler_metric"));
+    }
+}
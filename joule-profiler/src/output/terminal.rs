@@ -1,10 +1,20 @@
+use std::collections::BTreeMap;
+
+use anstream::println;
+use anstyle::{AnsiColor, Style};
 use anyhow::Result;
+use log::warn;
 
 use crate::{
-    config::{ListSensorsConfig, ProfileConfig},
-    measurement::{MeasurementResult, PhaseMeasurementResult},
-    output::OutputFormatTrait,
-    source::{Metric, Sensor},
+    cli::{EnergyUnit, MetricFormat},
+    config::{ListSensorsConfig, Mode, ProfileConfig},
+    measurement::{
+        CV_WARNING_THRESHOLD, CalibrationResult, EnergyTrend, IntervalEnergy, MeasurementResult,
+        MetricComparison, MetricSummary, PhaseMeasurementResult, PhaseSummary,
+        TREND_DRIFT_THRESHOLD, TREND_R2_THRESHOLD, TokenCount, energy_uj, power_by_metric,
+    },
+    output::{ENERGY_UNIT, OutputFormatTrait, format_float},
+    source::{FailedSource, Metric, Sensor},
 };
 
 /// Constants for formatting
@@ -12,13 +22,108 @@ const BORDER_DOUBLE: &str = "═";
 const BORDER_SINGLE: &str = "─";
 const BOX_WIDTH: usize = 50;
 
+/// Styles of the colored output, stripped by anstream when colors are disabled
+pub const TITLE_STYLE: Style = Style::new().bold();
+pub const VALUE_STYLE: Style = Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(AnsiColor::Green)));
+const WARNING_STYLE: Style = Style::new().fg_color(Some(anstyle::Color::Ansi(AnsiColor::Yellow)));
+const PHASE_STYLE: Style = Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(AnsiColor::Cyan)));
+pub const SUCCESS_STYLE: Style =
+    Style::new().fg_color(Some(anstyle::Color::Ansi(AnsiColor::Green)));
+
+/// Totals of a session of iterations, shown in its footer
+#[derive(Debug, Default, PartialEq)]
+struct SessionTotals {
+    iterations: usize,
+    /// Runs that exited with a nonzero code
+    failed: usize,
+    wall_ms: u128,
+    /// Energy in µJ of each domain, summed over the iterations
+    energy_uj: BTreeMap<String, u64>,
+}
+
+impl SessionTotals {
+    fn from_simple(results: &[MeasurementResult]) -> Self {
+        let mut totals = Self::default();
+        for result in results {
+            totals.add_run(result.exit_code, result.duration_ms, &result.metrics);
+        }
+        totals
+    }
+
+    /// The energy of an iteration is the one of its START -> END phase, the sum of its
+    /// phases without it (e.g. with `--inner-only`)
+    fn from_phases(results: &[PhaseMeasurementResult]) -> Self {
+        let mut totals = Self::default();
+        for result in results {
+            let global = result
+                .phases
+                .iter()
+                .find(|phase| phase.start_token.is_none() && phase.end_token.is_none());
+            let metrics: Vec<Metric> = match global {
+                Some(phase) => phase.metrics.clone(),
+                None => result
+                    .phases
+                    .iter()
+                    .flat_map(|phase| phase.metrics.iter().cloned())
+                    .collect(),
+            };
+            totals.add_run(result.exit_code, result.duration_ms, &metrics);
+        }
+        totals
+    }
+
+    fn add_run(&mut self, exit_code: i32, duration_ms: u128, metrics: &[Metric]) {
+        self.iterations += 1;
+        if exit_code != 0 {
+            self.failed += 1;
+        }
+        self.wall_ms += duration_ms;
+        for metric in metrics {
+            if let Some(energy_uj) = energy_uj(metric) {
+                *self.energy_uj.entry(metric.name.clone()).or_default() += energy_uj;
+            }
+        }
+    }
+}
+
+/// SI prefixes recognized when scaling a metric to another unit
+const UNIT_PREFIXES: [(&str, f64); 6] = [
+    ("n", 1e-9),
+    ("µ", 1e-6),
+    ("u", 1e-6),
+    ("m", 1e-3),
+    ("k", 1e3),
+    ("M", 1e6),
+];
+
 #[derive(Debug, Clone, Default)]
-pub struct TerminalOutput;
+pub struct TerminalOutput {
+    /// Unit and precision overrides of the metrics, by name
+    metric_formats: Vec<MetricFormat>,
+    /// Unit of the energy metrics without an override, µJ if not set
+    energy_unit: Option<EnergyUnit>,
+}
 
 impl OutputFormatTrait for TerminalOutput {
     fn simple_single(&mut self, config: &ProfileConfig, result: &MeasurementResult) -> Result<()> {
         self.display_command(&config.cmd);
-        self.display_result(&result.metrics, "")
+        self.display_result(
+            &result.metrics,
+            result.duration_ms,
+            None,
+            config.precision,
+            "",
+        )?;
+        self.display_failed_sources(&result.failed_sources, "");
+        self.display_attempts(result.attempts, "");
+        self.display_counter_wraps(result.counter_wraps, "");
+        self.display_repetitions(result.repetitions, "");
+        self.display_intervals(&result.intervals, config.precision, "");
+        Ok(())
     }
 
     fn simple_iterations(
@@ -30,8 +135,29 @@ impl OutputFormatTrait for TerminalOutput {
 
         for (idx, result) in results.iter().enumerate() {
             self.display_iteration_header(idx, results.len());
-            self.display_result(&result.metrics, "")?;
+            self.display_result(
+                &result.metrics,
+                result.duration_ms,
+                None,
+                config.precision,
+                "",
+            )?;
+            self.display_failed_sources(&result.failed_sources, "");
+            self.display_attempts(result.attempts, "");
+            self.display_counter_wraps(result.counter_wraps, "");
+            self.display_repetitions(result.repetitions, "");
+            self.display_intervals(&result.intervals, config.precision, "");
+        }
+
+        self.display_summary(
+            "Summary",
+            &MetricSummary::from_iterations_without_outliers(results, config.drop_outliers),
+            config.precision,
+        );
+        if config.trend {
+            self.display_trend(EnergyTrend::from_iterations(results), config.precision);
         }
+        self.display_session_totals(&SessionTotals::from_simple(results), config.precision);
 
         Ok(())
     }
@@ -43,7 +169,7 @@ impl OutputFormatTrait for TerminalOutput {
     ) -> Result<()> {
         self.display_command(&config.cmd);
 
-        for phase in result.phases.iter() {
+        for (phase, percents) in result.phases.iter().zip(result.energy_percents()) {
             self.display_phase_header(
                 &phase.name,
                 phase.start_token.as_deref(),
@@ -52,8 +178,16 @@ impl OutputFormatTrait for TerminalOutput {
                 phase.end_line,
                 "",
             );
-            self.display_result(&phase.metrics, "")?;
+            self.display_result(
+                &phase.metrics,
+                phase.duration_ms,
+                Some(&percents),
+                config.precision,
+                "",
+            )?;
+            self.display_counter_wraps(phase.counter_wraps, "");
         }
+        self.display_failed_sources(&result.failed_sources, "");
 
         Ok(())
     }
@@ -72,7 +206,8 @@ impl OutputFormatTrait for TerminalOutput {
         for (idx, iteration_results) in results.iter().enumerate() {
             self.display_iteration_header(idx, results.len());
 
-            for phase in &iteration_results.phases {
+            let percents = iteration_results.energy_percents();
+            for (phase, percents) in iteration_results.phases.iter().zip(&percents) {
                 self.display_phase_header(
                     &phase.name,
                     phase.start_token.as_deref(),
@@ -81,9 +216,95 @@ impl OutputFormatTrait for TerminalOutput {
                     phase.end_line,
                     "  ",
                 );
-                self.display_result(&phase.metrics, "  ")?;
+                self.display_result(
+                    &phase.metrics,
+                    phase.duration_ms,
+                    Some(percents),
+                    config.precision,
+                    "  ",
+                )?;
+                self.display_counter_wraps(phase.counter_wraps, "  ");
             }
+            self.display_failed_sources(&iteration_results.failed_sources, "  ");
+        }
+
+        self.display_token_counts(&TokenCount::from_iterations(results));
+        for phase in PhaseSummary::from_iterations(results) {
+            self.display_summary(
+                &format!("Summary: {}", phase.name),
+                &phase.metrics,
+                config.precision,
+            );
+        }
+        self.display_session_totals(&SessionTotals::from_phases(results), config.precision);
+
+        Ok(())
+    }
+
+    fn calibration(&mut self, config: &ProfileConfig, results: &[CalibrationResult]) -> Result<()> {
+        self.display_command(&config.cmd);
+
+        let Some(baseline) = results.first() else {
+            return Ok(());
+        };
+
+        for result in results {
+            let rate = match result.polling_rate_s {
+                Some(rate) => format!("{} s", rate),
+                None => "none".to_string(),
+            };
+            self.display_phase_header(
+                &format!("Polling rate: {}", rate),
+                None,
+                None,
+                None,
+                None,
+                "",
+            );
+            self.display_result(
+                &result.metrics,
+                result.duration_ms,
+                None,
+                config.precision,
+                "",
+            )?;
+            println!("  {:<20}: {:>10}", "Measure count", result.measure_count);
+            println!("  {:<20}: {:>10} µs", "Measure delta", result.measure_delta);
+            println!("  {:<20}: {:>10} ms", "Duration", result.duration_ms);
+            println!(
+                "  {:<20}: {:>+10.prec$} %",
+                "Energy deviation",
+                result.energy_deviation_pct(baseline),
+                prec = config.precision
+            );
+        }
+
+        Ok(())
+    }
+
+    fn comparison(
+        &mut self,
+        config: &ProfileConfig,
+        comparison: &[MetricComparison],
+    ) -> Result<()> {
+        if let Mode::CompareMode(compare_config) = &config.mode {
+            println!();
+            self.print_header("Commands");
+            println!("  Baseline  : {}", compare_config.baseline);
+            println!("  Candidate : {}", compare_config.candidate);
+        }
+
+        println!();
+        self.print_header(&format!("Comparison ({} iteration(s))", config.iterations));
+        println!(
+            "  {:<20} | {:>14} | {:>14} | {:>14} | {:>9}",
+            "Name", "Baseline", "Candidate", "Delta", "Change"
+        );
+        println!("  {}", BORDER_SINGLE.repeat(BOX_WIDTH + 32));
+        for metric in comparison {
+            println!("  {}", comparison_line(metric, config.precision));
         }
+        println!("{}", BORDER_DOUBLE.repeat(BOX_WIDTH));
 
         Ok(())
     }
@@ -113,6 +334,14 @@ impl OutputFormatTrait for TerminalOutput {
 }
 
 impl TerminalOutput {
+    pub fn set_metric_formats(&mut self, formats: &[MetricFormat]) {
+        self.metric_formats = formats.to_vec();
+    }
+
+    pub fn set_energy_unit(&mut self, unit: EnergyUnit) {
+        self.energy_unit = Some(unit);
+    }
+
     /// Display command header
     fn display_command(&self, command: &[String]) {
         if !command.is_empty() {
@@ -125,23 +354,20 @@ impl TerminalOutput {
     /// Print a formatted header
     fn print_header(&self, title: &str) {
         println!("╔{}╗", BORDER_DOUBLE.repeat(BOX_WIDTH));
-        println!("║  {:<width$} ║", title, width = BOX_WIDTH - 3);
+        let title = format!("{:<width$}", title, width = BOX_WIDTH - 3);
+        println!("║  {} ║", paint(TITLE_STYLE, &title));
         println!("╚{}╝", BORDER_DOUBLE.repeat(BOX_WIDTH));
     }
 
-    /// Print a formatted sub-header
-    fn print_subheader(&self, title: &str, prefix: &str) {
+    /// Print a formatted sub-header, its title in the style
+    fn print_subheader(&self, style: Style, title: &str, prefix: &str) {
         println!(
             "{}┌{}┐",
             prefix,
             BORDER_SINGLE.repeat(BOX_WIDTH - prefix.len())
         );
-        println!(
-            "{}│ {:<width$}│",
-            prefix,
-            title,
-            width = BOX_WIDTH - prefix.len() - 3
-        );
+        let title = format!("{:<width$}", title, width = BOX_WIDTH - prefix.len() - 3);
+        println!("{}│ {}│", prefix, paint(style, &title));
         println!(
             "{}└{}┘",
             prefix,
@@ -149,19 +375,43 @@ impl TerminalOutput {
         );
     }
 
-    /// Display a single measurement result
-    fn display_result(&self, metrics: &[Metric], prefix: &str) -> Result<()> {
+    /// Display a single measurement result, followed by the average power of its energy metrics
+    fn display_result(
+        &self,
+        metrics: &[Metric],
+        duration_ms: u128,
+        percents: Option<&BTreeMap<String, f64>>,
+        precision: usize,
+        prefix: &str,
+    ) -> Result<()> {
         println!();
         println!("{}{}", prefix, BORDER_DOUBLE.repeat(BOX_WIDTH));
 
         let mut keys: Vec<_> = metrics.iter().map(|metric| &metric.name).cloned().collect();
         keys.sort_unstable();
 
-        for metric in metrics {
-            println!(
-                "{}  {:<20}: {:10.6} {}",
-                prefix, metric.name, metric.value, metric.unit
-            );
+        for (source, source_metrics) in group_by_source(metrics) {
+            println!("{}  [{}]", prefix, source);
+            for metric in source_metrics {
+                let format = self
+                    .metric_formats
+                    .iter()
+                    .find(|format| format.name == metric.name);
+                let line = match (format, self.energy_unit) {
+                    (None, Some(unit)) if metric.unit == ENERGY_UNIT => {
+                        energy_line(metric, unit, precision, prefix)
+                    }
+                    _ => metric_line(metric, prefix, format),
+                };
+                // The share of the phase in the energy of the iteration
+                match percents.and_then(|percents| percents.get(&metric.name)) {
+                    Some(percent) => println!("{} ({:.prec$}%)", line, percent, prec = precision),
+                    None => println!("{}", line),
+                }
+            }
+        }
+        for (name, power) in power_by_metric(metrics, duration_ms) {
+            println!("{}", power_line(&name, power, precision, prefix));
         }
 
         // println!("{}  {:<20}: {:>10.6} s", prefix, "Duration", duration_s);
@@ -171,6 +421,171 @@ impl TerminalOutput {
         Ok(())
     }
 
+    /// Display a notice for each source that failed during the measurement
+    fn display_failed_sources(&self, failed_sources: &[FailedSource], prefix: &str) {
+        for failed in failed_sources {
+            let warning = format!(
+                "⚠ Source {} failed, its metrics are missing: {}",
+                failed.name, failed.error
+            );
+            println!("{}  {}", prefix, paint(WARNING_STYLE, &warning));
+        }
+    }
+
+    /// Flag the results measured after retrying failed runs with `--retries`
+    fn display_attempts(&self, attempts: u32, prefix: &str) {
+        if attempts > 1 {
+            let warning = format!("⚠ Measured at attempt {}, the command is flaky", attempts);
+            println!("{}  {}", prefix, paint(WARNING_STYLE, &warning));
+        }
+    }
+
+    /// Flag the results whose energy was corrected for wrapped counters
+    fn display_counter_wraps(&self, counter_wraps: u64, prefix: &str) {
+        if counter_wraps > 0 {
+            let times = if counter_wraps == 1 { "time" } else { "times" };
+            let warning = format!("⚠ counter wrapped {} {}", counter_wraps, times);
+            println!("{}  {}", prefix, paint(WARNING_STYLE, &warning));
+        }
+    }
+
+    /// Note the results of a batch measured with `--min-duration`, given per run
+    fn display_repetitions(&self, repetitions: u32, prefix: &str) {
+        if repetitions > 1 {
+            println!(
+                "{}  Per run, averaged over {} back-to-back runs",
+                prefix, repetitions
+            );
+        }
+    }
+
+    /// Display the energy of each interval of `--simple-samples`, one line per interval
+    fn display_intervals(&self, intervals: &[IntervalEnergy], precision: usize, prefix: &str) {
+        if intervals.is_empty() {
+            return;
+        }
+
+        println!("{}  Energy per interval:", prefix);
+        let unit = self.energy_unit.unwrap_or(EnergyUnit::Uj);
+        for interval in intervals {
+            println!(
+                "{}    {:>10} s  {}",
+                prefix,
+                format_float(interval.start_us as f64 / 1e6, 3),
+                interval_energies(&interval.metrics, unit, precision)
+            );
+        }
+    }
+
+    /// Display the statistics of the metrics over the iterations
+    fn display_summary(&self, title: &str, summary: &[MetricSummary], precision: usize) {
+        println!();
+        self.print_header(title);
+        println!(
+            "  {:<20} | {:>12} | {:>12} | {:>12} | {:>12} | {:>12} | {:>12} | {:>8}",
+            "Name", "Mean", "Stddev", "Min", "Median", "Max", "SEM", "CV"
+        );
+        println!("  {}", BORDER_SINGLE.repeat(BOX_WIDTH + 70));
+
+        for metric in summary {
+            println!("  {}", summary_line(metric, precision));
+        }
+
+        if summary.iter().any(|metric| metric.unstable) {
+            let warning = format!(
+                "⚠ CV above {:.0} %, the benchmark is unstable",
+                CV_WARNING_THRESHOLD * 100.0
+            );
+            println!("  {}", paint(WARNING_STYLE, &warning));
+        }
+        for metric in summary.iter().filter(|metric| metric.dropped > 0) {
+            println!(
+                "  {}: {} outlier iterations dropped, {} kept",
+                metric.name, metric.dropped, metric.samples
+            );
+        }
+        println!("{}", BORDER_DOUBLE.repeat(BOX_WIDTH));
+    }
+
+    /// Display the footer of the session: its wall time, failed runs and the energy of each
+    /// domain over all the iterations
+    fn display_session_totals(&self, totals: &SessionTotals, precision: usize) {
+        println!();
+        self.print_header("Session totals");
+        println!(
+            "  {:<20}: {} s",
+            "Wall time",
+            format_float(totals.wall_ms as f64 / 1000.0, precision)
+        );
+        let failed = format!("{} / {}", totals.failed, totals.iterations);
+        if totals.failed > 0 {
+            println!("  {:<20}: {}", "Failed runs", paint(WARNING_STYLE, &failed));
+        } else {
+            println!("  {:<20}: {}", "Failed runs", failed);
+        }
+
+        if !totals.energy_uj.is_empty() {
+            println!(
+                "  {:<20} | {:>16} | {:>16}",
+                "Name", "Total", "Per iteration"
+            );
+            println!("  {}", BORDER_SINGLE.repeat(BOX_WIDTH + 6));
+        }
+        let unit = self.energy_unit.unwrap_or(EnergyUnit::Uj);
+        for (name, &energy_uj) in &totals.energy_uj {
+            println!(
+                "  {}",
+                session_energy_line(name, energy_uj, totals.iterations, unit, precision)
+            );
+        }
+        println!("{}", BORDER_DOUBLE.repeat(BOX_WIDTH));
+    }
+
+    /// Display the trend of the total energy over the iterations
+    fn display_trend(&self, trend: Option<EnergyTrend>, precision: usize) {
+        println!();
+        self.print_header("Energy trend");
+        match trend {
+            Some(trend) => {
+                println!("  {}", trend_line(&trend, precision));
+                if trend.drifting {
+                    let warning = format!(
+                        "⚠ The energy drifts over the iterations (R² above {}, change above {:.0} %)",
+                        TREND_R2_THRESHOLD,
+                        TREND_DRIFT_THRESHOLD * 100.0
+                    );
+                    println!("  {}", paint(WARNING_STYLE, &warning));
+                }
+            }
+            None => println!("  Not enough iterations to fit a trend"),
+        }
+        println!("{}", BORDER_DOUBLE.repeat(BOX_WIDTH));
+    }
+
+    /// Display the occurrences of each token per iteration
+    fn display_token_counts(&self, counts: &[TokenCount]) {
+        if counts.is_empty() {
+            return;
+        }
+
+        println!();
+        self.print_header("Token occurrences per iteration");
+        for count in counts {
+            println!("  {}", token_count_line(count));
+        }
+
+        if counts.iter().any(TokenCount::is_variable) {
+            println!(
+                "  {}",
+                paint(
+                    WARNING_STYLE,
+                    "⚠ Some tokens were not emitted the same number of times in every iteration"
+                )
+            );
+        }
+        println!("{}", BORDER_DOUBLE.repeat(BOX_WIDTH));
+    }
+
     /// Display iteration header
     fn display_iteration_header(&self, idx: usize, total: usize) {
         println!("\n╔{}╗", BORDER_DOUBLE.repeat(BOX_WIDTH));
@@ -196,10 +611,11 @@ impl TerminalOutput {
         println!();
         if prefix.is_empty() {
             println!("╔{}╗", BORDER_DOUBLE.repeat(BOX_WIDTH));
-            println!("║  Phase: {:<width$} ║", phase_name, width = BOX_WIDTH - 10);
+            let title = format!("Phase: {:<width$}", phase_name, width = BOX_WIDTH - 10);
+            println!("║  {} ║", paint(PHASE_STYLE, &title));
             println!("╚{}╝", BORDER_DOUBLE.repeat(BOX_WIDTH));
         } else {
-            self.print_subheader(&format!("Phase: {}", phase_name), prefix);
+            self.print_subheader(PHASE_STYLE, &format!("Phase: {}", phase_name), prefix);
         }
 
         // Display token information
@@ -222,3 +638,432 @@ impl TerminalOutput {
         }
     }
 }
+
+/// Wrap the text in the style escape codes.
+pub fn paint(style: Style, text: &str) -> String {
+    format!("{}{}{:#}", style, text, style)
+}
+
+/// Format one metric of a result, highlighting its value.
+fn metric_line(metric: &Metric, prefix: &str, format: Option<&MetricFormat>) -> String {
+    let (value, unit) = match format.and_then(|format| formatted_value(metric, format)) {
+        Some((value, unit)) => (value, unit),
+        None => (metric.value.to_string(), metric.unit.as_str()),
+    };
+
+    value_line(&metric.name, &value, unit, prefix)
+}
+
+/// Format one energy metric converted from microjoules to the `--unit`.
+fn energy_line(metric: &Metric, unit: EnergyUnit, precision: usize, prefix: &str) -> String {
+    let value = format_float(unit.from_uj(metric.value as f64), precision);
+    value_line(&metric.name, &value, unit.symbol(), prefix)
+}
+
+fn value_line(name: &str, value: &str, unit: &str, prefix: &str) -> String {
+    // Padded before painting, the escape codes would count in the width
+    let value = format!("{:>10}", value);
+    format!(
+        "{}  {:<20}: {} {}",
+        prefix,
+        name,
+        paint(VALUE_STYLE, &value),
+        unit
+    )
+}
+
+/// Format the average power of a metric, aligned with the metrics lines.
+fn power_line(name: &str, power_w: f64, precision: usize, prefix: &str) -> String {
+    let value = format!("{:>10.prec$}", power_w, prec = precision);
+    format!(
+        "{}  {:<20}: {} W",
+        prefix,
+        format!("{}_POWER", name),
+        paint(VALUE_STYLE, &value)
+    )
+}
+
+/// Value of the metric scaled to the unit of the format, `None` if the units measure
+/// different quantities.
+fn formatted_value<'a>(metric: &Metric, format: &'a MetricFormat) -> Option<(String, &'a str)> {
+    let Some(scale) = unit_scale(&metric.unit, &format.unit) else {
+        warn!(
+            "Cannot display {} in {}, its unit is {}",
+            metric.name, format.unit, metric.unit
+        );
+        return None;
+    };
+
+    let value = format!(
+        "{:.prec$}",
+        metric.value as f64 * scale,
+        prec = format.precision
+    );
+    Some((value, &format.unit))
+}
+
+/// Factor converting a value from one unit to another differing only by their SI prefix.
+fn unit_scale(from: &str, to: &str) -> Option<f64> {
+    let (from_factor, from_base) = split_unit_prefix(from);
+    let (to_factor, to_base) = split_unit_prefix(to);
+    (from_base == to_base).then(|| from_factor / to_factor)
+}
+
+fn split_unit_prefix(unit: &str) -> (f64, &str) {
+    UNIT_PREFIXES
+        .iter()
+        .find_map(|(prefix, factor)| {
+            unit.strip_prefix(prefix)
+                .filter(|base| !base.is_empty())
+                .map(|base| (*factor, base))
+        })
+        .unwrap_or((1.0, unit))
+}
+
+/// Format one row of the summary, marking the unstable metrics.
+fn summary_line(metric: &MetricSummary, precision: usize) -> String {
+    let sem = match metric.sem {
+        Some(sem) => format!("{:.prec$}", sem, prec = precision),
+        None => "-".to_string(),
+    };
+    format!(
+        "{:<20} | {:>12.prec$} | {:>12.prec$} | {:>12.prec$} | {:>12.prec$} | {:>12.prec$} | \
+         {:>12} | {:>6.prec$} %{}",
+        metric.name,
+        metric.mean,
+        metric.stddev,
+        metric.min,
+        metric.median,
+        metric.max,
+        sem,
+        metric.cv * 100.0,
+        if metric.unstable { " ⚠" } else { "" },
+        prec = precision
+    )
+}
+
+/// Format one row of the comparison, the mean energies with their unit.
+fn comparison_line(metric: &MetricComparison, precision: usize) -> String {
+    let change = match metric.change_pct {
+        Some(change) => format!("{:+.prec$} %", change, prec = precision),
+        None => "-".to_string(),
+    };
+    format!(
+        "{:<20} | {:>14} | {:>14} | {:>14} | {:>9}",
+        metric.name,
+        format!(
+            "{:.prec$} {}",
+            metric.baseline,
+            metric.unit,
+            prec = precision
+        ),
+        format!(
+            "{:.prec$} {}",
+            metric.candidate,
+            metric.unit,
+            prec = precision
+        ),
+        format!("{:+.prec$} {}", metric.delta, metric.unit, prec = precision),
+        change
+    )
+}
+
+/// Format the energy metrics of an interval in the unit, whatever their measured unit.
+fn interval_energies(metrics: &[Metric], unit: EnergyUnit, precision: usize) -> String {
+    let energies: Vec<String> = metrics
+        .iter()
+        .filter_map(|metric| {
+            let value = unit.from_uj(energy_uj(metric)? as f64);
+            Some(format!(
+                "{} {} {}",
+                metric.name,
+                format_float(value, precision),
+                unit.symbol()
+            ))
+        })
+        .collect();
+    energies.join(" | ")
+}
+
+/// Format the total energy of a domain over the session and its average per iteration.
+fn session_energy_line(
+    name: &str,
+    energy_uj: u64,
+    iterations: usize,
+    unit: EnergyUnit,
+    precision: usize,
+) -> String {
+    let total = unit.from_uj(energy_uj as f64);
+    let average = total / iterations.max(1) as f64;
+    format!(
+        "{:<20} | {:>16} | {:>16}",
+        name,
+        format!("{} {}", format_float(total, precision), unit.symbol()),
+        format!("{} {}", format_float(average, precision), unit.symbol())
+    )
+}
+
+/// Format the slope of the total energy, its R² and the change over the run.
+fn trend_line(trend: &EnergyTrend, precision: usize) -> String {
+    format!(
+        "Slope: {:+.prec$} µJ/iteration | R²: {:.prec$} | Change over the run: {:+.prec$} %",
+        trend.slope_uj,
+        trend.r_squared,
+        trend.relative_drift * 100.0,
+        prec = precision
+    )
+}
+
+/// Format the occurrences of a token, marking the tokens emitted a variable number of times.
+fn token_count_line(count: &TokenCount) -> String {
+    let counts: Vec<_> = count.counts.iter().map(usize::to_string).collect();
+    format!(
+        "{:<20}: {}{}",
+        count.token,
+        counts.join(", "),
+        if count.is_variable() { " ⚠" } else { "" }
+    )
+}
+
+/// Group metrics by source, keeping the sources in order of first appearance.
+fn group_by_source(metrics: &[Metric]) -> Vec<(&str, Vec<&Metric>)> {
+    let mut groups: Vec<(&str, Vec<&Metric>)> = Vec::new();
+
+    for metric in metrics {
+        match groups
+            .iter_mut()
+            .find(|(source, _)| *source == metric.source)
+        {
+            Some((_, group)) => group.push(metric),
+            None => groups.push((&metric.source, vec![metric])),
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::PhaseResult;
+
+    fn metric(name: &str, source: &str) -> Metric {
+        Metric {
+            name: name.to_string(),
+            value: 1,
+            unit: "µJ".to_string(),
+            source: source.to_string(),
+        }
+    }
+
+    #[test]
+    fn group_by_source_groups_metrics_under_their_source() {
+        let metrics = vec![
+            metric("PACKAGE_0", "powercap"),
+            metric("GPU_0", "nvml"),
+            metric("CORE_0", "powercap"),
+        ];
+
+        let groups = group_by_source(&metrics);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, "powercap");
+        let names: Vec<_> = groups[0].1.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["PACKAGE_0", "CORE_0"]);
+        assert_eq!(groups[1].0, "nvml");
+        assert_eq!(groups[1].1[0].name, "GPU_0");
+    }
+
+    #[test]
+    fn no_escape_codes_when_colors_are_disabled() {
+        use std::io::Write;
+
+        use anstream::{AutoStream, ColorChoice};
+
+        let line = metric_line(&metric("PACKAGE_0", "powercap"), "", None);
+
+        let mut plain = AutoStream::new(Vec::new(), ColorChoice::Never);
+        writeln!(plain, "{}", line).unwrap();
+        let plain = String::from_utf8(plain.into_inner()).unwrap();
+        assert!(!plain.contains('\x1b'));
+        assert_eq!(plain, format!("  {:<20}: {:>10} µJ\n", "PACKAGE_0", 1));
+
+        let mut colored = AutoStream::new(Vec::new(), ColorChoice::Always);
+        writeln!(colored, "{}", line).unwrap();
+        let colored = String::from_utf8(colored.into_inner()).unwrap();
+        assert!(colored.contains('\x1b'));
+    }
+
+    #[test]
+    fn metric_format_scales_the_value_to_its_unit() {
+        let mut package = metric("PACKAGE_0", "powercap");
+        package.value = 1_234_567;
+        let format = |unit: &str, precision| MetricFormat {
+            name: "PACKAGE_0".to_string(),
+            unit: unit.to_string(),
+            precision,
+        };
+
+        let line = metric_line(&package, "", Some(&format("J", 3)));
+        let plain = anstream::adapter::strip_str(&line).to_string();
+        assert_eq!(plain, format!("  {:<20}: {:>10} J", "PACKAGE_0", "1.235"));
+
+        let line = metric_line(&package, "", Some(&format("mJ", 1)));
+        assert!(line.contains("1234.6") && line.ends_with(" mJ"));
+
+        // Not an energy, the raw value is kept
+        let line = metric_line(&package, "", Some(&format("W", 2)));
+        assert!(line.contains("1234567") && line.ends_with(" µJ"));
+    }
+
+    #[test]
+    fn energy_unit_converts_from_microjoules() {
+        let mut package = metric("PACKAGE_0", "powercap");
+        package.value = 7_200_000_000;
+
+        let line = energy_line(&package, EnergyUnit::Wh, 3, "");
+        let plain = anstream::adapter::strip_str(&line).to_string();
+        assert_eq!(plain, format!("  {:<20}: {:>10} Wh", "PACKAGE_0", "2.000"));
+
+        let line = energy_line(&package, EnergyUnit::Kj, 1, "");
+        assert!(line.contains("7.2") && line.ends_with(" kJ"));
+    }
+
+    #[test]
+    fn summary_line_marks_unstable_metrics() {
+        let summary = |cv: f64, unstable: bool| MetricSummary {
+            name: "PACKAGE-0_0".to_string(),
+            unit: "µJ".to_string(),
+            mean: 100.0,
+            stddev: cv * 100.0,
+            sem: None,
+            cv,
+            unstable,
+            min: 90.0,
+            median: 100.0,
+            max: 110.0,
+            samples: 5,
+            dropped: 0,
+        };
+
+        let stable = summary_line(&summary(0.02, false), 1);
+        assert!(stable.contains("   2.0 %"));
+        assert!(stable.contains("|         90.0 |        100.0 |        110.0 |"));
+        assert!(!stable.contains('⚠'));
+
+        let unstable = summary_line(&summary(0.6, true), 1);
+        assert!(unstable.contains("  60.0 %"));
+        assert!(unstable.ends_with('⚠'));
+    }
+
+    #[test]
+    fn comparison_and_trend_use_the_precision() {
+        let comparison = MetricComparison {
+            name: "PACKAGE-0_0".to_string(),
+            unit: "µJ".to_string(),
+            baseline: 100.0,
+            candidate: 112.345,
+            delta: 12.345,
+            change_pct: Some(12.345),
+        };
+        let line = comparison_line(&comparison, 1);
+        assert!(line.contains(" 112.3 µJ |") && line.contains(" +12.3 µJ |"));
+        assert!(line.ends_with(" +12.3 %"));
+        assert!(comparison_line(&comparison, 3).ends_with(" +12.345 %"));
+
+        let trend = EnergyTrend {
+            slope_uj: 1.23456,
+            r_squared: 0.98765,
+            relative_drift: 0.0123456,
+            drifting: true,
+        };
+        assert_eq!(
+            trend_line(&trend, 2),
+            "Slope: +1.23 µJ/iteration | R²: 0.99 | Change over the run: +1.23 %"
+        );
+    }
+
+    #[test]
+    fn session_totals_sum_the_iterations() {
+        let run = |exit_code, phases: Vec<PhaseResult>| PhaseMeasurementResult {
+            phases,
+            duration_ms: 1_500,
+            exit_code,
+            ..Default::default()
+        };
+        let phase = |start_token: Option<&str>, value| PhaseResult {
+            name: "phase".to_string(),
+            start_token: start_token.map(str::to_string),
+            metrics: vec![
+                Metric {
+                    value,
+                    ..metric("PACKAGE-0_0", "powercap")
+                },
+                Metric {
+                    unit: "ratio".to_string(),
+                    ..metric("RATIO", "powercap")
+                },
+                // Converted to µJ
+                Metric {
+                    value: 2,
+                    unit: "mJ".to_string(),
+                    ..metric("GPU_0", "nvml")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let totals = SessionTotals::from_phases(&[
+            // The whole run phase counts rather than the phases inside it
+            run(0, vec![phase(None, 1_000), phase(Some("__A__"), 400)]),
+            run(
+                1,
+                vec![phase(Some("__A__"), 300), phase(Some("__B__"), 200)],
+            ),
+        ]);
+
+        assert_eq!(
+            totals,
+            SessionTotals {
+                iterations: 2,
+                failed: 1,
+                wall_ms: 3_000,
+                energy_uj: BTreeMap::from([
+                    ("GPU_0".to_string(), 6_000),
+                    ("PACKAGE-0_0".to_string(), 1_500)
+                ]),
+            }
+        );
+        assert_eq!(
+            session_energy_line("PACKAGE-0_0", 1_500_000, 2, EnergyUnit::J, 2),
+            format!(
+                "{:<20} | {:>16} | {:>16}",
+                "PACKAGE-0_0", "1.50 J", "0.75 J"
+            )
+        );
+    }
+
+    #[test]
+    fn interval_energies_convert_every_energy_unit() {
+        let metrics = [
+            Metric {
+                value: 1_500_000,
+                ..metric("PACKAGE-0_0", "powercap")
+            },
+            Metric {
+                value: 250,
+                unit: "mJ".to_string(),
+                ..metric("GPU_0", "nvml")
+            },
+            Metric {
+                unit: "kB".to_string(),
+                ..metric("PEAK_RSS_KB", "procfs")
+            },
+        ];
+
+        assert_eq!(
+            interval_energies(&metrics, EnergyUnit::J, 2),
+            "PACKAGE-0_0 1.50 J | GPU_0 0.25 J"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    output::round_float,
+    source::{PowerSample, TimeBin},
+    util::file::{FileMode, create_file_with_user_permissions},
+};
+
+/// Energy and average power of a metric over a polling interval.
+#[derive(Debug, Serialize)]
+struct SampleRow<'a> {
+    /// Iteration, from 1
+    iteration: usize,
+    /// End of the interval in seconds after the first snapshot
+    second: f64,
+    /// Duration of the interval in seconds
+    interval_s: f64,
+    domain: &'a str,
+    energy_uj: u64,
+    power_w: f64,
+}
+
+/// Write the energy time series as `second;domain;energy_uj` rows, the second being the
+/// start of the bin. With several iterations an `iteration` column comes first.
+pub fn write_time_series(
+    path: &str,
+    bin_s: f64,
+    iterations: &[Vec<TimeBin>],
+    file_mode: FileMode,
+) -> Result<()> {
+    let mut file = create_file_with_user_permissions(path, file_mode)
+        .with_context(|| format!("Failed to create {}", path))?;
+    let include_iteration = iterations.len() > 1;
+
+    if include_iteration {
+        write!(file, "iteration;")?;
+    }
+    writeln!(file, "second;domain;energy_uj")?;
+
+    for (idx, bins) in iterations.iter().enumerate() {
+        for bin in bins {
+            let mut metrics: Vec<_> = bin.metrics.iter().collect();
+            metrics.sort_by(|a, b| a.name.cmp(&b.name));
+
+            for metric in metrics {
+                if include_iteration {
+                    write!(file, "{};", idx + 1)?;
+                }
+                writeln!(
+                    file,
+                    "{};{};{}",
+                    // Rounded to the microsecond to hide the float error on the product
+                    round_float(bin.index as f64 * bin_s, 6),
+                    metric.name,
+                    metric.value
+                )?;
+            }
+        }
+    }
+
+    info!("Energy time series written to {}", path);
+    Ok(())
+}
+
+/// Write the energy and average power of every metric over each polling interval, as a JSON
+/// array of rows if the path ends with `.json`, else as `second;domain;energy_uj;power_w`
+/// rows with an `iteration` column first with several iterations.
+pub fn write_samples(
+    path: &str,
+    iterations: &[Vec<PowerSample>],
+    file_mode: FileMode,
+) -> Result<()> {
+    let rows: Vec<SampleRow> = iterations
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, samples)| {
+            samples.iter().flat_map(move |sample| {
+                sample.metrics.iter().map(move |metric| SampleRow {
+                    iteration: idx + 1,
+                    second: round_float(sample.elapsed_us as f64 / 1e6, 6),
+                    interval_s: round_float(sample.interval_us as f64 / 1e6, 6),
+                    domain: &metric.name,
+                    energy_uj: metric.value,
+                    // µJ per µs are watts
+                    power_w: match sample.interval_us {
+                        0 => 0.0,
+                        interval_us => round_float(metric.value as f64 / interval_us as f64, 6),
+                    },
+                })
+            })
+        })
+        .collect();
+
+    let mut file = create_file_with_user_permissions(path, file_mode)
+        .with_context(|| format!("Failed to create {}", path))?;
+    if path.ends_with(".json") {
+        writeln!(file, "{}", serde_json::to_string_pretty(&rows)?)?;
+    } else {
+        let include_iteration = iterations.len() > 1;
+        if include_iteration {
+            write!(file, "iteration;")?;
+        }
+        writeln!(file, "second;domain;energy_uj;power_w")?;
+        for row in &rows {
+            if include_iteration {
+                write!(file, "{};", row.iteration)?;
+            }
+            writeln!(
+                file,
+                "{};{};{};{}",
+                row.second, row.domain, row.energy_uj, row.power_w
+            )?;
+        }
+    }
+
+    info!("{} polling samples written to {}", rows.len(), path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::source::{Metric, Metrics};
+
+    fn metrics(values: &[(&str, u64)]) -> Metrics {
+        values
+            .iter()
+            .map(|(name, value)| Metric {
+                name: name.to_string(),
+                value: *value,
+                unit: "µJ".to_string(),
+                source: "powercap".to_string(),
+            })
+            .collect()
+    }
+
+    fn bin(index: u64, values: &[(&str, u64)]) -> TimeBin {
+        TimeBin {
+            index,
+            metrics: metrics(values),
+        }
+    }
+
+    fn sample(elapsed_us: u128, interval_us: u128, values: &[(&str, u64)]) -> PowerSample {
+        PowerSample {
+            elapsed_us,
+            interval_us,
+            metrics: metrics(values),
+        }
+    }
+
+    #[test]
+    fn rows_start_at_the_bin_second() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("series.csv").to_string_lossy().to_string();
+
+        let bins = vec![
+            bin(0, &[("PACKAGE-0_0", 100), ("DRAM_0", 10)]),
+            bin(3, &[("PACKAGE-0_0", 400), ("DRAM_0", 40)]),
+        ];
+        write_time_series(&path, 0.1, &[bins], FileMode::default()).unwrap();
+
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            "second;domain;energy_uj\n\
+             0;DRAM_0;10\n\
+             0;PACKAGE-0_0;100\n\
+             0.3;DRAM_0;40\n\
+             0.3;PACKAGE-0_0;400\n"
+        );
+    }
+
+    #[test]
+    fn time_series_is_created_with_the_output_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("series.csv").to_string_lossy().to_string();
+
+        write_time_series(&path, 1.0, &[vec![]], FileMode(0o600)).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn samples_are_written_with_their_power() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("samples.csv").to_string_lossy().to_string();
+
+        let iterations = vec![
+            vec![
+                sample(
+                    100_000,
+                    100_000,
+                    &[("DRAM_0", 50), ("PACKAGE-0_0", 1_500_000)],
+                ),
+                sample(
+                    150_000,
+                    50_000,
+                    &[("DRAM_0", 25), ("PACKAGE-0_0", 1_000_000)],
+                ),
+            ],
+            vec![sample(
+                100_000,
+                100_000,
+                &[("DRAM_0", 60), ("PACKAGE-0_0", 0)],
+            )],
+        ];
+        write_samples(&path, &iterations, FileMode::default()).unwrap();
+
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            "iteration;second;domain;energy_uj;power_w\n\
+             1;0.1;DRAM_0;50;0.0005\n\
+             1;0.1;PACKAGE-0_0;1500000;15\n\
+             1;0.15;DRAM_0;25;0.0005\n\
+             1;0.15;PACKAGE-0_0;1000000;20\n\
+             2;0.1;DRAM_0;60;0.0006\n\
+             2;0.1;PACKAGE-0_0;0;0\n"
+        );
+    }
+
+    #[test]
+    fn samples_are_written_as_json_rows() {
+        let dir = tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("samples.json")
+            .to_string_lossy()
+            .to_string();
+
+        let samples = vec![sample(500_000, 250_000, &[("PACKAGE-0_0", 2_500_000)])];
+        write_samples(&path, &[samples], FileMode::default()).unwrap();
+
+        let rows: serde_json::Value =
+            serde_json::from_str(&read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            rows,
+            serde_json::json!([{
+                "iteration": 1,
+                "second": 0.5,
+                "interval_s": 0.25,
+                "domain": "PACKAGE-0_0",
+                "energy_uj": 2_500_000,
+                "power_w": 10.0
+            }])
+        );
+    }
+}
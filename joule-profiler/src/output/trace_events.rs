@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, io::Write};
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    measurement::PhaseMeasurementResult,
+    util::file::{FileMode, create_file_with_user_permissions},
+};
+
+/// Process of all the events, the iterations are its threads.
+const TRACE_PID: u32 = 1;
+
+/// Complete event of the Chrome Trace Event Format, with its start and duration.
+#[derive(Debug, Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: &'static str,
+    ph: &'static str,
+    /// Start in microseconds after the start of the command
+    ts: u128,
+    /// Duration in microseconds
+    dur: u128,
+    pid: u32,
+    /// Iteration, from 1
+    tid: usize,
+    /// Metrics of the phase, `NAME (unit)` to value
+    args: BTreeMap<String, u64>,
+}
+
+/// Write one complete event per phase as a JSON array of Chrome trace events, for Perfetto
+/// or chrome://tracing. The phases of each iteration are on their own thread, starting from
+/// the start of the command.
+pub fn write_trace_events(
+    path: &str,
+    results: &[PhaseMeasurementResult],
+    file_mode: FileMode,
+) -> Result<()> {
+    let events: Vec<TraceEvent> = results
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, result)| {
+            result.phases.iter().map(move |phase| TraceEvent {
+                name: &phase.name,
+                cat: "phase",
+                ph: "X",
+                ts: phase.offset_us,
+                dur: phase.duration_ms * 1000,
+                pid: TRACE_PID,
+                tid: idx + 1,
+                args: phase
+                    .metrics
+                    .iter()
+                    .map(|metric| (format!("{} ({})", metric.name, metric.unit), metric.value))
+                    .collect(),
+            })
+        })
+        .collect();
+
+    let mut file = create_file_with_user_permissions(path, file_mode)
+        .with_context(|| format!("Failed to create {}", path))?;
+    writeln!(file, "{}", serde_json::to_string_pretty(&events)?)?;
+
+    info!("{} trace events written to {}", events.len(), path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use serde_json::Value;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        measurement::{PhaseResult, PhaseToken},
+        source::Metric,
+    };
+
+    #[test]
+    fn each_phase_is_a_complete_event() {
+        let metric = Metric {
+            name: "PACKAGE-0_0".to_string(),
+            value: 1_500,
+            unit: "µJ".to_string(),
+            source: "powercap".to_string(),
+        };
+        let token = PhaseToken::Token("__A__".to_string());
+        let result = PhaseMeasurementResult {
+            phases: vec![
+                PhaseResult::new(&PhaseToken::Start, &token, None, Some(1), vec![metric], 12)
+                    .starting_at(0),
+                PhaseResult::new(&token, &PhaseToken::End, Some(1), None, Vec::new(), 30)
+                    .starting_at(12_400),
+            ],
+            duration_ms: 42,
+            ..Default::default()
+        };
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.json");
+
+        write_trace_events(path.to_str().unwrap(), &[result], FileMode::default()).unwrap();
+
+        let events: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+        let events = events.as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "START -> __A__");
+        assert_eq!(events[0]["ph"], "X");
+        assert_eq!(events[0]["ts"], 0);
+        assert_eq!(events[0]["dur"], 12_000);
+        assert_eq!(events[0]["tid"], 1);
+        assert_eq!(events[0]["args"]["PACKAGE-0_0 (µJ)"], 1_500);
+        assert_eq!(events[1]["name"], "__A__ -> END");
+        assert_eq!(events[1]["ts"], 12_400);
+        assert_eq!(events[1]["dur"], 30_000);
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::Result;
+use log::{debug, trace};
+
+use crate::error::JouleProfilerError;
+
+const PROC_STAT_PATH: &str = "/proc/stat";
+
+/// CPU time consumed by a cgroup and by the whole machine at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuUsage {
+    /// CPU time used by the cgroup in microseconds
+    pub cgroup_usec: u64,
+    /// Busy (non idle) CPU time of the machine in microseconds
+    pub total_usec: u64,
+}
+
+/// Read the CPU usage of a cgroup v2 and of the whole machine.
+pub fn read_cpu_usage(cgroup: &Path) -> Result<CpuUsage> {
+    let cpu_stat_path = cgroup.join("cpu.stat");
+    let cpu_stat = read_to_string(&cpu_stat_path).map_err(|e| {
+        JouleProfilerError::CgroupReadError(format!("{}: {}", cpu_stat_path.display(), e))
+    })?;
+    let cgroup_usec = parse_cpu_stat_usage(&cpu_stat).ok_or_else(|| {
+        JouleProfilerError::CgroupReadError(format!(
+            "usage_usec not found in {}",
+            cpu_stat_path.display()
+        ))
+    })?;
+
+    let usage = CpuUsage {
+        cgroup_usec,
+        total_usec: ticks_to_usec(read_cpu_ticks()?.busy, clock_ticks_per_second()),
+    };
+    trace!("CPU usage of {}: {:?}", cgroup.display(), usage);
+    Ok(usage)
+}
+
+/// Extract the `usage_usec` field of a cgroup v2 `cpu.stat` file.
+pub fn parse_cpu_stat_usage(cpu_stat: &str) -> Option<u64> {
+    cpu_stat.lines().find_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        (key == "usage_usec").then(|| value.trim().parse().ok())?
+    })
+}
+
+/// Busy and total CPU ticks of the whole machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuTicks {
+    /// Ticks spent outside of idle and iowait
+    pub busy: u64,
+    /// All the ticks, idle and iowait included
+    pub total: u64,
+}
+
+/// Sum the busy ticks of the aggregated `cpu` line of `/proc/stat`, idle and iowait excluded.
+pub fn parse_proc_stat_busy_ticks(proc_stat: &str) -> Option<u64> {
+    parse_proc_stat_ticks(proc_stat).map(|ticks| ticks.busy)
+}
+
+/// Read the busy and total ticks of the aggregated `cpu` line of `/proc/stat`.
+pub fn parse_proc_stat_ticks(proc_stat: &str) -> Option<CpuTicks> {
+    let line = proc_stat
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some("cpu"))?;
+
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+
+    // user nice system idle iowait irq softirq steal (guest time is already in user)
+    let busy = fields
+        .iter()
+        .take(8)
+        .enumerate()
+        .filter(|(idx, _)| *idx != 3 && *idx != 4)
+        .map(|(_, ticks)| ticks)
+        .sum();
+    let total = fields.iter().take(8).sum();
+
+    Some(CpuTicks { busy, total })
+}
+
+/// Read the CPU ticks of the whole machine from `/proc/stat`.
+pub fn read_cpu_ticks() -> Result<CpuTicks> {
+    let proc_stat = read_to_string(PROC_STAT_PATH)
+        .map_err(|e| JouleProfilerError::CgroupReadError(format!("{}: {}", PROC_STAT_PATH, e)))?;
+    parse_proc_stat_ticks(&proc_stat).ok_or_else(|| {
+        JouleProfilerError::CgroupReadError(format!("Invalid format of {}", PROC_STAT_PATH)).into()
+    })
+}
+
+/// Share of the machine CPU time that was busy between two readings, in [0, 1].
+pub fn cpu_utilization(begin: &CpuTicks, end: &CpuTicks) -> f64 {
+    let busy = end.busy.saturating_sub(begin.busy);
+    let total = end.total.saturating_sub(begin.total);
+
+    if total == 0 {
+        return 0.0;
+    }
+    (busy as f64 / total as f64).clamp(0.0, 1.0)
+}
+
+/// Share of the machine busy CPU time used by the cgroup between two usages, in [0, 1].
+pub fn cpu_share(begin: &CpuUsage, end: &CpuUsage) -> f64 {
+    let cgroup = end.cgroup_usec.saturating_sub(begin.cgroup_usec);
+    let total = end.total_usec.saturating_sub(begin.total_usec);
+
+    if total == 0 {
+        return 0.0;
+    }
+
+    let share = (cgroup as f64 / total as f64).clamp(0.0, 1.0);
+    debug!(
+        "Cgroup used {} µs of {} µs busy CPU time ({:.2} %)",
+        cgroup,
+        total,
+        share * 100.0
+    );
+    share
+}
+
+fn ticks_to_usec(ticks: u64, ticks_per_second: u64) -> u64 {
+    ticks * 1_000_000 / ticks_per_second.max(1)
+}
+
+fn clock_ticks_per_second() -> u64 {
+    // SAFETY: sysconf has no preconditions and only reads a system configuration value
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as u64 } else { 100 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPU_STAT_BEGIN: &str = "usage_usec 1000000\nuser_usec 800000\nsystem_usec 200000\n";
+    const CPU_STAT_END: &str = "usage_usec 1250000\nuser_usec 1000000\nsystem_usec 250000\n";
+
+    #[test]
+    fn parse_cpu_stat_reads_usage() {
+        assert_eq!(parse_cpu_stat_usage(CPU_STAT_BEGIN), Some(1_000_000));
+        assert_eq!(parse_cpu_stat_usage("user_usec 10\n"), None);
+    }
+
+    #[test]
+    fn parse_proc_stat_excludes_idle_and_iowait() {
+        let proc_stat = "cpu  100 10 40 5000 20 5 5 0 0 0\ncpu0 50 5 20 2500 10 2 3 0 0 0\n";
+        assert_eq!(parse_proc_stat_busy_ticks(proc_stat), Some(160));
+    }
+
+    #[test]
+    fn cpu_utilization_between_two_proc_stat_readings() {
+        let begin = parse_proc_stat_ticks("cpu  100 0 0 900 0 0 0 0 0 0\n").unwrap();
+        let end = parse_proc_stat_ticks("cpu  175 0 25 950 0 0 0 0 0 0\n").unwrap();
+
+        assert_eq!(begin.total, 1_000);
+        assert_eq!(cpu_utilization(&begin, &end), 100.0 / 150.0);
+        assert_eq!(cpu_utilization(&end, &end), 0.0);
+    }
+
+    #[test]
+    fn cpu_share_from_fixture_values() {
+        let begin = CpuUsage {
+            cgroup_usec: parse_cpu_stat_usage(CPU_STAT_BEGIN).unwrap(),
+            total_usec: ticks_to_usec(1_000, 100),
+        };
+        let end = CpuUsage {
+            cgroup_usec: parse_cpu_stat_usage(CPU_STAT_END).unwrap(),
+            total_usec: ticks_to_usec(1_100, 100),
+        };
+
+        // 250 ms of cgroup time over 1 s of busy time
+        assert_eq!(cpu_share(&begin, &end), 0.25);
+    }
+
+    #[test]
+    fn cpu_share_is_clamped_and_handles_idle_machine() {
+        let begin = CpuUsage {
+            cgroup_usec: 0,
+            total_usec: 0,
+        };
+        let end = CpuUsage {
+            cgroup_usec: 2_000,
+            total_usec: 1_000,
+        };
+        assert_eq!(cpu_share(&begin, &end), 1.0);
+        assert_eq!(cpu_share(&begin, &begin), 0.0);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use log::trace;
+
+use crate::{
+    cli::FakeMetric,
+    source::{Metric, MetricReader, MetricSource, Metrics, PollingStats, Sensor, SourceResult},
+};
+
+/// Name of the source reported in the metrics.
+const SOURCE_NAME: &str = "fake";
+
+/// Create a source reporting the given metrics in every phase.
+pub fn init_fake(metrics: &[FakeMetric]) -> MetricSource {
+    MetricSource::Fake(Fake::new(
+        metrics
+            .iter()
+            .map(|metric| Metric {
+                name: metric.name.clone(),
+                value: metric.value,
+                unit: "µJ".to_string(),
+                source: SOURCE_NAME.to_string(),
+            })
+            .collect(),
+    ))
+}
+
+/// Deterministic source reporting the same metrics in every phase, to exercise the outputs
+/// without RAPL.
+#[derive(Clone, Debug)]
+pub struct Fake {
+    metrics: Metrics,
+    /// Number of completed phases
+    phases: usize,
+    /// Number of snapshots taken
+    count: u64,
+}
+
+impl Fake {
+    pub fn new(metrics: Metrics) -> Self {
+        Self {
+            metrics,
+            phases: 0,
+            count: 0,
+        }
+    }
+}
+
+impl MetricReader for Fake {
+    fn measure(&mut self) -> Result<()> {
+        trace!("Fake measurement");
+        self.count += 1;
+        Ok(())
+    }
+
+    fn phase(&mut self) -> Result<()> {
+        self.measure()?;
+        self.phases += 1;
+        Ok(())
+    }
+
+    fn retrieve(&mut self) -> Result<SourceResult> {
+        let polling = PollingStats {
+            measure_count: self.count,
+            ..Default::default()
+        };
+
+        Ok(SourceResult {
+            // The last phase ends with the measurement
+            measures: vec![self.metrics.clone(); self.phases + 1],
+            count: self.count,
+            measure_delta: 0,
+            max_read_spread_us: 0,
+            failed_sources: Vec::new(),
+            time_series: Vec::new(),
+            samples: Vec::new(),
+            raw_counters: BTreeMap::new(),
+            raw_readings: BTreeMap::new(),
+            counter_wraps: Vec::new(),
+            phase_power_ranges: Vec::new(),
+            polling: BTreeMap::from([(SOURCE_NAME.to_string(), polling)]),
+            power_range: BTreeMap::new(),
+        })
+    }
+
+    fn get_sensors(&self) -> Result<Vec<Sensor>> {
+        Ok(self
+            .metrics
+            .iter()
+            .map(|metric| Sensor {
+                name: metric.name.clone(),
+                source: SOURCE_NAME.to_string(),
+                unit: metric.unit.clone(),
+            })
+            .collect())
+    }
+
+    fn get_name(&self) -> &'static str {
+        "Fake"
+    }
+}
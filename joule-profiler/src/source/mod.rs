@@ -1,21 +1,33 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
 use anyhow::Result;
 use enum_dispatch::enum_dispatch;
-use log::{error, info};
-use serde::Serialize;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use tokio::{
     select,
-    sync::mpsc::{Receiver, Sender, channel},
+    sync::mpsc::{
+        Receiver, Sender, UnboundedReceiver, UnboundedSender, channel, error::TrySendError,
+        unbounded_channel,
+    },
     task::JoinHandle,
     time::{MissedTickBehavior, interval},
 };
 
-use crate::source::rapl::Rapl;
+use crate::{
+    error::JouleProfilerError,
+    measurement::diverging_metrics,
+    source::{fake::Fake, nvml::Nvml, procfs::Procfs, rapl::Rapl},
+};
 
+pub mod cgroup;
+pub mod fake;
+pub mod nvml;
+pub mod proc;
+pub mod procfs;
 pub mod rapl;
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Metric {
     pub name: String,
     pub value: u64,
@@ -23,12 +35,25 @@ pub struct Metric {
     pub source: String,
 }
 
+impl Metric {
+    pub fn new(name: &str, value: u64, unit: &str, source: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+            unit: unit.to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
 pub type Metrics = Vec<Metric>;
 
 #[derive(Debug, Clone, Copy)]
 pub enum SourceEvent {
     Measure,
     Phase,
+    /// New phase whose boundary happened at the given timestamp in microseconds
+    PhaseAt(u128),
     Start,
     Pause,
     Stop,
@@ -42,6 +67,12 @@ pub trait MetricReader {
     /// Initialize a new measure phase.
     fn phase(&mut self) -> Result<()>;
 
+    /// Initialize a new measure phase whose boundary happened at the given timestamp,
+    /// aligning it on the nearest completed measure if supported.
+    fn phase_at(&mut self, _timestamp_us: u128) -> Result<()> {
+        self.phase()
+    }
+
     /// Retrieve all sensors measures.
     fn retrieve(&mut self) -> Result<SourceResult>;
 
@@ -54,44 +85,256 @@ pub trait MetricReader {
     }
 
     fn get_name(&self) -> &'static str;
+
+    /// Whether the counters advanced during the last measured interval, `None` if unknown.
+    fn advanced(&self) -> Option<bool> {
+        None
+    }
+
+    /// Metrics of the last completed phase, `None` if unsupported.
+    fn last_phase(&self) -> Option<Metrics> {
+        None
+    }
+
+    /// Forget the last measure, the energy consumed until the next measure is not counted.
+    fn discard_interval(&mut self) {}
 }
 
 #[enum_dispatch(MetricReader)]
 #[derive(Clone, Debug)]
+// Only a few sources live for the whole measurement, their size does not matter
+#[allow(clippy::large_enum_variant)]
 pub enum MetricSource {
     Rapl(Rapl),
+    Nvml(Nvml),
+    Procfs(Procfs),
+    Fake(Fake),
+}
+
+/// Energy measured during one fixed-size wall-clock bin, starting `index` bins after the
+/// first snapshot.
+#[derive(Clone, Debug)]
+pub struct TimeBin {
+    pub index: u64,
+    pub metrics: Metrics,
+}
+
+/// Energy of one polling interval, ending `elapsed_us` after the first snapshot.
+#[derive(Clone, Debug)]
+pub struct PowerSample {
+    pub elapsed_us: u128,
+    /// Duration of the interval in microseconds
+    pub interval_us: u128,
+    pub metrics: Metrics,
 }
 
 pub struct SourceResult {
     pub measures: Vec<Metrics>,
     pub count: u64,
     pub measure_delta: u128,
+    /// Largest time taken to read all the sensors of one measure in microseconds
+    pub max_read_spread_us: u128,
+    /// Sources whose worker failed, their metrics are missing from the measures
+    pub failed_sources: Vec<FailedSource>,
+    /// Energy per wall-clock bin sorted by index, empty unless binning is enabled
+    pub time_series: Vec<TimeBin>,
+    /// Energy of every polling interval sorted by time, empty unless the samples are kept
+    pub samples: Vec<PowerSample>,
+    /// Energy in µJ per domain path (or MSR domain) before the aggregation in metrics
+    pub raw_counters: BTreeMap<String, u64>,
+    /// Effective polling of each source, by source name
+    pub polling: BTreeMap<String, PollingStats>,
+    /// Lowest and highest power of each metric over the polling intervals, by metric name
+    pub power_range: BTreeMap<String, PowerRange>,
+    /// Lowest and highest power of each metric over the polling intervals of each phase,
+    /// parallel to the measures, empty for the sources without counters
+    pub phase_power_ranges: Vec<BTreeMap<String, PowerRange>>,
+    /// First and last readings of the energy counters per domain path, empty for the
+    /// sources without counters
+    pub raw_readings: BTreeMap<String, RawReading>,
+    /// Number of energy counter wraps of each phase, parallel to the measures, empty for the
+    /// sources without counters
+    pub counter_wraps: Vec<u64>,
+}
+
+/// Absolute values of an energy counter at the first and the last snapshot of a
+/// measurement, to debug its wraps.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawReading {
+    pub start_uj: u64,
+    pub end_uj: u64,
+    /// Range of the counter, it wraps to 0 past it
+    pub max_energy_uj: u64,
+}
+
+/// Lowest and highest average power of a metric over the intervals between two snapshots.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PowerRange {
+    pub min_power_w: f64,
+    pub peak_power_w: f64,
+}
+
+impl PowerRange {
+    /// Widen the range with the power of another interval.
+    pub fn record(range: Option<Self>, power_w: f64) -> Self {
+        match range {
+            Some(range) => Self {
+                min_power_w: range.min_power_w.min(power_w),
+                peak_power_w: range.peak_power_w.max(power_w),
+            },
+            None => Self {
+                min_power_w: power_w,
+                peak_power_w: power_w,
+            },
+        }
+    }
+}
+
+/// Timing of the snapshots taken by a source, to diagnose the sampling quality.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollingStats {
+    /// Configured polling interval, `None` when the source only measures on events
+    pub interval_us: Option<u128>,
+    /// Number of snapshots taken
+    pub measure_count: u64,
+    pub avg_interval_us: u128,
+    pub min_interval_us: u128,
+    pub max_interval_us: u128,
+    /// Time elapsed between the snapshots
+    pub total_elapsed_us: u128,
+}
+
+/// A metrics source whose worker failed during the measurement.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FailedSource {
+    pub name: String,
+    pub error: String,
 }
 
+/// Detection of counters that stop advancing while polling.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Watchdog {
+    /// Consecutive polling intervals without energy before firing, 0 disables the watchdog
+    pub max_stalled_intervals: u32,
+    /// Abort the measurement when the watchdog fires instead of only logging an error
+    pub strict: bool,
+}
+
+/// Number of consecutive stalled intervals observed by a polling worker.
+#[derive(Debug, Default)]
+struct WatchdogState {
+    stalled: u32,
+}
+
+impl WatchdogState {
+    /// Record whether the last interval advanced, failing if the watchdog fires in strict mode.
+    fn observe(&mut self, watchdog: &Watchdog, name: &str, advanced: Option<bool>) -> Result<()> {
+        match advanced {
+            Some(false) => self.stalled += 1,
+            Some(true) => self.stalled = 0,
+            None => return Ok(()),
+        }
+
+        if watchdog.max_stalled_intervals == 0 || self.stalled != watchdog.max_stalled_intervals {
+            return Ok(());
+        }
+
+        let error = JouleProfilerError::CountersStalled(name.to_string(), self.stalled);
+        error!("{}, the measured energy is wrong", error);
+        if watchdog.strict {
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+/// Comparison of the metrics reported under the same name by several sources.
+#[derive(Debug, Clone, Copy)]
+pub struct CrossCheck {
+    /// Largest accepted difference to the first source in percent
+    pub tolerance_pct: f64,
+    /// Abort the measurement on a divergence instead of only logging a warning
+    pub strict: bool,
+}
+
+/// Events buffered per worker before `send_event` waits for the worker, enough for bursts of
+/// phase tokens.
+pub const DEFAULT_EVENT_BUFFER: usize = 64;
+
 pub struct SourceManager {
     sources: Vec<MetricSource>,
-    senders: Vec<Sender<SourceEvent>>,
-    handles: Vec<JoinHandle<Result<SourceResult>>>,
+    watchdog: Watchdog,
+    cross_check: Option<CrossCheck>,
+    event_buffer: usize,
+    live_sender: Option<UnboundedSender<Metrics>>,
+    senders: Vec<(&'static str, Sender<SourceEvent>)>,
+    handles: Vec<(&'static str, JoinHandle<Result<SourceResult>>)>,
 }
 
 impl SourceManager {
     pub fn new(sources: Vec<MetricSource>) -> Self {
+        Self::with_watchdog(sources, Watchdog::default())
+    }
+
+    pub fn with_watchdog(sources: Vec<MetricSource>, watchdog: Watchdog) -> Self {
         Self {
             sources,
+            watchdog,
+            cross_check: None,
+            event_buffer: DEFAULT_EVENT_BUFFER,
+            live_sender: None,
             senders: Vec::new(),
             handles: Vec::new(),
         }
     }
 
+    /// Compare the metrics of the sources with the first one on join, only its metrics are
+    /// kept when several sources report the same name. The second source is the other RAPL
+    /// backend, only read for the comparison: its time series, samples, counter wraps and raw
+    /// counters are not merged, unless the first source failed.
+    pub fn set_cross_check(&mut self, cross_check: CrossCheck) {
+        self.cross_check = Some(cross_check);
+    }
+
+    /// Number of events buffered per worker, for the workers started after this call.
+    pub fn set_event_buffer(&mut self, event_buffer: usize) {
+        self.event_buffer = event_buffer.max(1);
+    }
+
+    /// Number of metrics sources.
+    pub fn sources_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Sensors of all the sources, in the order of the sources.
+    pub fn sensors(&self) -> Result<Vec<Sensor>> {
+        let mut sensors = Vec::new();
+        for source in &self.sources {
+            sensors.extend(source.get_sensors()?);
+        }
+        Ok(sensors)
+    }
+
+    /// Receive the metrics of each phase as soon as a source completes it, for the workers
+    /// started after this call.
+    pub fn live_phases(&mut self) -> UnboundedReceiver<Metrics> {
+        let (tx, rx) = unbounded_channel();
+        self.live_sender = Some(tx);
+        rx
+    }
+
     /// Start the metrics sources worker threads.
     pub async fn start_workers(&mut self) {
         let sources = self.sources.clone();
         let mut senders = Vec::new();
         let mut handles = Vec::new();
+        let watchdog = self.watchdog;
 
         for source in sources {
-            let (tx, rx) = channel(4);
-            senders.push(tx.clone());
+            let live = self.live_sender.clone();
+            let (tx, rx) = channel(self.event_buffer);
+            let name = source.get_name();
+            senders.push((name, tx));
 
             let handle = tokio::spawn(async move {
                 let poll_interval = source.get_polling_interval();
@@ -99,22 +342,38 @@ impl SourceManager {
                 info!("Worker started for source {:?}", source.get_name());
 
                 match poll_interval {
-                    Some(interval) => run_worker_with_polling(source, rx, interval).await,
-                    None => run_worker_event_only(source, rx).await,
+                    Some(interval) => {
+                        run_worker_with_polling(source, rx, interval, watchdog, live).await
+                    }
+                    None => run_worker_event_only(source, rx, live).await,
                 }
             });
 
-            handles.push(handle);
+            handles.push((name, handle));
         }
 
         self.handles = handles;
         self.senders = senders;
     }
 
-    /// Send an event to each metrics source.
+    /// Send an event to each metrics source, waiting for the workers whose buffer is full.
     pub async fn send_event(&self, event: SourceEvent) -> Result<()> {
-        for sender in &self.senders {
-            sender.send(event).await?;
+        for (name, sender) in &self.senders {
+            let sent = match sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(event)) => {
+                    debug!("Event buffer of the {} worker full, waiting for it", name);
+                    sender.send(event).await.is_ok()
+                }
+                Err(TrySendError::Closed(_)) => false,
+            };
+            // A closed channel means the worker already failed, it is reported on join
+            if !sent {
+                warn!(
+                    "Cannot send {:?} event, the {} worker already stopped",
+                    event, name
+                );
+            }
         }
         Ok(())
     }
@@ -134,7 +393,13 @@ impl SourceManager {
         self.send_event(SourceEvent::Phase).await
     }
 
-    /// Pause the polling of a metrics source if enabled.
+    /// Initialize a new phase aligned on the nearest measure of each metrics source.
+    pub async fn phase_at(&self, timestamp_us: u128) -> Result<()> {
+        self.send_event(SourceEvent::PhaseAt(timestamp_us)).await
+    }
+
+    /// Pause the polling of a metrics source if enabled, the energy consumed until the next
+    /// start is not counted.
     pub async fn pause(&self) -> Result<()> {
         self.send_event(SourceEvent::Pause).await
     }
@@ -151,13 +416,55 @@ impl SourceManager {
 
         let handles = std::mem::take(&mut self.handles);
         let mut all_phases = Vec::new();
+        // Result of the second backend of a cross-check, compared with the first source
+        let mut compared = None;
+        let mut failed_sources = Vec::new();
+
+        for (idx, (name, handle)) in handles.into_iter().enumerate() {
+            let error = match handle.await {
+                Ok(Ok(phases)) => {
+                    if self.cross_check.is_some() && idx == 1 && all_phases.len() == 1 {
+                        compared = Some(phases);
+                    } else {
+                        all_phases.push(phases);
+                    }
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    error!("Worker {} returned error: {:?}", name, e);
+                    // A strict watchdog or fully trimmed samples abort the whole measurement
+                    if let Some(
+                        JouleProfilerError::CountersStalled(..)
+                        | JouleProfilerError::AllSamplesTrimmed(..),
+                    ) = e.downcast_ref()
+                    {
+                        return Err(e);
+                    }
+                    e.to_string()
+                }
+                Err(_) => {
+                    error!("Worker {} panicked", name);
+                    "worker panicked".to_string()
+                }
+            };
+            failed_sources.push(FailedSource {
+                name: name.to_string(),
+                error,
+            });
+        }
 
-        for handle in handles {
-            match handle.await {
-                Ok(Ok(phases)) => all_phases.push(phases),
-                Ok(Err(e)) => error!("Worker returned error: {:?}", e),
-                Err(_) => error!("Worker panicked"),
-            }
+        // Nothing to merge, the counts below would be averaged over zero sources
+        if all_phases.is_empty() {
+            let errors: Vec<String> = failed_sources
+                .iter()
+                .map(|source| format!("{} ({})", source.name, source.error))
+                .collect();
+            let reason = if errors.is_empty() {
+                "no source configured".to_string()
+            } else {
+                errors.join(", ")
+            };
+            return Err(JouleProfilerError::NoSourceSucceeded(reason).into());
         }
 
         info!("All workers joined. Merging phases");
@@ -171,19 +478,85 @@ impl SourceManager {
 
         let mut measure_count = 0;
         let mut measure_delta = 0;
+        let max_read_spread_us = all_phases
+            .iter()
+            .map(|source_result| source_result.max_read_spread_us)
+            .max()
+            .unwrap_or(0);
 
         for i in 0..max_phases {
             let mut phase_metrics = Vec::new();
             for source_result in &all_phases {
                 measure_count += source_result.count;
                 measure_delta += source_result.measure_delta;
-                if let Some(measures) = source_result.measures.get(i) {
-                    phase_metrics.extend(measures.clone());
-                }
+                let Some(measures) = source_result.measures.get(i) else {
+                    continue;
+                };
+                phase_metrics.extend(measures.clone());
+            }
+            if let (Some(cross_check), Some(compared)) = (&self.cross_check, &compared)
+                && let Some(measures) = compared.measures.get(i)
+                && !phase_metrics.is_empty()
+            {
+                check_sources_agree(cross_check, &phase_metrics, measures)?;
             }
             merged.push(phase_metrics);
         }
 
+        let mut counter_wraps = vec![0; max_phases];
+        for source_result in &all_phases {
+            for (total, wraps) in counter_wraps.iter_mut().zip(&source_result.counter_wraps) {
+                *total += wraps;
+            }
+        }
+
+        let mut bins: BTreeMap<u64, Metrics> = BTreeMap::new();
+        for source_result in &mut all_phases {
+            for bin in std::mem::take(&mut source_result.time_series) {
+                bins.entry(bin.index).or_default().extend(bin.metrics);
+            }
+        }
+        let time_series = bins
+            .into_iter()
+            .map(|(index, metrics)| TimeBin { index, metrics })
+            .collect();
+        let mut samples: Vec<PowerSample> = all_phases
+            .iter_mut()
+            .flat_map(|source_result| std::mem::take(&mut source_result.samples))
+            .collect();
+        samples.sort_by_key(|sample| sample.elapsed_us);
+        let raw_counters = all_phases
+            .iter_mut()
+            .flat_map(|source_result| std::mem::take(&mut source_result.raw_counters))
+            .collect();
+        let raw_readings = all_phases
+            .iter_mut()
+            .flat_map(|source_result| std::mem::take(&mut source_result.raw_readings))
+            .collect();
+        let polling = all_phases
+            .iter_mut()
+            .flat_map(|source_result| std::mem::take(&mut source_result.polling))
+            .collect();
+
+        let mut power_range = BTreeMap::new();
+        for source_result in &mut all_phases {
+            for (name, range) in std::mem::take(&mut source_result.power_range) {
+                power_range.entry(name).or_insert(range);
+            }
+        }
+
+        let mut phase_power_ranges = vec![BTreeMap::new(); max_phases];
+        for source_result in &mut all_phases {
+            for (merged, ranges) in phase_power_ranges
+                .iter_mut()
+                .zip(std::mem::take(&mut source_result.phase_power_ranges))
+            {
+                for (name, range) in ranges {
+                    merged.entry(name).or_insert(range);
+                }
+            }
+        }
+
         let nb_sources = all_phases.len();
         measure_count /= nb_sources as u64;
         measure_delta /= nb_sources as u128;
@@ -194,11 +567,44 @@ impl SourceManager {
             measures: merged,
             count: measure_count,
             measure_delta,
+            max_read_spread_us,
+            failed_sources,
+            time_series,
+            samples,
+            raw_counters,
+            polling,
+            power_range,
+            phase_power_ranges,
+            raw_readings,
+            counter_wraps,
         })
     }
 }
 
-#[derive(Serialize)]
+/// Report the metrics diverging from the reference source, failing in strict mode.
+fn check_sources_agree(
+    cross_check: &CrossCheck,
+    reference: &[Metric],
+    other: &[Metric],
+) -> Result<()> {
+    for divergence in diverging_metrics(reference, other, cross_check.tolerance_pct) {
+        let error = JouleProfilerError::SourcesDiverge(
+            divergence.name,
+            divergence.reference,
+            divergence.value,
+            divergence.deviation_pct,
+        );
+        if cross_check.strict {
+            error!("{}", error);
+            return Err(error.into());
+        }
+        warn!("{}", error);
+    }
+    Ok(())
+}
+
+/// A metric a source measures, as listed by `list-sensors`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Sensor {
     pub name: String,
     pub unit: String,
@@ -209,16 +615,42 @@ pub struct Sensor {
 async fn run_worker_event_only<S: MetricReader>(
     mut source: S,
     mut rx: Receiver<SourceEvent>,
+    live: Option<UnboundedSender<Metrics>>,
 ) -> Result<SourceResult> {
+    let mut paused = false;
     loop {
         match rx.recv().await {
             Some(SourceEvent::Stop) => return source.retrieve(),
-            Some(event) => handle_event_no_polling(&mut source, event),
-            None => {}
+            Some(SourceEvent::Pause) => paused = true,
+            Some(SourceEvent::Start) if paused => {
+                paused = false;
+                source.discard_interval();
+            }
+            Some(event) => {
+                handle_event_no_polling(&mut source, event);
+                send_live_phase(&source, event, live.as_ref());
+            }
+            // The manager was dropped without stopping the worker
+            None => return source.retrieve(),
         }
     }
 }
 
+/// Send the metrics of the phase completed by the event to the live receiver, if any.
+fn send_live_phase<S: MetricReader>(
+    source: &S,
+    event: SourceEvent,
+    live: Option<&UnboundedSender<Metrics>>,
+) {
+    let (Some(live), SourceEvent::Phase | SourceEvent::PhaseAt(_)) = (live, event) else {
+        return;
+    };
+    if let Some(metrics) = source.last_phase() {
+        // The receiver is dropped once the live output is no longer needed
+        let _ = live.send(metrics);
+    }
+}
+
 /// Handle an event for a no-polling worker (only phase and measure events supported).
 fn handle_event_no_polling<S: MetricReader>(source: &mut S, event: SourceEvent) {
     match event {
@@ -227,6 +659,11 @@ fn handle_event_no_polling<S: MetricReader>(source: &mut S, event: SourceEvent)
                 error!("Phase error: {:?}", e);
             }
         }
+        SourceEvent::PhaseAt(timestamp_us) => {
+            if let Err(e) = source.phase_at(timestamp_us) {
+                error!("Phase error: {:?}", e);
+            }
+        }
         SourceEvent::Measure => {
             if let Err(e) = source.measure() {
                 error!("Measure error: {:?}", e);
@@ -240,8 +677,11 @@ async fn run_worker_with_polling<S: MetricReader>(
     mut source: S,
     mut rx: Receiver<SourceEvent>,
     polling_interval: Duration,
+    watchdog: Watchdog,
+    live: Option<UnboundedSender<Metrics>>,
 ) -> Result<SourceResult> {
     let mut polling_active = true;
+    let mut watchdog_state = WatchdogState::default();
 
     let mut reload_timer = interval(polling_interval);
     reload_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
@@ -250,8 +690,17 @@ async fn run_worker_with_polling<S: MetricReader>(
         select! {
             Some(event) = rx.recv() => {
                 match event {
-                    SourceEvent::Start => polling_active = true,
-                    SourceEvent::Pause => polling_active = false,
+                    // The energy consumed while paused is not counted
+                    SourceEvent::Start if !polling_active => {
+                        polling_active = true;
+                        source.discard_interval();
+                    }
+                    SourceEvent::Start => {}
+                    SourceEvent::Pause if polling_active => {
+                        source.measure()?;
+                        polling_active = false;
+                    }
+                    SourceEvent::Pause => {}
                     SourceEvent::Stop => return source.retrieve(),
                     SourceEvent::Measure => {
                         source.measure()?;
@@ -259,13 +708,250 @@ async fn run_worker_with_polling<S: MetricReader>(
                     SourceEvent::Phase => {
                         source.phase()?;
                     },
+                    SourceEvent::PhaseAt(timestamp_us) => {
+                        source.phase_at(timestamp_us)?;
+                    },
                 }
+                send_live_phase(&source, event, live.as_ref());
             }
             _ = reload_timer.tick() => {
                 if polling_active {
                     source.measure()?;
+                    watchdog_state.observe(&watchdog, source.get_name(), source.advanced())?;
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::rapl::domain::RaplDomain;
+    use std::{
+        fs::{rename, write},
+        path::Path,
+    };
+    use tempfile::tempdir;
+
+    fn rapl_source(path: std::path::PathBuf, polling_rate_s: Option<f64>) -> MetricSource {
+        let domain = RaplDomain {
+            name: "package".to_string(),
+            socket: 0,
+            path,
+            max_energy_uj: u32::MAX as u64,
+        };
+        MetricSource::Rapl(Rapl::new(vec![domain], polling_rate_s))
+    }
+
+    #[test]
+    fn watchdog_fires_after_consecutive_stalled_intervals() {
+        let watchdog = Watchdog {
+            max_stalled_intervals: 2,
+            strict: true,
+        };
+        let mut state = WatchdogState::default();
+
+        assert!(state.observe(&watchdog, "Powercap", Some(false)).is_ok());
+        assert!(state.observe(&watchdog, "Powercap", Some(true)).is_ok());
+        assert!(state.observe(&watchdog, "Powercap", Some(false)).is_ok());
+        assert!(state.observe(&watchdog, "Powercap", None).is_ok());
+        assert!(state.observe(&watchdog, "Powercap", Some(false)).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn strict_watchdog_aborts_on_frozen_counters() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+        write(&energy_file, "100").unwrap();
+
+        let watchdog = Watchdog {
+            max_stalled_intervals: 3,
+            strict: true,
+        };
+        let mut manager =
+            SourceManager::with_watchdog(vec![rapl_source(energy_file, Some(0.001))], watchdog);
+        manager.start_workers().await;
+        manager.start().await.unwrap();
+        manager.measure().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let Err(err) = manager.join().await else {
+            panic!("the watchdog did not abort the measurement");
+        };
+        assert!(err.to_string().contains("stopped advancing"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cross_check_fails_when_sources_diverge() {
+        let dir = tempdir().unwrap();
+        let powercap = dir.path().join("powercap_energy_uj");
+        let msr = dir.path().join("msr_energy_uj");
+
+        let measure = |strict| {
+            let (powercap, msr) = (powercap.clone(), msr.clone());
+            async move {
+                write(&powercap, "100").unwrap();
+                write(&msr, "100").unwrap();
+                let sources = vec![
+                    rapl_source(powercap.clone(), None),
+                    rapl_source(msr.clone(), None),
+                ];
+                let mut manager = SourceManager::new(sources);
+                manager.set_cross_check(CrossCheck {
+                    tolerance_pct: 5.0,
+                    strict,
+                });
+                manager.start_workers().await;
+                manager.start().await.unwrap();
+                manager.measure().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                // 1000 µJ against 1400 µJ, 40 % apart
+                write(&powercap, "1100").unwrap();
+                write(&msr, "1500").unwrap();
+                manager.measure().await.unwrap();
+                manager.join().await
+            }
+        };
+
+        let Err(err) = measure(true).await else {
+            panic!("the diverging sources were accepted");
+        };
+        assert!(
+            err.to_string()
+                .contains("PACKAGE_0 differs between the sources")
+        );
+
+        let result = measure(false).await.unwrap();
+        assert_eq!(result.measures[0].len(), 1);
+        assert_eq!(result.measures[0][0].value, 1_000);
+        // The other backend is only compared
+        let powercap_key = powercap.to_string_lossy().to_string();
+        assert_eq!(
+            result.raw_counters.keys().collect::<Vec<_>>(),
+            [&powercap_key]
+        );
+        assert_eq!(
+            result.raw_readings.keys().collect::<Vec<_>>(),
+            [&powercap_key]
+        );
+    }
+
+    /// Replace the counter in one step, the polling worker could read a truncated file.
+    fn replace_counter(path: &Path, value: &str) {
+        let tmp = path.with_extension("tmp");
+        write(&tmp, value).unwrap();
+        rename(tmp, path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn energy_consumed_while_paused_is_not_counted() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+        write(&energy_file, "100").unwrap();
+
+        let mut manager = SourceManager::new(vec![rapl_source(energy_file.clone(), Some(0.001))]);
+        manager.start_workers().await;
+        manager.pause().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Energy of the start delay
+        replace_counter(&energy_file, "600");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        manager.start().await.unwrap();
+        manager.measure().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        replace_counter(&energy_file, "700");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        manager.measure().await.unwrap();
+
+        let result = manager.join().await.unwrap();
+        assert_eq!(result.measures[0][0].value, 100);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rapid_phases_are_all_delivered_in_order() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+        write(&energy_file, "100").unwrap();
+
+        let mut manager = SourceManager::new(vec![rapl_source(energy_file, None)]);
+        manager.set_event_buffer(2);
+        let mut live = manager.live_phases();
+        manager.start_workers().await;
+        manager.measure().await.unwrap();
+        for _ in 0..500 {
+            manager.phase().await.unwrap();
+        }
+
+        // The stop is sent last, a phase handled after it would be missing
+        let result = manager.join().await.unwrap();
+        assert_eq!(result.measures.len(), 500);
+        let mut live_phases = 0;
+        while live.try_recv().is_ok() {
+            live_phases += 1;
+        }
+        assert_eq!(live_phases, 500);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn join_reports_failed_source() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+        write(&energy_file, "100").unwrap();
+
+        let working = rapl_source(energy_file, None);
+        let failing = rapl_source(dir.path().join("missing"), Some(0.001));
+
+        let mut manager = SourceManager::new(vec![working, failing]);
+        manager.start_workers().await;
+        manager.start().await.unwrap();
+        manager.measure().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        manager.measure().await.unwrap();
+
+        let result = manager.join().await.unwrap();
+
+        assert_eq!(result.failed_sources.len(), 1);
+        assert_eq!(result.failed_sources[0].name, "Powercap");
+        assert!(!result.failed_sources[0].error.is_empty());
+        assert_eq!(result.measures.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn join_fails_when_every_source_failed() {
+        let dir = tempdir().unwrap();
+        let failing = rapl_source(dir.path().join("missing"), Some(0.001));
+
+        let mut manager = SourceManager::new(vec![failing]);
+        manager.start_workers().await;
+        manager.start().await.unwrap();
+        manager.measure().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        manager.measure().await.unwrap();
+
+        let Err(err) = manager.join().await else {
+            panic!("the measurement should fail without a successful source");
+        };
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(JouleProfilerError::NoSourceSucceeded(reason)) if reason.starts_with("Powercap (")
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn join_without_sources_is_an_error() {
+        let mut manager = SourceManager::new(Vec::new());
+        manager.start_workers().await;
+
+        let Err(err) = manager.join().await else {
+            panic!("the measurement should fail without a successful source");
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "No metric source completed the measurement: no source configured"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use log::{debug, info, trace};
+use tokio::time::Instant;
+
+use crate::source::{
+    Metric, MetricReader, MetricSource, Metrics, PollingStats, Sensor, SourceResult,
+};
+
+/// Name of the source reported in the metrics.
+const SOURCE_NAME: &str = "nvml";
+
+/// Unit of the GPU energy counters.
+const ENERGY_UNIT: &str = "mJ";
+
+/// Reads the energy consumed by a GPU since the driver was loaded, in millijoules.
+type EnergyReader = Arc<dyn Fn(u32) -> Result<u64> + Send + Sync>;
+
+/// Initialize the NVML library and measure the energy of every GPU it reports, requires
+/// the `nvml` feature.
+pub fn init_nvml() -> Result<MetricSource> {
+    #[cfg(feature = "nvml")]
+    {
+        use crate::error::JouleProfilerError;
+
+        let nvml = nvml_wrapper::Nvml::init()
+            .map_err(|e| JouleProfilerError::NvmlError(format!("initialization: {}", e)))?;
+        let count = nvml
+            .device_count()
+            .map_err(|e| JouleProfilerError::NvmlError(format!("device count: {}", e)))?;
+        info!("Found {} NVML device(s)", count);
+
+        let nvml = Arc::new(nvml);
+        let reader: EnergyReader = Arc::new(move |index| {
+            let energy = nvml
+                .device_by_index(index)
+                .and_then(|device| device.total_energy_consumption())
+                .map_err(|e| JouleProfilerError::NvmlError(format!("GPU {}: {}", index, e)))?;
+            Ok(energy)
+        });
+
+        Ok(MetricSource::Nvml(Nvml::new((0..count).collect(), reader)))
+    }
+
+    #[cfg(not(feature = "nvml"))]
+    anyhow::bail!("GPU measurement requires joule-profiler built with the `nvml` feature")
+}
+
+/// NVIDIA GPUs energy source, accumulating the deltas of the total energy counter of
+/// each device.
+#[derive(Clone)]
+pub struct Nvml {
+    devices: Vec<u32>,
+    reader: EnergyReader,
+    measures: Vec<HashMap<String, u64>>,
+    measure_counters: HashMap<String, u64>,
+
+    /// Total energy of each device read with the last snapshot
+    last_energy: Option<Vec<u64>>,
+
+    /// Number of snapshots taken
+    count: u64,
+
+    /// Monotonic timestamp of the first and last snapshots
+    first_instant: Option<Instant>,
+    last_instant: Option<Instant>,
+}
+
+impl fmt::Debug for Nvml {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nvml")
+            .field("devices", &self.devices)
+            .field("count", &self.count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Nvml {
+    #[cfg_attr(not(feature = "nvml"), allow(dead_code))]
+    pub fn new(devices: Vec<u32>, reader: EnergyReader) -> Self {
+        Self {
+            devices,
+            reader,
+            measures: Vec::new(),
+            measure_counters: HashMap::new(),
+            last_energy: None,
+            count: 0,
+            first_instant: None,
+            last_instant: None,
+        }
+    }
+
+    fn metric_name(index: u32) -> String {
+        format!("GPU_{}", index)
+    }
+
+    fn to_metrics(measure: &HashMap<String, u64>) -> Metrics {
+        measure
+            .iter()
+            .map(|(name, value)| Metric {
+                name: name.clone(),
+                value: *value,
+                unit: ENERGY_UNIT.to_string(),
+                source: SOURCE_NAME.to_string(),
+            })
+            .collect()
+    }
+}
+
+impl MetricReader for Nvml {
+    fn measure(&mut self) -> Result<()> {
+        trace!("Starting NVML measurement");
+
+        let energy = self
+            .devices
+            .iter()
+            .map(|index| (self.reader)(*index))
+            .collect::<Result<Vec<u64>>>()?;
+
+        if let Some(last) = &self.last_energy {
+            for ((index, new), old) in self.devices.iter().zip(&energy).zip(last) {
+                // The counter only resets when the driver is reloaded
+                let delta = new.saturating_sub(*old);
+                let counter = self
+                    .measure_counters
+                    .entry(Self::metric_name(*index))
+                    .or_insert(0);
+                *counter = counter.saturating_add(delta);
+            }
+        }
+
+        let now = Instant::now();
+        self.first_instant.get_or_insert(now);
+        self.last_instant = Some(now);
+        self.last_energy = Some(energy);
+        self.count += 1;
+        Ok(())
+    }
+
+    fn phase(&mut self) -> Result<()> {
+        self.measure()?;
+        let phase_counters = std::mem::take(&mut self.measure_counters);
+        debug!("NVML phase counters: {:?}", phase_counters);
+        self.measures.push(phase_counters);
+        Ok(())
+    }
+
+    fn retrieve(&mut self) -> Result<SourceResult> {
+        let remaining = std::mem::take(&mut self.measure_counters);
+        if !remaining.is_empty() {
+            self.measures.push(remaining);
+        }
+
+        let measures: Vec<Metrics> = self.measures.iter().map(Self::to_metrics).collect();
+
+        let total_elapsed_us = match (self.first_instant, self.last_instant) {
+            (Some(first), Some(last)) => (last - first).as_micros(),
+            _ => 0,
+        };
+        let avg_interval_us = if self.count > 1 {
+            total_elapsed_us / (self.count - 1) as u128
+        } else {
+            0
+        };
+        let polling = PollingStats {
+            interval_us: None,
+            measure_count: self.count,
+            avg_interval_us,
+            min_interval_us: avg_interval_us,
+            max_interval_us: avg_interval_us,
+            total_elapsed_us,
+        };
+
+        info!("Retrieved {} NVML phases", measures.len());
+        Ok(SourceResult {
+            measures,
+            count: self.count,
+            measure_delta: avg_interval_us,
+            max_read_spread_us: 0,
+            failed_sources: Vec::new(),
+            time_series: Vec::new(),
+            samples: Vec::new(),
+            raw_counters: BTreeMap::new(),
+            raw_readings: BTreeMap::new(),
+            counter_wraps: Vec::new(),
+            phase_power_ranges: Vec::new(),
+            polling: BTreeMap::from([(SOURCE_NAME.to_string(), polling)]),
+            power_range: BTreeMap::new(),
+        })
+    }
+
+    fn get_sensors(&self) -> Result<Vec<Sensor>> {
+        Ok(self
+            .devices
+            .iter()
+            .map(|index| Sensor {
+                name: Self::metric_name(*index),
+                source: SOURCE_NAME.to_string(),
+                unit: ENERGY_UNIT.to_string(),
+            })
+            .collect())
+    }
+
+    fn get_name(&self) -> &'static str {
+        "NVML"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn gpu_energy_deltas_are_accumulated_per_device() {
+        let energy = Arc::new(Mutex::new(vec![1_000, 50]));
+        let shared = energy.clone();
+        let reader: EnergyReader =
+            Arc::new(move |index| Ok(shared.lock().unwrap()[index as usize]));
+        let mut nvml = Nvml::new(vec![0, 1], reader);
+
+        nvml.measure().unwrap();
+        *energy.lock().unwrap() = vec![1_400, 80];
+        nvml.phase().unwrap();
+        *energy.lock().unwrap() = vec![1_500, 200];
+        nvml.measure().unwrap();
+
+        let result = nvml.retrieve().unwrap();
+        let value = |phase: usize, name: &str| {
+            result.measures[phase]
+                .iter()
+                .find(|metric| metric.name == name)
+                .map(|metric| metric.value)
+        };
+        assert_eq!(result.measures.len(), 2);
+        assert_eq!(value(0, "GPU_0"), Some(400));
+        assert_eq!(value(0, "GPU_1"), Some(30));
+        assert_eq!(value(1, "GPU_0"), Some(100));
+        assert_eq!(value(1, "GPU_1"), Some(120));
+        assert_eq!(result.count, 3);
+
+        let sensors = nvml.get_sensors().unwrap();
+        assert_eq!(sensors[1].name, "GPU_1");
+        assert_eq!(sensors[1].unit, "mJ");
+        assert_eq!(sensors[1].source, "nvml");
+    }
+}
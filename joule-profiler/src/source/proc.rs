@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::{debug, trace};
+
+use crate::error::JouleProfilerError;
+
+/// CPU time consumed by one thread of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadCpuTime {
+    pub tid: u32,
+    /// Thread name (`comm`)
+    pub name: String,
+    /// User and system time in clock ticks
+    pub ticks: u64,
+}
+
+/// Read the CPU time of every thread listed in `/proc/<pid>/task`.
+pub fn read_thread_cpu_times(pid: u32) -> Result<Vec<ThreadCpuTime>> {
+    read_task_dir(&PathBuf::from(format!("/proc/{}/task", pid)))
+}
+
+fn read_task_dir(task_dir: &Path) -> Result<Vec<ThreadCpuTime>> {
+    let entries = read_dir(task_dir)
+        .map_err(|e| JouleProfilerError::ProcReadError(format!("{}: {}", task_dir.display(), e)))?;
+
+    let mut threads = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Some(tid) = entry.file_name().to_str().and_then(|tid| tid.parse().ok()) else {
+            continue;
+        };
+        // The thread may have exited since the directory was listed
+        let Ok(stat) = read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if let Some((name, ticks)) = parse_task_stat(&stat) {
+            threads.push(ThreadCpuTime { tid, name, ticks });
+        }
+    }
+
+    trace!("Read {} thread(s) in {}", threads.len(), task_dir.display());
+    Ok(threads)
+}
+
+/// Extract the name and the `utime + stime` ticks of a `/proc/<pid>/task/<tid>/stat` line.
+pub fn parse_task_stat(stat: &str) -> Option<(String, u64)> {
+    // The name is between parentheses and may itself contain spaces or parentheses
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+
+    // Fields after the name start at the 3rd one (state), utime and stime are the 14th and 15th
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+
+    Some((name, utime + stime))
+}
+
+/// Keeps the last CPU time seen for each thread of a process, including the threads that
+/// exited between two samples.
+#[derive(Debug, Default)]
+pub struct ThreadSampler {
+    threads: BTreeMap<u32, ThreadCpuTime>,
+}
+
+impl ThreadSampler {
+    /// Read the threads of the process, a failed read (process already exited) is ignored.
+    pub fn sample(&mut self, pid: u32) {
+        match read_thread_cpu_times(pid) {
+            Ok(threads) => self.update(threads),
+            Err(e) => debug!("Skipping thread sample: {:#}", e),
+        }
+    }
+
+    fn update(&mut self, threads: Vec<ThreadCpuTime>) {
+        for thread in threads {
+            match self.threads.get_mut(&thread.tid) {
+                Some(known) => known.ticks = known.ticks.max(thread.ticks),
+                None => {
+                    self.threads.insert(thread.tid, thread);
+                }
+            }
+        }
+    }
+
+    /// Threads seen during the sampling, sorted by thread id.
+    pub fn into_threads(self) -> Vec<ThreadCpuTime> {
+        self.threads.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const TASK_STAT: &str = "4242 (tokio-rt (1)) S 4200 4200 4200 0 -1 4194368 150 0 0 0 \
+                             120 30 0 0 20 0 8 0 1000 100000 500 18446744073709551615";
+
+    #[test]
+    fn parse_task_stat_sums_user_and_system_ticks() {
+        assert_eq!(
+            parse_task_stat(TASK_STAT),
+            Some(("tokio-rt (1)".to_string(), 150))
+        );
+        assert_eq!(parse_task_stat("4242 (short) S 1 2"), None);
+    }
+
+    #[test]
+    fn exited_threads_are_kept_by_the_sampler() {
+        let dir = tempdir().unwrap();
+        for (tid, ticks) in [(10, 5), (11, 7)] {
+            let task = dir.path().join(tid.to_string());
+            create_dir_all(&task).unwrap();
+            let stat = format!("{} (worker) R 1 1 1 0 -1 0 0 0 0 0 {} 0 0 0", tid, ticks);
+            write(task.join("stat"), stat).unwrap();
+        }
+
+        let mut sampler = ThreadSampler::default();
+        sampler.update(read_task_dir(dir.path()).unwrap());
+        // Thread 11 exited, thread 10 kept running
+        sampler.update(vec![ThreadCpuTime {
+            tid: 10,
+            name: "worker".to_string(),
+            ticks: 9,
+        }]);
+
+        let ticks: Vec<_> = sampler
+            .into_threads()
+            .iter()
+            .map(|thread| (thread.tid, thread.ticks))
+            .collect();
+        assert_eq!(ticks, [(10, 9), (11, 7)]);
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_dir, read_link, read_to_string},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::{debug, trace};
+
+use crate::source::{
+    Metric, MetricReader, MetricSource, Metrics, PollingStats, Sensor, SourceResult,
+    proc::parse_task_stat,
+};
+
+/// Name of the source reported in the metrics.
+const SOURCE_NAME: &str = "procfs";
+
+/// Procfs mount point the processes are read from.
+const PROC_PATH: &str = "/proc";
+
+/// Interval between two samples of the children, the usage of the last one before the
+/// program exits is lost.
+const PROCFS_POLLING_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Name of the peak resident set size metric.
+pub const PEAK_RSS_KB: &str = "PEAK_RSS_KB";
+
+/// Name of the CPU time metric.
+pub const CPU_TIME_MS: &str = "CPU_TIME_MS";
+
+/// Create a source sampling the memory and CPU time of the profiled program.
+pub fn init_procfs() -> MetricSource {
+    MetricSource::Procfs(Procfs::new(Path::new(PROC_PATH), std::process::id()))
+}
+
+/// Last usage read for a process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct ProcessUsage {
+    /// Peak resident set size (`VmHWM`) in kB
+    peak_rss_kb: u64,
+    /// User and system time of the process and of its waited-for children, in clock ticks
+    cpu_ticks: u64,
+}
+
+/// Peak memory and CPU time of the profiled program, read from procfs.
+///
+/// The program is not known when the sources start, so the children of the profiler are
+/// discovered by polling `/proc/<pid>/task/*/children`, the profiled program being its only
+/// child. A child is only sampled once it has exec'd the program, before that it is a copy of
+/// the profiler and its memory is the one of the profiler. The usage of each child is kept
+/// after it exits, the CPU time including the one of its own waited-for children (e.g. the
+/// commands of a `--shell` pipeline). A phase reports the CPU time spent during it, summed
+/// over the children, and the largest peak RSS of a child at its end.
+#[derive(Clone, Debug)]
+pub struct Procfs {
+    proc_root: PathBuf,
+    /// Pid of the profiler, whose children are sampled
+    parent_pid: u32,
+    /// Last usage of every child seen, by pid
+    children: BTreeMap<u32, ProcessUsage>,
+    /// CPU time of the children at the start of the current phase, in clock ticks
+    phase_cpu_ticks: u64,
+    measures: Vec<Metrics>,
+    /// Number of samples taken
+    count: u64,
+    /// Clock ticks per second, the unit of the CPU times in procfs
+    ticks_per_s: u64,
+}
+
+impl Procfs {
+    pub fn new(proc_root: &Path, parent_pid: u32) -> Self {
+        // SAFETY: sysconf has no memory safety preconditions
+        let ticks_per_s = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        Self {
+            proc_root: proc_root.to_path_buf(),
+            parent_pid,
+            children: BTreeMap::new(),
+            phase_cpu_ticks: 0,
+            measures: Vec::new(),
+            count: 0,
+            // The POSIX minimum, should sysconf fail
+            ticks_per_s: u64::try_from(ticks_per_s).unwrap_or(100).max(1),
+        }
+    }
+
+    /// Pids of the current children of the profiler, listed per thread of the profiler.
+    fn child_pids(&self) -> Vec<u32> {
+        let task_dir = self
+            .proc_root
+            .join(self.parent_pid.to_string())
+            .join("task");
+        let Ok(entries) = read_dir(&task_dir) else {
+            debug!("Cannot list {}", task_dir.display());
+            return Vec::new();
+        };
+
+        let mut pids: Vec<u32> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| read_to_string(entry.path().join("children")).ok())
+            .flat_map(|children| {
+                children
+                    .split_whitespace()
+                    .filter_map(|pid| pid.parse().ok())
+                    .collect::<Vec<u32>>()
+            })
+            .collect();
+        pids.sort_unstable();
+        pids.dedup();
+        pids
+    }
+
+    /// Executable of a process, `None` when it cannot be read (e.g. for a zombie).
+    fn exe(&self, pid: u32) -> Option<PathBuf> {
+        read_link(self.proc_root.join(pid.to_string()).join("exe")).ok()
+    }
+
+    /// Read the usage of a process, `None` if it exited since it was listed.
+    fn read_usage(&self, pid: u32) -> Option<ProcessUsage> {
+        let process_dir = self.proc_root.join(pid.to_string());
+        let status = read_to_string(process_dir.join("status")).ok()?;
+        let stat = read_to_string(process_dir.join("stat")).ok()?;
+        Some(ProcessUsage {
+            peak_rss_kb: parse_peak_rss_kb(&status).unwrap_or(0),
+            cpu_ticks: parse_cpu_ticks(&stat)?,
+        })
+    }
+
+    /// CPU time summed over the children seen so far and the largest of their peak RSS, the
+    /// peaks of the children not being reached at the same time.
+    fn totals(&self) -> ProcessUsage {
+        self.children
+            .values()
+            .fold(ProcessUsage::default(), |total, usage| ProcessUsage {
+                peak_rss_kb: total.peak_rss_kb.max(usage.peak_rss_kb),
+                cpu_ticks: total.cpu_ticks + usage.cpu_ticks,
+            })
+    }
+
+    /// Metrics of the current phase, ending now.
+    fn phase_metrics(&self) -> Metrics {
+        let totals = self.totals();
+        let cpu_ticks = totals.cpu_ticks.saturating_sub(self.phase_cpu_ticks);
+        vec![
+            Metric::new(
+                CPU_TIME_MS,
+                cpu_ticks * 1000 / self.ticks_per_s,
+                "ms",
+                SOURCE_NAME,
+            ),
+            Metric::new(PEAK_RSS_KB, totals.peak_rss_kb, "kB", SOURCE_NAME),
+        ]
+    }
+}
+
+/// Extract the `VmHWM` of a `/proc/<pid>/status` file, in kB.
+fn parse_peak_rss_kb(status: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix("VmHWM:")?;
+        value.trim().trim_end_matches("kB").trim().parse().ok()
+    })
+}
+
+/// Extract the `utime + stime + cutime + cstime` ticks of a `/proc/<pid>/stat` line.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let (_, own_ticks) = parse_task_stat(stat)?;
+    // cutime and cstime are the 16th and 17th fields, after the name in parentheses
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let cutime: u64 = fields.get(13)?.parse().ok()?;
+    let cstime: u64 = fields.get(14)?.parse().ok()?;
+    Some(own_ticks + cutime + cstime)
+}
+
+impl MetricReader for Procfs {
+    fn measure(&mut self) -> Result<()> {
+        let profiler_exe = self.exe(self.parent_pid);
+        for pid in self.child_pids() {
+            if profiler_exe.is_some() && self.exe(pid) == profiler_exe {
+                trace!("Process {} has not exec'd the program yet", pid);
+                continue;
+            }
+            let Some(usage) = self.read_usage(pid) else {
+                continue;
+            };
+            trace!("Process {} usage: {:?}", pid, usage);
+            // The usage only grows, while a zombie child not yet reaped has no VmHWM
+            let known = self.children.entry(pid).or_default();
+            known.peak_rss_kb = known.peak_rss_kb.max(usage.peak_rss_kb);
+            known.cpu_ticks = known.cpu_ticks.max(usage.cpu_ticks);
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn phase(&mut self) -> Result<()> {
+        self.measure()?;
+        let metrics = self.phase_metrics();
+        debug!("Procfs phase metrics: {:?}", metrics);
+        self.measures.push(metrics);
+        self.phase_cpu_ticks = self.totals().cpu_ticks;
+        Ok(())
+    }
+
+    fn retrieve(&mut self) -> Result<SourceResult> {
+        // The last phase ends with the measurement
+        self.measures.push(self.phase_metrics());
+
+        let interval_us = PROCFS_POLLING_INTERVAL.as_micros();
+        let polling = PollingStats {
+            interval_us: Some(interval_us),
+            measure_count: self.count,
+            avg_interval_us: interval_us,
+            ..Default::default()
+        };
+
+        Ok(SourceResult {
+            measures: std::mem::take(&mut self.measures),
+            count: self.count,
+            measure_delta: interval_us,
+            max_read_spread_us: 0,
+            failed_sources: Vec::new(),
+            time_series: Vec::new(),
+            samples: Vec::new(),
+            raw_counters: BTreeMap::new(),
+            raw_readings: BTreeMap::new(),
+            counter_wraps: Vec::new(),
+            phase_power_ranges: Vec::new(),
+            polling: BTreeMap::from([(SOURCE_NAME.to_string(), polling)]),
+            power_range: BTreeMap::new(),
+        })
+    }
+
+    fn get_sensors(&self) -> Result<Vec<Sensor>> {
+        Ok([(CPU_TIME_MS, "ms"), (PEAK_RSS_KB, "kB")]
+            .iter()
+            .map(|(name, unit)| Sensor {
+                name: name.to_string(),
+                source: SOURCE_NAME.to_string(),
+                unit: unit.to_string(),
+            })
+            .collect())
+    }
+
+    fn get_polling_interval(&self) -> Option<Duration> {
+        Some(PROCFS_POLLING_INTERVAL)
+    }
+
+    fn get_name(&self) -> &'static str {
+        "procfs"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, remove_file, write},
+        os::unix::fs::symlink,
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const PARENT_PID: u32 = 100;
+
+    /// Set the executable of a process.
+    fn write_exe(root: &Path, pid: u32, exe: &str) {
+        let dir = root.join(pid.to_string());
+        create_dir_all(&dir).unwrap();
+        let _ = remove_file(dir.join("exe"));
+        symlink(exe, dir.join("exe")).unwrap();
+    }
+
+    /// Write the procfs files of a child of the profiler.
+    fn write_child(root: &Path, pid: u32, peak_rss_kb: u64, ticks: [u64; 4]) {
+        let dir = root.join(pid.to_string());
+        create_dir_all(&dir).unwrap();
+        if !dir.join("exe").is_symlink() {
+            write_exe(root, pid, "/usr/bin/bench");
+        }
+        write(
+            dir.join("status"),
+            format!(
+                "Name:\tbench\nVmPeak:\t  20000 kB\nVmHWM:\t  {} kB\nVmRSS:\t  900 kB\n",
+                peak_rss_kb
+            ),
+        )
+        .unwrap();
+        let [utime, stime, cutime, cstime] = ticks;
+        write(
+            dir.join("stat"),
+            format!(
+                "{} (my bench) R {} 1 1 0 -1 0 0 0 0 0 {} {} {} {} 20 0 1 0",
+                pid, PARENT_PID, utime, stime, cutime, cstime
+            ),
+        )
+        .unwrap();
+    }
+
+    fn write_children(root: &Path, pids: &[u32]) {
+        let task = root.join(format!("{}/task/{}", PARENT_PID, PARENT_PID));
+        create_dir_all(&task).unwrap();
+        let pids: Vec<String> = pids.iter().map(|pid| pid.to_string()).collect();
+        write(task.join("children"), pids.join(" ")).unwrap();
+    }
+
+    fn procfs(root: &Path) -> Procfs {
+        Procfs {
+            ticks_per_s: 100,
+            ..Procfs::new(root, PARENT_PID)
+        }
+    }
+
+    fn value(metrics: &[Metric], name: &str) -> u64 {
+        metrics
+            .iter()
+            .find(|metric| metric.name == name)
+            .unwrap()
+            .value
+    }
+
+    #[test]
+    fn status_and_stat_are_parsed() {
+        assert_eq!(
+            parse_peak_rss_kb("Name:\tx\nVmHWM:\t  5120 kB\n"),
+            Some(5120)
+        );
+        assert_eq!(parse_peak_rss_kb("Name:\tkthread\n"), None);
+        assert_eq!(
+            parse_cpu_ticks("7 (a (b)) S 1 1 1 0 -1 0 0 0 0 0 10 5 3 2 20 0"),
+            Some(20)
+        );
+        assert_eq!(parse_cpu_ticks("7 (a) S 1 1 1 0 -1 0 0 0 0 0 10 5"), None);
+    }
+
+    #[test]
+    fn usage_of_the_children_is_kept_after_they_exit() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let mut procfs = procfs(root);
+
+        // Nothing is spawned yet
+        procfs.measure().unwrap();
+        write_child(root, 200, 4_000, [10, 5, 0, 0]);
+        write_children(root, &[200]);
+        procfs.measure().unwrap();
+        write_child(root, 200, 6_000, [30, 10, 2, 0]);
+        procfs.phase().unwrap();
+        write_child(root, 200, 6_500, [50, 20, 2, 0]);
+        procfs.measure().unwrap();
+        // The child exited, a zombie has no memory
+        write_child(root, 200, 0, [50, 20, 2, 0]);
+        procfs.measure().unwrap();
+        // Then it was reaped
+        write_children(root, &[]);
+        procfs.measure().unwrap();
+
+        let result = procfs.retrieve().unwrap();
+        assert_eq!(result.measures.len(), 2);
+        assert_eq!(value(&result.measures[0], CPU_TIME_MS), 420);
+        assert_eq!(value(&result.measures[0], PEAK_RSS_KB), 6_000);
+        assert_eq!(value(&result.measures[1], CPU_TIME_MS), 300);
+        assert_eq!(value(&result.measures[1], PEAK_RSS_KB), 6_500);
+        assert_eq!(result.measures[1][0].unit, "ms");
+        assert_eq!(result.measures[1][1].unit, "kB");
+        assert_eq!(result.count, 6);
+    }
+
+    #[test]
+    fn children_are_read_from_every_thread_of_the_profiler() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write_children(root, &[200]);
+        let other_thread = root.join(format!("{}/task/101", PARENT_PID));
+        create_dir_all(&other_thread).unwrap();
+        write(other_thread.join("children"), "201 200\n").unwrap();
+
+        assert_eq!(procfs(root).child_pids(), [200, 201]);
+    }
+
+    #[test]
+    fn children_are_only_sampled_once_they_have_exec_d() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write_exe(root, PARENT_PID, "/usr/bin/joule-profiler");
+        let mut procfs = procfs(root);
+
+        // Forked, still the profiler
+        write_child(root, 200, 50_000, [5, 0, 0, 0]);
+        write_exe(root, 200, "/usr/bin/joule-profiler");
+        write_children(root, &[200]);
+        procfs.measure().unwrap();
+        write_exe(root, 200, "/usr/bin/bench");
+        write_child(root, 200, 1_000, [10, 0, 0, 0]);
+        procfs.measure().unwrap();
+
+        let result = procfs.retrieve().unwrap();
+        assert_eq!(value(&result.measures[0], PEAK_RSS_KB), 1_000);
+        assert_eq!(value(&result.measures[0], CPU_TIME_MS), 100);
+    }
+
+    #[test]
+    fn peak_rss_is_the_largest_of_the_children() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let mut procfs = procfs(root);
+
+        write_child(root, 200, 4_000, [10, 0, 0, 0]);
+        write_child(root, 201, 3_000, [20, 0, 0, 0]);
+        write_children(root, &[200, 201]);
+        procfs.measure().unwrap();
+
+        let result = procfs.retrieve().unwrap();
+        assert_eq!(value(&result.measures[0], PEAK_RSS_KB), 4_000);
+        assert_eq!(value(&result.measures[0], CPU_TIME_MS), 300);
+    }
+}
@@ -1,9 +1,10 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
 use crate::error::JouleProfilerError;
+use crate::util::glob::glob_matches;
 use anyhow::Result;
 use log::{debug, error, info, trace, warn};
 
@@ -20,6 +21,23 @@ pub struct RaplDomain {
     pub max_energy_uj: u64,
 }
 
+/// Options of the powercap domains discovery.
+#[derive(Debug, Clone, Default)]
+pub struct DomainOptions {
+    /// Range used for the domains lacking `max_energy_range_uj`, excluded if `None`
+    pub assume_max_energy_uj: Option<u64>,
+    /// Keep only the first of the domains exposed under several paths
+    pub dedup: bool,
+    /// File of `name=normalized` rules renaming the domains read from their `name` file
+    pub domain_map: Option<String>,
+    /// Names (or name prefixes) of the domains to measure, all the domains if `None`
+    pub domains: Option<Vec<String>>,
+    /// Globs of the domain names to keep, all the domains if empty
+    pub include: Vec<String>,
+    /// Globs of the domain names to leave out, winning over `include`
+    pub exclude: Vec<String>,
+}
+
 /// Checks if the operating system is Linux.
 pub fn check_os() -> Result<()> {
     #[cfg(target_os = "linux")]
@@ -38,13 +56,16 @@ pub fn check_os() -> Result<()> {
 pub fn get_domains(
     base_path: Option<&str>,
     spec: Option<&HashSet<u32>>,
+    options: &DomainOptions,
 ) -> Result<Vec<RaplDomain>> {
     check_os()?;
 
     let base = rapl_base_path(base_path);
-    check_rapl(&base)?;
+    for path in split_base_paths(&base) {
+        check_rapl(path)?;
+    }
 
-    let domains = discover_domains(&base)?;
+    let domains = discover_domains(&base, options)?;
     let sockets = parse_or_all_sockets(&domains, spec);
 
     let filtered: Vec<RaplDomain> = domains
@@ -52,7 +73,90 @@ pub fn get_domains(
         .filter(|d| sockets.contains(&d.socket))
         .collect();
 
-    Ok(filtered)
+    let domains = match &options.domains {
+        Some(requested) => select_domains(filtered, requested)?,
+        None => filtered,
+    };
+    probe_energy_read(&domains)?;
+    Ok(domains)
+}
+
+/// Read the energy counter of the first domain, so that a missing read permission is
+/// reported before the measurement with the file to fix. The discovery only needs the
+/// `name` and `max_energy_range_uj` files, readable by all users unlike `energy_uj`.
+fn probe_energy_read(domains: &[RaplDomain]) -> Result<()> {
+    let Some(domain) = domains.first() else {
+        return Ok(());
+    };
+    match fs::read_to_string(&domain.path) {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            error!("Cannot read {}: {}", domain.path.display(), e);
+            Err(
+                JouleProfilerError::EnergyCounterNotReadable(domain.path.display().to_string())
+                    .into(),
+            )
+        }
+        // The other errors are reported by the reads of the measurement
+        _ => Ok(()),
+    }
+}
+
+/// Keep the domains whose name starts with one of the requested names (case insensitive),
+/// every requested name must match at least one domain.
+pub fn select_domains(domains: Vec<RaplDomain>, requested: &[String]) -> Result<Vec<RaplDomain>> {
+    let requested: Vec<String> = requested.iter().map(|name| name.to_lowercase()).collect();
+    let matches = |domain: &RaplDomain, name: &str| domain.name.to_lowercase().starts_with(name);
+
+    if let Some(missing) = requested
+        .iter()
+        .find(|name| !domains.iter().any(|domain| matches(domain, name)))
+    {
+        let mut available: Vec<&str> = domains.iter().map(|domain| domain.name.as_str()).collect();
+        available.sort();
+        available.dedup();
+        return Err(
+            JouleProfilerError::UnknownDomain(missing.clone(), available.join(", ")).into(),
+        );
+    }
+
+    Ok(domains
+        .into_iter()
+        .filter(|domain| requested.iter().any(|name| matches(domain, name)))
+        .collect())
+}
+
+/// Keep the domains whose uppercased name matches one of the include globs (all of them
+/// without include globs) and none of the exclude globs, case insensitively. Filtering out
+/// every domain is an error.
+pub fn filter_domains(
+    domains: Vec<RaplDomain>,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<RaplDomain>> {
+    if include.is_empty() && exclude.is_empty() {
+        return Ok(domains);
+    }
+
+    let matches = |globs: &[String], domain: &RaplDomain| {
+        let name = domain.name.to_uppercase();
+        globs
+            .iter()
+            .any(|glob| glob_matches(&glob.to_uppercase(), &name))
+    };
+
+    let mut available: Vec<String> = domains.iter().map(|domain| domain.name.clone()).collect();
+    let kept: Vec<RaplDomain> = domains
+        .into_iter()
+        .filter(|domain| include.is_empty() || matches(include, domain))
+        .filter(|domain| !matches(exclude, domain))
+        .collect();
+
+    if kept.is_empty() {
+        available.sort();
+        available.dedup();
+        return Err(JouleProfilerError::NoDomainsLeftBySensorFilters(available.join(", ")).into());
+    }
+    Ok(kept)
 }
 
 /// Checks if the RAPL interface is available at the given base path.
@@ -68,6 +172,13 @@ pub fn check_rapl(base: &str) -> Result<()> {
 
     if !path.is_dir() {
         error!("RAPL path is not a directory: {}", base);
+        if let Some(suggested) = zone_base_directory(path) {
+            return Err(JouleProfilerError::RaplPathIsZoneFile(
+                base.into(),
+                suggested.display().to_string(),
+            )
+            .into());
+        }
         return Err(JouleProfilerError::InvalidRaplPath(base.into()).into());
     }
 
@@ -83,11 +194,95 @@ pub fn check_rapl(base: &str) -> Result<()> {
     Ok(())
 }
 
-/// Discovers all available RAPL domains at the given base path.
-pub fn discover_domains(base: &str) -> Result<Vec<RaplDomain>> {
-    info!("Discovering RAPL domains in {}", base);
+/// Base directory holding the powercap zone of a file such as `intel-rapl:0/energy_uj`,
+/// `None` if the file is not inside a zone.
+fn zone_base_directory(file: &Path) -> Option<&Path> {
+    let is_zone = |dir: &Path| {
+        dir.file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("intel-rapl:"))
+    };
+
+    let mut zone = file.parent().filter(|dir| is_zone(dir))?;
+    // Subzones such as intel-rapl:0/intel-rapl:0:0 are nested in their package zone
+    while let Some(parent) = zone.parent().filter(|dir| is_zone(dir)) {
+        zone = parent;
+    }
+    zone.parent()
+}
+
+/// Discovers all available RAPL domains at the given base paths, separated by commas.
+///
+/// The domains found through the same `energy_uj` file, once its links resolved, are merged
+/// into the first one. The domains without a readable `max_energy_range_uj` use the assumed
+/// range of the options, or are excluded if it is `None`. The duplicated domains are
+/// reported, see [`find_duplicate_domains`].
+pub fn discover_domains(base: &str, options: &DomainOptions) -> Result<Vec<RaplDomain>> {
+    let assume_max_energy_uj = options.assume_max_energy_uj;
+    let domain_map = match &options.domain_map {
+        Some(path) => load_domain_map(path)?,
+        None => HashMap::new(),
+    };
 
     let mut domains = Vec::new();
+    for path in split_base_paths(base) {
+        scan_base_path(path, &mut domains, assume_max_energy_uj, &domain_map)?;
+    }
+
+    let mut energy_files = HashSet::new();
+    domains.retain(|domain| {
+        let canonical = fs::canonicalize(&domain.path).unwrap_or_else(|_| domain.path.clone());
+        let first = energy_files.insert(canonical);
+        if !first {
+            debug!(
+                "Domain {} at {} already discovered",
+                domain.name,
+                domain.path.display()
+            );
+        }
+        first
+    });
+
+    let duplicates = find_duplicate_domains(&domains);
+    for &(duplicate, original) in &duplicates {
+        warn!(
+            "Domain {} of socket {} is exposed twice ({} and {}), its energy is counted twice{}",
+            domains[duplicate].name,
+            domains[duplicate].socket,
+            domains[original].path.display(),
+            domains[duplicate].path.display(),
+            if options.dedup {
+                ", keeping the first one"
+            } else {
+                " (use --dedup-domains)"
+            }
+        );
+    }
+    if options.dedup {
+        domains = domains
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, _)| !duplicates.iter().any(|(duplicate, _)| duplicate == idx))
+            .map(|(_, domain)| domain)
+            .collect();
+    }
+
+    if domains.is_empty() {
+        warn!("No RAPL domains found");
+        return Err(JouleProfilerError::NoDomains.into());
+    }
+
+    info!("Discovered {} RAPL domains", domains.len());
+    Ok(domains)
+}
+
+/// Add the domains of the `intel-rapl:*` zones of a base path and their subzones.
+fn scan_base_path(
+    base: &str,
+    domains: &mut Vec<RaplDomain>,
+    assume_max_energy_uj: Option<u64>,
+    domain_map: &HashMap<String, String>,
+) -> Result<()> {
+    info!("Discovering RAPL domains in {}", base);
 
     let entries = fs::read_dir(base).map_err(|e| {
         error!("Failed to read RAPL base directory: {}", e);
@@ -116,38 +311,115 @@ pub fn discover_domains(base: &str) -> Result<Vec<RaplDomain>> {
             continue;
         }
 
-        add_domain_if_energy(&path, &mut domains)?;
+        add_domain_if_energy(&path, domains, assume_max_energy_uj, domain_map)?;
 
         for sub in fs::read_dir(&path)? {
             let sub = sub?;
             let sub_path = sub.path();
             if sub_path.is_dir() {
-                add_domain_if_energy(&sub_path, &mut domains)?;
+                add_domain_if_energy(&sub_path, domains, assume_max_energy_uj, domain_map)?;
             }
         }
     }
 
-    if domains.is_empty() {
-        warn!("No RAPL domains found");
-        return Err(JouleProfilerError::NoDomains.into());
+    Ok(())
+}
+
+/// Base paths of a comma-separated list, the empty entries ignored.
+pub fn split_base_paths(base: &str) -> impl Iterator<Item = &str> {
+    base.split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+}
+
+/// Find the domains with the same name, socket and current energy as a previous one, the same
+/// zone exposed under two sysfs paths, as `(duplicate, original)` indices.
+fn find_duplicate_domains(domains: &[RaplDomain]) -> Vec<(usize, usize)> {
+    let energies: Vec<Option<u64>> = domains
+        .iter()
+        .map(|domain| read_energy(domain).ok())
+        .collect();
+
+    let mut duplicates = Vec::new();
+    for (idx, domain) in domains.iter().enumerate() {
+        let Some(energy) = energies[idx] else {
+            continue;
+        };
+        let original = (0..idx).find(|&other| {
+            domains[other].name == domain.name
+                && domains[other].socket == domain.socket
+                && energies[other] == Some(energy)
+        });
+        if let Some(original) = original {
+            duplicates.push((idx, original));
+        }
     }
+    duplicates
+}
 
-    info!("Discovered {} RAPL domains", domains.len());
-    Ok(domains)
+/// Load the domain name normalization rules of a `--domain-map` file.
+pub fn load_domain_map(path: &str) -> Result<HashMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| JouleProfilerError::InvalidDomainMap(format!("{}: {}", path, e)))?;
+    parse_domain_map(&content)
+        .map_err(|e| JouleProfilerError::InvalidDomainMap(format!("{}: {}", path, e)).into())
+}
+
+/// Parse `name=normalized` lines, blank lines and `#` comments are ignored.
+fn parse_domain_map(content: &str) -> Result<HashMap<String, String>, String> {
+    let mut map = HashMap::new();
+
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (from, to) = line
+            .split_once('=')
+            .map(|(from, to)| (from.trim(), to.trim()))
+            .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+            .ok_or_else(|| format!("line {}: expected NAME=NORMALIZED, got '{}'", idx + 1, line))?;
+        map.insert(from.to_string(), to.to_string());
+    }
+
+    Ok(map)
 }
 
 /// Adds a RAPL domain to the output vector if it contains an energy_uj file.
-fn add_domain_if_energy(dir: &Path, out: &mut Vec<RaplDomain>) -> Result<()> {
+fn add_domain_if_energy(
+    dir: &Path,
+    out: &mut Vec<RaplDomain>,
+    assume_max_energy_uj: Option<u64>,
+    domain_map: &HashMap<String, String>,
+) -> Result<()> {
     let energy_path = dir.join("energy_uj");
     if !energy_path.exists() {
         trace!("No energy_uj in {:?}", dir);
         return Ok(());
     }
 
-    let name = fs::read_to_string(dir.join("name"))
-        .unwrap_or_else(|_| "unknown".to_string())
-        .trim()
-        .to_string();
+    // Without a name file, the directory (e.g. intel-rapl:0:1) names the domain
+    let name = match fs::read_to_string(dir.join("name")) {
+        Ok(name) => name.trim().to_string(),
+        Err(e) => {
+            let leaf = dir
+                .file_name()
+                .map(|leaf| leaf.to_string_lossy().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            debug!(
+                "Cannot read the name of {:?} ({}), naming it {}",
+                dir, e, leaf
+            );
+            leaf
+        }
+    };
+    let name = match domain_map.get(&name) {
+        Some(mapped) => {
+            debug!("Domain name {} mapped to {}", name, mapped);
+            mapped.clone()
+        }
+        None => name,
+    };
 
     let socket = extract_socket_number(dir)?;
 
@@ -159,7 +431,15 @@ fn add_domain_if_energy(dir: &Path, out: &mut Vec<RaplDomain>) -> Result<()> {
                 .ok()
                 .and_then(|s| s.trim().parse::<u64>().ok())
         })
-        .flatten();
+        .flatten()
+        .or_else(|| {
+            let assumed = assume_max_energy_uj?;
+            warn!(
+                "Domain {:?} missing max_energy_range_uj, assuming {} µJ",
+                dir, assumed
+            );
+            Some(assumed)
+        });
 
     if let Some(max_energy_uj) = max_energy_uj_option {
         debug!(
@@ -174,7 +454,7 @@ fn add_domain_if_energy(dir: &Path, out: &mut Vec<RaplDomain>) -> Result<()> {
             max_energy_uj,
         });
     } else {
-        warn!("Domain {:?} missing max_energy_range_uj", dir);
+        warn!("Domain {:?} missing max_energy_range_uj, excluding it", dir);
     }
 
     Ok(())
@@ -209,30 +489,56 @@ pub fn filter_sockets(spec: &HashSet<u32>, sockets: &HashSet<u32>) -> HashSet<u3
 pub fn read_energy(domain: &RaplDomain) -> Result<u64> {
     trace!("Reading energy for domain {}", domain.name);
 
-    let content = fs::read_to_string(&domain.path).map_err(|e| {
-        error!("Failed to read energy for {}: {}", domain.name, e);
-        if e.kind() == std::io::ErrorKind::PermissionDenied {
-            JouleProfilerError::InsufficientPermissions
-        } else {
-            JouleProfilerError::RaplReadError(format!("Failed to read {}: {}", domain.name, e))
-        }
+    let energy = read_energy_retrying(domain, || {
+        fs::read_to_string(&domain.path).map_err(|e| {
+            error!("Failed to read energy for {}: {}", domain.name, e);
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                JouleProfilerError::EnergyCounterNotReadable(domain.path.display().to_string())
+                    .into()
+            } else {
+                JouleProfilerError::RaplReadError(format!("Failed to read {}: {}", domain.name, e))
+                    .into()
+            }
+        })
     })?;
 
-    let energy = content.trim().parse::<u64>().map_err(|_| {
+    trace!("Energy {} = {} µJ", domain.name, energy);
+    Ok(energy)
+}
+
+/// Parses an energy counter value obtained with `read`, reading it once more on a parse failure.
+///
+/// The counter may be read while the kernel updates it and return a partial value, only a
+/// value that is still invalid on the second read is reported as corrupt.
+pub fn read_energy_retrying(
+    domain: &RaplDomain,
+    mut read: impl FnMut() -> Result<String>,
+) -> Result<u64> {
+    let content = read()?;
+    if let Ok(energy) = content.trim().parse::<u64>() {
+        return Ok(energy);
+    }
+
+    debug!(
+        "Unparsable energy value '{}' in {}, reading it again",
+        content.trim(),
+        domain.name
+    );
+
+    let content = read()?;
+    content.trim().parse::<u64>().map_err(|_| {
         error!(
             "Invalid energy value '{}' in {}",
             content.trim(),
             domain.name
         );
         JouleProfilerError::ParseEnergyError(format!(
-            "Invalid energy value '{}' in domain {}",
+            "Invalid energy value '{}' in domain {} (corrupt after retry)",
             content.trim(),
             domain.name
         ))
-    })?;
-
-    trace!("Energy {} = {} µJ", domain.name, energy);
-    Ok(energy)
+        .into()
+    })
 }
 
 /// Resolves the RAPL base path from configuration and environment.
@@ -261,6 +567,7 @@ pub fn parse_or_all_sockets(domains: &[RaplDomain], spec: Option<&HashSet<u32>>)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::source::{MetricReader, rapl::Rapl};
     use std::fs::{create_dir_all, write};
     use tempfile::tempdir;
 
@@ -287,6 +594,34 @@ mod tests {
         assert_eq!(path, "/custom/path");
     }
 
+    #[test]
+    fn zone_file_path_suggests_the_base_directory() {
+        let dir = tempdir().unwrap();
+        let subzone = dir.path().join("intel-rapl:0").join("intel-rapl:0:0");
+        create_dir_all(&subzone).unwrap();
+        let energy_file = subzone.join("energy_uj");
+        write(&energy_file, "1000").unwrap();
+
+        let err = check_rapl(&energy_file.to_string_lossy()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "RAPL path {} is a file of a powercap zone, use the base directory instead: {}",
+                energy_file.display(),
+                dir.path().display()
+            )
+        );
+
+        let other_file = dir.path().join("notes.txt");
+        write(&other_file, "").unwrap();
+        let err = check_rapl(&other_file.to_string_lossy()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(JouleProfilerError::InvalidRaplPath(_))
+        ));
+    }
+
     #[test]
     fn extract_socket_number_from_path() {
         let path = std::path::Path::new("/sys/devices/intel-rapl:2/intel-rapl:2:0");
@@ -308,7 +643,7 @@ mod tests {
 
         make_domain_dir(base, "package", 0, 100, 1_000);
 
-        let domains = discover_domains(base.to_str().unwrap()).unwrap();
+        let domains = discover_domains(base.to_str().unwrap(), &DomainOptions::default()).unwrap();
 
         assert_eq!(domains.len(), 1);
         let d = &domains[0];
@@ -318,21 +653,246 @@ mod tests {
     }
 
     #[test]
-    fn discover_domains_ignores_missing_max_energy() {
+    fn domains_of_several_base_paths_are_merged() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        make_domain_dir(first.path(), "package-0", 0, 100, 1_000);
+        make_domain_dir(second.path(), "package-1", 1, 200, 1_000);
+        // The first base path reached again through a link
+        let link = second.path().join("link");
+        std::os::unix::fs::symlink(first.path(), &link).unwrap();
+
+        let bases = format!(
+            "{},{}, {}",
+            first.path().display(),
+            second.path().display(),
+            link.display()
+        );
+        let mut domains = get_domains(Some(&bases), None, &DomainOptions::default()).unwrap();
+        domains.sort_by_key(|domain| domain.socket);
+
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[0].name, "package-0");
+        assert_eq!(domains[0].path, first.path().join("intel-rapl:0/energy_uj"));
+        assert_eq!(domains[1].name, "package-1");
+        assert_eq!(domains[1].socket, 1);
+
+        let sockets = HashSet::from([1]);
+        let domains = get_domains(Some(&bases), Some(&sockets), &DomainOptions::default());
+        assert_eq!(domains.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn domain_without_name_file_is_named_after_its_directory() {
         let dir = tempdir().unwrap();
         let base = dir.path();
 
         let domain = base.join("intel-rapl:0");
         create_dir_all(&domain).unwrap();
         write(domain.join("energy_uj"), "100").unwrap();
+        write(domain.join("max_energy_range_uj"), "1000").unwrap();
+
+        let domains = discover_domains(base.to_str().unwrap(), &DomainOptions::default()).unwrap();
+
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].name, "intel-rapl:0");
+        assert_eq!(domains[0].socket, 0);
+    }
 
-        let err = discover_domains(base.to_str().unwrap())
+    #[test]
+    fn discover_domains_ignores_missing_max_energy_without_fallback() {
+        let dir = tempdir().unwrap();
+        let base = dir.path();
+
+        let domain = base.join("intel-rapl:0");
+        create_dir_all(&domain).unwrap();
+        write(domain.join("energy_uj"), "100").unwrap();
+
+        let err = discover_domains(base.to_str().unwrap(), &DomainOptions::default())
             .unwrap_err()
             .to_string();
 
         assert!(err.contains("No RAPL domains found"));
     }
 
+    #[test]
+    fn discover_domains_assumes_max_energy_when_missing() {
+        let dir = tempdir().unwrap();
+        let base = dir.path();
+
+        let domain = base.join("intel-rapl:0");
+        create_dir_all(&domain).unwrap();
+        write(domain.join("name"), "package-0").unwrap();
+        write(domain.join("energy_uj"), "100").unwrap();
+        make_domain_dir(base, "dram", 1, 100, 1_000);
+
+        let mut domains = discover_domains(
+            base.to_str().unwrap(),
+            &DomainOptions {
+                assume_max_energy_uj: Some(u32::MAX as u64),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        domains.sort_by_key(|domain| domain.socket);
+
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[0].name, "package-0");
+        assert_eq!(domains[0].max_energy_uj, u32::MAX as u64);
+        assert_eq!(domains[1].max_energy_uj, 1_000);
+    }
+
+    #[test]
+    fn duplicated_domains_collapse_to_one() {
+        let dir = tempdir().unwrap();
+        let base = dir.path();
+
+        make_domain_dir(base, "package-0", 0, 5_000, 1_000_000);
+        // The same zone exposed again as a subzone of itself
+        let alias = base.join("intel-rapl:0").join("intel-rapl:0:9");
+        create_dir_all(&alias).unwrap();
+        write(alias.join("name"), "package-0").unwrap();
+        write(alias.join("energy_uj"), "5000").unwrap();
+        write(alias.join("max_energy_range_uj"), "1000000").unwrap();
+
+        let discover = |dedup| {
+            let options = DomainOptions {
+                dedup,
+                ..Default::default()
+            };
+            discover_domains(base.to_str().unwrap(), &options).unwrap()
+        };
+
+        assert_eq!(discover(false).len(), 2);
+        let domains = discover(true);
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].name, "package-0");
+        assert_eq!(domains[0].path, base.join("intel-rapl:0").join("energy_uj"));
+    }
+
+    #[test]
+    fn domain_map_normalizes_the_sensor_name() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("powercap");
+        make_domain_dir(&base, "package-0", 0, 100, 1_000);
+        let map = dir.path().join("domains.map");
+        write(
+            &map,
+            "# kernel 5.x names\npackage-0 = package\n\ndram=dram\n",
+        )
+        .unwrap();
+
+        let options = DomainOptions {
+            domain_map: Some(map.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let domains = discover_domains(base.to_str().unwrap(), &options).unwrap();
+
+        assert_eq!(domains[0].name, "package");
+        let sensors = Rapl::new(domains, None).get_sensors().unwrap();
+        assert_eq!(sensors[0].name, "PACKAGE_0");
+    }
+
+    #[test]
+    fn requested_domain_missing_lists_the_available_ones() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("powercap");
+        let package = make_domain_dir(&base, "package-0", 0, 100, 1_000);
+        let core = package.join("intel-rapl:0:0");
+        create_dir_all(&core).unwrap();
+        write(core.join("name"), "core").unwrap();
+        write(core.join("energy_uj"), "50").unwrap();
+        write(core.join("max_energy_range_uj"), "1000").unwrap();
+        let options = |names: &[&str]| DomainOptions {
+            domains: Some(names.iter().map(|name| name.to_string()).collect()),
+            ..Default::default()
+        };
+
+        let selected = get_domains(base.to_str(), None, &options(&["PACKAGE"])).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "package-0");
+
+        let err = get_domains(base.to_str(), None, &options(&["package", "dram"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Domain dram not found, available domains: core, package-0"
+        );
+    }
+
+    #[test]
+    fn unreadable_energy_counter_is_reported_with_its_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("powercap");
+        let package = make_domain_dir(&base, "package-0", 0, 100, 1_000);
+        let energy_file = package.join("energy_uj");
+        fs::set_permissions(&energy_file, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let err = JouleProfilerError::EnergyCounterNotReadable(energy_file.display().to_string());
+        assert!(err.to_string().starts_with(&format!(
+            "Permission denied reading the energy counter {}. Run with sudo",
+            energy_file.display()
+        )));
+        assert!(err.to_string().contains("CAP_DAC_READ_SEARCH"));
+
+        // SAFETY: geteuid has no preconditions and cannot fail
+        if unsafe { libc::geteuid() } == 0 {
+            // Root reads the counter whatever its mode
+            assert!(get_domains(base.to_str(), None, &DomainOptions::default()).is_ok());
+        } else {
+            let err = get_domains(base.to_str(), None, &DomainOptions::default()).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<JouleProfilerError>(),
+                Some(JouleProfilerError::EnergyCounterNotReadable(path))
+                    if *path == energy_file.display().to_string()
+            ));
+        }
+    }
+
+    #[test]
+    fn sensors_are_filtered_by_name_globs() {
+        let domain = |name: &str| RaplDomain {
+            path: PathBuf::from(name),
+            name: name.to_string(),
+            socket: 0,
+            max_energy_uj: 1_000,
+        };
+        let domains = vec![domain("package-0"), domain("core"), domain("dram")];
+        let globs = |globs: &[&str]| {
+            globs
+                .iter()
+                .map(|glob| glob.to_string())
+                .collect::<Vec<_>>()
+        };
+        let names = |domains: Vec<RaplDomain>| {
+            domains
+                .into_iter()
+                .map(|domain| domain.name)
+                .collect::<Vec<_>>()
+        };
+
+        let kept = filter_domains(domains.clone(), &globs(&["PACKAGE*", "dram"]), &[]).unwrap();
+        assert_eq!(names(kept), ["package-0", "dram"]);
+
+        // Exclude wins over include
+        let kept = filter_domains(domains.clone(), &globs(&["*"]), &globs(&["CORE"])).unwrap();
+        assert_eq!(names(kept), ["package-0", "dram"]);
+
+        let err = filter_domains(domains, &globs(&["psys"]), &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No domain left by --include-sensors/--exclude-sensors, available domains: core, \
+             dram, package-0"
+        );
+    }
+
+    #[test]
+    fn invalid_domain_map_line_is_reported() {
+        let err = parse_domain_map("package-0=package\npsys\n").unwrap_err();
+        assert!(err.contains("line 2"));
+    }
+
     #[test]
     fn read_energy_reads_valid_value() {
         let dir = tempdir().unwrap();
@@ -367,6 +927,43 @@ mod tests {
         assert!(err.contains("Invalid energy value"));
     }
 
+    #[test]
+    fn read_energy_retries_a_transient_unparsable_value() {
+        let domain = RaplDomain {
+            path: PathBuf::from("energy_uj"),
+            name: "package".to_string(),
+            socket: 0,
+            max_energy_uj: 1_000,
+        };
+
+        // The first read returns a value caught mid-update
+        let mut reads = ["12a", "123\n"].into_iter();
+        let energy =
+            read_energy_retrying(&domain, || Ok(reads.next().unwrap().to_string())).unwrap();
+        assert_eq!(energy, 123);
+    }
+
+    #[test]
+    fn read_energy_reports_persistently_corrupt_value() {
+        let domain = RaplDomain {
+            path: PathBuf::from("energy_uj"),
+            name: "package".to_string(),
+            socket: 0,
+            max_energy_uj: 1_000,
+        };
+
+        let mut read_count = 0;
+        let err = read_energy_retrying(&domain, || {
+            read_count += 1;
+            Ok("garbage".to_string())
+        })
+        .unwrap_err()
+        .to_string();
+
+        assert_eq!(read_count, 2);
+        assert!(err.contains("corrupt after retry"));
+    }
+
     // ---------- sockets utilities ----------
 
     #[test]
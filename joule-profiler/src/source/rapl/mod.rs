@@ -1,44 +1,161 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fs::read_to_string,
     time::Duration,
 };
 
 use anyhow::Result;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use tokio::time::Instant;
 
 use crate::{
+    cli::RaplBackendKind,
     error::JouleProfilerError,
     source::{
-        Metric, MetricReader, MetricSource, Metrics, Sensor, SourceResult,
+        Metric, MetricReader, MetricSource, Metrics, PollingStats, PowerRange, PowerSample,
+        RawReading, Sensor, SourceResult, TimeBin,
+        cgroup::{CpuTicks, cpu_utilization, read_cpu_ticks},
         rapl::{
-            domain::{RaplDomain, get_domains},
-            snapshot::{EnergySnapshot, compute_measurement_from_snapshots},
+            domain::{
+                DomainOptions, RaplDomain, filter_domains, get_domains, read_energy_retrying,
+                select_domains,
+            },
+            msr_backend::{MsrBackend, discover_msr_domains},
+            snapshot::{
+                EnergySnapshot, aggregate_domain_diffs, compute_domain_diffs,
+                may_wrap_more_than_once, wrapped_domains,
+            },
         },
     },
     util::time::get_timestamp,
 };
 
 pub mod domain;
+pub mod msr_backend;
 pub mod snapshot;
 
+/// Measurement options of the RAPL source.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RaplOptions {
+    /// Polling interval in seconds, `None` to only measure on events
+    pub polling_rate_s: Option<f64>,
+    /// Machine CPU utilization in [0, 1] a polling interval must exceed to be counted
+    pub min_utilization: Option<f64>,
+    /// Width in seconds of the bins of the energy time series, `None` to disable it
+    pub time_bin_s: Option<f64>,
+    /// Whether the energy of every polling interval is kept
+    pub keep_samples: bool,
+    /// Number of polling intervals dropped at the start and at the end of the measurement
+    pub trim_samples: usize,
+}
+
 pub fn init_rapl(
     rapl_path: Option<&str>,
     sockets: Option<&HashSet<u32>>,
-    polling_rate_s: Option<f64>,
+    backend: RaplBackendKind,
+    domain_options: &DomainOptions,
+    options: RaplOptions,
 ) -> Result<MetricSource> {
-    let domains = get_domains(rapl_path, sockets)?;
-    let rapl = Rapl::new(domains, polling_rate_s);
+    let (domains, backend) = discover_rapl_domains(rapl_path, sockets, backend, domain_options)?;
+    let polling_rate_s = options.polling_rate_s;
+    let mut rapl = Rapl::with_backend(domains, polling_rate_s, backend);
+
+    if let Some(bin_s) = options.time_bin_s {
+        if polling_rate_s.is_none() {
+            warn!("The energy time series needs --rapl-polling to split the energy in bins");
+        }
+        rapl.set_time_bin(Duration::from_secs_f64(bin_s));
+    }
+
+    if options.keep_samples {
+        if polling_rate_s.is_none() {
+            warn!("The samples file needs --rapl-polling to record the polling intervals");
+        }
+        rapl.keep_samples();
+    }
+
+    match (options.min_utilization, polling_rate_s) {
+        (Some(threshold), Some(_)) => rapl.set_min_utilization(threshold),
+        (Some(_), None) => warn!("CPU utilization gating requires --rapl-polling, ignoring it"),
+        (None, _) => {}
+    }
+
+    match (options.trim_samples, polling_rate_s) {
+        (0, _) => {}
+        (samples, Some(_)) => rapl.set_trim_samples(samples),
+        (_, None) => warn!("Trimming samples requires --rapl-polling, ignoring it"),
+    }
     Ok(MetricSource::Rapl(rapl))
 }
 
+/// Discover the RAPL domains readable with the given backend.
+pub fn discover_rapl_domains(
+    rapl_path: Option<&str>,
+    sockets: Option<&HashSet<u32>>,
+    backend: RaplBackendKind,
+    domain_options: &DomainOptions,
+) -> Result<(Vec<RaplDomain>, RaplBackend)> {
+    match backend {
+        RaplBackendKind::Powercap => Ok((
+            filter_domains(
+                get_domains(rapl_path, sockets, domain_options)?,
+                &domain_options.include,
+                &domain_options.exclude,
+            )?,
+            RaplBackend::Powercap,
+        )),
+        RaplBackendKind::Msr => {
+            if rapl_path.is_some() {
+                warn!("RAPL path is ignored with the MSR backend");
+            }
+            let (domains, msr) = discover_msr_domains(sockets)?;
+            let domains = match &domain_options.domains {
+                Some(requested) => select_domains(domains, requested)?,
+                None => domains,
+            };
+            let domains =
+                filter_domains(domains, &domain_options.include, &domain_options.exclude)?;
+            Ok((domains, RaplBackend::Msr(msr)))
+        }
+    }
+}
+
+/// Interface used to read the RAPL energy counters.
+#[derive(Clone, Debug, Default)]
+pub enum RaplBackend {
+    /// Powercap sysfs energy_uj files
+    #[default]
+    Powercap,
+    /// Model specific registers read through /dev/cpu/N/msr
+    Msr(MsrBackend),
+}
+
+/// Add energy to a counter, saturating at `u64::MAX` (about 584 years of a 1 kW package) with
+/// a warning rather than wrapping to a small value.
+fn accumulate_saturating(counter: &mut u64, value: u64, name: &str) {
+    match counter.checked_add(value) {
+        Some(total) => *counter = total,
+        None => {
+            if *counter != u64::MAX {
+                warn!("Energy counter {} saturated at {} µJ", name, u64::MAX);
+            }
+            *counter = u64::MAX;
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Rapl {
     domains: Vec<RaplDomain>,
+    backend: RaplBackend,
     measures: Vec<HashMap<String, u64>>,
+    /// First snapshot of the measurement, for the raw readings of the counters
+    first_measure: Option<EnergySnapshot>,
     last_measure: Option<EnergySnapshot>,
     measure_counters: HashMap<String, u64>,
+    /// Energy counted per domain path over the whole measurement, before the per name and
+    /// socket aggregation
+    raw_counters: BTreeMap<String, u64>,
     poll_interval: Option<Duration>,
 
     /// Number of snapshots taken
@@ -47,8 +164,73 @@ pub struct Rapl {
     /// Total elapsed time between snapshots
     total_elapsed: Duration,
 
+    /// Shortest and longest time between two snapshots
+    min_interval: Option<Duration>,
+    max_interval: Option<Duration>,
+
     /// Monotonic timestamp of last snapshot
     last_instant: Option<Instant>,
+
+    /// Largest read spread between the domains of a snapshot in microseconds
+    max_read_spread_us: u128,
+
+    /// Whether any counter advanced between the two last snapshots
+    last_advanced: Option<bool>,
+
+    /// Machine CPU utilization in [0, 1] an interval must exceed for its energy to be counted
+    min_utilization: Option<f64>,
+
+    /// CPU ticks of the machine read with the last snapshot, when gating on utilization
+    last_cpu_ticks: Option<CpuTicks>,
+
+    /// Number of intervals whose energy was dropped because of a low CPU utilization
+    gated_intervals: u64,
+
+    /// Width of the wall-clock bins of the energy time series in microseconds
+    time_bin_us: Option<u128>,
+
+    /// Timestamp in microseconds of the first snapshot, origin of the bins
+    first_timestamp_us: Option<u128>,
+
+    /// Energy of each domain per bin index
+    time_bins: BTreeMap<u64, HashMap<String, u64>>,
+
+    /// Energy of every counted interval, `None` unless the samples are kept
+    samples: Option<Vec<PowerSample>>,
+
+    /// Number of intervals dropped at the start and at the end of the measurement
+    trim_samples: usize,
+
+    /// Number of intervals dropped so far at the start
+    trimmed_start: usize,
+
+    /// Last intervals with the timestamp closing them and their duration in microseconds,
+    /// held back until it is known whether they end the measurement
+    pending_intervals: VecDeque<(HashMap<String, u64>, u128, u128)>,
+
+    /// Number of intervals added to the counters
+    counted_intervals: u64,
+
+    /// Lowest and highest power of each metric over the counted intervals
+    power_range: HashMap<String, PowerRange>,
+
+    /// Lowest and highest power of each metric over the intervals of the current phase
+    measure_power_range: HashMap<String, PowerRange>,
+
+    /// Lowest and highest power of each metric of each stored phase, parallel to the measures
+    phase_power_ranges: Vec<HashMap<String, PowerRange>>,
+
+    /// Longest counted interval in microseconds, checked again as the peak power rises
+    longest_interval_us: u128,
+
+    /// Domain paths already reported as possibly wrapping several times in an interval
+    wrap_warned: HashSet<String>,
+
+    /// Number of counter wraps seen in the current phase
+    measure_wraps: u64,
+
+    /// Number of counter wraps of each stored phase, parallel to the measures
+    phase_wraps: Vec<u64>,
 }
 
 impl MetricReader for Rapl {
@@ -56,23 +238,8 @@ impl MetricReader for Rapl {
         trace!("Starting RAPL measurement");
 
         let new_measure = self.read_snapshot()?;
+        self.accumulate(new_measure)?;
 
-        let now = Instant::now();
-        if let Some(last) = self.last_instant {
-            self.total_elapsed += now.duration_since(last);
-        }
-        self.last_instant = Some(now);
-        self.count += 1;
-
-        if let Some(old) = self.last_measure.take() {
-            let diff = compute_measurement_from_snapshots(&self.domains, &old, &new_measure)?;
-            for (k, v) in diff.iter() {
-                *self.measure_counters.entry(k.clone()).or_insert(0) += *v;
-                debug!("Updated counter {} = {}", k, self.measure_counters[k]);
-            }
-        }
-
-        self.last_measure = Some(new_measure);
         trace!("Measurement complete");
         Ok(())
     }
@@ -80,39 +247,67 @@ impl MetricReader for Rapl {
     fn phase(&mut self) -> Result<()> {
         info!("Starting a new phase");
         self.measure()?;
+        self.flush_pending_intervals();
 
         let phase_counters = std::mem::take(&mut self.measure_counters);
         debug!("Phase counters: {:?}", phase_counters);
 
-        self.measures.push(phase_counters);
+        self.push_measure(phase_counters);
         info!("Phase completed, stored counters");
         Ok(())
     }
 
+    fn phase_at(&mut self, timestamp_us: u128) -> Result<()> {
+        let Some(last) = self.last_snapshot_timestamp() else {
+            return self.phase();
+        };
+
+        let new_measure = self.read_snapshot()?;
+
+        // The boundary is closer to the last completed snapshot than to the new one,
+        // so the interval since that snapshot belongs to the next phase
+        if timestamp_us.abs_diff(last) < new_measure.timestamp_us.abs_diff(timestamp_us) {
+            debug!("Aligning phase boundary on the last snapshot");
+            self.flush_pending_intervals();
+            let phase_counters = std::mem::take(&mut self.measure_counters);
+            self.push_measure(phase_counters);
+            self.accumulate(new_measure)?;
+        } else {
+            self.accumulate(new_measure)?;
+            self.flush_pending_intervals();
+            let phase_counters = std::mem::take(&mut self.measure_counters);
+            self.push_measure(phase_counters);
+        }
+
+        info!("Aligned phase completed, stored counters");
+        Ok(())
+    }
+
     fn retrieve(&mut self) -> Result<SourceResult> {
         info!("Retrieving all measures");
 
+        if self.trim_samples > 0 {
+            // The intervals still held back are the last ones of the measurement
+            self.pending_intervals.clear();
+            if self.counted_intervals == 0 {
+                return Err(JouleProfilerError::AllSamplesTrimmed(
+                    self.trim_samples,
+                    self.count.saturating_sub(1),
+                )
+                .into());
+            }
+        }
+
         let remaining = std::mem::take(&mut self.measure_counters);
         if !remaining.is_empty() {
             debug!("Adding remaining counters: {:?}", remaining);
-            self.measures.push(remaining);
+            self.push_measure(remaining);
         }
 
         let measures: Vec<Metrics> = self
             .measures
             .iter()
-            .map(|measure| {
-                let mut metrics = Vec::with_capacity(measure.len());
-                for (domain_name, value) in measure.iter() {
-                    metrics.push(Metric {
-                        name: domain_name.clone(),
-                        value: *value,
-                        unit: "µJ".to_string(),
-                        source: "powercap".to_string(),
-                    });
-                }
-                metrics
-            })
+            .map(|measure| self.to_metrics(measure))
             .collect();
 
         let avg_delta_us = if self.count > 1 {
@@ -121,12 +316,60 @@ impl MetricReader for Rapl {
             0
         };
 
-        info!("Retrieved {} phases", measures.len());
+        info!(
+            "Retrieved {} phases, domains read within {} µs",
+            measures.len(),
+            self.max_read_spread_us
+        );
+        if self.min_utilization.is_some() {
+            info!(
+                "Dropped the energy of {} of {} intervals with a low CPU utilization",
+                self.gated_intervals,
+                self.count.saturating_sub(1)
+            );
+        }
+
+        let polling = PollingStats {
+            interval_us: self.poll_interval.map(|interval| interval.as_micros()),
+            measure_count: self.count,
+            avg_interval_us: avg_delta_us,
+            min_interval_us: self.min_interval.unwrap_or_default().as_micros(),
+            max_interval_us: self.max_interval.unwrap_or_default().as_micros(),
+            total_elapsed_us: self.total_elapsed.as_micros(),
+        };
 
         Ok(SourceResult {
             measures,
             count: self.count,
             measure_delta: avg_delta_us,
+            polling: BTreeMap::from([(self.source_name().to_string(), polling)]),
+            power_range: self
+                .power_range
+                .iter()
+                .map(|(name, range)| (name.clone(), *range))
+                .collect(),
+            max_read_spread_us: self.max_read_spread_us,
+            failed_sources: Vec::new(),
+            raw_counters: std::mem::take(&mut self.raw_counters),
+            raw_readings: self.raw_readings(),
+            counter_wraps: std::mem::take(&mut self.phase_wraps),
+            phase_power_ranges: std::mem::take(&mut self.phase_power_ranges)
+                .into_iter()
+                .map(|ranges| ranges.into_iter().collect())
+                .collect(),
+            time_series: self
+                .time_bins
+                .iter()
+                .map(|(index, measure)| TimeBin {
+                    index: *index,
+                    metrics: self.to_metrics(measure),
+                })
+                .collect(),
+            samples: self
+                .samples
+                .as_mut()
+                .map(std::mem::take)
+                .unwrap_or_default(),
         })
     }
 
@@ -138,7 +381,7 @@ impl MetricReader for Rapl {
                 let name = format!("{}_{}", domain.name.to_uppercase(), domain.socket);
                 Sensor {
                     name,
-                    source: "powercap".to_string(),
+                    source: self.source_name().to_string(),
                     unit: "µJ".to_string(),
                 }
             })
@@ -152,21 +395,341 @@ impl MetricReader for Rapl {
     }
 
     fn get_name(&self) -> &'static str {
-        "Powercap"
+        match self.backend {
+            RaplBackend::Powercap => "Powercap",
+            RaplBackend::Msr(_) => "MSR",
+        }
+    }
+
+    fn advanced(&self) -> Option<bool> {
+        self.last_advanced
+    }
+
+    fn last_phase(&self) -> Option<Metrics> {
+        self.measures.last().map(|measure| self.to_metrics(measure))
+    }
+
+    fn discard_interval(&mut self) {
+        debug!("Discarding the energy since the last snapshot");
+        self.last_measure = None;
+        self.last_instant = None;
+        self.last_cpu_ticks = None;
     }
 }
 
 impl Rapl {
     pub fn new(domains: Vec<RaplDomain>, polling_rate_s: Option<f64>) -> Self {
+        Self::with_backend(domains, polling_rate_s, RaplBackend::Powercap)
+    }
+
+    pub fn with_backend(
+        domains: Vec<RaplDomain>,
+        polling_rate_s: Option<f64>,
+        backend: RaplBackend,
+    ) -> Self {
         Rapl {
             domains,
+            backend,
             poll_interval: polling_rate_s.map(Duration::from_secs_f64),
             measures: Vec::new(),
+            first_measure: None,
             last_measure: None,
             measure_counters: HashMap::new(),
+            raw_counters: BTreeMap::new(),
             count: 0,
             total_elapsed: Duration::ZERO,
+            min_interval: None,
+            max_interval: None,
             last_instant: None,
+            max_read_spread_us: 0,
+            last_advanced: None,
+            min_utilization: None,
+            last_cpu_ticks: None,
+            gated_intervals: 0,
+            time_bin_us: None,
+            first_timestamp_us: None,
+            time_bins: BTreeMap::new(),
+            samples: None,
+            trim_samples: 0,
+            trimmed_start: 0,
+            pending_intervals: VecDeque::new(),
+            counted_intervals: 0,
+            power_range: HashMap::new(),
+            measure_power_range: HashMap::new(),
+            phase_power_ranges: Vec::new(),
+            longest_interval_us: 0,
+            wrap_warned: HashSet::new(),
+            measure_wraps: 0,
+            phase_wraps: Vec::new(),
+        }
+    }
+
+    /// Drop the energy of the first and last polling intervals, which straddle the spawn
+    /// and the exit of the command.
+    pub fn set_trim_samples(&mut self, samples: usize) {
+        self.trim_samples = samples;
+    }
+
+    /// Also accumulate the energy in wall-clock bins of the given width, each interval
+    /// counts in the bin holding the snapshot closing it.
+    pub fn set_time_bin(&mut self, bin: Duration) {
+        self.time_bin_us = Some(bin.as_micros().max(1));
+    }
+
+    /// Also keep the energy of every counted interval, instead of only adding it to the
+    /// counters.
+    pub fn keep_samples(&mut self) {
+        self.samples = Some(Vec::new());
+    }
+
+    /// Only count the energy of the polling intervals where the machine CPU utilization
+    /// exceeds the threshold, in [0, 1].
+    ///
+    /// The utilization is sampled from `/proc/stat` for the whole machine, not only for the
+    /// profiled program, with the resolution of the clock ticks (usually 10 ms per CPU).
+    /// Intervals shorter than a few ticks therefore give a noisy utilization.
+    pub fn set_min_utilization(&mut self, threshold: f64) {
+        self.min_utilization = Some(threshold);
+    }
+
+    /// Timestamp in microseconds of the last completed snapshot.
+    pub fn last_snapshot_timestamp(&self) -> Option<u128> {
+        self.last_measure
+            .as_ref()
+            .map(|snapshot| snapshot.timestamp_us)
+    }
+
+    /// Add the energy consumed since the last snapshot to the current counters.
+    fn accumulate(&mut self, new_measure: EnergySnapshot) -> Result<()> {
+        let cpu_ticks = match self.min_utilization {
+            Some(_) => Some(read_cpu_ticks()?),
+            None => None,
+        };
+        self.accumulate_with_ticks(new_measure, cpu_ticks)
+    }
+
+    /// Whether the energy of the interval ending with these CPU ticks must be counted.
+    fn interval_counted(&self, cpu_ticks: Option<&CpuTicks>) -> bool {
+        match (self.min_utilization, &self.last_cpu_ticks, cpu_ticks) {
+            (Some(threshold), Some(begin), Some(end)) => {
+                let utilization = cpu_utilization(begin, end);
+                trace!("CPU utilization over the interval: {:.3}", utilization);
+                utilization > threshold
+            }
+            _ => true,
+        }
+    }
+
+    fn accumulate_with_ticks(
+        &mut self,
+        new_measure: EnergySnapshot,
+        cpu_ticks: Option<CpuTicks>,
+    ) -> Result<()> {
+        let counted = self.interval_counted(cpu_ticks.as_ref());
+        self.last_cpu_ticks = cpu_ticks;
+
+        let now = Instant::now();
+        if let Some(last) = self.last_instant {
+            let elapsed = now.duration_since(last);
+            self.total_elapsed += elapsed;
+            self.min_interval = Some(self.min_interval.map_or(elapsed, |min| min.min(elapsed)));
+            self.max_interval = Some(self.max_interval.map_or(elapsed, |max| max.max(elapsed)));
+        }
+        self.last_instant = Some(now);
+        self.count += 1;
+        self.max_read_spread_us = self.max_read_spread_us.max(new_measure.read_spread_us);
+        let first_timestamp_us = *self
+            .first_timestamp_us
+            .get_or_insert(new_measure.timestamp_us);
+        if self.first_measure.is_none() {
+            self.first_measure = Some(new_measure.clone());
+        }
+
+        if let Some(old) = self.last_measure.take() {
+            let diff = compute_domain_diffs(&self.domains, &old, &new_measure)?;
+            for domain in wrapped_domains(&self.domains, &old, &new_measure) {
+                debug!(
+                    "The {} counter of socket {} wrapped",
+                    domain.name, domain.socket
+                );
+                self.measure_wraps += 1;
+            }
+            self.last_advanced = Some(diff.values().any(|v| *v > 0));
+            if !counted {
+                self.gated_intervals += 1;
+                debug!("CPU utilization below the threshold, dropping the interval energy");
+                self.last_measure = Some(new_measure);
+                return Ok(());
+            }
+            if self.trimmed_start < self.trim_samples {
+                self.trimmed_start += 1;
+                debug!("Dropping interval {} at the start", self.trimmed_start);
+            } else {
+                let elapsed_us = new_measure.timestamp_us.saturating_sub(first_timestamp_us);
+                let interval_us = new_measure.timestamp_us.saturating_sub(old.timestamp_us);
+                self.pending_intervals
+                    .push_back((diff, elapsed_us, interval_us));
+                if self.pending_intervals.len() > self.trim_samples
+                    && let Some((diff, elapsed_us, interval_us)) =
+                        self.pending_intervals.pop_front()
+                {
+                    self.add_interval(diff, elapsed_us, interval_us);
+                }
+            }
+        }
+
+        self.last_measure = Some(new_measure);
+        Ok(())
+    }
+
+    /// Warn when the peak power of a metric seen so far would consume the whole energy range
+    /// of its domain over the longest interval: the counter may then have wrapped more than
+    /// once, under-counting the energy by multiples of the range. The longest interval is
+    /// kept since its own power is under-estimated when it wrapped, a later interval being
+    /// needed to reveal the peak.
+    fn check_wraps(&mut self, interval_us: u128) {
+        self.longest_interval_us = self.longest_interval_us.max(interval_us);
+
+        for domain in &self.domains {
+            let key = format!("{}_{}", domain.name.to_uppercase(), domain.socket);
+            let Some(range) = self.power_range.get(&key) else {
+                continue;
+            };
+            let path = domain.path.to_string_lossy().to_string();
+
+            if may_wrap_more_than_once(
+                self.longest_interval_us,
+                range.peak_power_w,
+                domain.max_energy_uj,
+            ) && self.wrap_warned.insert(path)
+            {
+                warn!(
+                    "The {} counter of socket {} may have wrapped more than once in {} ms at \
+                     {:.1} W (range {} µJ), its energy may be under-counted; poll faster \
+                     with --rapl-polling",
+                    domain.name,
+                    domain.socket,
+                    self.longest_interval_us / 1000,
+                    range.peak_power_w,
+                    domain.max_energy_uj
+                );
+            }
+        }
+    }
+
+    /// Add the energy of an interval of `interval_us` ending `elapsed_us` after the first
+    /// snapshot, the difference being keyed by domain path.
+    fn add_interval(
+        &mut self,
+        domain_diff: HashMap<String, u64>,
+        elapsed_us: u128,
+        interval_us: u128,
+    ) {
+        self.counted_intervals += 1;
+        let diff = aggregate_domain_diffs(&self.domains, &domain_diff);
+        for (path, v) in domain_diff {
+            let counter = self.raw_counters.entry(path).or_insert(0);
+            *counter = counter.saturating_add(v);
+        }
+        for (k, v) in diff.iter() {
+            accumulate_saturating(self.measure_counters.entry(k.clone()).or_insert(0), *v, k);
+            debug!("Updated counter {} = {}", k, self.measure_counters[k]);
+            // µJ per µs are watts
+            if interval_us > 0 {
+                let power_w = *v as f64 / interval_us as f64;
+                let range = PowerRange::record(self.power_range.get(k).copied(), power_w);
+                self.power_range.insert(k.clone(), range);
+                let range = PowerRange::record(self.measure_power_range.get(k).copied(), power_w);
+                self.measure_power_range.insert(k.clone(), range);
+            }
+        }
+        self.check_wraps(interval_us);
+        if self.samples.is_some() {
+            let mut metrics = self.to_metrics(&diff);
+            metrics.sort_by(|a, b| a.name.cmp(&b.name));
+            let samples = self.samples.get_or_insert_default();
+            samples.push(PowerSample {
+                elapsed_us,
+                interval_us,
+                metrics,
+            });
+        }
+        if let Some(bin_us) = self.time_bin_us {
+            let bin = self
+                .time_bins
+                .entry((elapsed_us / bin_us) as u64)
+                .or_default();
+            for (k, v) in diff {
+                let counter = bin.entry(k).or_insert(0);
+                *counter = counter.saturating_add(v);
+            }
+        }
+    }
+
+    /// Store the counters of a phase with the number of counter wraps and the power range
+    /// seen during it.
+    fn push_measure(&mut self, counters: HashMap<String, u64>) {
+        self.measures.push(counters);
+        self.phase_wraps
+            .push(std::mem::take(&mut self.measure_wraps));
+        self.phase_power_ranges
+            .push(std::mem::take(&mut self.measure_power_range));
+    }
+
+    /// Counter values of each domain at the first and the last snapshot, empty without a
+    /// snapshot.
+    fn raw_readings(&self) -> BTreeMap<String, RawReading> {
+        let (Some(first), Some(last)) = (&self.first_measure, &self.last_measure) else {
+            return BTreeMap::new();
+        };
+
+        self.domains
+            .iter()
+            .filter_map(|domain| {
+                let path = domain.path.to_string_lossy().to_string();
+                let reading = RawReading {
+                    start_uj: *first.energies_uj.get(&path)?,
+                    end_uj: *last.energies_uj.get(&path)?,
+                    max_energy_uj: domain.max_energy_uj,
+                };
+                Some((path, reading))
+            })
+            .collect()
+    }
+
+    /// Add the intervals held back for trimming, a phase boundary is not the end.
+    fn flush_pending_intervals(&mut self) {
+        while let Some((diff, elapsed_us, interval_us)) = self.pending_intervals.pop_front() {
+            self.add_interval(diff, elapsed_us, interval_us);
+        }
+    }
+
+    /// Convert the counters of a phase to metrics.
+    fn to_metrics(&self, measure: &HashMap<String, u64>) -> Metrics {
+        measure
+            .iter()
+            .map(|(domain_name, value)| Metric {
+                name: domain_name.clone(),
+                value: *value,
+                unit: "µJ".to_string(),
+                source: self.source_name().to_string(),
+            })
+            .collect()
+    }
+
+    /// Take the energy accumulated since the last call, sorted by metric name.
+    pub fn take_interval_metrics(&mut self) -> Metrics {
+        let counters = std::mem::take(&mut self.measure_counters);
+        let mut metrics = self.to_metrics(&counters);
+        metrics.sort_by(|a, b| a.name.cmp(&b.name));
+        metrics
+    }
+
+    /// Name of the source reported in the metrics.
+    fn source_name(&self) -> &'static str {
+        match self.backend {
+            RaplBackend::Powercap => "powercap",
+            RaplBackend::Msr(_) => "msr",
         }
     }
 
@@ -178,37 +741,38 @@ impl Rapl {
 
         let mut map = HashMap::with_capacity(self.domains.len());
 
+        // The domains are read one after the other, the spread bounds the skew between them
+        let batch_start = Instant::now();
         for domain in &self.domains {
-            let val_str = read_to_string(&domain.path).map_err(|e| {
-                error!("Failed to read energy from {}: {:?}", domain.name, e);
-                if e.kind() == std::io::ErrorKind::PermissionDenied {
-                    JouleProfilerError::InsufficientPermissions
-                } else {
-                    JouleProfilerError::RaplReadError(e.to_string())
-                }
-            })?;
-
-            let val_uj: u64 = val_str.trim().parse().map_err(|e| {
-                error!(
-                    "Invalid energy value '{}' in domain '{}': {:?}",
-                    val_str.trim(),
-                    domain.name,
-                    e
-                );
-                JouleProfilerError::ParseEnergyError(format!(
-                    "Invalid energy value '{}' in domain '{}': {}",
-                    val_str.trim(),
-                    domain.name,
-                    e
-                ))
-            })?;
+            let val_uj = match &self.backend {
+                RaplBackend::Powercap => Self::read_powercap_energy(domain)?,
+                RaplBackend::Msr(msr) => msr.read_energy_uj(domain)?,
+            };
 
             map.insert(domain.path.to_string_lossy().to_string(), val_uj);
         }
+        let read_spread_us = batch_start.elapsed().as_micros();
+        trace!("Snapshot domains read within {} µs", read_spread_us);
 
         Ok(EnergySnapshot {
             energies_uj: map,
             timestamp_us: get_timestamp(),
+            read_spread_us,
+        })
+    }
+
+    /// Read the energy counter of a domain from its powercap energy_uj file.
+    fn read_powercap_energy(domain: &RaplDomain) -> Result<u64> {
+        read_energy_retrying(domain, || {
+            read_to_string(&domain.path).map_err(|e| {
+                error!("Failed to read energy from {}: {:?}", domain.name, e);
+                if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    JouleProfilerError::EnergyCounterNotReadable(domain.path.display().to_string())
+                        .into()
+                } else {
+                    JouleProfilerError::RaplReadError(e.to_string()).into()
+                }
+            })
         })
     }
 }
@@ -244,6 +808,410 @@ mod tests {
         assert!(snapshot.timestamp_us > 0);
     }
 
+    #[test]
+    fn read_spread_is_bounded_by_snapshot_duration() {
+        let dir = tempdir().unwrap();
+        // Enough files for their reads to take a measurable time
+        let domains = (0..200)
+            .map(|socket| {
+                let energy_file = dir.path().join(format!("package_{}_energy_uj", socket));
+                write(&energy_file, "100").unwrap();
+                make_domain("package", socket, &energy_file)
+            })
+            .collect();
+        let mut rapl = Rapl::new(domains, None);
+
+        let before = std::time::Instant::now();
+        let snapshot = rapl.read_snapshot().unwrap();
+        let elapsed_us = before.elapsed().as_micros();
+
+        assert_eq!(snapshot.energies_uj.len(), 200);
+        assert!(snapshot.read_spread_us > 0);
+        assert!(snapshot.read_spread_us <= elapsed_us);
+
+        rapl.measure().unwrap();
+        rapl.measure().unwrap();
+        let result = rapl.retrieve().unwrap();
+        assert!(result.max_read_spread_us > 0);
+        assert!(result.max_read_spread_us < 1_000_000);
+    }
+
+    #[test]
+    fn raw_counters_have_an_entry_per_domain_path() {
+        let dir = tempdir().unwrap();
+        // Two zones of the same name and socket are summed in a single metric
+        let paths: Vec<_> = ["intel-rapl:0", "intel-rapl:0:0", "intel-rapl:0:1"]
+            .iter()
+            .map(|zone| dir.path().join(format!("{}_energy_uj", zone)))
+            .collect();
+        let domains = vec![
+            make_domain("package-0", 0, &paths[0]),
+            make_domain("core", 0, &paths[1]),
+            make_domain("core", 0, &paths[2]),
+        ];
+        let mut rapl = Rapl::new(domains, None);
+
+        for path in &paths {
+            write(path, "100").unwrap();
+        }
+        rapl.measure().unwrap();
+        for (path, energy) in paths.iter().zip(["400", "150", "250"]) {
+            write(path, energy).unwrap();
+        }
+        rapl.measure().unwrap();
+
+        let result = rapl.retrieve().unwrap();
+        let expected: BTreeMap<_, _> = paths
+            .iter()
+            .zip([300, 50, 150])
+            .map(|(path, value)| (path.to_string_lossy().to_string(), value))
+            .collect();
+        assert_eq!(result.raw_counters, expected);
+        let core = result.measures[0]
+            .iter()
+            .find(|m| m.name == "CORE_0")
+            .unwrap();
+        assert_eq!(core.value, 200);
+    }
+
+    #[test]
+    fn raw_readings_keep_the_first_and_last_counter_values() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("intel-rapl:0_energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package-0", 0, &path)], None);
+
+        write(&path, "4294967000").unwrap();
+        rapl.measure().unwrap();
+        // The counter wraps between the two last snapshots
+        write(&path, "4294967200").unwrap();
+        rapl.measure().unwrap();
+        write(&path, "100").unwrap();
+        rapl.measure().unwrap();
+
+        let result = rapl.retrieve().unwrap();
+        let key = path.to_string_lossy().to_string();
+        assert_eq!(
+            result.raw_readings,
+            BTreeMap::from([(
+                key,
+                RawReading {
+                    start_uj: 4_294_967_000,
+                    end_uj: 100,
+                    max_energy_uj: u32::MAX as u64,
+                }
+            )])
+        );
+        assert_eq!(result.measures[0][0].value, 395);
+
+        // Without a snapshot there is nothing to report
+        let mut rapl = Rapl::new(vec![make_domain("package-0", 0, &path)], None);
+        assert!(rapl.retrieve().unwrap().raw_readings.is_empty());
+    }
+
+    #[test]
+    fn accumulation_near_the_maximum_saturates() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], None);
+        rapl.measure_counters
+            .insert("PACKAGE_0".to_string(), u64::MAX - 10);
+
+        let diff = HashMap::from([(path.to_string_lossy().to_string(), 100)]);
+        rapl.add_interval(diff.clone(), 0, 0);
+        assert_eq!(rapl.measure_counters["PACKAGE_0"], u64::MAX);
+
+        rapl.add_interval(diff, 0, 0);
+        assert_eq!(rapl.measure_counters["PACKAGE_0"], u64::MAX);
+    }
+
+    #[test]
+    fn low_utilization_intervals_are_not_accumulated() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+        let domain = make_domain("package", 0, &energy_file);
+        let mut rapl = Rapl::new(vec![domain], Some(0.01));
+        rapl.set_min_utilization(0.5);
+
+        // (energy, busy ticks, total ticks): 80 % busy, then 10 %, then 60 %
+        let samples = [(0, 0, 0), (100, 80, 100), (300, 90, 200), (350, 150, 300)];
+        for (energy, busy, total) in samples {
+            write(&energy_file, energy.to_string()).unwrap();
+            let snapshot = rapl.read_snapshot().unwrap();
+            rapl.accumulate_with_ticks(snapshot, Some(CpuTicks { busy, total }))
+                .unwrap();
+        }
+
+        assert_eq!(*rapl.measure_counters.values().next().unwrap(), 150);
+        assert_eq!(rapl.gated_intervals, 1);
+        assert_eq!(rapl.advanced(), Some(true));
+    }
+
+    #[test]
+    fn energy_is_binned_by_elapsed_time() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], Some(0.25));
+        rapl.set_time_bin(Duration::from_secs(1));
+
+        // (energy, timestamp): intervals ending at 0.5 s, 1.0 s, 1.5 s and 3.2 s
+        let samples = [
+            (0, 10_000_000),
+            (100, 10_500_000),
+            (250, 11_000_000),
+            (300, 11_500_000),
+            (700, 13_200_000),
+        ];
+        for (energy, timestamp_us) in samples {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+
+        let result = rapl.retrieve().unwrap();
+        let bins: Vec<(u64, u64)> = result
+            .time_series
+            .iter()
+            .map(|bin| (bin.index, bin.metrics[0].value))
+            .collect();
+        assert_eq!(bins, [(0, 100), (1, 200), (3, 400)]);
+        assert_eq!(result.measures[0][0].value, 700);
+        assert!(result.samples.is_empty());
+    }
+
+    #[test]
+    fn samples_are_kept_per_interval() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], Some(0.5));
+        rapl.keep_samples();
+
+        for (energy, timestamp_us) in [(0, 10_000_000), (100, 10_500_000), (400, 11_500_000)] {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+
+        let result = rapl.retrieve().unwrap();
+        let samples: Vec<(u128, u128, u64)> = result
+            .samples
+            .iter()
+            .map(|sample| {
+                (
+                    sample.elapsed_us,
+                    sample.interval_us,
+                    sample.metrics[0].value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            [(500_000, 500_000, 100), (1_500_000, 1_000_000, 300)]
+        );
+        // The samples of the next run start over
+        assert!(rapl.retrieve().unwrap().samples.is_empty());
+    }
+
+    #[test]
+    fn intervals_long_enough_for_several_wraps_are_reported() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/dram/energy_uj");
+        let domain = RaplDomain {
+            max_energy_uj: 1_000_000,
+            ..make_domain("dram", 0, path)
+        };
+        let mut rapl = Rapl::new(vec![domain], None);
+
+        // 0.5 J in 50 ms is 10 W, the 1 J range is consumed in 100 ms
+        let samples = [(0, 0), (500_000, 50_000), (600_000, 90_000)];
+        for (energy, timestamp_us) in samples {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+        assert!(rapl.wrap_warned.is_empty());
+
+        // A 2 s interval at the 10 W peak could hold 20 wraps
+        let snapshot = EnergySnapshot {
+            energies_uj: HashMap::from([(path.to_string_lossy().to_string(), 700_000)]),
+            timestamp_us: 2_090_000,
+            read_spread_us: 0,
+        };
+        rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        assert!(rapl.wrap_warned.contains(path.to_string_lossy().as_ref()));
+    }
+
+    #[test]
+    fn first_interval_is_reported_once_the_peak_is_known() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/dram/energy_uj");
+        let domain = RaplDomain {
+            max_energy_uj: 1_000_000,
+            ..make_domain("dram", 0, path)
+        };
+        let mut rapl = Rapl::new(vec![domain], None);
+
+        // A 2 s interval at 0.05 W, then 0.5 J in 50 ms is a 10 W peak
+        let samples = [(0, 0), (100_000, 2_000_000), (600_000, 2_050_000)];
+        for (energy, timestamp_us) in samples {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+        assert!(rapl.wrap_warned.contains(path.to_string_lossy().as_ref()));
+    }
+
+    #[test]
+    fn counter_wraps_are_counted_per_phase() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let domain = RaplDomain {
+            max_energy_uj: 1_000,
+            ..make_domain("package", 0, path)
+        };
+        let mut rapl = Rapl::new(vec![domain], None);
+        let accumulate = |rapl: &mut Rapl, energy, timestamp_us| {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        };
+
+        // Two wraps in the first phase, none in the second one
+        accumulate(&mut rapl, 900, 0);
+        accumulate(&mut rapl, 100, 1_000);
+        accumulate(&mut rapl, 950, 2_000);
+        accumulate(&mut rapl, 50, 3_000);
+        let counters = std::mem::take(&mut rapl.measure_counters);
+        rapl.push_measure(counters);
+        accumulate(&mut rapl, 400, 4_000);
+
+        let result = rapl.retrieve().unwrap();
+        assert_eq!(result.counter_wraps, [2, 0]);
+        assert_eq!(result.measures.len(), 2);
+        // Every wrap added the range to the energy
+        assert_eq!(result.measures[0][0].value, 1_150);
+    }
+
+    #[test]
+    fn power_range_tracks_the_lowest_and_highest_interval() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], Some(0.01));
+
+        // Intervals of 10 ms at 5 W, 40 ms at 2 W and 5 ms at 20 W
+        let samples = [
+            (0, 0),
+            (50_000, 10_000),
+            (130_000, 50_000),
+            (230_000, 55_000),
+        ];
+        for (energy, timestamp_us) in samples {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+
+        let result = rapl.retrieve().unwrap();
+        let name = &result.measures[0][0].name;
+        assert_eq!(
+            result.power_range[name],
+            PowerRange {
+                min_power_w: 2.0,
+                peak_power_w: 20.0,
+            }
+        );
+    }
+
+    #[test]
+    fn power_range_is_tracked_per_phase() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], Some(0.01));
+        let accumulate = |rapl: &mut Rapl, energy, timestamp_us| {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        };
+
+        // 5 W then 2 W in the first phase, 20 W in the second one
+        accumulate(&mut rapl, 0, 0);
+        accumulate(&mut rapl, 50_000, 10_000);
+        accumulate(&mut rapl, 130_000, 50_000);
+        let counters = std::mem::take(&mut rapl.measure_counters);
+        rapl.push_measure(counters);
+        accumulate(&mut rapl, 230_000, 55_000);
+
+        let result = rapl.retrieve().unwrap();
+        let name = &result.measures[0][0].name;
+        let range = |min_power_w, peak_power_w| PowerRange {
+            min_power_w,
+            peak_power_w,
+        };
+        assert_eq!(result.phase_power_ranges.len(), 2);
+        assert_eq!(result.phase_power_ranges[0][name], range(2.0, 5.0));
+        assert_eq!(result.phase_power_ranges[1][name], range(20.0, 20.0));
+        assert_eq!(result.power_range[name], range(2.0, 20.0));
+    }
+
+    #[test]
+    fn trimmed_samples_are_excluded_from_the_total() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], Some(0.01));
+        rapl.set_trim_samples(1);
+
+        // Intervals of 1000 (spawn), 10, 20, 30 and 5000 (exit) µJ
+        for (idx, energy) in [0, 1_000, 1_010, 1_030, 1_060, 6_060]
+            .into_iter()
+            .enumerate()
+        {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us: idx as u128 * 10_000,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+
+        let result = rapl.retrieve().unwrap();
+        assert_eq!(result.measures[0][0].value, 60);
+    }
+
+    #[test]
+    fn trimming_every_sample_is_an_error() {
+        let path = std::path::Path::new("/fake/intel-rapl:0/energy_uj");
+        let mut rapl = Rapl::new(vec![make_domain("package", 0, path)], Some(0.01));
+        rapl.set_trim_samples(2);
+
+        for energy in [0, 100, 200, 300, 400] {
+            let snapshot = EnergySnapshot {
+                energies_uj: HashMap::from([(path.to_string_lossy().to_string(), energy)]),
+                timestamp_us: 0,
+                read_spread_us: 0,
+            };
+            rapl.accumulate_with_ticks(snapshot, None).unwrap();
+        }
+
+        let Err(err) = rapl.retrieve() else {
+            panic!("expected every sample to be trimmed");
+        };
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(JouleProfilerError::AllSamplesTrimmed(2, 4))
+        ));
+    }
+
     #[test]
     fn measure_accumulates_energy_diff() {
         let dir = tempdir().unwrap();
@@ -337,6 +1305,49 @@ mod tests {
         assert_eq!(*value, 200);
     }
 
+    #[test]
+    fn phase_at_aligns_boundary_on_nearest_snapshot() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+
+        write(&energy_file, "0").unwrap();
+        let domain = make_domain("package", 0, &energy_file);
+        let mut rapl = Rapl::new(vec![domain], None);
+
+        rapl.measure().unwrap();
+        write(&energy_file, "100").unwrap();
+        rapl.measure().unwrap();
+
+        // The token fires right after the last polling snapshot, the worker handles it late
+        let boundary = rapl.last_snapshot_timestamp().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write(&energy_file, "500").unwrap();
+        rapl.phase_at(boundary).unwrap();
+
+        let result = rapl.retrieve().unwrap();
+        assert_eq!(result.measures.len(), 2);
+        assert_eq!(result.measures[0][0].value, 100);
+        assert_eq!(result.measures[1][0].value, 400);
+    }
+
+    #[test]
+    fn phase_at_keeps_latest_snapshot_when_closer() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+
+        write(&energy_file, "0").unwrap();
+        let domain = make_domain("package", 0, &energy_file);
+        let mut rapl = Rapl::new(vec![domain], None);
+
+        rapl.measure().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write(&energy_file, "500").unwrap();
+        rapl.phase_at(get_timestamp()).unwrap();
+
+        assert_eq!(rapl.measures.len(), 1);
+        assert_eq!(*rapl.measures[0].values().next().unwrap(), 500);
+    }
+
     #[test]
     fn measure_delta_is_average_of_intervals() {
         let dir = tempdir().unwrap();
@@ -353,4 +1364,28 @@ mod tests {
         let result = rapl.retrieve().unwrap();
         assert!(result.measure_delta > 0);
     }
+
+    #[test]
+    fn polling_stats_reflect_the_interval_and_count() {
+        let dir = tempdir().unwrap();
+        let energy_file = dir.path().join("energy_uj");
+        write(&energy_file, "0").unwrap();
+
+        let domain = make_domain("package", 0, &energy_file);
+        let mut rapl = Rapl::new(vec![domain], Some(0.05));
+
+        for _ in 0..3 {
+            rapl.measure().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let result = rapl.retrieve().unwrap();
+        let polling = &result.polling["powercap"];
+        assert_eq!(polling.interval_us, Some(50_000));
+        assert_eq!(polling.measure_count, 3);
+        assert!(polling.min_interval_us >= 2_000);
+        assert!(polling.min_interval_us <= polling.avg_interval_us);
+        assert!(polling.avg_interval_us <= polling.max_interval_us);
+        assert_eq!(polling.avg_interval_us, polling.total_elapsed_us / 2);
+    }
 }
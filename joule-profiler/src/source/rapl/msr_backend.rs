@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::{debug, error, info, trace, warn};
+
+use crate::{
+    error::JouleProfilerError,
+    source::rapl::domain::{RaplDomain, check_os},
+};
+
+/// Register holding the RAPL power, energy and time units.
+pub const MSR_RAPL_POWER_UNIT: u64 = 0x606;
+/// Package energy counter register.
+pub const MSR_PKG_ENERGY_STATUS: u64 = 0x611;
+/// DRAM energy counter register.
+pub const MSR_DRAM_ENERGY_STATUS: u64 = 0x619;
+/// Cores (power plane 0) energy counter register.
+pub const MSR_PP0_ENERGY_STATUS: u64 = 0x639;
+/// Uncore (power plane 1) energy counter register.
+pub const MSR_PP1_ENERGY_STATUS: u64 = 0x641;
+
+/// Energy status registers read for each socket, with their powercap-like domain name.
+const ENERGY_REGISTERS: [(&str, u64); 4] = [
+    ("package", MSR_PKG_ENERGY_STATUS),
+    ("core", MSR_PP0_ENERGY_STATUS),
+    ("uncore", MSR_PP1_ENERGY_STATUS),
+    ("dram", MSR_DRAM_ENERGY_STATUS),
+];
+
+const CPU_SYSFS_PATH: &str = "/sys/devices/system/cpu";
+
+/// An energy status register read through the MSR device of one CPU of a socket.
+#[derive(Debug, Clone)]
+pub struct MsrRegister {
+    /// Path of the MSR device of the CPU used to read the socket counters
+    pub device: PathBuf,
+    /// Address of the energy status register
+    pub address: u64,
+    /// Energy unit of the socket in microjoules
+    pub energy_unit_uj: f64,
+}
+
+/// Reads RAPL energy counters directly from the model specific registers.
+#[derive(Debug, Clone, Default)]
+pub struct MsrBackend {
+    /// Registers indexed by their domain key
+    registers: HashMap<String, MsrRegister>,
+}
+
+impl MsrBackend {
+    /// Read the energy of a domain in microjoules.
+    pub fn read_energy_uj(&self, domain: &RaplDomain) -> Result<u64> {
+        let key = domain.path.to_string_lossy();
+        let register = self.registers.get(key.as_ref()).ok_or_else(|| {
+            JouleProfilerError::RaplReadError(format!(
+                "No MSR register for domain '{}'",
+                domain.name
+            ))
+        })?;
+
+        let raw = read_msr(&register.device, register.address)?;
+        Ok(energy_status_to_uj(raw, register.energy_unit_uj))
+    }
+}
+
+/// Discover the RAPL domains readable through the MSR devices, filtered with spec if one is provided.
+pub fn discover_msr_domains(spec: Option<&HashSet<u32>>) -> Result<(Vec<RaplDomain>, MsrBackend)> {
+    check_os()?;
+    info!("Discovering RAPL MSR domains");
+
+    let mut domains = Vec::new();
+    let mut backend = MsrBackend::default();
+
+    for (socket, cpu) in socket_cpus(Path::new(CPU_SYSFS_PATH))? {
+        if spec.is_some_and(|spec| !spec.contains(&socket)) {
+            trace!("Skipping socket {} not in spec", socket);
+            continue;
+        }
+
+        let device = PathBuf::from(format!("/dev/cpu/{}/msr", cpu));
+        if !device.exists() {
+            error!(
+                "MSR device {:?} not found, is the msr module loaded?",
+                device
+            );
+            return Err(JouleProfilerError::RaplNotAvailable(device.display().to_string()).into());
+        }
+
+        let energy_unit_uj = decode_energy_unit_uj(read_msr(&device, MSR_RAPL_POWER_UNIT)?);
+        debug!(
+            "Socket {} read through cpu {}, energy unit = {} µJ",
+            socket, cpu, energy_unit_uj
+        );
+
+        for (name, address) in ENERGY_REGISTERS {
+            if let Err(e) = read_msr(&device, address) {
+                warn!(
+                    "Register {:#x} ({}) unavailable on socket {}: {}",
+                    address, name, socket, e
+                );
+                continue;
+            }
+
+            let name = if name == "package" {
+                format!("package-{}", socket)
+            } else {
+                name.to_string()
+            };
+
+            let key = format!("{}@{:#x}", device.display(), address);
+            backend.registers.insert(
+                key.clone(),
+                MsrRegister {
+                    device: device.clone(),
+                    address,
+                    energy_unit_uj,
+                },
+            );
+
+            domains.push(RaplDomain {
+                path: PathBuf::from(key),
+                name,
+                socket,
+                max_energy_uj: max_energy_uj(energy_unit_uj),
+            });
+        }
+    }
+
+    if domains.is_empty() {
+        warn!("No RAPL MSR domains found");
+        return Err(JouleProfilerError::NoDomains.into());
+    }
+
+    info!("Discovered {} RAPL MSR domains", domains.len());
+    Ok((domains, backend))
+}
+
+/// Find the first CPU of each socket, the MSR package counters being shared by the whole socket.
+fn socket_cpus(cpu_sysfs: &Path) -> Result<Vec<(u32, u32)>> {
+    let mut sockets: HashMap<u32, u32> = HashMap::new();
+
+    for entry in fs::read_dir(cpu_sysfs)? {
+        let path = entry?.path();
+        let Some(cpu) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("cpu"))
+            .and_then(|idx| idx.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        let socket = fs::read_to_string(path.join("topology/physical_package_id"))
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+
+        sockets
+            .entry(socket)
+            .and_modify(|first| *first = (*first).min(cpu))
+            .or_insert(cpu);
+    }
+
+    let mut sockets: Vec<(u32, u32)> = sockets.into_iter().collect();
+    sockets.sort_unstable();
+    Ok(sockets)
+}
+
+/// Read a 64 bits model specific register from an MSR device.
+fn read_msr(device: &Path, address: u64) -> Result<u64> {
+    let file = File::open(device).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            JouleProfilerError::InsufficientPermissions
+        } else {
+            JouleProfilerError::RaplReadError(format!("Failed to open {:?}: {}", device, e))
+        }
+    })?;
+
+    let mut buffer = [0u8; 8];
+    file.read_exact_at(&mut buffer, address).map_err(|e| {
+        JouleProfilerError::RaplReadError(format!(
+            "Failed to read MSR {:#x} from {:?}: {}",
+            address, device, e
+        ))
+    })?;
+
+    Ok(u64::from_le_bytes(buffer))
+}
+
+/// Decode the energy unit in microjoules from the `MSR_RAPL_POWER_UNIT` register.
+///
+/// The energy status unit is stored in bits 12:8, the energy unit being 1 / 2^ESU joules.
+pub fn decode_energy_unit_uj(power_unit: u64) -> f64 {
+    let esu = (power_unit >> 8) & 0x1f;
+    1_000_000.0 / (1u64 << esu) as f64
+}
+
+/// Convert a raw energy status register to microjoules, the counter being the 32 low bits.
+pub fn energy_status_to_uj(energy_status: u64, energy_unit_uj: f64) -> u64 {
+    ((energy_status & 0xffff_ffff) as f64 * energy_unit_uj) as u64
+}
+
+/// Maximum energy in microjoules before the 32 bits energy counter wraps.
+pub fn max_energy_uj(energy_unit_uj: f64) -> u64 {
+    ((u32::MAX as f64 + 1.0) * energy_unit_uj) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn decode_energy_unit_from_power_unit_register() {
+        // Typical value on Intel client CPUs: ESU = 14 -> 1/16384 J
+        let unit = decode_energy_unit_uj(0x000a_0e03);
+        assert_eq!(unit, 61.03515625);
+    }
+
+    #[test]
+    fn energy_status_is_scaled_with_unit() {
+        let unit = decode_energy_unit_uj(0x000a_0e03);
+        assert_eq!(energy_status_to_uj(16_384, unit), 1_000_000);
+    }
+
+    #[test]
+    fn energy_status_ignores_reserved_high_bits() {
+        let unit = decode_energy_unit_uj(0x000a_0e03);
+        assert_eq!(
+            energy_status_to_uj(0xdead_0000_0000_4000, unit),
+            energy_status_to_uj(0x4000, unit)
+        );
+    }
+
+    #[test]
+    fn max_energy_is_counter_range_times_unit() {
+        assert_eq!(max_energy_uj(1.0), 1 << 32);
+        assert_eq!(max_energy_uj(0.5), 1 << 31);
+    }
+
+    #[test]
+    fn socket_cpus_picks_first_cpu_of_each_socket() {
+        let dir = tempdir().unwrap();
+        for (cpu, socket) in [(0, 0), (1, 1), (2, 0), (3, 1)] {
+            let topology = dir.path().join(format!("cpu{}/topology", cpu));
+            create_dir_all(&topology).unwrap();
+            write(topology.join("physical_package_id"), socket.to_string()).unwrap();
+        }
+        create_dir_all(dir.path().join("cpufreq")).unwrap();
+
+        let sockets = socket_cpus(dir.path()).unwrap();
+        assert_eq!(sockets, vec![(0, 0), (1, 1)]);
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use anyhow::Result;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 
 use crate::{error::JouleProfilerError, source::rapl::domain::RaplDomain};
 
@@ -9,10 +9,12 @@ use crate::{error::JouleProfilerError, source::rapl::domain::RaplDomain};
 pub struct EnergySnapshot {
     pub energies_uj: HashMap<String, u64>,
     pub timestamp_us: u128,
+    /// Time between the first and the last domain read of the snapshot in microseconds
+    pub read_spread_us: u128,
 }
 
-/// Compute one measurement from two energy snapshots.
-pub fn compute_measurement_from_snapshots(
+/// Compute the energy of each domain between two snapshots, keyed by the domain path.
+pub fn compute_domain_diffs(
     domains: &[RaplDomain],
     begin: &EnergySnapshot,
     end: &EnergySnapshot,
@@ -22,7 +24,7 @@ pub fn compute_measurement_from_snapshots(
         domains.len()
     );
 
-    let mut per_domain_socket: HashMap<(String, u32), u64> = HashMap::new();
+    let mut diffs: HashMap<String, u64> = HashMap::new();
 
     for domain in domains {
         let key = domain.path.to_string_lossy().to_string();
@@ -59,9 +61,27 @@ pub fn compute_measurement_from_snapshots(
             domain.name, domain.socket, start_uj, end_uj, diff_uj, max_uj
         );
 
+        diffs.insert(key, diff_uj);
+    }
+
+    Ok(diffs)
+}
+
+/// Sum the energy of the domains per name and socket, keyed by the metric name.
+pub fn aggregate_domain_diffs(
+    domains: &[RaplDomain],
+    diffs: &HashMap<String, u64>,
+) -> HashMap<String, u64> {
+    let mut per_domain_socket: HashMap<(String, u32), u64> = HashMap::new();
+
+    for domain in domains {
+        let diff_uj = diffs
+            .get(domain.path.to_string_lossy().as_ref())
+            .copied()
+            .unwrap_or(0);
         per_domain_socket
             .entry((domain.name.clone(), domain.socket))
-            .and_modify(|v| *v += diff_uj)
+            .and_modify(|v| *v = v.saturating_add(diff_uj))
             .or_insert(diff_uj);
     }
 
@@ -78,26 +98,86 @@ pub fn compute_measurement_from_snapshots(
         energy_uj.len()
     );
 
-    Ok(energy_uj)
+    energy_uj
 }
 
+/// Energy ranges above this are 64-bit counters that never wrap in practice (2^48 µJ is
+/// about 9 years at 1 kW).
+const NON_WRAPPING_MAX_ENERGY_UJ: u64 = 1 << 48;
+
 /// Compute the energy difference between two measures, handle overflows with max value.
+///
+/// At most one wrap is assumed between the two reads: when the counter consumed its whole
+/// range or more in between (a slow polling or a small range, some DRAM domains wrap every
+/// minute), the energy is under-counted by whole multiples of `max`, see
+/// [`may_wrap_more_than_once`]. A decrease of a non-wrapping counter is not an overflow, it
+/// is reported as no energy.
 fn energy_diff(start: u64, end: u64, max: u64) -> u64 {
     if end >= start {
         end - start
-    } else {
+    } else if counter_wrapped(start, end, max) {
         (max - start) + end
+    } else {
+        warn!(
+            "Energy counter decreased from {} to {} µJ without wrapping (range {} µJ), ignoring \
+             the interval",
+            start, end, max
+        );
+        0
     }
 }
 
+/// Whether the counter wrapped between the two reads, [`energy_diff`] then adding its range.
+pub fn counter_wrapped(start: u64, end: u64, max: u64) -> bool {
+    end < start && max <= NON_WRAPPING_MAX_ENERGY_UJ
+}
+
+/// Domains whose counter wrapped between the two snapshots, the ones missing from a snapshot
+/// being left out.
+pub fn wrapped_domains<'a>(
+    domains: &'a [RaplDomain],
+    begin: &EnergySnapshot,
+    end: &EnergySnapshot,
+) -> Vec<&'a RaplDomain> {
+    domains
+        .iter()
+        .filter(|domain| {
+            let key = domain.path.to_string_lossy();
+            match (
+                begin.energies_uj.get(key.as_ref()),
+                end.energies_uj.get(key.as_ref()),
+            ) {
+                (Some(start), Some(end)) => counter_wrapped(*start, *end, domain.max_energy_uj),
+                _ => false,
+            }
+        })
+        .collect()
+}
+
+/// Whether a counter consuming `power_uj_per_us` may have wrapped more than once over the
+/// interval, its energy reaching the whole range that [`energy_diff`] cannot see.
+pub fn may_wrap_more_than_once(interval_us: u128, power_uj_per_us: f64, max: u64) -> bool {
+    max <= NON_WRAPPING_MAX_ENERGY_UJ && interval_us as f64 * power_uj_per_us >= max as f64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn compute_measurement(
+        domains: &[RaplDomain],
+        begin: &EnergySnapshot,
+        end: &EnergySnapshot,
+    ) -> Result<HashMap<String, u64>> {
+        let diffs = compute_domain_diffs(domains, begin, end)?;
+        Ok(aggregate_domain_diffs(domains, &diffs))
+    }
+
     fn snapshot(values: &[(&str, u64)]) -> EnergySnapshot {
         EnergySnapshot {
             energies_uj: values.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
             timestamp_us: 0,
+            read_spread_us: 0,
         }
     }
 
@@ -122,12 +202,58 @@ mod tests {
         assert_eq!(diff, 200);
     }
 
+    #[test]
+    fn long_intervals_at_high_power_may_wrap_several_times() {
+        // 20 W over 60 s is 1.2 MJ, above a range of 1 MJ
+        assert!(may_wrap_more_than_once(60_000_000, 20.0, 1_000_000_000));
+        assert!(!may_wrap_more_than_once(10_000_000, 20.0, 1_000_000_000));
+        assert!(!may_wrap_more_than_once(60_000_000, 20.0, u64::MAX));
+    }
+
     #[test]
     fn energy_diff_exact_wrap() {
         let diff = super::energy_diff(900, 0, 1_000);
         assert_eq!(diff, 100);
     }
 
+    #[test]
+    fn energy_diff_never_wraps_64_bit_counters() {
+        let diff = super::energy_diff(5_000_000, 1_000, u64::MAX);
+        assert_eq!(diff, 0);
+
+        let domains = vec![domain("package", 0, "/sys/powercap/package0", 1 << 60)];
+        let begin = snapshot(&[("/sys/powercap/package0", 5_000_000)]);
+        let end = snapshot(&[("/sys/powercap/package0", 1_000)]);
+        let result = compute_measurement(&domains, &begin, &end).unwrap();
+        assert_eq!(result["PACKAGE_0"], 0);
+
+        // The 32-bit range still wraps
+        assert_eq!(
+            super::energy_diff(900, 100, u32::MAX as u64),
+            u32::MAX as u64 - 800
+        );
+    }
+
+    #[test]
+    fn only_the_decreasing_wrapping_counters_have_wrapped() {
+        assert!(counter_wrapped(900, 100, 1_000));
+        assert!(!counter_wrapped(100, 900, 1_000));
+        assert!(!counter_wrapped(900, 100, u64::MAX));
+
+        let domains = vec![
+            domain("package", 0, "/pkg0", 1_000),
+            domain("dram", 0, "/dram0", 1_000),
+            domain("core", 0, "/core0", 1_000),
+        ];
+        let begin = snapshot(&[("/pkg0", 900), ("/dram0", 100), ("/core0", 900)]);
+        let end = snapshot(&[("/pkg0", 100), ("/dram0", 200)]);
+        let wrapped: Vec<&str> = wrapped_domains(&domains, &begin, &end)
+            .iter()
+            .map(|domain| domain.name.as_str())
+            .collect();
+        assert_eq!(wrapped, ["package"]);
+    }
+
     #[test]
     fn compute_single_domain_single_socket() {
         let domains = vec![domain("package", 0, "/sys/powercap/package0", 1_000)];
@@ -135,7 +261,7 @@ mod tests {
         let begin = snapshot(&[("/sys/powercap/package0", 100)]);
         let end = snapshot(&[("/sys/powercap/package0", 250)]);
 
-        let result = compute_measurement_from_snapshots(&domains, &begin, &end).unwrap();
+        let result = compute_measurement(&domains, &begin, &end).unwrap();
 
         assert_eq!(result.len(), 1);
         assert_eq!(result["PACKAGE_0"], 150);
@@ -148,7 +274,7 @@ mod tests {
         let begin = snapshot(&[("/sys/powercap/package0", 900)]);
         let end = snapshot(&[("/sys/powercap/package0", 100)]);
 
-        let result = compute_measurement_from_snapshots(&domains, &begin, &end).unwrap();
+        let result = compute_measurement(&domains, &begin, &end).unwrap();
 
         assert_eq!(result["PACKAGE_0"], 200);
     }
@@ -164,7 +290,7 @@ mod tests {
 
         let end = snapshot(&[("/core0", 300), ("/core1", 500)]);
 
-        let result = compute_measurement_from_snapshots(&domains, &begin, &end).unwrap();
+        let result = compute_measurement(&domains, &begin, &end).unwrap();
 
         // (300-100) + (500-200) = 200 + 300 = 500
         assert_eq!(result["CORE_0"], 500);
@@ -181,7 +307,7 @@ mod tests {
 
         let end = snapshot(&[("/pkg0", 200), ("/pkg1", 700)]);
 
-        let result = compute_measurement_from_snapshots(&domains, &begin, &end).unwrap();
+        let result = compute_measurement(&domains, &begin, &end).unwrap();
 
         assert_eq!(result["PACKAGE_0"], 100);
         assert_eq!(result["PACKAGE_1"], 300);
@@ -194,7 +320,7 @@ mod tests {
         let begin = snapshot(&[]);
         let end = snapshot(&[("/pkg0", 100)]);
 
-        let err = compute_measurement_from_snapshots(&domains, &begin, &end)
+        let err = compute_measurement(&domains, &begin, &end)
             .unwrap_err()
             .to_string();
 
@@ -208,7 +334,7 @@ mod tests {
         let begin = snapshot(&[("/pkg0", 100)]);
         let end = snapshot(&[]);
 
-        let err = compute_measurement_from_snapshots(&domains, &begin, &end)
+        let err = compute_measurement(&domains, &begin, &end)
             .unwrap_err()
             .to_string();
 
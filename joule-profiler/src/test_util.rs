@@ -0,0 +1,34 @@
+//! Helpers shared by the unit tests.
+
+use crate::{config::ProfileConfig, source::Metric};
+use std::{
+    fs::{create_dir_all, write},
+    path::Path,
+};
+
+/// Write a powercap tree with a single package domain under `base`, to measure without RAPL.
+pub fn fake_rapl_tree(base: &Path) {
+    let dir = base.join("intel-rapl:0");
+    create_dir_all(&dir).unwrap();
+    write(dir.join("name"), "package-0").unwrap();
+    write(dir.join("energy_uj"), "1000").unwrap();
+    write(dir.join("max_energy_range_uj"), "1000000").unwrap();
+}
+
+/// An energy metric of the powercap source, in µJ.
+pub fn metric(name: &str, value: u64) -> Metric {
+    metric_in(name, value, "µJ")
+}
+
+/// A metric of the powercap source in the given `unit`.
+pub fn metric_in(name: &str, value: u64, unit: &str) -> Metric {
+    Metric::new(name, value, unit, "powercap")
+}
+
+/// The profile configuration of `./bench --fast`, the other options left to their defaults.
+pub fn config() -> ProfileConfig {
+    ProfileConfig {
+        cmd: vec!["./bench".to_string(), "--fast".to_string()],
+        ..Default::default()
+    }
+}
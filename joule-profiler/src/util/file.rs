@@ -11,23 +11,45 @@ const ROOT_UID_ENV_VAR: &str = "SUDO_UID";
 const ROOT_GID_ENV_VAR: &str = "SUDO_GID";
 const URW_GRW_OR_PERMS: u32 = 0o664;
 
+/// Permissions of the created output files, `rw-rw-r--` by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u32);
+
+impl Default for FileMode {
+    fn default() -> Self {
+        Self(URW_GRW_OR_PERMS)
+    }
+}
+
 /// Create a file with user permissions in case of running with root permissions.
-pub fn create_file_with_user_permissions(path: &str) -> Result<File> {
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(path)?;
+pub fn create_file_with_user_permissions(path: &str, mode: FileMode) -> Result<File> {
+    open_with_user_permissions(path, mode, false)
+}
+
+/// Open a file to write at its end, created with user permissions if it does not exist.
+pub fn append_file_with_user_permissions(path: &str, mode: FileMode) -> Result<File> {
+    open_with_user_permissions(path, mode, true)
+}
 
-    file.set_permissions(Permissions::from_mode(URW_GRW_OR_PERMS))?;
+fn open_with_user_permissions(path: &str, mode: FileMode, append: bool) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    let file = options.open(path)?;
 
-    let uid: u32 = env::var(ROOT_UID_ENV_VAR)?
-        .parse()
-        .context("Unable to parse root UID to u32")?;
+    file.set_permissions(Permissions::from_mode(mode.0))?;
 
-    let gid: u32 = env::var(ROOT_GID_ENV_VAR)?
-        .parse()
-        .context("Unable to parse root GID to u32")?;
+    // Not running through sudo, the file already belongs to the current user
+    let (Ok(uid), Ok(gid)) = (env::var(ROOT_UID_ENV_VAR), env::var(ROOT_GID_ENV_VAR)) else {
+        return Ok(file);
+    };
+
+    let uid: u32 = uid.parse().context("Unable to parse root UID to u32")?;
+    let gid: u32 = gid.parse().context("Unable to parse root GID to u32")?;
 
     chown(path, Some(uid), Some(gid))?;
 
@@ -45,3 +67,47 @@ pub fn get_absolute_path(filename: &str) -> Result<String> {
 
     Ok(absolute_path.display().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::metadata;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn file_is_created_with_the_given_mode() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("result.json");
+
+        create_file_with_user_permissions(&path.to_string_lossy(), FileMode(0o600)).unwrap();
+
+        let mode = metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn appended_file_keeps_its_content() {
+        use std::io::Write;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("results.csv");
+        let path = path.to_string_lossy();
+
+        writeln!(
+            append_file_with_user_permissions(&path, FileMode::default()).unwrap(),
+            "a"
+        )
+        .unwrap();
+        writeln!(
+            append_file_with_user_permissions(&path, FileMode::default()).unwrap(),
+            "b"
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&*path).unwrap(), "a\nb\n");
+
+        create_file_with_user_permissions(&path, FileMode::default()).unwrap();
+        assert_eq!(std::fs::read_to_string(&*path).unwrap(), "");
+    }
+}
@@ -0,0 +1,42 @@
+/// Match a name against a glob where `*` matches any sequence and `?` any single character.
+pub fn glob_matches(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut g, mut n) = (0, 0);
+    // Position of the last `*` in the glob and of the name when it was met, to backtrack
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == name[n]) {
+            g += 1;
+            n += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            star = Some((g, n));
+            g += 1;
+        } else if let Some((star_g, star_n)) = star {
+            g = star_g + 1;
+            n = star_n + 1;
+            star = Some((star_g, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("*_0", "DRAM_0"));
+        assert!(glob_matches("PACKAGE-?_1", "PACKAGE-1_1"));
+        assert!(glob_matches("*AM*", "DRAM_1"));
+        assert!(glob_matches("CORE_0", "CORE_0"));
+        assert!(!glob_matches("CORE", "CORE_0"));
+        assert!(!glob_matches("?", ""));
+    }
+}
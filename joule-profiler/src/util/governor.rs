@@ -0,0 +1,155 @@
+use std::{
+    fs::{read_dir, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+/// Sysfs directory holding the `cpuN/cpufreq/scaling_governor` files.
+pub const CPU_SYSFS_PATH: &str = "/sys/devices/system/cpu";
+
+/// Governor set during the measurement to reduce frequency scaling variance.
+pub const PERFORMANCE_GOVERNOR: &str = "performance";
+
+/// Restores the saved CPU frequency governors when dropped, even if the measurement failed.
+#[derive(Debug)]
+pub struct GovernorGuard {
+    saved: Vec<(PathBuf, String)>,
+}
+
+impl Drop for GovernorGuard {
+    fn drop(&mut self) {
+        for (path, governor) in &self.saved {
+            match write(path, governor) {
+                Ok(()) => debug!("Restored governor {} in {}", governor, path.display()),
+                Err(e) => warn!(
+                    "Failed to restore governor {} in {}: {}",
+                    governor,
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// Set the `performance` governor on all the CPUs for the lifetime of the returned guard.
+///
+/// Requires root, otherwise or if the governors cannot be changed a warning is logged and
+/// the measurement continues with the current governors.
+pub fn stabilize_governor(cpu_root: &Path) -> Option<GovernorGuard> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    if unsafe { libc::geteuid() } != 0 {
+        warn!("--stabilize-governor requires root, keeping the current CPU governors");
+        return None;
+    }
+
+    match set_governor(cpu_root, PERFORMANCE_GOVERNOR) {
+        Ok(guard) => {
+            info!(
+                "Set the {} governor on {} CPU(s)",
+                PERFORMANCE_GOVERNOR,
+                guard.saved.len()
+            );
+            Some(guard)
+        }
+        Err(e) => {
+            warn!("Failed to set the CPU governors, continuing: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Write the governor of every CPU found in the tree, saving the previous ones in the guard.
+///
+/// The governors already changed are restored if one of the writes fails.
+pub fn set_governor(cpu_root: &Path, governor: &str) -> Result<GovernorGuard> {
+    let mut guard = GovernorGuard { saved: Vec::new() };
+
+    for path in governor_paths(cpu_root)? {
+        let previous = read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .trim()
+            .to_string();
+        write(&path, governor).with_context(|| format!("Failed to write {}", path.display()))?;
+        guard.saved.push((path, previous));
+    }
+
+    Ok(guard)
+}
+
+/// Paths of the `scaling_governor` files of the CPUs, sorted.
+fn governor_paths(cpu_root: &Path) -> Result<Vec<PathBuf>> {
+    let entries =
+        read_dir(cpu_root).with_context(|| format!("Failed to read {}", cpu_root.display()))?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.strip_prefix("cpu")
+                .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+        })
+        .map(|entry| entry.path().join("cpufreq").join("scaling_governor"))
+        .filter(|path| path.exists())
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn add_cpu(root: &Path, name: &str, governor: &str) -> PathBuf {
+        let cpufreq = root.join(name).join("cpufreq");
+        create_dir_all(&cpufreq).unwrap();
+        let path = cpufreq.join("scaling_governor");
+        write(&path, format!("{}\n", governor)).unwrap();
+        path
+    }
+
+    fn governor(path: &Path) -> String {
+        read_to_string(path).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn governors_are_set_then_restored_on_drop() {
+        let dir = tempdir().unwrap();
+        let cpu0 = add_cpu(dir.path(), "cpu0", "powersave");
+        let cpu1 = add_cpu(dir.path(), "cpu1", "schedutil");
+        // Not a CPU directory, must be left untouched
+        let cpufreq = add_cpu(dir.path(), "cpufreq", "powersave");
+
+        let guard = set_governor(dir.path(), PERFORMANCE_GOVERNOR).unwrap();
+        assert_eq!(governor(&cpu0), "performance");
+        assert_eq!(governor(&cpu1), "performance");
+        assert_eq!(governor(&cpufreq), "powersave");
+
+        drop(guard);
+        assert_eq!(governor(&cpu0), "powersave");
+        assert_eq!(governor(&cpu1), "schedutil");
+    }
+
+    #[test]
+    fn governors_are_restored_when_measurement_fails() {
+        let dir = tempdir().unwrap();
+        let cpu0 = add_cpu(dir.path(), "cpu0", "powersave");
+
+        let measure = || -> Result<()> {
+            let _guard = set_governor(dir.path(), PERFORMANCE_GOVERNOR)?;
+            assert_eq!(governor(&cpu0), "performance");
+            anyhow::bail!("measurement failed")
+        };
+
+        assert!(measure().is_err());
+        assert_eq!(governor(&cpu0), "powersave");
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::{
+    Once,
+    atomic::{AtomicBool, Ordering},
+};
+
+use log::warn;
+
+/// Exit code of the profiler interrupted by SIGINT, as a shell reports it (128 + SIGINT).
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+static WATCH: Once = Once::new();
+
+/// Handle SIGINT (Ctrl-C) instead of dying, so that the results of the completed iterations
+/// are still written.
+///
+/// The iteration running when the signal arrives is completed: the command, receiving the
+/// interrupt from the terminal as well, exits and the sources are joined as usual. A second
+/// interrupt exits immediately, e.g. when the command ignores SIGINT.
+pub fn watch_interrupt() {
+    WATCH.call_once(|| {
+        tokio::spawn(async {
+            if tokio::signal::ctrl_c().await.is_err() {
+                warn!("Cannot listen to SIGINT, an interrupt loses the results");
+                return;
+            }
+            warn!("Interrupted, writing the results measured so far (interrupt again to abort)");
+            INTERRUPTED.store(true, Ordering::SeqCst);
+
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+        });
+    });
+}
+
+/// Whether the profiler received SIGINT since [`watch_interrupt`].
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
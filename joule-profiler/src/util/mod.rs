@@ -1,2 +1,9 @@
 pub mod file;
+pub mod glob;
+pub mod governor;
+pub mod interrupt;
+pub mod page_cache;
+pub mod pty;
+pub mod stats;
+pub mod sysinfo;
 pub mod time;
@@ -0,0 +1,56 @@
+use std::{fs::write, path::Path};
+
+use log::{debug, warn};
+
+/// Procfs file freeing the page cache, dentries and inodes when `3` is written to it.
+pub const DROP_CACHES_PATH: &str = "/proc/sys/vm/drop_caches";
+
+/// Write the dirty pages back and drop the page cache through `path` (requires root), so
+/// that the next iteration starts from a cold cache.
+///
+/// Returns `false` with a warning if the caches cannot be dropped, the iterations continue
+/// with a warm cache.
+pub fn drop_page_cache(path: &Path) -> bool {
+    // SAFETY: sync has no preconditions and cannot fail
+    unsafe { libc::sync() };
+
+    match write(path, "3") {
+        Ok(()) => {
+            debug!("Dropped the page cache");
+            true
+        }
+        Err(e) => {
+            warn!(
+                "Cannot drop the page cache with {} (requires root), continuing with a warm \
+                 cache: {}",
+                path.display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn caches_are_dropped_by_writing_3() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("drop_caches");
+
+        assert!(drop_page_cache(&path));
+        assert_eq!(read_to_string(path).unwrap(), "3");
+    }
+
+    #[test]
+    fn unwritable_drop_caches_is_skipped() {
+        let dir = tempdir().unwrap();
+        assert!(!drop_page_cache(&dir.path().join("missing/drop_caches")));
+    }
+}
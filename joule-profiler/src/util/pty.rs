@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::fd::{FromRawFd, OwnedFd},
+    ptr,
+};
+
+use anyhow::Result;
+
+use crate::error::JouleProfilerError;
+
+/// Open a pseudo-terminal and return its master and slave sides.
+///
+/// Output post-processing is disabled so lines written by the child keep their `\n` endings.
+pub fn open_pty() -> Result<(OwnedFd, OwnedFd)> {
+    let mut master = -1;
+    let mut slave = -1;
+
+    // SAFETY: both pointers are valid for writes, the name, termios and winsize are optional
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    if ret != 0 {
+        return Err(JouleProfilerError::CommandExecutionFailed(format!(
+            "Failed to open a pseudo-terminal: {}",
+            io::Error::last_os_error()
+        ))
+        .into());
+    }
+
+    // SAFETY: openpty succeeded so both descriptors are open and owned by us
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    disable_output_processing(&slave)?;
+    Ok((master, slave))
+}
+
+fn disable_output_processing(slave: &OwnedFd) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: termios is plain data, it is filled by tcgetattr before being used
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: the descriptor is open and termios is valid for writes
+    if unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    termios.c_oflag &= !libc::OPOST;
+
+    // SAFETY: the descriptor is open and termios was initialized by tcgetattr
+    if unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Reader of the master side of a pseudo-terminal.
+///
+/// Linux reports `EIO` once every slave descriptor is closed, which is the end of the output.
+pub struct PtyReader(File);
+
+impl PtyReader {
+    pub fn new(master: OwnedFd) -> Self {
+        Self(File::from(master))
+    }
+}
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
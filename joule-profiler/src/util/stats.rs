@@ -0,0 +1,119 @@
+/// Least squares fit of `y = slope * x + intercept`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearRegression {
+    pub slope: f64,
+    pub intercept: f64,
+    /// Coefficient of determination in [0, 1], zero when the values are constant
+    pub r_squared: f64,
+}
+
+/// Fit a line through the points, `None` with less than two distinct `x`.
+pub fn linear_regression(points: &[(f64, f64)]) -> Option<LinearRegression> {
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+
+    let ss_xx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let ss_xy: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let ss_yy: f64 = points.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+    if points.len() < 2 || ss_xx == 0.0 {
+        return None;
+    }
+
+    let slope = ss_xy / ss_xx;
+    let r_squared = if ss_yy == 0.0 {
+        0.0
+    } else {
+        ss_xy * ss_xy / (ss_xx * ss_yy)
+    };
+
+    Some(LinearRegression {
+        slope,
+        intercept: mean_y - slope * mean_x,
+        r_squared,
+    })
+}
+
+/// Iterations of the two-means split in [`step_levels`], enough for the levels to settle.
+const STEP_ITERATIONS: usize = 20;
+
+/// Low and high levels of a signal switching between two states (e.g. the power with turbo
+/// boost engaging and disengaging).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepLevels {
+    pub low: f64,
+    pub high: f64,
+}
+
+/// Split the values into a low and a high level (two-means clustering), `None` when the high
+/// level is less than `min_ratio` times the low one, the values being then a single state.
+pub fn step_levels(values: &[f64], min_ratio: f64) -> Option<StepLevels> {
+    let mut low = values.iter().copied().reduce(f64::min)?;
+    let mut high = values.iter().copied().reduce(f64::max)?;
+
+    for _ in 0..STEP_ITERATIONS {
+        let threshold = (low + high) / 2.0;
+        let (lows, highs): (Vec<f64>, Vec<f64>) = values.iter().partition(|v| **v < threshold);
+        if lows.is_empty() || highs.is_empty() {
+            return None;
+        }
+        low = lows.iter().sum::<f64>() / lows.len() as f64;
+        high = highs.iter().sum::<f64>() / highs.len() as f64;
+    }
+
+    (high > low * min_ratio).then_some(StepLevels { low, high })
+}
+
+impl StepLevels {
+    /// Fraction of the values in the high state and number of switches between the states.
+    pub fn occupancy(&self, values: &[f64]) -> (f64, usize) {
+        let threshold = (self.low + self.high) / 2.0;
+        let states: Vec<bool> = values.iter().map(|value| *value >= threshold).collect();
+
+        let high = states.iter().filter(|high| **high).count();
+        let steps = states.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        (high as f64 / values.len().max(1) as f64, steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regression_fits_a_noisy_line() {
+        let points = [(1.0, 2.1), (2.0, 3.9), (3.0, 6.2), (4.0, 7.8)];
+
+        let fit = linear_regression(&points).unwrap();
+
+        assert!((fit.slope - 1.94).abs() < 1e-9);
+        assert!((fit.intercept - 0.15).abs() < 1e-9);
+        assert!(fit.r_squared > 0.99 && fit.r_squared <= 1.0);
+        assert_eq!(linear_regression(&[(1.0, 5.0)]), None);
+        assert_eq!(linear_regression(&[(1.0, 5.0), (1.0, 6.0)]), None);
+    }
+
+    #[test]
+    fn bimodal_power_is_split_into_two_levels() {
+        // 20 W, then turbo at 45 W for 4 of the 10 intervals in two bursts
+        let power = [20.1, 19.8, 45.3, 44.9, 20.2, 20.0, 19.9, 45.1, 44.7, 20.0];
+
+        let levels = step_levels(&power, 1.25).unwrap();
+        assert!((levels.low - 20.0).abs() < 0.1);
+        assert!((levels.high - 45.0).abs() < 0.1);
+
+        let (high_fraction, steps) = levels.occupancy(&power);
+        assert!((high_fraction - 0.4).abs() < 1e-9);
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn steady_power_has_a_single_level() {
+        assert_eq!(step_levels(&[20.0, 21.0, 19.5, 20.5], 1.25), None);
+        assert_eq!(step_levels(&[20.0, 20.0], 1.25), None);
+        assert_eq!(step_levels(&[], 1.25), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{fs::read_to_string, path::Path};
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::util::time::now_iso8601_utc;
+
+/// Procfs mount point, read for the machine metadata.
+pub const PROC_PATH: &str = "/proc";
+
+/// State of the CPUs affecting the energy of a run, recorded with the results.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SysInfo {
+    /// Whether turbo boost is enabled, `None` if the driver does not expose it
+    pub turbo: Option<bool>,
+    /// Simultaneous multithreading control (`on`, `off`, `notsupported`...)
+    pub smt: Option<String>,
+    /// Frequency governor of the first CPU
+    pub governor: Option<String>,
+}
+
+/// Read a sysfs value, `None` if the file is missing or unreadable.
+fn read_value(path: &Path) -> Option<String> {
+    match read_to_string(path) {
+        Ok(value) => Some(value.trim().to_string()),
+        Err(e) => {
+            debug!("Cannot read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Read the CPUs state from the sysfs CPU directory (e.g. `/sys/devices/system/cpu`).
+///
+/// Turbo boost is read from `intel_pstate/no_turbo`, or from `cpufreq/boost` with the
+/// other drivers (e.g. acpi-cpufreq on AMD).
+pub fn read_sysinfo(cpu_root: &Path) -> SysInfo {
+    let turbo = match read_value(&cpu_root.join("intel_pstate/no_turbo")) {
+        Some(no_turbo) => Some(no_turbo == "0"),
+        None => read_value(&cpu_root.join("cpufreq/boost")).map(|boost| boost == "1"),
+    };
+
+    SysInfo {
+        turbo,
+        smt: read_value(&cpu_root.join("smt/control")),
+        governor: read_value(&cpu_root.join("cpu0/cpufreq/scaling_governor")),
+    }
+}
+
+/// Machine that produced the results, to tell archived runs apart. The values that cannot
+/// be read are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Metadata {
+    pub hostname: Option<String>,
+    /// Model name of the first CPU, as in `/proc/cpuinfo`
+    pub cpu_model: Option<String>,
+    /// Kernel release (e.g. `6.8.0-45-generic`)
+    pub kernel: Option<String>,
+    /// Number of sockets with measured metrics
+    pub sockets: Option<usize>,
+    /// RAPL base path the domains are read from
+    pub rapl_path: Option<String>,
+    /// ISO-8601 UTC date and time the results were written
+    pub timestamp: String,
+}
+
+/// Read the machine metadata from the procfs directory (e.g. `/proc`), the sockets and the
+/// RAPL path being known from the measurement.
+pub fn read_metadata(
+    proc_root: &Path,
+    sockets: Option<usize>,
+    rapl_path: Option<String>,
+) -> Metadata {
+    let cpu_model = read_value(&proc_root.join("cpuinfo")).and_then(|cpuinfo| {
+        cpuinfo.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == "model name").then(|| value.trim().to_string())
+        })
+    });
+
+    Metadata {
+        hostname: read_value(&proc_root.join("sys/kernel/hostname")),
+        cpu_model,
+        kernel: read_value(&proc_root.join("sys/kernel/osrelease")),
+        sockets,
+        rapl_path,
+        timestamp: now_iso8601_utc(),
+    }
+}
+
+/// Warn about the CPUs state adding variance to the measurements.
+pub fn warn_variance(info: &SysInfo) {
+    if info.turbo == Some(true) {
+        warn!("Turbo boost is enabled, the CPU frequency and energy vary between runs");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn cpu_state_is_read_from_sysfs() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("intel_pstate")).unwrap();
+        create_dir_all(root.join("smt")).unwrap();
+        create_dir_all(root.join("cpu0/cpufreq")).unwrap();
+        write(root.join("intel_pstate/no_turbo"), "0\n").unwrap();
+        write(root.join("smt/control"), "on\n").unwrap();
+        write(root.join("cpu0/cpufreq/scaling_governor"), "powersave\n").unwrap();
+
+        assert_eq!(
+            read_sysinfo(root),
+            SysInfo {
+                turbo: Some(true),
+                smt: Some("on".to_string()),
+                governor: Some("powersave".to_string()),
+            }
+        );
+
+        write(root.join("intel_pstate/no_turbo"), "1\n").unwrap();
+        assert_eq!(read_sysinfo(root).turbo, Some(false));
+    }
+
+    #[test]
+    fn metadata_is_read_from_procfs() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("sys/kernel")).unwrap();
+        write(root.join("sys/kernel/hostname"), "bench-01\n").unwrap();
+        write(root.join("sys/kernel/osrelease"), "6.8.0-45-generic\n").unwrap();
+        write(
+            root.join("cpuinfo"),
+            "processor\t: 0\nvendor_id\t: GenuineIntel\nmodel name\t: Intel(R) Xeon(R) Gold 6130\n",
+        )
+        .unwrap();
+
+        let metadata = read_metadata(root, Some(2), Some("/rapl".to_string()));
+        assert_eq!(metadata.hostname.as_deref(), Some("bench-01"));
+        assert_eq!(metadata.kernel.as_deref(), Some("6.8.0-45-generic"));
+        assert_eq!(
+            metadata.cpu_model.as_deref(),
+            Some("Intel(R) Xeon(R) Gold 6130")
+        );
+        assert_eq!(metadata.sockets, Some(2));
+
+        // Nothing readable, the run is not failed
+        let metadata = read_metadata(&root.join("missing"), None, None);
+        assert_eq!(metadata.hostname, None);
+        assert_eq!(metadata.cpu_model, None);
+        assert!(metadata.timestamp.ends_with('Z'));
+    }
+
+    #[test]
+    fn boost_is_read_without_intel_pstate() {
+        let dir = tempdir().unwrap();
+        create_dir_all(dir.path().join("cpufreq")).unwrap();
+        write(dir.path().join("cpufreq/boost"), "1\n").unwrap();
+
+        let info = read_sysinfo(dir.path());
+        assert_eq!(info.turbo, Some(true));
+        assert_eq!(info.smt, None);
+        assert_eq!(info.governor, None);
+    }
+}
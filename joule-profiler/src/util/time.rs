@@ -7,3 +7,54 @@ pub fn get_timestamp() -> u128 {
         .unwrap_or(Duration::from_secs(0))
         .as_micros()
 }
+
+/// Format seconds since the Unix epoch as an ISO-8601 UTC date and time
+/// (`2024-01-31T12:00:00Z`).
+pub fn iso8601_utc(secs: u64) -> String {
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date of the days since the epoch, with the years starting in March so that the
+    // leap day ends them (H. Hinnant's days_from_civil inverse)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Current date and time as an ISO-8601 UTC string.
+pub fn now_iso8601_utc() -> String {
+    iso8601_utc((get_timestamp() / 1_000_000) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_formatted_in_utc() {
+        assert_eq!(iso8601_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso8601_utc(1_706_702_461), "2024-01-31T12:01:01Z");
+        assert_eq!(iso8601_utc(1_735_689_599), "2024-12-31T23:59:59Z");
+    }
+}
@@ -0,0 +1,715 @@
+//! End-to-end tests of the output formats, measuring with the fake source instead of RAPL.
+
+use std::{
+    fs::read_to_string,
+    process::{Command, Output},
+};
+
+use serde_json::Value;
+use tempfile::tempdir;
+
+const FAKE_SOURCE: &str = "PACKAGE-0_0=1500000,DRAM_0=20000";
+
+fn run_joule_profiler(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_joule-profiler"))
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
+
+fn joule_profiler(args: &[&str]) -> String {
+    let output = run_joule_profiler(args);
+    assert!(
+        output.status.success(),
+        "joule-profiler failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn terminal_output_displays_the_fake_metrics() {
+    let stdout = joule_profiler(&["simple", "--fake-source", FAKE_SOURCE, "--", "true"]);
+
+    assert!(stdout.contains("[fake]"));
+    assert!(stdout.contains("PACKAGE-0_0         :    1500000 µJ"));
+    assert!(stdout.contains("DRAM_0              :      20000 µJ"));
+}
+
+#[test]
+fn csv_output_has_a_row_per_iteration() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("runs.csv");
+    let path = path.to_str().unwrap();
+
+    joule_profiler(&[
+        "simple",
+        "--csv",
+        "--jouleit-file",
+        path,
+        "-n",
+        "2",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+
+    let csv = read_to_string(path).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    // Two iterations followed by the mean, stddev, min, median and max rows
+    assert_eq!(lines.len(), 8);
+    assert!(lines[0].starts_with("command;iteration;DRAM_0;PACKAGE-0_0;"));
+    assert!(lines[1].starts_with("true;0;20000;1500000;"));
+    assert!(lines[2].starts_with("true;1;20000;1500000;"));
+    assert!(lines[3].starts_with("true;mean;20000."));
+}
+
+#[test]
+fn appended_runs_are_accumulated_in_one_file() {
+    let dir = tempdir().unwrap();
+    let csv_path = dir.path().join("runs.csv");
+    let json_path = dir.path().join("runs.json");
+
+    for (format, path) in [("--csv", &csv_path), ("--json", &json_path)] {
+        for _ in 0..2 {
+            joule_profiler(&[
+                "simple",
+                format,
+                "--jouleit-file",
+                path.to_str().unwrap(),
+                "--append",
+                "--fake-source",
+                FAKE_SOURCE,
+                "--",
+                "true",
+            ]);
+        }
+    }
+
+    // A single header for the rows of both runs
+    let csv = read_to_string(&csv_path).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("command;"));
+    assert!(lines[1..].iter().all(|line| line.starts_with("true;")));
+
+    // One document per line
+    let json = read_to_string(&json_path).unwrap();
+    let documents: Vec<Value> = json
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(documents.len(), 2);
+    assert!(documents.iter().all(|document| document["exit_code"] == 0));
+}
+
+#[test]
+fn json_output_reports_the_metrics_and_exit_code() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("run.json");
+    let path = path.to_str().unwrap();
+
+    let output = run_joule_profiler(&[
+        "simple",
+        "--json",
+        "--jouleit-file",
+        path,
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "exit 3",
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let json: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+    assert_eq!(json["mode"], "simple");
+    assert_eq!(json["exit_code"], 3);
+    assert_eq!(json["metadata"]["sockets"], 1);
+    assert!(json["metadata"]["timestamp"].is_string());
+    let metrics = json["metrics"].as_array().unwrap();
+    assert_eq!(metrics.len(), 2);
+    assert!(json["power_w"]["PACKAGE-0_0"].is_number());
+    assert!(metrics.iter().any(|metric| {
+        metric["name"] == "PACKAGE-0_0"
+            && metric["value"] == 1_500_000
+            && metric["source"] == "fake"
+    }));
+}
+
+#[test]
+fn procfs_reports_the_memory_and_cpu_time_of_the_command() {
+    let stdout = joule_profiler(&[
+        "simple",
+        "--json",
+        "--jouleit-file",
+        "-",
+        "--procfs",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done",
+    ]);
+
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    let metrics = json["metrics"].as_array().unwrap();
+    let metric = |name: &str| {
+        metrics
+            .iter()
+            .find(|metric| metric["name"] == name)
+            .unwrap_or_else(|| panic!("{} missing from {:?}", name, metrics))
+    };
+    assert_eq!(metric("PEAK_RSS_KB")["unit"], "kB");
+    assert!(metric("PEAK_RSS_KB")["value"].as_u64().unwrap() > 0);
+    assert_eq!(metric("CPU_TIME_MS")["unit"], "ms");
+    assert_eq!(metric("CPU_TIME_MS")["source"], "procfs");
+    // The memory and CPU time have no power
+    assert!(json["power_w"].get("PEAK_RSS_KB").is_none());
+    assert_eq!(metric("PACKAGE-0_0")["value"], 1_500_000);
+}
+
+#[test]
+fn energy_unit_converts_the_json_metrics() {
+    let stdout = joule_profiler(&[
+        "simple",
+        "--json",
+        "--jouleit-file",
+        "-",
+        "--unit",
+        "j",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    let package = json["metrics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|metric| metric["name"] == "PACKAGE-0_0")
+        .unwrap();
+    assert_eq!(package["value"], 1.5);
+    assert_eq!(package["unit"], "J");
+}
+
+#[test]
+fn energy_unit_converts_the_json_summary() {
+    let stdout = joule_profiler(&[
+        "simple",
+        "--json",
+        "--jouleit-file",
+        "-",
+        "-n",
+        "2",
+        "--unit",
+        "j",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    let package = json["summary"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|metric| metric["name"] == "PACKAGE-0_0")
+        .unwrap();
+    assert_eq!(package["unit"], "J");
+    assert_eq!(package["mean"], 1.5);
+    assert_eq!(package["max"], 1.5);
+}
+
+#[test]
+fn quiet_discards_the_program_output_but_still_detects_the_tokens() {
+    let stdout = joule_profiler(&[
+        "simple",
+        "--quiet",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "echo",
+        "chatty",
+    ]);
+    assert!(!stdout.lines().any(|line| line == "chatty"));
+    assert!(stdout.contains("[fake]"));
+
+    // Written to a file, the program output would otherwise go to stdout
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("phases.json");
+    let path = path.to_str().unwrap();
+    let stdout = joule_profiler(&[
+        "phases",
+        "--quiet",
+        "--json",
+        "--jouleit-file",
+        path,
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "echo chatty; echo __A__",
+    ]);
+    assert!(!stdout.lines().any(|line| line == "chatty"));
+    let json: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+    assert_eq!(json["phases"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn quiet_also_discards_the_merged_stderr() {
+    let output = run_joule_profiler(&[
+        "simple",
+        "--quiet",
+        "--merge-stderr",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "echo chatty; echo noisy >&2",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        !stdout
+            .lines()
+            .any(|line| line == "chatty" || line == "noisy")
+    );
+    assert!(!stderr.lines().any(|line| line == "noisy"));
+    assert!(stdout.contains("[fake]"));
+}
+
+#[test]
+fn written_marker_goes_to_stderr() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("run.json");
+    let path = path.to_str().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_joule-profiler"))
+        .args([
+            "simple",
+            "--json",
+            "--jouleit-file",
+            path,
+            "--fake-source",
+            FAKE_SOURCE,
+            "--",
+            "echo",
+            "data",
+        ])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "data\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("JSON written to: {}", path)));
+}
+
+#[test]
+fn dash_output_file_writes_the_results_to_stdout() {
+    let output = Command::new(env!("CARGO_BIN_EXE_joule-profiler"))
+        .args([
+            "phases",
+            "--json",
+            "--jouleit-file",
+            "-",
+            "--fake-source",
+            FAKE_SOURCE,
+            "--",
+            "echo",
+            "__WORK__",
+        ])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["mode"], "phases");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("__WORK__"));
+    assert!(stderr.contains("JSON written to: stdout"));
+}
+
+#[test]
+fn json_phases_output_has_a_phase_per_token() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("phases.json");
+    let path = path.to_str().unwrap();
+
+    joule_profiler(&[
+        "phases",
+        "--json",
+        "--jouleit-file",
+        path,
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "echo __WORK_START__; echo __WORK_END__",
+    ]);
+
+    let json: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+    let names: Vec<&str> = json["phases"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|phase| phase["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "START -> __WORK_START__",
+            "__WORK_START__ -> __WORK_END__",
+            "__WORK_END__ -> END"
+        ]
+    );
+    for phase in json["phases"].as_array().unwrap() {
+        assert_eq!(phase["metrics"].as_array().unwrap().len(), 2);
+    }
+
+    // The phases follow each other on the wall clock
+    let phases = json["phases"].as_array().unwrap();
+    for phase in phases {
+        assert!(phase["start_us"].as_u64().unwrap() <= phase["end_us"].as_u64().unwrap());
+    }
+    for pair in phases.windows(2) {
+        assert_eq!(pair[0]["end_us"], pair[1]["start_us"]);
+    }
+}
+
+#[test]
+fn json_phases_output_has_the_polling_of_each_source() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("phases.json");
+    let path = path.to_str().unwrap();
+
+    joule_profiler(&[
+        "phases",
+        "--json",
+        "--jouleit-file",
+        path,
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "echo __WORK_START__; echo __WORK_END__",
+    ]);
+
+    let json: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+    let polling = &json["polling"]["fake"];
+    // A snapshot at least at each phase boundary
+    assert!(polling["measure_count"].as_u64().unwrap() >= 3);
+    assert_eq!(polling["interval_us"], Value::Null);
+}
+
+#[test]
+fn canonical_json_is_identical_between_runs() {
+    let dir = tempdir().unwrap();
+    let run = |name: &str| {
+        let path = dir.path().join(name);
+        let path = path.to_str().unwrap();
+        joule_profiler(&[
+            "phases",
+            "--json",
+            "--canonical-json",
+            "-n",
+            "2",
+            "--jouleit-file",
+            path,
+            "--fake-source",
+            FAKE_SOURCE,
+            "--",
+            "sh",
+            "-c",
+            "echo __WORK_START__; sleep 0.01; echo __WORK_END__",
+        ]);
+        read_to_string(path).unwrap()
+    };
+
+    let first = run("first.json");
+    let second = run("second.json");
+
+    assert_eq!(first, second);
+    assert!(!first.contains("duration"));
+    let json: Value = serde_json::from_str(&first).unwrap();
+    assert_eq!(
+        json["iterations"][0]["phases"][0]["metrics"][0]["name"],
+        "DRAM_0"
+    );
+}
+
+#[test]
+fn json_metrics_are_grouped_by_socket() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("sockets.json");
+    let path = path.to_str().unwrap();
+
+    joule_profiler(&[
+        "simple",
+        "--json",
+        "--json-group-by-socket",
+        "--jouleit-file",
+        path,
+        "--fake-source",
+        "PACKAGE-0_0=1500000,DRAM_0=20000,PACKAGE-1_1=1400000,DRAM_1=30000",
+        "--",
+        "true",
+    ]);
+
+    let json: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+    let names = |socket: &str| -> Vec<String> {
+        let mut names: Vec<String> = json["metrics"][socket]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|metric| metric["name"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names("0"), ["DRAM_0", "PACKAGE-0_0"]);
+    assert_eq!(names("1"), ["DRAM_1", "PACKAGE-1_1"]);
+}
+
+#[test]
+fn interrupted_iterations_still_write_their_results() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("interrupted.json");
+
+    let child = Command::new(env!("CARGO_BIN_EXE_joule-profiler"))
+        .args(["simple", "--json", "-n", "100", "--jouleit-file"])
+        .arg(&path)
+        .args(["--fake-source", FAKE_SOURCE, "--", "sleep", "0.2"])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    // SAFETY: kill has no memory safety preconditions, the child is not reaped yet
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130));
+    let json: Value = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+    let iterations = json["iterations"].as_array().unwrap().len();
+    assert!((1..100).contains(&iterations), "{} iterations", iterations);
+}
+
+#[test]
+fn fields_only_write_the_selected_columns_and_keys() {
+    let dir = tempdir().unwrap();
+    let run = |format: &str, name: &str| {
+        let path = dir.path().join(name);
+        let path = path.to_str().unwrap();
+        joule_profiler(&[
+            "simple",
+            format,
+            "--fields",
+            "command,duration_ms",
+            "--jouleit-file",
+            path,
+            "--fake-source",
+            FAKE_SOURCE,
+            "--",
+            "true",
+        ]);
+        read_to_string(path).unwrap()
+    };
+
+    let json: Value = serde_json::from_str(&run("--json", "fields.json")).unwrap();
+    let mut keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["command", "duration_ms", "metrics"]);
+
+    let csv = run("--csv", "fields.csv");
+    let header: Vec<&str> = csv.lines().next().unwrap().split(';').collect();
+    assert_eq!(
+        header,
+        [
+            "command",
+            "DRAM_0",
+            "PACKAGE-0_0",
+            "duration_ms",
+            "DRAM_0_power_w",
+            "PACKAGE-0_0_power_w"
+        ]
+    );
+    for field in ["measure_count", "measure_delta", "exit_code"] {
+        assert!(!csv.contains(field));
+    }
+    assert_eq!(csv.lines().nth(1).unwrap().split(';').count(), header.len());
+}
+
+#[test]
+fn exit_code_of_the_command_is_propagated() {
+    let status = |args: &[&str]| run_joule_profiler(args).status.code();
+
+    let failing = [
+        "simple",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "exit 3",
+    ];
+    assert_eq!(status(&failing), Some(3));
+    assert_eq!(
+        status(&["simple", "--fake-source", FAKE_SOURCE, "--", "true"]),
+        Some(0)
+    );
+
+    // The profiler failing is told apart from the command failing
+    let output = Command::new(env!("CARGO_BIN_EXE_joule-profiler"))
+        .args(["simple", "--", "true"])
+        .env("JOULE_PROFILER_RAPL_PATH", "/nonexistent")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(70));
+}
+
+#[test]
+fn post_hook_runs_on_the_written_results() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("run.json");
+    let path = path.to_str().unwrap();
+    let hook_output = dir.path().join("hook.txt");
+    let hook = format!(
+        "cp \"$1\" {}.json; echo $JP_EXIT_CODE $JP_TOTAL_UJ > {}; false",
+        hook_output.display(),
+        hook_output.display()
+    );
+
+    // The results are written before the hook, which fails without failing the profiler
+    joule_profiler(&[
+        "simple",
+        "--json",
+        "--jouleit-file",
+        path,
+        "--post-hook",
+        &format!("sh -c '{}' sh", hook),
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+
+    assert_eq!(read_to_string(&hook_output).unwrap(), "0 1520000\n");
+    let copy = read_to_string(format!("{}.json", hook_output.display())).unwrap();
+    assert_eq!(copy, read_to_string(path).unwrap());
+
+    let output = run_joule_profiler(&[
+        "simple",
+        "--post-hook",
+        "true",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--post-hook needs a results file"));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn html_report_is_written_to_the_file() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("report.html");
+    let path = path.to_str().unwrap();
+
+    joule_profiler(&[
+        "simple",
+        "--html",
+        path,
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+
+    let html = read_to_string(path).unwrap();
+    assert!(html.contains("<svg"));
+    assert!(html.contains("PACKAGE-0_0"));
+}
+
+#[test]
+fn format_none_writes_nothing() {
+    let dir = tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_joule-profiler"))
+        .args([
+            "simple",
+            "--format",
+            "none",
+            "--fake-source",
+            FAKE_SOURCE,
+            "--",
+            "true",
+        ])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(dir.path().read_dir().unwrap().count(), 0);
+}
+
+#[test]
+fn print_total_writes_only_the_package_energy() {
+    let output = run_joule_profiler(&[
+        "simple",
+        "--print-total",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "echo",
+        "chatty",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1500000\n");
+    // The program output goes to stderr
+    assert!(String::from_utf8(output.stderr).unwrap().contains("chatty"));
+
+    let stdout = joule_profiler(&[
+        "simple",
+        "--print-total=j",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "true",
+    ]);
+    assert_eq!(stdout, "1.500\n");
+}
+
+#[test]
+fn print_total_sums_the_phases() {
+    let output = run_joule_profiler(&[
+        "phases",
+        "--print-total",
+        "--fake-source",
+        FAKE_SOURCE,
+        "--",
+        "sh",
+        "-c",
+        "echo chatty; echo __A__",
+    ]);
+    assert!(output.status.success());
+    // The fake source reports its energy for each of the two phases
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3000000\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("chatty"));
+}